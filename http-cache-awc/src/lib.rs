@@ -0,0 +1,291 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The awc client implementation for http-cache. [`Cache`] wraps an
+//! [`awc::Client`] so that requests built with it are run through the cache.
+//! ```no_run
+//! use http_cache_awc::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+//!
+//! #[actix_rt::main]
+//! async fn main() -> http_cache::Result<()> {
+//!     let client = Cache::new(
+//!         awc::Client::default(),
+//!         HttpCache {
+//!             mode: CacheMode::Default,
+//!             manager: CACacheManager::default(),
+//!             options: HttpCacheOptions::default(),
+//!         },
+//!     );
+//!     let req = client
+//!         .client
+//!         .get("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching");
+//!     let mut res = client.send(req).await?;
+//!     println!("{:?}", res.body().await);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! awc futures aren't `Send`, so requests are driven through
+//! [`LocalMiddleware`] and have to run on a single-threaded runtime such as
+//! actix's own. Response bodies are read in full, up to
+//! [`Cache::payload_limit`], so they can be stored. Responses are returned
+//! as an [`awc::ClientResponse`] either way.
+//!
+//! awc has no public constructor for a `ClientResponse`, so they are built
+//! with [`awc::test::TestResponse`]. This is a limitation: one returned by
+//! the cache has the default timeout and none of the extensions the
+//! original had. Header values that aren't UTF-8 are stored with the bad
+//! bytes replaced instead of failing the request.
+use anyhow::anyhow;
+use std::{collections::HashMap, fmt};
+
+use actix_http::{BoxedPayloadStream, Payload};
+use awc::{
+    http::header::HeaderMap, Client, ClientRequest, ClientResponse,
+    FrozenClientRequest,
+};
+use bytes::Bytes;
+use http::{
+    header::{HeaderName, CACHE_CONTROL},
+    request::{self, Parts},
+    HeaderValue, Method, StatusCode,
+};
+use http_cache::{
+    now, CacheError, CacheManager, LocalMiddleware, Result, CACHE_STATUS,
+};
+use http_cache_semantics::CachePolicy;
+use url::Url;
+
+pub use http_cache::{
+    CacheMode, CacheOptions, CacheStatusHeader, CookiePolicy, HttpCache,
+    HttpCacheOptions, HttpResponse, RedirectKey, SetCookiePolicy,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
+
+#[cfg(feature = "manager-memory")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-memory")))]
+pub use http_cache::MemoryManager;
+
+/// The payload limit awc itself applies when reading a response body
+pub const DEFAULT_PAYLOAD_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Wraps an [`awc::Client`] so that requests consult the cache
+pub struct Cache<T: CacheManager> {
+    /// The client used to fetch upstream resources
+    pub client: Client,
+    /// The cache that requests are run through
+    pub cache: HttpCache<T>,
+    /// The largest response body read from upstream, in bytes. Defaults to
+    /// [`DEFAULT_PAYLOAD_LIMIT`].
+    pub payload_limit: usize,
+}
+
+impl<T: CacheManager + fmt::Debug> fmt::Debug for Cache<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("cache", &self.cache)
+            .field("payload_limit", &self.payload_limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: CacheManager> Cache<T> {
+    /// Creates a new cache client
+    pub fn new(client: Client, cache: HttpCache<T>) -> Self {
+        Self { client, cache, payload_limit: DEFAULT_PAYLOAD_LIMIT }
+    }
+
+    /// Sends a GET request to the url, consulting the cache
+    pub async fn get(&self, url: &str) -> Result<ClientResponse> {
+        self.send(self.client.get(url)).await
+    }
+
+    /// Sends the request without a body, consulting the cache
+    pub async fn send(&self, req: ClientRequest) -> Result<ClientResponse> {
+        self.send_body(req, Bytes::new()).await
+    }
+
+    /// Sends the request with the body, consulting the cache
+    pub async fn send_body(
+        &self,
+        req: ClientRequest,
+        body: impl Into<Bytes>,
+    ) -> Result<ClientResponse> {
+        let req = match req.freeze() {
+            Ok(r) => r,
+            Err(e) => return Err(CacheError::General(anyhow!(e.to_string()))),
+        };
+        let middleware = AwcMiddleware {
+            url: Url::parse(&req.get_uri().to_string())?,
+            req,
+            extra_headers: HeaderMap::new(),
+            body: body.into(),
+            limit: self.payload_limit,
+        };
+        if !self.cache.can_cache_request(&middleware) {
+            self.cache.run_no_cache(&middleware).await?;
+            let mut headers = Vec::new();
+            for (name, value) in self.cache.bypass_headers(&middleware) {
+                headers.push((
+                    HeaderName::from_static(name),
+                    HeaderValue::from_str(&value)?,
+                ));
+            }
+            let res = match middleware.req.send_body(middleware.body).await {
+                Ok(r) => r,
+                Err(e) => {
+                    return Err(CacheError::General(anyhow!(e.to_string())))
+                }
+            };
+            return Ok(res.map_body(|head, payload| {
+                for (name, value) in headers {
+                    if name == CACHE_STATUS {
+                        head.headers.append(name, value);
+                    } else {
+                        head.headers.insert(name, value);
+                    }
+                }
+                let payload: BoxedPayloadStream = Box::pin(payload);
+                Payload::Stream { payload }
+            }));
+        }
+        let res = self.cache.run(middleware).await?;
+        convert_response(res)
+    }
+}
+
+/// Implements ['LocalMiddleware'] for awc
+pub(crate) struct AwcMiddleware {
+    pub req: FrozenClientRequest,
+    // Headers the cache added, such as the conditional request headers
+    pub extra_headers: HeaderMap,
+    pub body: Bytes,
+    pub url: Url,
+    pub limit: usize,
+}
+
+impl LocalMiddleware for AwcMiddleware {
+    fn is_method_get_head(&self) -> bool {
+        self.req.get_method() == Method::GET
+            || self.req.get_method() == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        self.policy_with_options(response, CacheOptions::default())
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &Parts) -> Result<()> {
+        for header in parts.headers.iter() {
+            self.extra_headers.insert(header.0.clone(), header.1.clone());
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.extra_headers
+            .insert(CACHE_CONTROL, HeaderValue::from_str("no-cache")?);
+        Ok(())
+    }
+    fn parts(&self) -> Result<Parts> {
+        let mut converted = request::Builder::new()
+            .method(self.req.get_method().clone())
+            .uri(self.req.get_uri().clone())
+            .body(())?;
+        let headers = converted.headers_mut();
+        for header in self.req.headers().iter() {
+            headers.append(header.0.clone(), header.1.clone());
+        }
+        for header in self.extra_headers.iter() {
+            headers.insert(header.0.clone(), header.1.clone());
+        }
+        Ok(converted.into_parts().0)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.req.get_method().as_ref().to_string())
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        let sent = self
+            .req
+            .extra_headers(self.extra_headers.clone())
+            .send_body(self.body.clone());
+        let mut res = match sent.await {
+            Ok(r) => r,
+            Err(e) => return Err(CacheError::General(anyhow!(e.to_string()))),
+        };
+        // Values that aren't UTF-8 are kept with the bad bytes replaced, as
+        // failing the whole response over one header helps no one
+        let mut headers = HashMap::new();
+        for header in res.headers().iter() {
+            headers.insert(
+                header.0.as_str().to_owned(),
+                String::from_utf8_lossy(header.1.as_bytes()).into_owned(),
+            );
+        }
+        let status = res.status().as_u16();
+        let version = res.version().try_into()?;
+        let body = match res.body().limit(self.limit).await {
+            Ok(b) => b,
+            Err(e) => return Err(CacheError::General(anyhow!(e.to_string()))),
+        };
+        Ok(HttpResponse {
+            body,
+            headers,
+            trailers: HashMap::new(),
+            status,
+            url: self.url.clone(),
+            version,
+        })
+    }
+}
+
+// Converts an [`HttpResponse`] to an awc [`ClientResponse`]
+fn convert_response(response: HttpResponse) -> Result<ClientResponse> {
+    let status = StatusCode::from_u16(response.status)?;
+    let mut headers = HeaderMap::new();
+    for header in response.headers {
+        headers.insert(
+            HeaderName::from_bytes(header.0.as_bytes())?,
+            HeaderValue::from_str(header.1.as_str())?,
+        );
+    }
+    // `ClientResponse::new` is private to awc, so the response is made with
+    // its test builder and the head is filled in after
+    let res = awc::test::TestResponse::default()
+        .version(response.version.into())
+        .set_payload(response.body)
+        .finish();
+    Ok(res.map_body(|head, payload| {
+        head.status = status;
+        head.headers = headers;
+        payload
+    }))
+}
@@ -0,0 +1,249 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The http-client implementation for http-cache. [`Cache`] wraps any
+//! [`HttpClient`] so that requests sent with it are run through the cache.
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use http_cache_client::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+//! use http_client::HttpClient;
+//! use http_types::{Method, Request};
+//!
+//! async fn run(client: Arc<dyn HttpClient>) -> http_cache::Result<()> {
+//!     let client = Cache::new(
+//!         client,
+//!         HttpCache {
+//!             mode: CacheMode::Default,
+//!             manager: CACacheManager::default(),
+//!             options: HttpCacheOptions::default(),
+//!         },
+//!     );
+//!     let req = Request::new(
+//!         Method::Get,
+//!         "https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching",
+//!     );
+//!     let mut res = client.send(req).await?;
+//!     println!("{:?}", res.body_string().await);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Request and response bodies are buffered so they can be stored. Headers
+//! with several values are sent on as they are, but are joined into one
+//! comma separated value when stored, as the cache keeps one value per
+//! header name. The [`CacheMode`] can be overridden for a single request by
+//! adding it as an extension through [`Request::ext_mut`].
+use anyhow::anyhow;
+use std::{collections::HashMap, convert::TryInto, str::FromStr, sync::Arc};
+
+use http::{
+    header::{HeaderName, CACHE_CONTROL},
+    request::{self, Parts},
+    HeaderValue,
+};
+use http_cache::{
+    now, CacheError, CacheManager, Middleware, Result, CACHE_STATUS,
+};
+use http_cache_semantics::CachePolicy;
+use http_client::HttpClient;
+use http_types::{headers, Method, Request, Response, StatusCode, Version};
+use url::Url;
+
+pub use http_cache::{
+    CacheMode, CacheOptions, CacheStatusHeader, CookiePolicy, HttpCache,
+    HttpCacheOptions, HttpResponse, RedirectKey, SetCookiePolicy,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
+
+#[cfg(feature = "manager-memory")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-memory")))]
+pub use http_cache::MemoryManager;
+
+/// Wraps an [`HttpClient`] so that requests consult the cache
+#[derive(Debug)]
+pub struct Cache<T: CacheManager> {
+    /// The client used to fetch upstream resources
+    pub client: Arc<dyn HttpClient>,
+    /// The cache that requests are run through
+    pub cache: HttpCache<T>,
+}
+
+impl<T: CacheManager> Cache<T> {
+    /// Creates a new cache client
+    pub fn new(client: Arc<dyn HttpClient>, cache: HttpCache<T>) -> Self {
+        Self { client, cache }
+    }
+
+    /// Sends the request, consulting the cache. A [`CacheMode`] extension
+    /// on the request overrides the mode for this request only.
+    pub async fn send(&self, mut req: Request) -> Result<Response> {
+        let mode = req.ext().get::<CacheMode>().copied();
+        let body = match req.take_body().into_bytes().await {
+            Ok(b) => b,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
+        let middleware =
+            ClientMiddleware { req, body, mode, client: self.client.as_ref() };
+        if !self.cache.can_cache_request(&middleware) {
+            self.cache.run_no_cache(&middleware).await?;
+            let mut res = middleware.send().await?;
+            for (name, value) in self.cache.bypass_headers(&middleware) {
+                if name == CACHE_STATUS {
+                    res.append_header(name, value.as_str());
+                } else {
+                    res.insert_header(name, value.as_str());
+                }
+            }
+            return Ok(res);
+        }
+        let res = self.cache.run(middleware).await?;
+        convert_response(res)
+    }
+}
+
+/// Implements ['Middleware'] for http-client
+pub(crate) struct ClientMiddleware<'a> {
+    pub req: Request,
+    pub body: Vec<u8>,
+    pub mode: Option<CacheMode>,
+    pub client: &'a dyn HttpClient,
+}
+
+impl ClientMiddleware<'_> {
+    // Sends a copy of the request, the cache may need to send it again
+    async fn send(&self) -> Result<Response> {
+        let mut req = self.req.clone();
+        req.set_body(self.body.clone());
+        match self.client.send(req).await {
+            Ok(r) => Ok(r),
+            Err(e) => Err(CacheError::General(anyhow!(e))),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for ClientMiddleware<'_> {
+    fn is_method_get_head(&self) -> bool {
+        self.req.method() == Method::Get || self.req.method() == Method::Head
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        self.policy_with_options(response, CacheOptions::default())
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &Parts) -> Result<()> {
+        for header in parts.headers.iter() {
+            let value = to_header_value(header.1.as_bytes())?;
+            self.req.insert_header(header.0.as_str(), value);
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.req.insert_header(CACHE_CONTROL.as_str(), "no-cache");
+        Ok(())
+    }
+    fn parts(&self) -> Result<Parts> {
+        let mut converted = request::Builder::new()
+            .method(self.req.method().as_ref())
+            .uri(self.req.url().as_str())
+            .body(())?;
+        let headers = converted.headers_mut();
+        for (name, values) in self.req.iter() {
+            let name = HeaderName::from_str(name.as_str())?;
+            for value in values {
+                headers.append(
+                    name.clone(),
+                    HeaderValue::from_str(value.as_str())?,
+                );
+            }
+        }
+        Ok(converted.into_parts().0)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.req.url().clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.req.method().as_ref().to_string())
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        self.mode
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        let mut res = self.send().await?;
+        let mut headers = HashMap::new();
+        for (name, values) in res.iter() {
+            let joined = values.iter().map(|v| v.as_str()).collect::<Vec<_>>();
+            headers.insert(name.as_str().to_owned(), joined.join(", "));
+        }
+        let status = res.status().into();
+        let version = res.version().unwrap_or(Version::Http1_1);
+        let body = match res.body_bytes().await {
+            Ok(b) => b,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
+        Ok(HttpResponse {
+            body: body.into(),
+            headers,
+            trailers: HashMap::new(),
+            status,
+            url: self.req.url().clone(),
+            version: version.try_into()?,
+        })
+    }
+}
+
+// Converts header bytes to an http-types header value
+fn to_header_value(bytes: &[u8]) -> Result<headers::HeaderValue> {
+    match headers::HeaderValue::from_bytes(bytes.to_vec()) {
+        Ok(v) => Ok(v),
+        Err(_e) => Err(CacheError::BadHeader),
+    }
+}
+
+// Converts an [`HttpResponse`] to an http-types [`Response`]
+fn convert_response(response: HttpResponse) -> Result<Response> {
+    let status: StatusCode = match response.status.try_into() {
+        Ok(s) => s,
+        Err(e) => return Err(CacheError::General(anyhow!(e))),
+    };
+    let mut converted = Response::new(status);
+    for header in &response.headers {
+        let name = match headers::HeaderName::from_str(header.0) {
+            Ok(n) => n,
+            Err(_e) => return Err(CacheError::BadHeader),
+        };
+        converted.insert_header(name, to_header_value(header.1.as_bytes())?);
+    }
+    converted.set_version(Some(response.version.into()));
+    // http-types bodies can't borrow the buffer, so this copies it
+    converted.set_body(response.body.to_vec());
+    Ok(converted)
+}
@@ -0,0 +1,375 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The curl client implementation for http-cache. [`Cache`] performs the
+//! transfers for requests that miss the cache with libcurl, through the
+//! [`curl`] crate's [`Easy2`] handles, on the calling thread.
+//! ```no_run
+//! use http_cache_curl::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+//!
+//! fn main() -> http_cache::Result<()> {
+//!     let client = Cache::new(HttpCache {
+//!         mode: CacheMode::Default,
+//!         manager: CACacheManager::default(),
+//!         options: HttpCacheOptions::default(),
+//!     });
+//!     let res = client.get("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching")?;
+//!     println!("{}", String::from_utf8_lossy(res.body()));
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Each transfer is performed on a handle of its own unless a [`Multi`]
+//! handle is passed to [`Cache::execute_multi`], which lets connections be
+//! reused across requests. Redirects aren't followed. Responses are
+//! returned as an [`http::Response`] with the body read into a `Vec<u8>`.
+//!
+//! Header values that aren't valid UTF-8 are read as ISO-8859-1, and
+//! headers the response repeats are joined into one comma separated value,
+//! as the cache keeps one value per header name. The [`CacheMode`] can be
+//! overridden for a single request by passing it to [`Cache::execute`] as
+//! an extension.
+use anyhow::anyhow;
+use std::{collections::HashMap, time::Duration};
+
+use curl::{
+    easy::{Easy2, Handler, List, WriteError},
+    multi::Multi,
+};
+use http::{
+    header::{HeaderName, CACHE_CONTROL},
+    request::{self, Parts},
+    HeaderMap, HeaderValue, Method, Request, Response,
+};
+use http_cache::{
+    now, CacheError, CacheManager, HttpVersion, LocalMiddleware, Result,
+    CACHE_STATUS,
+};
+use http_cache_semantics::CachePolicy;
+use url::Url;
+
+pub use http_cache::{
+    CacheMode, CacheOptions, CacheStatusHeader, CookiePolicy, HttpCache,
+    HttpCacheOptions, HttpResponse, RedirectKey, SetCookiePolicy,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
+
+#[cfg(feature = "manager-memory")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-memory")))]
+pub use http_cache::MemoryManager;
+
+/// Runs requests through the cache, performing transfers with libcurl
+#[derive(Debug)]
+pub struct Cache<T: CacheManager> {
+    /// The cache that requests are run through
+    pub cache: HttpCache<T>,
+}
+
+/// Collects the status line, headers and body of a transfer
+#[derive(Debug, Default)]
+struct Collector {
+    version: Option<HttpVersion>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Handler for Collector {
+    fn write(&mut self, data: &[u8]) -> std::result::Result<usize, WriteError> {
+        self.body.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn header(&mut self, data: &[u8]) -> bool {
+        let line = decode(data);
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if line.starts_with("HTTP/") {
+            // Interim responses, such as `100 Continue`, come first and
+            // their headers don't belong to the final response
+            self.version = parse_version(line);
+            self.headers.clear();
+        } else if line.starts_with(&[' ', '\t'][..]) {
+            // A folded line continues the previous header's value
+            if let Some((_, value)) = self.headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            self.headers
+                .push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+        true
+    }
+}
+
+// Reads header bytes as UTF-8, or as ISO-8859-1 when they aren't valid
+// UTF-8, which maps every byte to the character with the same value
+fn decode(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(s) => s.to_string(),
+        Err(_) => data.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+// Reads the version from a status line such as `HTTP/1.1 200 OK`
+fn parse_version(line: &str) -> Option<HttpVersion> {
+    match line.split_whitespace().next()? {
+        "HTTP/0.9" => Some(HttpVersion::Http09),
+        "HTTP/1.0" => Some(HttpVersion::Http10),
+        "HTTP/1.1" => Some(HttpVersion::Http11),
+        "HTTP/2" | "HTTP/2.0" => Some(HttpVersion::H2),
+        "HTTP/3" | "HTTP/3.0" => Some(HttpVersion::H3),
+        _ => None,
+    }
+}
+
+fn curl_error(e: impl std::error::Error + Send + Sync + 'static) -> CacheError {
+    CacheError::General(anyhow!(e))
+}
+
+/// Implements ['LocalMiddleware'] for curl
+pub(crate) struct CurlMiddleware<'a> {
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub mode: Option<CacheMode>,
+    pub multi: Option<&'a Multi>,
+}
+
+impl CurlMiddleware<'_> {
+    // Sets up a handle for the request
+    fn handle(&self) -> Result<Easy2<Collector>> {
+        let mut easy = Easy2::new(Collector::default());
+        easy.url(self.url.as_str()).map_err(curl_error)?;
+        if self.method == Method::GET {
+            easy.get(true).map_err(curl_error)?;
+        } else if self.method == Method::HEAD {
+            easy.nobody(true).map_err(curl_error)?;
+        } else {
+            easy.custom_request(self.method.as_str()).map_err(curl_error)?;
+        }
+        if !self.body.is_empty() {
+            easy.post_fields_copy(&self.body).map_err(curl_error)?;
+        }
+        let mut list = List::new();
+        for (name, value) in self.headers.iter() {
+            list.append(&format!("{}: {}", name, value.to_str()?))
+                .map_err(curl_error)?;
+        }
+        easy.http_headers(list).map_err(curl_error)?;
+        Ok(easy)
+    }
+
+    // Performs the transfer, whatever the response status
+    fn send(&self) -> Result<HttpResponse> {
+        let easy = self.handle()?;
+        let mut easy = match self.multi {
+            Some(multi) => perform_multi(multi, easy)?,
+            None => {
+                easy.perform().map_err(curl_error)?;
+                easy
+            }
+        };
+        let status = easy.response_code().map_err(curl_error)?;
+        let url = match easy.effective_url().map_err(curl_error)? {
+            Some(url) => Url::parse(url)?,
+            None => self.url.clone(),
+        };
+        let collector = easy.get_mut();
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for (name, value) in collector.headers.drain(..) {
+            match headers.get_mut(&name) {
+                Some(joined) => {
+                    joined.push_str(", ");
+                    joined.push_str(&value);
+                }
+                None => {
+                    headers.insert(name, value);
+                }
+            }
+        }
+        Ok(HttpResponse {
+            body: std::mem::take(&mut collector.body).into(),
+            headers,
+            trailers: HashMap::new(),
+            status: status as u16,
+            url,
+            version: collector.version.ok_or(CacheError::BadVersion)?,
+        })
+    }
+}
+
+// Drives the multi handle until the transfer is done. Transfers others
+// added to the handle are driven along with it.
+fn perform_multi(
+    multi: &Multi,
+    easy: Easy2<Collector>,
+) -> Result<Easy2<Collector>> {
+    let handle = multi.add2(easy).map_err(curl_error)?;
+    loop {
+        let running = multi.perform().map_err(curl_error)?;
+        let mut done = None;
+        multi.messages(|msg| {
+            if let Some(res) = msg.result_for2(&handle) {
+                done = Some(res);
+            }
+        });
+        if let Some(res) = done {
+            let easy = multi.remove2(handle).map_err(curl_error)?;
+            res.map_err(curl_error)?;
+            return Ok(easy);
+        }
+        if running == 0 {
+            return Err(CacheError::General(anyhow!(
+                "the transfer stopped without completing"
+            )));
+        }
+        multi.wait(&mut [], Duration::from_secs(1)).map_err(curl_error)?;
+    }
+}
+
+impl LocalMiddleware for CurlMiddleware<'_> {
+    fn is_method_get_head(&self) -> bool {
+        self.method == Method::GET || self.method == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        self.policy_with_options(response, CacheOptions::default())
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &Parts) -> Result<()> {
+        for header in parts.headers.iter() {
+            self.headers.insert(header.0.clone(), header.1.clone());
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.headers.insert(CACHE_CONTROL, HeaderValue::from_str("no-cache")?);
+        Ok(())
+    }
+    fn parts(&self) -> Result<Parts> {
+        let mut converted = request::Builder::new()
+            .method(self.method.as_str())
+            .uri(self.url.as_str())
+            .body(())?;
+        *converted.headers_mut() = self.headers.clone();
+        Ok(converted.into_parts().0)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.method.as_ref().to_string())
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        self.mode
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        self.send()
+    }
+}
+
+// Converts an [`HttpResponse`] to an [`http::Response`]
+fn convert_response(response: HttpResponse) -> Result<Response<Vec<u8>>> {
+    let mut ret_res = Response::builder()
+        .status(response.status)
+        .version(response.version.into())
+        .body(response.body.to_vec())?;
+    for header in response.headers {
+        ret_res.headers_mut().insert(
+            HeaderName::from_bytes(header.0.as_bytes())?,
+            HeaderValue::from_str(header.1.as_str())?,
+        );
+    }
+    Ok(ret_res)
+}
+
+impl<T: CacheManager> Cache<T> {
+    /// Creates a new cache client
+    pub fn new(cache: HttpCache<T>) -> Self {
+        Self { cache }
+    }
+
+    /// Sends a GET request to the url, consulting the cache
+    pub fn get(&self, url: &str) -> Result<Response<Vec<u8>>> {
+        let req = Request::get(url).body(Vec::new())?;
+        self.execute(req)
+    }
+
+    /// Executes the request, consulting the cache. A [`CacheMode`] extension
+    /// on the request overrides the mode for this request only.
+    pub fn execute(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+        self.run(req, None)
+    }
+
+    /// Executes the request like [`Cache::execute`], adding the transfer to
+    /// the multi handle so that it shares the handle's connections. The
+    /// handle is driven until the transfer is done, so it shouldn't be
+    /// driven elsewhere at the same time.
+    pub fn execute_multi(
+        &self,
+        req: Request<Vec<u8>>,
+        multi: &Multi,
+    ) -> Result<Response<Vec<u8>>> {
+        self.run(req, Some(multi))
+    }
+
+    fn run(
+        &self,
+        req: Request<Vec<u8>>,
+        multi: Option<&Multi>,
+    ) -> Result<Response<Vec<u8>>> {
+        let (parts, body) = req.into_parts();
+        let middleware = CurlMiddleware {
+            url: Url::parse(&parts.uri.to_string())?,
+            method: parts.method,
+            headers: parts.headers,
+            body,
+            mode: parts.extensions.get::<CacheMode>().copied(),
+            multi,
+        };
+        if !self.cache.can_cache_request(&middleware) {
+            futures_executor::block_on(self.cache.run_no_cache(&middleware))?;
+            let headers = self.cache.bypass_headers(&middleware);
+            let mut res = convert_response(middleware.send()?)?;
+            for (name, value) in headers {
+                let value = HeaderValue::from_str(&value)?;
+                if name == CACHE_STATUS {
+                    res.headers_mut().append(name, value);
+                } else {
+                    res.headers_mut().insert(name, value);
+                }
+            }
+            return Ok(res);
+        }
+        let res = futures_executor::block_on(self.cache.run(middleware))?;
+        convert_response(res)
+    }
+}
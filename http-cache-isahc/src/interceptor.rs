@@ -0,0 +1,49 @@
+//! The cache as an isahc [`Interceptor`], for clients built with
+//! [`isahc::HttpClientBuilder`]. Interceptors are an unstable isahc API, so
+//! this module follows along with it.
+//! ```no_run
+//! use http_cache_isahc::interceptor::CacheInterceptor;
+//! use http_cache_isahc::{CACacheManager, CacheMode, HttpCache, HttpCacheOptions};
+//! use isahc::HttpClient;
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     futures::executor::block_on(async {
+//!         let client = HttpClient::builder()
+//!             .interceptor(CacheInterceptor(HttpCache {
+//!                 mode: CacheMode::Default,
+//!                 manager: CACacheManager::default(),
+//!                 options: HttpCacheOptions::default(),
+//!             }))
+//!             .build()?;
+//!         client
+//!             .get_async("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching")
+//!             .await?;
+//!         Ok(())
+//!     })
+//! }
+//! ```
+use crate::{handle, Sender};
+
+use http_cache::{CacheError, CacheManager, HttpCache};
+use isahc::{
+    interceptor::{Context, Interceptor, InterceptorFuture},
+    AsyncBody, Request,
+};
+
+/// Runs the requests of the client it's added to through the cache
+#[derive(Debug)]
+pub struct CacheInterceptor<T: CacheManager>(pub HttpCache<T>);
+
+impl<T: CacheManager> Interceptor for CacheInterceptor<T> {
+    type Err = CacheError;
+
+    fn intercept<'a>(
+        &'a self,
+        request: Request<AsyncBody>,
+        ctx: Context<'a>,
+    ) -> InterceptorFuture<'a, Self::Err> {
+        Box::pin(async move {
+            handle(&self.0, request, Sender::Context(&ctx)).await
+        })
+    }
+}
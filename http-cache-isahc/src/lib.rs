@@ -0,0 +1,301 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The isahc client implementation for http-cache. [`Cache`] wraps an
+//! [`isahc::HttpClient`] so that requests are run through the cache.
+//! ```no_run
+//! use http_cache_isahc::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+//! use isahc::{AsyncReadResponseExt, HttpClient};
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     futures::executor::block_on(async {
+//!         let client = Cache::new(
+//!             HttpClient::new()?,
+//!             HttpCache {
+//!                 mode: CacheMode::Default,
+//!                 manager: CACacheManager::default(),
+//!                 options: HttpCacheOptions::default(),
+//!             },
+//!         );
+//!         let mut res = client
+//!             .get_async("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching")
+//!             .await?;
+//!         println!("{}", res.text().await?);
+//!         Ok(())
+//!     })
+//! }
+//! ```
+//!
+//! With the `unstable-interceptors` feature the cache can instead be added
+//! to an [`isahc::HttpClientBuilder`] as an interceptor, see
+//! [`interceptor`].
+//!
+//! Response bodies are buffered so they can be stored. The extensions isahc
+//! attaches to a response, such as its [`Metrics`] and addresses, are kept
+//! on responses that came from the network, including revalidated ones.
+//! Responses served from the cache alone have none. The [`CacheMode`] can
+//! be overridden for a single request by adding it as an extension, which
+//! is removed before the request is sent on.
+//!
+//! [`Metrics`]: isahc::Metrics
+use anyhow::anyhow;
+use std::{collections::HashMap, mem, sync::Mutex};
+
+use futures::{io::Cursor, AsyncReadExt};
+use http::{
+    header::{HeaderName, CACHE_CONTROL},
+    request::{self, Parts},
+    Extensions, HeaderValue, Method, Request, Response,
+};
+use http_cache::{
+    now, CacheError, CacheManager, Middleware, Result, CACHE_STATUS,
+};
+use http_cache_semantics::CachePolicy;
+use isahc::{AsyncBody, AsyncReadResponseExt, HttpClient, ResponseExt};
+use url::Url;
+
+pub use http_cache::{
+    CacheMode, CacheOptions, CacheStatusHeader, CookiePolicy, HttpCache,
+    HttpCacheOptions, HttpResponse, RedirectKey, SetCookiePolicy,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
+
+#[cfg(feature = "manager-memory")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-memory")))]
+pub use http_cache::MemoryManager;
+
+#[cfg(feature = "unstable-interceptors")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable-interceptors")))]
+pub mod interceptor;
+
+/// Wraps an [`isahc::HttpClient`] so that requests consult the cache
+#[derive(Debug)]
+pub struct Cache<T: CacheManager> {
+    /// The client used to fetch upstream resources
+    pub client: HttpClient,
+    /// The cache that requests are run through
+    pub cache: HttpCache<T>,
+}
+
+impl<T: CacheManager> Cache<T> {
+    /// Creates a new cache client
+    pub fn new(client: HttpClient, cache: HttpCache<T>) -> Self {
+        Self { client, cache }
+    }
+
+    /// Sends a GET request to the uri, consulting the cache
+    pub async fn get_async<U>(&self, uri: U) -> Result<Response<AsyncBody>>
+    where
+        http::Uri: TryFrom<U>,
+        <http::Uri as TryFrom<U>>::Error: Into<http::Error>,
+    {
+        self.send_async(Request::get(uri).body(AsyncBody::empty())?).await
+    }
+
+    /// Sends the request, consulting the cache
+    pub async fn send_async<B: Into<AsyncBody>>(
+        &self,
+        req: Request<B>,
+    ) -> Result<Response<AsyncBody>> {
+        handle(&self.cache, req.map(Into::into), Sender::Client(&self.client))
+            .await
+    }
+}
+
+// Where requests that reach the network are sent
+pub(crate) enum Sender<'a> {
+    Client(&'a HttpClient),
+    #[cfg(feature = "unstable-interceptors")]
+    Context(&'a isahc::interceptor::Context<'a>),
+}
+
+impl Sender<'_> {
+    async fn send(
+        &self,
+        req: Request<AsyncBody>,
+    ) -> Result<Response<AsyncBody>> {
+        let res = match self {
+            Self::Client(client) => client.send_async(req).await,
+            #[cfg(feature = "unstable-interceptors")]
+            Self::Context(ctx) => ctx.send(req).await,
+        };
+        res.map_err(|e| CacheError::General(anyhow!(e)))
+    }
+}
+
+/// Implements ['Middleware'] for isahc
+pub(crate) struct IsahcMiddleware<'a> {
+    pub parts: Parts,
+    pub body: Vec<u8>,
+    pub url: Url,
+    pub mode: Option<CacheMode>,
+    pub sender: Sender<'a>,
+    // The extensions of the last response fetched from the network
+    pub fetched: &'a Mutex<Option<Extensions>>,
+}
+
+#[async_trait::async_trait]
+impl Middleware for IsahcMiddleware<'_> {
+    fn is_method_get_head(&self) -> bool {
+        self.parts.method == Method::GET || self.parts.method == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        self.policy_with_options(response, CacheOptions::default())
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &Parts) -> Result<()> {
+        for header in parts.headers.iter() {
+            self.parts.headers.insert(header.0.clone(), header.1.clone());
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.parts
+            .headers
+            .insert(CACHE_CONTROL, HeaderValue::from_str("no-cache")?);
+        Ok(())
+    }
+    fn parts(&self) -> Result<Parts> {
+        let mut converted = request::Builder::new()
+            .method(self.parts.method.clone())
+            .uri(self.parts.uri.clone())
+            .version(self.parts.version)
+            .body(())?;
+        *converted.headers_mut() = self.parts.headers.clone();
+        Ok(converted.into_parts().0)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.parts.method.as_ref().to_string())
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        self.mode
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        // The request configuration isahc keeps in the extensions has to
+        // travel with the request
+        let mut parts = self.parts()?;
+        parts.extensions = mem::take(&mut self.parts.extensions);
+        let req =
+            Request::from_parts(parts, AsyncBody::from(self.body.clone()));
+        let mut res = self.sender.send(req).await?;
+        let mut headers = HashMap::new();
+        for header in res.headers() {
+            headers.insert(
+                header.0.as_str().to_owned(),
+                String::from_utf8_lossy(header.1.as_bytes()).into_owned(),
+            );
+        }
+        let url = match res.effective_uri() {
+            Some(uri) => Url::parse(&uri.to_string())?,
+            None => self.url.clone(),
+        };
+        let status = res.status().into();
+        let version = res.version().try_into()?;
+        let body = match res.bytes().await {
+            Ok(b) => b,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
+        *self.fetched.lock().expect("lock poisoned") =
+            Some(mem::take(res.extensions_mut()));
+        Ok(HttpResponse {
+            body: body.into(),
+            headers,
+            trailers: HashMap::new(),
+            status,
+            url,
+            version,
+        })
+    }
+}
+
+// Converts an [`HttpResponse`] to an isahc [`Response`]
+fn convert_response(response: HttpResponse) -> Result<Response<AsyncBody>> {
+    // Reading through a cursor shares the body rather than copying it
+    let len = response.body.len() as u64;
+    let body = AsyncBody::from_reader_sized(Cursor::new(response.body), len);
+    let mut ret_res = Response::builder()
+        .status(response.status)
+        .version(response.version.into())
+        .body(body)?;
+    for header in response.headers {
+        ret_res.headers_mut().insert(
+            HeaderName::from_bytes(header.0.as_bytes())?,
+            HeaderValue::from_str(header.1.as_str())?,
+        );
+    }
+    Ok(ret_res)
+}
+
+// Runs the request through the cache, sending it on with the sender
+pub(crate) async fn handle<T: CacheManager>(
+    cache: &HttpCache<T>,
+    req: Request<AsyncBody>,
+    sender: Sender<'_>,
+) -> Result<Response<AsyncBody>> {
+    let (mut parts, mut body) = req.into_parts();
+    let mode = parts.extensions.remove::<CacheMode>();
+    let fetched = Mutex::new(None);
+    let mut middleware = IsahcMiddleware {
+        url: Url::parse(&parts.uri.to_string())?,
+        parts,
+        body: Vec::new(),
+        mode,
+        sender,
+        fetched: &fetched,
+    };
+    if !cache.can_cache_request(&middleware) {
+        cache.run_no_cache(&middleware).await?;
+        let headers = cache.bypass_headers(&middleware);
+        let req = Request::from_parts(middleware.parts, body);
+        let mut res = middleware.sender.send(req).await?;
+        for (name, value) in headers {
+            let value = HeaderValue::from_str(&value)?;
+            if name == CACHE_STATUS {
+                res.headers_mut().append(name, value);
+            } else {
+                res.headers_mut().insert(name, value);
+            }
+        }
+        return Ok(res);
+    }
+    if let Err(e) = body.read_to_end(&mut middleware.body).await {
+        return Err(CacheError::General(anyhow!(e)));
+    }
+    let res = cache.run(middleware).await?;
+    let mut converted = convert_response(res)?;
+    if let Some(extensions) = fetched.into_inner().expect("lock poisoned") {
+        *converted.extensions_mut() = extensions;
+    }
+    Ok(converted)
+}
@@ -0,0 +1,210 @@
+//! A blocking cache client built on [`reqwest::blocking::Client`].
+//!
+//! The cache logic is shared with the async middleware, requests are driven
+//! to completion on the calling thread.
+//! ```no_run
+//! use http_cache_reqwest::blocking::Cache;
+//! use http_cache_reqwest::{CACacheManager, CacheMode, HttpCache, HttpCacheOptions};
+//!
+//! fn main() -> http_cache::Result<()> {
+//!     let client = Cache::new(
+//!         reqwest::blocking::Client::new(),
+//!         HttpCache {
+//!             mode: CacheMode::Default,
+//!             manager: CACacheManager::default(),
+//!             options: HttpCacheOptions::default(),
+//!         },
+//!     );
+//!     client.get("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching")?;
+//!     Ok(())
+//! }
+//! ```
+use anyhow::anyhow;
+use std::{collections::HashMap, convert::TryInto, str::FromStr};
+
+use http::{
+    header::{HeaderName, CACHE_CONTROL},
+    request::{self, Parts},
+    HeaderValue, Method,
+};
+use http_cache::{
+    now, CacheError, CacheLookupStatus, CacheManager, CacheOptions, HttpCache,
+    HttpResponse, Middleware, Result, CACHE_STATUS,
+};
+use http_cache_semantics::CachePolicy;
+use reqwest::{
+    blocking::{Client, Request, Response},
+    IntoUrl, ResponseBuilderExt,
+};
+use url::Url;
+
+/// Wraps a [`reqwest::blocking::Client`] so that requests consult the cache
+#[derive(Debug)]
+pub struct Cache<T: CacheManager> {
+    /// The client used to fetch upstream resources
+    pub client: Client,
+    /// The cache that requests are run through
+    pub cache: HttpCache<T>,
+}
+
+/// Implements ['Middleware'] for the reqwest blocking client
+pub(crate) struct BlockingMiddleware<'a> {
+    pub req: Request,
+    pub client: &'a Client,
+}
+
+#[async_trait::async_trait]
+impl Middleware for BlockingMiddleware<'_> {
+    fn is_method_get_head(&self) -> bool {
+        self.req.method() == Method::GET || self.req.method() == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            CacheOptions::default(),
+        ))
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &Parts) -> Result<()> {
+        for header in parts.headers.iter() {
+            self.req.headers_mut().insert(header.0.clone(), header.1.clone());
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.req
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_str("no-cache")?);
+        Ok(())
+    }
+    fn parts(&self) -> Result<Parts> {
+        let mut converted = request::Builder::new()
+            .method(self.req.method().as_str())
+            .uri(self.req.url().as_str())
+            .body(())?;
+        *converted.headers_mut() = self.req.headers().clone();
+        Ok(converted.into_parts().0)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.req.url().clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.req.method().as_ref().to_string())
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        let copied_req = self.req.try_clone().ok_or(CacheError::BadRequest)?;
+        let res = match self.client.execute(copied_req) {
+            Ok(r) => r,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
+        let mut headers = HashMap::new();
+        for header in res.headers() {
+            headers.insert(
+                header.0.as_str().to_owned(),
+                String::from_utf8_lossy(header.1.as_bytes()).into_owned(),
+            );
+        }
+        let url = res.url().clone();
+        let status = res.status().into();
+        let version = res.version();
+        let body = match res.bytes() {
+            Ok(b) => b,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
+        Ok(HttpResponse {
+            body,
+            headers,
+            trailers: HashMap::new(),
+            status,
+            url,
+            version: version.try_into()?,
+        })
+    }
+}
+
+// Converts an [`HttpResponse`] to a reqwest blocking [`Response`]
+fn convert_response(response: HttpResponse) -> Result<Response> {
+    let mut ret_res = http::Response::builder()
+        .status(response.status)
+        .url(response.url)
+        .version(response.version.into())
+        .body(response.body)?;
+    for header in response.headers {
+        ret_res.headers_mut().insert(
+            HeaderName::from_str(header.0.as_str())?,
+            HeaderValue::from_str(header.1.as_str())?,
+        );
+    }
+    Ok(Response::from(ret_res))
+}
+
+impl<T: CacheManager> Cache<T> {
+    /// Creates a new blocking cache client
+    pub fn new(client: Client, cache: HttpCache<T>) -> Self {
+        Self { client, cache }
+    }
+
+    /// Sends a GET request to the url, consulting the cache
+    pub fn get<U: IntoUrl>(&self, url: U) -> Result<Response> {
+        let url = match url.into_url() {
+            Ok(u) => u,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
+        self.execute(Request::new(Method::GET, url))
+    }
+
+    /// Executes the request, consulting the cache
+    pub fn execute(&self, req: Request) -> Result<Response> {
+        let middleware = BlockingMiddleware { req, client: &self.client };
+        if !self.cache.can_cache_request(&middleware) {
+            futures_executor::block_on(self.cache.run_no_cache(&middleware))?;
+            let headers = self.cache.bypass_headers(&middleware);
+            let mut res = match self.client.execute(middleware.req) {
+                Ok(r) => r,
+                Err(e) => return Err(CacheError::General(anyhow!(e))),
+            };
+            for (name, value) in headers {
+                let value = HeaderValue::from_str(&value)?;
+                if name == CACHE_STATUS {
+                    res.headers_mut().append(name, value);
+                } else {
+                    res.headers_mut().insert(name, value);
+                }
+            }
+            return Ok(res);
+        }
+        let res = futures_executor::block_on(self.cache.run(middleware))?;
+        convert_response(res)
+    }
+
+    /// Issues a conditional request for the stored response, refreshing or
+    /// replacing it, see [`HttpCache::revalidate`]
+    pub fn revalidate<U: IntoUrl>(
+        &self,
+        method: Method,
+        url: U,
+    ) -> Result<CacheLookupStatus> {
+        let url = match url.into_url() {
+            Ok(u) => u,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
+        let middleware = BlockingMiddleware {
+            req: Request::new(method, url),
+            client: &self.client,
+        };
+        futures_executor::block_on(self.cache.revalidate(middleware))
+    }
+}
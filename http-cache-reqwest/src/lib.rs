@@ -15,7 +15,7 @@
 //! ```no_run
 //! use reqwest::Client;
 //! use reqwest_middleware::{ClientBuilder, Result};
-//! use http_cache_reqwest::{Cache, CacheMode, CACacheManager, HttpCache};
+//! use http_cache_reqwest::{Cache, CacheMode, CACacheManager, HttpCache, HttpCacheOptions};
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<()> {
@@ -23,7 +23,7 @@
 //!         .with(Cache(HttpCache {
 //!             mode: CacheMode::Default,
 //!             manager: CACacheManager::default(),
-//!             options: None,
+//!             options: HttpCacheOptions::default(),
 //!         }))
 //!         .build();
 //!     client
@@ -33,12 +33,116 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! Responses are buffered so they can be stored. Requests that the cache
+//! can't participate in (non GET/HEAD methods or [`CacheMode::NoStore`]) are
+//! passed straight through, leaving the response body as a stream. Whether a
+//! body is buffered is decided from the request alone: the response to a
+//! request the cache takes part in is read in full even when it turns out
+//! not to be storable, such as one sent with `Cache-Control: no-store`, and
+//! a response being stored isn't streamed to the caller while it's written.
+//! To skip the cache and buffering for a single request, add the
+//! [`NoBuffer`] extension.
+//! ```no_run
+//! # use reqwest::Client;
+//! # use reqwest_middleware::{ClientBuilder, Result};
+//! # use http_cache_reqwest::{Cache, CacheMode, CACacheManager, HttpCache, HttpCacheOptions, NoBuffer};
+//! # #[tokio::main]
+//! # async fn main() -> Result<()> {
+//! # let client = ClientBuilder::new(Client::new())
+//! #     .with(Cache(HttpCache {
+//! #         mode: CacheMode::Default,
+//! #         manager: CACacheManager::default(),
+//! #         options: HttpCacheOptions::default(),
+//! #     }))
+//! #     .build();
+//! let mut res = client
+//!     .get("https://example.com/large-file")
+//!     .with_extension(NoBuffer)
+//!     .send()
+//!     .await?;
+//! while let Some(chunk) = res.chunk().await? {
+//!     // write the chunk somewhere
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The [`CacheMode`] can also be overridden for a single request by adding it
+//! as an extension. The extension is removed before the request is sent on.
+//! ```no_run
+//! # use reqwest::Client;
+//! # use reqwest_middleware::{ClientBuilder, Result};
+//! # use http_cache_reqwest::{Cache, CacheMode, CACacheManager, HttpCache, HttpCacheOptions};
+//! # #[tokio::main]
+//! # async fn main() -> Result<()> {
+//! # let client = ClientBuilder::new(Client::new())
+//! #     .with(Cache(HttpCache {
+//! #         mode: CacheMode::Default,
+//! #         manager: CACacheManager::default(),
+//! #         options: HttpCacheOptions::default(),
+//! #     }))
+//! #     .build();
+//! client
+//!     .get("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching")
+//!     .with_extension(CacheMode::Reload)
+//!     .send()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Likewise the [`CacheOptions`] the cache policy is created with can be
+//! overridden for a single request by adding them as an extension.
+//!
+//! The same controls are available fluently through
+//! [`CacheRequestBuilderExt`], and can be combined on one request.
+//! ```no_run
+//! # use reqwest::Client;
+//! # use reqwest_middleware::{ClientBuilder, Result};
+//! # use http_cache_reqwest::{Cache, CacheMode, CACacheManager, HttpCache, HttpCacheOptions};
+//! use http_cache_reqwest::CacheRequestBuilderExt;
+//! # #[tokio::main]
+//! # async fn main() -> Result<()> {
+//! # let client = ClientBuilder::new(Client::new())
+//! #     .with(Cache(HttpCache {
+//! #         mode: CacheMode::Default,
+//! #         manager: CACacheManager::default(),
+//! #         options: HttpCacheOptions::default(),
+//! #     }))
+//! #     .build();
+//! let list = url::Url::parse("https://example.com/articles").unwrap();
+//! client
+//!     .get("https://example.com/articles/latest")
+//!     .cache_key("latest-article")
+//!     .cache_bust([list])
+//!     .send()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## WebAssembly
+//!
+//! The middleware doesn't build for `wasm32`. reqwest's wasm `Response` can
+//! only come from a browser fetch, with no way to build one from a stored
+//! response, so cache hits couldn't be served.
+#[cfg(target_arch = "wasm32")]
+compile_error!(
+    "http-cache-reqwest doesn't support wasm32: reqwest's wasm Response can't \
+     be built from a stored response, see the WebAssembly section of the \
+     crate docs"
+);
+
+#[cfg(feature = "blocking")]
+#[cfg_attr(docsrs, doc(cfg(feature = "blocking")))]
+pub mod blocking;
+
 use anyhow::anyhow;
 use std::{
     collections::HashMap,
     convert::{TryFrom, TryInto},
     str::FromStr,
-    time::SystemTime,
 };
 
 use http::{
@@ -46,14 +150,20 @@ use http::{
     request::Parts,
     HeaderValue, Method,
 };
-use http_cache::{CacheError, CacheManager, Middleware, Result};
+use http_cache::{
+    now, CacheError, CacheManager, Middleware, Result, CACHE_STATUS,
+};
 use http_cache_semantics::CachePolicy;
 use reqwest::{Request, Response, ResponseBuilderExt};
-use reqwest_middleware::{Error, Next};
+use reqwest_middleware::{Error, Next, RequestBuilder};
 use task_local_extensions::Extensions;
 use url::Url;
 
-pub use http_cache::{CacheMode, CacheOptions, HttpCache, HttpResponse};
+pub use http_cache::{
+    CacheLookupStatus, CacheMode, CacheOptions, CacheStatusHeader,
+    CookiePolicy, HttpCache, HttpCacheOptions, HttpResponse, RedirectKey,
+    SetCookiePolicy,
+};
 
 #[cfg(feature = "manager-cacache")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
@@ -67,9 +177,83 @@ pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
 #[derive(Debug)]
 pub struct Cache<T: CacheManager>(pub HttpCache<T>);
 
+/// Request extension that sends the request straight to the inner client,
+/// without consulting or updating the cache, so the response body is never
+/// buffered.
+///
+/// This is the only way to keep the body of a cacheable request streaming.
+/// The cache can't tell a response won't be stored until it has it, and by
+/// then the body has been read in full, so a large `no-store` download still
+/// takes up its whole size in memory without this extension.
+#[derive(Debug, Clone, Copy)]
+pub struct NoBuffer;
+
+/// Request extension that stores the response under the given key instead
+/// of the one derived from the url. The extension is removed before the
+/// request is sent on.
+#[derive(Debug, Clone)]
+pub struct CacheKey(pub String);
+
+/// Request extension that removes the stored responses for the given urls
+/// before the request is run. The extension is removed before the request
+/// is sent on.
+#[derive(Debug, Clone, Default)]
+pub struct CacheBust(pub Vec<Url>);
+
+/// Adds per-request cache controls to [`RequestBuilder`], by inserting the
+/// extensions the [`Cache`] middleware honors.
+pub trait CacheRequestBuilderExt {
+    /// Overrides the [`CacheMode`] for this request
+    fn cache_mode(self, mode: CacheMode) -> Self;
+    /// Overrides the [`CacheOptions`] policies are created with for this
+    /// request
+    fn cache_options(self, options: CacheOptions) -> Self;
+    /// Stores the response under the given key, see [`CacheKey`]
+    fn cache_key(self, key: impl Into<String>) -> Self;
+    /// Removes the stored responses for the urls before the request is run,
+    /// see [`CacheBust`]. Calling this again adds to the list.
+    fn cache_bust(self, urls: impl IntoIterator<Item = Url>) -> Self;
+    /// Fetches the response from the origin, updating the cache, the same
+    /// as [`CacheMode::NoCache`]
+    fn no_cache(self) -> Self;
+    /// Sends the request without consulting the cache, see [`NoBuffer`]
+    fn no_buffer(self) -> Self;
+}
+
+impl CacheRequestBuilderExt for RequestBuilder {
+    fn cache_mode(self, mode: CacheMode) -> Self {
+        self.with_extension(mode)
+    }
+    fn cache_options(self, options: CacheOptions) -> Self {
+        self.with_extension(options)
+    }
+    fn cache_key(self, key: impl Into<String>) -> Self {
+        self.with_extension(CacheKey(key.into()))
+    }
+    fn cache_bust(mut self, urls: impl IntoIterator<Item = Url>) -> Self {
+        let extensions = self.extensions();
+        match extensions.get_mut::<CacheBust>() {
+            Some(bust) => bust.0.extend(urls),
+            None => {
+                extensions.insert(CacheBust(urls.into_iter().collect()));
+            }
+        }
+        self
+    }
+    fn no_cache(self) -> Self {
+        self.cache_mode(CacheMode::NoCache)
+    }
+    fn no_buffer(self) -> Self {
+        self.with_extension(NoBuffer)
+    }
+}
+
 /// Implements ['Middleware'] for reqwest
 pub(crate) struct ReqwestMiddleware<'a> {
     pub req: Request,
+    pub mode: Option<CacheMode>,
+    pub key: Option<String>,
+    pub options: Option<CacheOptions>,
     pub next: Next<'a>,
     pub extensions: &'a mut Extensions,
 }
@@ -80,7 +264,12 @@ impl Middleware for ReqwestMiddleware<'_> {
         self.req.method() == Method::GET || self.req.method() == Method::HEAD
     }
     fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
-        Ok(CachePolicy::new(&self.parts()?, &response.parts()?))
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            CacheOptions::default(),
+        ))
     }
     fn policy_with_options(
         &self,
@@ -90,7 +279,7 @@ impl Middleware for ReqwestMiddleware<'_> {
         Ok(CachePolicy::new_options(
             &self.parts()?,
             &response.parts()?,
-            SystemTime::now(),
+            now(),
             options,
         ))
     }
@@ -120,6 +309,15 @@ impl Middleware for ReqwestMiddleware<'_> {
     fn method(&self) -> Result<String> {
         Ok(self.req.method().as_ref().to_string())
     }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        self.mode
+    }
+    fn overridden_cache_key(&self) -> Option<String> {
+        self.key.clone()
+    }
+    fn overridden_cache_options(&self) -> Option<CacheOptions> {
+        self.options
+    }
     async fn remote_fetch(&mut self) -> Result<HttpResponse> {
         let copied_req = self.req.try_clone().ok_or(CacheError::BadRequest)?;
         let res = match self.next.clone().run(copied_req, self.extensions).await
@@ -131,20 +329,20 @@ impl Middleware for ReqwestMiddleware<'_> {
         for header in res.headers() {
             headers.insert(
                 header.0.as_str().to_owned(),
-                header.1.to_str()?.to_owned(),
+                String::from_utf8_lossy(header.1.as_bytes()).into_owned(),
             );
         }
         let url = res.url().clone();
         let status = res.status().into();
         let version = res.version();
-        let body: Vec<u8> = match res.bytes().await {
+        let body = match res.bytes().await {
             Ok(b) => b,
             Err(e) => return Err(CacheError::General(anyhow!(e))),
-        }
-        .to_vec();
+        };
         Ok(HttpResponse {
             body,
             headers,
+            trailers: HashMap::new(),
             status,
             url,
             version: version.try_into()?,
@@ -157,7 +355,7 @@ fn convert_response(response: HttpResponse) -> anyhow::Result<Response> {
     let mut ret_res = http::Response::builder()
         .status(response.status)
         .url(response.url)
-        .version(response.version.try_into()?)
+        .version(response.version.into())
         .body(response.body)?;
     for header in response.headers {
         ret_res.headers_mut().insert(
@@ -176,7 +374,42 @@ impl<T: CacheManager> reqwest_middleware::Middleware for Cache<T> {
         extensions: &mut Extensions,
         next: Next<'_>,
     ) -> std::result::Result<Response, Error> {
-        let middleware = ReqwestMiddleware { req, next, extensions };
+        // Streaming bodies can't be copied for the cache, so requests
+        // carrying one are sent on as is
+        let bypass = extensions.get::<NoBuffer>().is_some()
+            || matches!(req.body(), Some(body) if body.as_bytes().is_none());
+        let mode = extensions.remove::<CacheMode>();
+        let key = extensions.remove::<CacheKey>().map(|key| key.0);
+        let options = extensions.remove::<CacheOptions>();
+        if let Some(bust) = extensions.remove::<CacheBust>() {
+            for url in bust.0 {
+                if let Err(e) = self.0.delete("GET", &url).await {
+                    return Err(Error::Middleware(anyhow::anyhow!(e)));
+                }
+            }
+        }
+        let middleware =
+            ReqwestMiddleware { req, mode, key, options, next, extensions };
+        if bypass || !self.0.can_cache_request(&middleware) {
+            if let Err(e) = self.0.run_no_cache(&middleware).await {
+                return Err(Error::Middleware(anyhow::anyhow!(e)));
+            }
+            let headers = self.0.bypass_headers(&middleware);
+            let mut res = middleware
+                .next
+                .run(middleware.req, middleware.extensions)
+                .await?;
+            for (name, value) in headers {
+                let value = HeaderValue::from_str(&value)
+                    .map_err(|e| Error::Middleware(anyhow::anyhow!(e)))?;
+                if name == CACHE_STATUS {
+                    res.headers_mut().append(name, value);
+                } else {
+                    res.headers_mut().insert(name, value);
+                }
+            }
+            return Ok(res);
+        }
         let res = match self.0.run(middleware).await {
             Ok(r) => r,
             Err(e) => return Err(Error::Middleware(anyhow::anyhow!(e))),
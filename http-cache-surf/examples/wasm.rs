@@ -0,0 +1,46 @@
+//! Fetches the same url twice from the browser, the second response is
+//! served from the in-memory cache. Build it with
+//!
+//! ```sh
+//! cargo build --example wasm --target wasm32-unknown-unknown \
+//!     --no-default-features --features manager-memory,surf/wasm-client
+//! wasm-bindgen --target web --out-dir pkg \
+//!     ../target/wasm32-unknown-unknown/debug/examples/wasm.wasm
+//! ```
+//!
+//! and load `pkg/wasm.js` as a module from a page, the outcome of each fetch
+//! is logged to the console.
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    use http_cache_surf::{
+        Cache, CacheMode, HttpCache, HttpCacheOptions, MemoryManager,
+    };
+
+    wasm_bindgen_futures::spawn_local(async {
+        let client = surf::client().with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: MemoryManager::default(),
+            options: HttpCacheOptions::default(),
+        }));
+        let url = "https://httpbin.org/cache/60";
+        for pass in ["cold", "hot"] {
+            let message = match client.get(url).await {
+                Ok(res) => format!(
+                    "{} pass: {} x-cache: {}",
+                    pass,
+                    res.status(),
+                    res.header("x-cache").map_or("none", |v| v.as_str()),
+                ),
+                Err(e) => format!("{} pass failed: {}", pass, e),
+            };
+            web_sys::console::log_1(&message.into());
+        }
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    eprintln!(
+        "this example runs in the browser, build it for wasm32-unknown-unknown"
+    );
+}
@@ -13,7 +13,7 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
 //! The surf middleware implementation for http-cache.
 //! ```no_run
-//! use http_cache_surf::{Cache, CacheMode, CACacheManager, HttpCache};
+//! use http_cache_surf::{Cache, CacheMode, CACacheManager, HttpCache, HttpCacheOptions};
 //!
 //! #[async_std::main]
 //! async fn main() -> surf::Result<()> {
@@ -22,26 +22,61 @@
 //!         .with(Cache(HttpCache {
 //!             mode: CacheMode::Default,
 //!             manager: CACacheManager::default(),
-//!             options: None,
+//!             options: HttpCacheOptions::default(),
 //!         }))
 //!         .send(req)
 //!         .await?;
 //!     Ok(())
 //! }
 //! ```
+//!
+//! ## Redirects
+//!
+//! surf responses don't report the url they were served from, so what gets
+//! cached when following redirects depends on where surf's `Redirect`
+//! middleware sits relative to the cache:
+//!
+//! - `.with(Cache(..)).with(Redirect::default())` caches the final response
+//!   under the originally requested url, and a hit skips the redirect
+//!   entirely.
+//! - `.with(Redirect::default()).with(Cache(..))` caches the final response
+//!   under the final url. The redirect itself is always fetched from the
+//!   origin, as `Redirect` probes outside of the middleware stack.
+//!
+//! Either way [`HttpCacheOptions::redirect_key`] has no effect. With
+//! [`HttpCacheOptions::cache_redirects`] set and redirects followed outside of
+//! the client, the redirect responses themselves are cached.
+//!
+//! ## WebAssembly
+//!
+//! The middleware leaves the choice of backend to the surf dependency. To use
+//! it from a browser, select surf's `wasm-client` backend and swap the
+//! default manager for `manager-memory`, as the cacache and moka managers
+//! need a filesystem and threads respectively:
+//!
+//! ```toml
+//! http-cache-surf = { version = "0.6.0", default-features = false, features = ["manager-memory"] }
+//! surf = { version = "2.3.2", default-features = false, features = ["wasm-client"] }
+//! ```
+//!
+//! Responses are then held in memory for as long as the page is open, see
+//! `examples/wasm.rs` for a page fetching the same url twice.
 use anyhow::anyhow;
-use std::{
-    collections::HashMap, convert::TryInto, str::FromStr, time::SystemTime,
-};
+use std::{collections::HashMap, convert::TryInto, str::FromStr};
 
 use http::{header::CACHE_CONTROL, request, request::Parts};
-use http_cache::{CacheError, CacheManager, Middleware, Result};
+use http_cache::{
+    now, CacheError, CacheManager, Middleware, Result, CACHE_STATUS,
+};
 use http_cache_semantics::CachePolicy;
 use http_types::{headers::HeaderValue, Method, Response, StatusCode, Version};
 use surf::{middleware::Next, Client, Request};
 use url::Url;
 
-pub use http_cache::{CacheMode, CacheOptions, HttpCache, HttpResponse};
+pub use http_cache::{
+    CacheMode, CacheOptions, CacheStatusHeader, CookiePolicy, HttpCache,
+    HttpCacheOptions, HttpResponse, RedirectKey, SetCookiePolicy,
+};
 
 #[cfg(feature = "manager-cacache")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
@@ -51,6 +86,10 @@ pub use http_cache::CACacheManager;
 #[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
 pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
 
+#[cfg(feature = "manager-memory")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-memory")))]
+pub use http_cache::MemoryManager;
+
 /// Wrapper for [`HttpCache`]
 #[derive(Debug)]
 pub struct Cache<T: CacheManager>(pub HttpCache<T>);
@@ -68,7 +107,12 @@ impl Middleware for SurfMiddleware<'_> {
         self.req.method() == Method::Get || self.req.method() == Method::Head
     }
     fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
-        Ok(CachePolicy::new(&self.parts()?, &response.parts()?))
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            CacheOptions::default(),
+        ))
     }
     fn policy_with_options(
         &self,
@@ -78,7 +122,7 @@ impl Middleware for SurfMiddleware<'_> {
         Ok(CachePolicy::new_options(
             &self.parts()?,
             &response.parts()?,
-            SystemTime::now(),
+            now(),
             options,
         ))
     }
@@ -120,11 +164,16 @@ impl Middleware for SurfMiddleware<'_> {
     }
     async fn remote_fetch(&mut self) -> Result<HttpResponse> {
         let url = self.req.url().clone();
-        let mut res =
-            match self.next.run(self.req.clone(), self.client.clone()).await {
-                Ok(r) => r,
-                Err(e) => return Err(CacheError::General(anyhow!(e))),
-            };
+        // Cloning leaves the body behind, so any body is moved into the
+        // outgoing request instead. GET and HEAD bodies are never touched.
+        let mut req = self.req.clone();
+        if !self.is_method_get_head() {
+            req.set_body(self.req.take_body());
+        }
+        let mut res = match self.next.run(req, self.client.clone()).await {
+            Ok(r) => r,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
         let mut headers = HashMap::new();
         for header in res.iter() {
             headers.insert(
@@ -139,8 +188,9 @@ impl Middleware for SurfMiddleware<'_> {
             Err(e) => return Err(CacheError::General(anyhow!(e))),
         };
         Ok(HttpResponse {
-            body,
+            body: body.into(),
             headers,
+            trailers: HashMap::new(),
             status,
             url,
             version: version.try_into()?,
@@ -157,6 +207,29 @@ impl<T: CacheManager> surf::middleware::Middleware for Cache<T> {
         next: Next<'_>,
     ) -> std::result::Result<surf::Response, http_types::Error> {
         let middleware = SurfMiddleware { req, client, next };
+        // Urls the cache can't key, such as custom schemes handled by the
+        // client, are forwarded without consulting the cache
+        let is_http = matches!(middleware.req.url().scheme(), "http" | "https");
+        if !is_http || middleware.parts().is_err() {
+            return middleware
+                .next
+                .run(middleware.req, middleware.client)
+                .await;
+        }
+        if !self.0.can_cache_request(&middleware) {
+            self.0.run_no_cache(&middleware).await?;
+            let headers = self.0.bypass_headers(&middleware);
+            let mut res =
+                middleware.next.run(middleware.req, middleware.client).await?;
+            for (name, value) in headers {
+                if name == CACHE_STATUS {
+                    res.append_header(name, value.as_str());
+                } else {
+                    res.insert_header(name, value.as_str());
+                }
+            }
+            return Ok(res);
+        }
         let res = self.0.run(middleware).await?;
         let mut converted = Response::new(StatusCode::Ok);
         for header in &res.headers {
@@ -164,8 +237,9 @@ impl<T: CacheManager> surf::middleware::Middleware for Cache<T> {
             converted.insert_header(header.0.as_str(), val);
         }
         converted.set_status(res.status.try_into()?);
-        converted.set_version(Some(res.version.try_into()?));
-        converted.set_body(res.body.clone());
+        converted.set_version(Some(res.version.into()));
+        // surf bodies can't borrow the buffer, so this copies it
+        converted.set_body(res.body.to_vec());
         Ok(surf::Response::from(converted))
     }
 }
@@ -0,0 +1,80 @@
+//! Counts the allocations made per fresh hit when the entry records the time
+//! until which it's fresh, and when it doesn't, as with entries stored before
+//! the time was recorded, leaving the policy to decide. Run it with
+//! `cargo bench -p http-cache-tests --bench fresh_hits`.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use futures::executor::block_on;
+use http_cache::{CacheMode, HttpCache, HttpCacheOptions, MemoryManager};
+use http_cache_tests::fake::{Boxed, FakeRequest, Origin};
+use url::Url;
+
+const REQUESTS: usize = 10_000;
+
+// The header the time is recorded in on stored entries
+const FRESH_UNTIL: &str = "x-cache-fresh-until";
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// Prints the allocations per hit and the time taken, returning the former.
+// The response is stored by the first request, every later one is a hit.
+fn measure(name: &str, recorded: bool) -> f64 {
+    let url = Url::parse("http://example.com/").unwrap();
+    let origin = Origin::default();
+    let manager = MemoryManager::default();
+    let cache = HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions::default(),
+    };
+    origin.push(&url, "max-age=86400, public", b"test");
+    block_on(cache.run(Boxed(FakeRequest::new(&url, None, &origin)))).unwrap();
+    if !recorded {
+        for (res, _) in manager.cache.write().unwrap().values_mut() {
+            res.headers.remove(FRESH_UNTIL);
+        }
+    }
+    let mut allocations = 0;
+    let start = Instant::now();
+    for _ in 0..REQUESTS {
+        let middleware = Boxed(FakeRequest::new(&url, None, &origin));
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        block_on(cache.run(middleware)).unwrap();
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    assert_eq!(origin.fetches(), 1);
+    let per_hit = allocations as f64 / REQUESTS as f64;
+    println!(
+        "{:<9} {:>6.2} allocations/hit {:>10.2?}",
+        name,
+        per_hit,
+        start.elapsed()
+    );
+    per_hit
+}
+
+fn main() {
+    let policy = measure("policy", false);
+    let recorded = measure("recorded", true);
+    println!("saved     {:>6.2} allocations/hit", policy - recorded);
+}
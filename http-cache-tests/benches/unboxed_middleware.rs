@@ -0,0 +1,73 @@
+//! Counts the allocations made per request when the cache drives the boxed
+//! `Middleware` trait and the `UnboxedMiddleware` trait. Run it with
+//! `cargo bench -p http-cache-tests --bench unboxed_middleware`.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use futures::executor::block_on;
+use http_cache::{
+    CacheMode, HttpCache, HttpCacheOptions, MemoryManager, UnboxedMiddleware,
+};
+use http_cache_tests::fake::{Boxed, FakeRequest, Origin, Unboxed};
+use url::Url;
+
+const REQUESTS: usize = 10_000;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// Prints the allocations per request and the time taken, returning the former.
+// The origin is fetched from on every request as nothing is stored
+fn measure<M: UnboxedMiddleware>(
+    name: &str,
+    wrap: impl Fn(FakeRequest) -> M,
+) -> f64 {
+    let url = Url::parse("http://example.com/").unwrap();
+    let origin = Origin::default();
+    let cache = HttpCache {
+        mode: CacheMode::NoStore,
+        manager: MemoryManager::default(),
+        options: HttpCacheOptions::default(),
+    };
+    let mut allocations = 0;
+    let start = Instant::now();
+    for _ in 0..REQUESTS {
+        origin.push(&url, "no-store", b"test");
+        let middleware = wrap(FakeRequest::new(&url, None, &origin));
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        block_on(cache.run(middleware)).unwrap();
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    let per_request = allocations as f64 / REQUESTS as f64;
+    println!(
+        "{:<8} {:>6.2} allocations/request {:>10.2?}",
+        name,
+        per_request,
+        start.elapsed()
+    );
+    per_request
+}
+
+fn main() {
+    let boxed = measure("boxed", Boxed);
+    let unboxed = measure("unboxed", Unboxed);
+    println!("saved    {:>6.2} allocations/request", boxed - unboxed);
+}
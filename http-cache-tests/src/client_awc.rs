@@ -0,0 +1,184 @@
+use crate::*;
+
+use http_cache_awc::Cache;
+
+fn build_client(mode: CacheMode, manager: &MokaManager) -> Cache<MokaManager> {
+    Cache::new(
+        awc::Client::default(),
+        HttpCache {
+            mode,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        },
+    )
+}
+
+#[actix_rt::test]
+async fn default_mode() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    let mut res = client.get(&url).await?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    assert_eq!(res.body().await?, TEST_BODY);
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_some());
+
+    // Hot pass to make sure the expect response was returned
+    let mut res = client.get(&url).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.body().await?, TEST_BODY);
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn no_store_mode() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::NoStore, &manager);
+
+    // Remote request but should not cache
+    client.get(&url).await?;
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_none());
+
+    // To verify our endpoint receives the request rather than a cache hit
+    let mut res = client.get(&url).await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    assert_eq!(res.body().await?, TEST_BODY);
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn revalidation_304() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+    let m_304 = Mock::given(method(GET))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(1);
+    let mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get(&url).await?;
+
+    drop(mock_guard);
+
+    let _mock_guard = mock_server.register_as_scoped(m_304).await;
+
+    // Hot pass to make sure revalidation request was sent
+    let mut res = client.get(&url).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.body().await?, TEST_BODY);
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn delete_after_non_get_head_method_request() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m_get = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let m_post = Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201).set_body_bytes("created"))
+        .expect(1);
+    let _mock_guard_get = mock_server.register_as_scoped(m_get).await;
+    let _mock_guard_post = mock_server.register_as_scoped(m_post).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get(&url).await?;
+    assert!(manager.get(GET, &Url::parse(&url)?).await?.is_some());
+
+    // Post request to make sure the cache object at the same resource was deleted
+    let req = client.client.post(&url);
+    let mut res = client.send_body(req, "x").await?;
+    assert_eq!(res.status(), 201);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    assert_eq!(res.body().await?, "created");
+    assert!(manager.get(GET, &Url::parse(&url)?).await?.is_none());
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn payload_limit() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let mut client = build_client(CacheMode::Default, &manager);
+    client.payload_limit = TEST_BODY.len() - 1;
+
+    // The body is over the limit, so nothing can be stored
+    assert!(client.get(&url).await.is_err());
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn non_utf8_headers() -> anyhow::Result<()> {
+    let url = serve_raw(
+        b"HTTP/1.1 200 OK\r\n\
+          Cache-Control: max-age=86400, public\r\n\
+          X-Name: caf\xe9\r\n\
+          Content-Length: 4\r\n\
+          \r\n\
+          test",
+    )?;
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    let mut res = client.get(&url).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.body().await?, TEST_BODY);
+    let (stored, _) = manager.get(GET, &Url::parse(&url)?).await?.unwrap();
+    assert_eq!(stored.headers.get("x-name").unwrap(), "caf\u{fffd}");
+
+    // The stored response is served without going back to the server
+    let res = client.get(&url).await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    Ok(())
+}
+
+#[cfg(test)]
+mod only_if_cached_mode {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn miss() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 0);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::OnlyIfCached, &manager);
+
+        // Should result in a cache miss and no remote request
+        let res = client.get(&url).await?;
+        assert_eq!(res.status(), 504);
+        assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use crate::*;
+
+use async_std::task::block_on;
+use curl::multi::Multi;
+use http_cache_curl::Cache;
+
+fn build_client(mode: CacheMode, manager: &MokaManager) -> Cache<MokaManager> {
+    Cache::new(HttpCache {
+        mode,
+        manager: manager.clone(),
+        options: HttpCacheOptions::default(),
+    })
+}
+
+#[test]
+fn default_mode() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+    // Try to load cached object
+    let data = block_on(manager.get(GET, &Url::parse(&url)?))?;
+    assert!(data.is_some());
+
+    // Hot pass to make sure the expect response was returned
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.body(), TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn no_store_mode() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::NoStore, &manager);
+
+    // Remote request but should not cache
+    client.get(&url)?;
+
+    // Try to load cached object
+    let data = block_on(manager.get(GET, &Url::parse(&url)?))?;
+    assert!(data.is_none());
+
+    // To verify our endpoint receives the request rather than a cache hit
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    Ok(())
+}
+
+#[test]
+fn only_if_cached_extension() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 0);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // The extension keeps the request from reaching the network
+    let mut req = http::Request::get(&url).body(Vec::new())?;
+    req.extensions_mut().insert(CacheMode::OnlyIfCached);
+    let res = client.execute(req)?;
+    assert_eq!(res.status(), 504);
+    Ok(())
+}
+
+#[test]
+fn error_status_is_a_response() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 503, 1);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    let res = client.get(&url)?;
+    assert_eq!(res.status(), 503);
+    assert_eq!(res.body(), TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn post_sends_body() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = Mock::given(method("POST"))
+        .and(wiremock::matchers::body_bytes(b"payload".to_vec()))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    let req = http::Request::post(&url).body(b"payload".to_vec())?;
+    let res = client.execute(req)?;
+    assert_eq!(res.status(), 201);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    Ok(())
+}
+
+#[test]
+fn repeated_and_non_utf8_headers() -> anyhow::Result<()> {
+    let url = serve_raw(
+        b"HTTP/1.1 200 OK\r\n\
+          Cache-Control: max-age=86400, public\r\n\
+          Link: </a.css>; rel=preload\r\n\
+          Link: </b.js>; rel=preload\r\n\
+          X-Folded: first\r\n\
+          \tsecond\r\n\
+          X-Name: caf\xe9\r\n\
+          Content-Length: 4\r\n\
+          \r\n\
+          test",
+    )?;
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    let res = client.get(&url)?;
+    assert_eq!(
+        res.headers().get("link").unwrap(),
+        "</a.css>; rel=preload, </b.js>; rel=preload"
+    );
+    assert_eq!(res.headers().get("x-folded").unwrap(), "first second");
+    let (stored, _) = block_on(manager.get(GET, &Url::parse(&url)?))?.unwrap();
+    assert_eq!(stored.headers.get("x-name").unwrap(), "café");
+
+    // The server is gone, so this can only be answered from the cache
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(
+        res.headers().get("x-name").unwrap().as_bytes(),
+        "café".as_bytes()
+    );
+    assert_eq!(res.body(), TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn multi_handle() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+    let multi = Multi::new();
+
+    // The same handle drives transfers for several requests
+    for path in ["a", "b"] {
+        let url = format!("{}/{}", &mock_server.uri(), path);
+        let req = http::Request::get(&url).body(Vec::new())?;
+        let res = client.execute_multi(req, &multi)?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        assert_eq!(res.body(), TEST_BODY);
+    }
+    let url = format!("{}/a", &mock_server.uri());
+    let req = http::Request::get(&url).body(Vec::new())?;
+    let res = client.execute_multi(req, &multi)?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use crate::*;
+
+use http_cache_client::Cache;
+use http_client::HttpClient;
+use http_types::{Request, Response};
+use std::sync::{Arc, Mutex};
+
+/// Answers every request with a copy of the response, keeping the requests
+#[derive(Debug, Default)]
+struct Canned {
+    status: u16,
+    headers: Vec<(&'static str, &'static str)>,
+    requests: Mutex<Vec<Request>>,
+}
+
+impl Canned {
+    fn new(status: u16, headers: &[(&'static str, &'static str)]) -> Arc<Self> {
+        Arc::new(Self {
+            status,
+            headers: headers.to_vec(),
+            requests: Mutex::default(),
+        })
+    }
+
+    fn sent(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+#[http_client::async_trait]
+impl HttpClient for Canned {
+    async fn send(
+        &self,
+        req: Request,
+    ) -> std::result::Result<Response, http_types::Error> {
+        let mut res = Response::new(self.status);
+        for (name, value) in &self.headers {
+            res.append_header(*name, *value);
+        }
+        res.set_body(TEST_BODY);
+        self.requests.lock().unwrap().push(req);
+        Ok(res)
+    }
+}
+
+fn build_client(
+    upstream: &Arc<Canned>,
+    mode: CacheMode,
+    manager: &MokaManager,
+    options: HttpCacheOptions,
+) -> Cache<MokaManager> {
+    Cache::new(
+        upstream.clone(),
+        HttpCache { mode, manager: manager.clone(), options },
+    )
+}
+
+fn get(url: &str) -> Request {
+    Request::new(Method::Get, url)
+}
+
+const URL: &str = "http://example.com/";
+
+#[async_std::test]
+async fn default_mode() -> anyhow::Result<()> {
+    let upstream = Canned::new(200, &[("cache-control", CACHEABLE_PUBLIC)]);
+    let manager = MokaManager::default();
+    let client = build_client(
+        &upstream,
+        CacheMode::Default,
+        &manager,
+        HttpCacheOptions::default(),
+    );
+
+    // Cold pass to load cache
+    let res = client.send(get(URL)).await?;
+    assert_eq!(res.header(XCACHELOOKUP).unwrap(), MISS);
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(URL)?).await?;
+    assert!(data.is_some());
+
+    // Hot pass to make sure the expect response was returned
+    let mut res = client.send(get(URL)).await?;
+    assert_eq!(res.header(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+    assert_eq!(res.body_bytes().await.unwrap(), TEST_BODY);
+    assert_eq!(upstream.sent(), 1);
+    Ok(())
+}
+
+#[async_std::test]
+async fn no_store_mode_extension() -> anyhow::Result<()> {
+    let upstream = Canned::new(200, &[("cache-control", CACHEABLE_PUBLIC)]);
+    let manager = MokaManager::default();
+    let client = build_client(
+        &upstream,
+        CacheMode::Default,
+        &manager,
+        HttpCacheOptions::default(),
+    );
+
+    // The extension overrides the client's mode for this request only
+    let mut req = get(URL);
+    req.ext_mut().insert(CacheMode::NoStore);
+    client.send(req).await?;
+    let data = manager.get(GET, &Url::parse(URL)?).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[async_std::test]
+async fn post_is_forwarded_with_body() -> anyhow::Result<()> {
+    let upstream = Canned::new(200, &[("cache-control", CACHEABLE_PUBLIC)]);
+    let manager = MokaManager::default();
+    let client = build_client(
+        &upstream,
+        CacheMode::Default,
+        &manager,
+        HttpCacheOptions::default(),
+    );
+
+    let mut req = Request::new(Method::Post, URL);
+    req.set_body("payload");
+    let res = client.send(req).await?;
+    assert_eq!(res.header(XCACHELOOKUP).unwrap(), MISS);
+    let mut sent = upstream.requests.lock().unwrap().pop().unwrap();
+    assert_eq!(sent.body_string().await.unwrap(), "payload");
+    let data = manager.get("POST", &Url::parse(URL)?).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[async_std::test]
+async fn multi_valued_response_headers_are_joined() -> anyhow::Result<()> {
+    let upstream = Canned::new(
+        200,
+        &[
+            ("cache-control", CACHEABLE_PUBLIC),
+            ("link", "</a.css>; rel=preload"),
+            ("link", "</b.js>; rel=preload"),
+        ],
+    );
+    let manager = MokaManager::default();
+    let client = build_client(
+        &upstream,
+        CacheMode::Default,
+        &manager,
+        HttpCacheOptions::default(),
+    );
+
+    let joined = "</a.css>; rel=preload, </b.js>; rel=preload";
+    let res = client.send(get(URL)).await?;
+    assert_eq!(res.header("link").unwrap(), joined);
+    let (stored, _) = manager.get(GET, &Url::parse(URL)?).await?.unwrap();
+    assert_eq!(stored.headers.get("link").unwrap(), joined);
+
+    let res = client.send(get(URL)).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+    assert_eq!(res.header("link").unwrap(), joined);
+    Ok(())
+}
+
+#[async_std::test]
+async fn multi_valued_request_headers_are_kept() -> anyhow::Result<()> {
+    let upstream = Canned::new(
+        200,
+        &[("cache-control", CACHEABLE_PUBLIC), ("vary", "accept-language")],
+    );
+    let manager = MokaManager::default();
+    let client = build_client(
+        &upstream,
+        CacheMode::Default,
+        &manager,
+        HttpCacheOptions::default(),
+    );
+    let request = |second: &str| {
+        let mut req = get(URL);
+        req.append_header("accept-language", "en");
+        req.append_header("accept-language", second);
+        req
+    };
+
+    client.send(request("de")).await?;
+    let sent = upstream.requests.lock().unwrap().pop().unwrap();
+    let values: Vec<_> = sent
+        .header("accept-language")
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().to_owned())
+        .collect();
+    assert_eq!(values, ["en", "de"]);
+
+    // The same values match the variant stored for them
+    let res = client.send(request("de")).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+    assert_eq!(upstream.sent(), 0);
+    Ok(())
+}
+
+#[async_std::test]
+async fn unusual_statuses_round_trip() -> anyhow::Result<()> {
+    let manager = MokaManager::default();
+    let options = HttpCacheOptions {
+        cache_redirects: true,
+        ..HttpCacheOptions::default()
+    };
+
+    // Not storable, so every request goes upstream with the status intact
+    let teapot = Canned::new(418, &[("cache-control", CACHEABLE_PUBLIC)]);
+    let client =
+        build_client(&teapot, CacheMode::Default, &manager, options.clone());
+    for _ in 0..2 {
+        let res = client.send(get(URL)).await?;
+        assert_eq!(res.status(), 418);
+        assert_eq!(res.header(XCACHE).unwrap(), MISS);
+    }
+    assert_eq!(teapot.sent(), 2);
+
+    // Storable when redirects are cached, and served with its status
+    let url = "http://example.com/moved";
+    let moved = Canned::new(
+        308,
+        &[("cache-control", CACHEABLE_PUBLIC), ("location", "/new")],
+    );
+    let client = build_client(&moved, CacheMode::Default, &manager, options);
+    client.send(get(url)).await?;
+    let res = client.send(get(url)).await?;
+    assert_eq!(res.status(), 308);
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+    assert_eq!(res.header("location").unwrap(), "/new");
+    assert_eq!(moved.sent(), 1);
+    Ok(())
+}
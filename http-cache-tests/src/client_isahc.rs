@@ -0,0 +1,282 @@
+use crate::*;
+
+use http_cache_isahc::Cache;
+use isahc::{AsyncReadResponseExt, HttpClient, Request, ResponseExt};
+
+fn build_client(mode: CacheMode, manager: &MokaManager) -> Cache<MokaManager> {
+    Cache::new(
+        HttpClient::new().unwrap(),
+        HttpCache {
+            mode,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        },
+    )
+}
+
+#[tokio::test]
+async fn default_mode() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    let res = client.get_async(&url).await?;
+    assert_eq!(res.headers()[XCACHELOOKUP], MISS);
+    assert_eq!(res.headers()[XCACHE], MISS);
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_some());
+
+    // Hot pass to make sure the expect response was returned
+    let mut res = client.get_async(&url).await?;
+    assert_eq!(res.headers()[XCACHELOOKUP], HIT);
+    assert_eq!(res.headers()[XCACHE], HIT);
+    // Served from the cache alone, so isahc attached nothing
+    assert!(res.local_addr().is_none());
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+#[tokio::test]
+async fn no_store_mode() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::NoStore, &manager);
+
+    // Remote request but should not cache
+    client.get_async(&url).await?;
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_none());
+
+    // To verify our endpoint receives the request rather than a cache hit
+    let mut res = client.get_async(&url).await?;
+    assert_eq!(res.headers()[XCACHE], MISS);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+#[tokio::test]
+async fn revalidation_304() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+    let m_304 = Mock::given(method(GET))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(1);
+    let mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get_async(&url).await?;
+
+    drop(mock_guard);
+
+    let _mock_guard = mock_server.register_as_scoped(m_304).await;
+
+    // Hot pass to make sure revalidation request was sent
+    let mut res = client.get_async(&url).await?;
+    assert_eq!(res.headers()[XCACHELOOKUP], HIT);
+    assert_eq!(res.headers()[XCACHE], HIT);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_after_non_get_head_method_request() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m_get = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let m_post = Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201).set_body_bytes("created"))
+        .expect(1);
+    let _mock_guard_get = mock_server.register_as_scoped(m_get).await;
+    let _mock_guard_post = mock_server.register_as_scoped(m_post).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get_async(&url).await?;
+    assert!(manager.get(GET, &Url::parse(&url)?).await?.is_some());
+
+    // Post request to make sure the cache object at the same resource was deleted
+    let res = client.send_async(Request::post(&url).body("x")?).await?;
+    assert_eq!(res.headers()[XCACHE], MISS);
+    assert!(manager.get(GET, &Url::parse(&url)?).await?.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn overridden_cache_mode() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 0);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Should result in a cache miss and no remote request
+    let mut req = Request::get(&url).body(())?;
+    req.extensions_mut().insert(CacheMode::OnlyIfCached);
+    let res = client.send_async(req).await?;
+    assert_eq!(res.status(), 504);
+    assert_eq!(res.headers()[XCACHE], MISS);
+    Ok(())
+}
+
+#[tokio::test]
+async fn response_extensions() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+    let m_304 = Mock::given(method(GET))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(1);
+    let mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // The response from the network keeps what isahc attached to it
+    let res = client.get_async(&url).await?;
+    assert!(res.local_addr().is_some());
+    assert_eq!(res.effective_uri().unwrap().to_string(), url);
+
+    drop(mock_guard);
+
+    let _mock_guard = mock_server.register_as_scoped(m_304).await;
+
+    // So does the revalidated one
+    let res = client.get_async(&url).await?;
+    assert_eq!(res.headers()[XCACHE], HIT);
+    assert!(res.local_addr().is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn non_utf8_headers() -> anyhow::Result<()> {
+    let url = serve_raw(
+        b"HTTP/1.1 200 OK\r\n\
+          Cache-Control: max-age=86400, public\r\n\
+          X-Name: caf\xe9\r\n\
+          Content-Length: 4\r\n\
+          \r\n\
+          test",
+    )?;
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    let mut res = client.get_async(&url).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    let (stored, _) = manager.get(GET, &Url::parse(&url)?).await?.unwrap();
+    assert_eq!(stored.headers.get("x-name").unwrap(), "caf\u{fffd}");
+
+    // The stored response is served without going back to the server
+    let res = client.get_async(&url).await?;
+    assert_eq!(res.headers()[XCACHE], HIT);
+    Ok(())
+}
+
+#[cfg(test)]
+mod interceptor {
+    use crate::*;
+
+    use http_cache_isahc::interceptor::CacheInterceptor;
+    use isahc::{AsyncReadResponseExt, HttpClient, ResponseExt};
+
+    fn build_client(mode: CacheMode, manager: &MokaManager) -> HttpClient {
+        HttpClient::builder()
+            .interceptor(CacheInterceptor(HttpCache {
+                mode,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn default_mode() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+
+        // Cold pass to load cache
+        let res = client.get_async(&url).await?;
+        assert_eq!(res.headers()[XCACHE], MISS);
+        assert!(res.local_addr().is_some());
+
+        // Try to load cached object
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_some());
+
+        // Hot pass to make sure the expect response was returned
+        let mut res = client.get_async(&url).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.local_addr().is_none());
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_store_mode() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::NoStore, &manager);
+
+        for _ in 0..2 {
+            let mut res = client.get_async(&url).await?;
+            assert_eq!(res.headers()[XCACHE], MISS);
+            assert_eq!(res.bytes().await?, TEST_BODY);
+        }
+
+        // Make sure nothing was stored
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revalidation_304() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+        let m_304 = Mock::given(method(GET))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1);
+        let mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+
+        // Cold pass to load cache
+        client.get_async(&url).await?;
+
+        drop(mock_guard);
+
+        let _mock_guard = mock_server.register_as_scoped(m_304).await;
+
+        // Hot pass to make sure revalidation request was sent
+        let mut res = client.get_async(&url).await?;
+        assert_eq!(res.headers()[XCACHELOOKUP], HIT);
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.local_addr().is_some());
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        Ok(())
+    }
+}
@@ -1,9 +1,10 @@
 use crate::*;
 use std::sync::Arc;
 
-use http_cache_reqwest::Cache;
+use http_cache_reqwest::{Cache, NoBuffer, RedirectKey};
 use reqwest::{Client, Request, ResponseBuilderExt};
 use reqwest_middleware::ClientBuilder;
+use task_local_extensions::Extensions;
 
 #[tokio::test]
 async fn default_mode() -> anyhow::Result<()> {
@@ -18,7 +19,7 @@ async fn default_mode() -> anyhow::Result<()> {
         .with(Cache(HttpCache {
             mode: CacheMode::Default,
             manager: manager.clone(),
-            options: None,
+            options: HttpCacheOptions::default(),
         }))
         .build();
 
@@ -48,7 +49,13 @@ async fn default_mode_with_options() -> anyhow::Result<()> {
         .with(Cache(HttpCache {
             mode: CacheMode::Default,
             manager: manager.clone(),
-            options: Some(CacheOptions { shared: false, ..Default::default() }),
+            options: HttpCacheOptions {
+                cache_options: Some(CacheOptions {
+                    shared: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
         }))
         .build();
 
@@ -61,6 +68,85 @@ async fn default_mode_with_options() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn hash_keys() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/?q={}", &mock_server.uri(), "a".repeat(4096));
+    let manager = MokaManager::default();
+    let options = HttpCacheOptions { hash_keys: true, ..Default::default() };
+
+    // Construct reqwest client with hashed keys
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: options.clone(),
+        }))
+        .build();
+
+    // Cold pass to load cache
+    client.get(url.clone()).send().await?;
+
+    // The response is stored under the hashed key rather than the url
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_none());
+    let key = options.cache_key(GET, &Url::parse(&url)?);
+    let key_url = Url::parse(&format!("urn:http-cache:{}", key))?;
+    let data = manager.get(GET, &key_url).await?;
+    assert_eq!(data.unwrap().0.url.as_str(), url);
+
+    // Hot pass to make sure the expect response was returned
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+struct CanonicalPaths;
+
+#[async_trait::async_trait]
+impl KeyRewriter for CanonicalPaths {
+    async fn rewrite(&self, parts: &http::request::Parts) -> String {
+        parts.uri.path().replace("/legacy", "/canonical")
+    }
+}
+
+#[tokio::test]
+async fn key_rewriter() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with a key rewriter
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions {
+                key_rewriter: Some(Arc::new(CanonicalPaths)),
+                ..Default::default()
+            },
+        }))
+        .build();
+
+    // Cold pass to load cache
+    client.get(format!("{}/canonical", &mock_server.uri())).send().await?;
+
+    // Try to load cached object under the rewritten key
+    let key_url = Url::parse("urn:http-cache:/canonical")?;
+    assert!(manager.get(GET, &key_url).await?.is_some());
+
+    // Hot pass through the aliased path shares the cache entry
+    let res =
+        client.get(format!("{}/legacy", &mock_server.uri())).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
 #[tokio::test]
 async fn no_cache_mode() -> anyhow::Result<()> {
     let mock_server = MockServer::start().await;
@@ -74,7 +160,7 @@ async fn no_cache_mode() -> anyhow::Result<()> {
         .with(Cache(HttpCache {
             mode: CacheMode::NoCache,
             manager: manager.clone(),
-            options: None,
+            options: HttpCacheOptions::default(),
         }))
         .build();
 
@@ -89,3 +175,2390 @@ async fn no_cache_mode() -> anyhow::Result<()> {
     client.get(url).send().await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn no_store_mode() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::NoStore,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // Remote request but should not cache
+    client.get(url.clone()).send().await?;
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_none());
+
+    // To verify our endpoint receives the request rather than a cache hit
+    let res = client.get(url).send().await?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+#[tokio::test]
+async fn no_buffer_extension() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // Remote request that skips the cache entirely
+    client.get(url.clone()).with_extension(NoBuffer).send().await?;
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_none());
+
+    // To verify our endpoint receives the request rather than a cache hit
+    let res = client.get(url).with_extension(NoBuffer).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+mod streaming_body {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::{multipart, Body, Client};
+    use reqwest_middleware::ClientBuilder;
+
+    #[tokio::test]
+    async fn multipart_upload() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m_get = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+        let m_post = Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(201).set_body_bytes("created"))
+            .expect(1);
+        let _mock_guard_get = mock_server.register_as_scoped(m_get).await;
+        let _mock_guard_post = mock_server.register_as_scoped(m_post).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+
+        // Construct reqwest client with cache defaults
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // Cold pass to load cache
+        client.get(url.clone()).send().await?;
+
+        // Upload a multipart form, which can't be cloned
+        let form = multipart::Form::new().text("field", "uploaded value");
+        let res = client.post(url.clone()).multipart(form).send().await?;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+        // Make sure the whole form reached the endpoint
+        let received = mock_server.received_requests().await.unwrap();
+        let body = String::from_utf8_lossy(&received[1].body);
+        assert!(body.contains("uploaded value"));
+
+        // The cached GET response is still invalidated
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wrap_stream() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(201).set_body_bytes("created"))
+            .expect(1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+
+        // Construct reqwest client with cache defaults
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // Send a streaming body, which can't be cloned
+        let chunks: Vec<std::io::Result<&'static [u8]>> =
+            vec![Ok(b"streamed "), Ok(b"body")];
+        let body = Body::wrap_stream(futures::stream::iter(chunks));
+        let res = client.put(url.clone()).body(body).send().await?;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+        // Make sure the streamed body reached the endpoint
+        let received = mock_server.received_requests().await.unwrap();
+        assert_eq!(received[0].body, b"streamed body");
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn delete_after_non_get_head_method_request() -> anyhow::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m_get = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let m_post = Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201).set_body_bytes("created"))
+        .expect(1);
+    let _mock_guard_get = mock_server.register_as_scoped(m_get).await;
+    let _mock_guard_post = mock_server.register_as_scoped(m_post).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+
+    // Construct reqwest client with cache defaults
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    // Cold pass to load cache
+    client.get(url.clone()).send().await?;
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_some());
+
+    // Post request to make sure the cache object at the same resource was deleted
+    let res = client.post(url.clone()).send().await?;
+    assert_eq!(res.bytes().await?, "created");
+
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn non_utf8_headers() -> anyhow::Result<()> {
+    let url = serve_raw(
+        b"HTTP/1.1 200 OK\r\n\
+          Cache-Control: max-age=86400, public\r\n\
+          X-Name: caf\xe9\r\n\
+          Content-Length: 4\r\n\
+          \r\n\
+          test",
+    )?;
+    let manager = MokaManager::default();
+    let client = ClientBuilder::new(Client::new())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    let res = client.get(&url).send().await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.bytes().await?, TEST_BODY);
+    let (stored, _) = manager.get(GET, &Url::parse(&url)?).await?.unwrap();
+    assert_eq!(stored.headers.get("x-name").unwrap(), "caf\u{fffd}");
+
+    // The stored response is served without going back to the server
+    let res = client.get(&url).send().await?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    Ok(())
+}
+
+#[cfg(test)]
+mod overridden_cache_mode {
+    use super::*;
+
+    #[tokio::test]
+    async fn reload() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+
+        // Construct reqwest client with cache defaults
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // Cold pass to load cache
+        client.get(url.clone()).send().await?;
+
+        // Should skip the cached object and update it with the response
+        let res = client
+            .get(url.clone())
+            .with_extension(CacheMode::Reload)
+            .send()
+            .await?;
+        assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+        // Try to load cached object
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_some());
+
+        // The override only applies to a single request
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_store() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+
+        // Construct reqwest client with cache defaults
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // Remote request but should not cache
+        let mut extensions = Extensions::new();
+        extensions.insert(CacheMode::NoStore);
+        let req = client.get(url.clone()).build()?;
+        client.execute_with_extensions(req, &mut extensions).await?;
+        assert!(extensions.get::<CacheMode>().is_none());
+
+        // Try to load cached object
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_if_cached_miss() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 0);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+
+        // Construct reqwest client with cache defaults
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // Should result in a synthetic response and no remote request
+        let res = client
+            .get(url.clone())
+            .with_extension(CacheMode::OnlyIfCached)
+            .send()
+            .await?;
+        assert_eq!(res.status(), 504);
+        assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+        // Try to load cached object
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_none());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod redirect_key {
+    use super::*;
+    use wiremock::matchers::path;
+
+    async fn mock_redirect(
+        mock_server: &MockServer,
+        expect_redirect: u64,
+        expect_target: u64,
+    ) -> (wiremock::MockGuard, wiremock::MockGuard) {
+        let m_redirect = Mock::given(method(GET))
+            .and(path("/latest"))
+            .respond_with(
+                ResponseTemplate::new(302).insert_header("location", "/v2"),
+            )
+            .expect(expect_redirect);
+        let m_target = Mock::given(method(GET))
+            .and(path("/v2"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(expect_target);
+        (
+            mock_server.register_as_scoped(m_redirect).await,
+            mock_server.register_as_scoped(m_target).await,
+        )
+    }
+
+    fn client(
+        manager: &MokaManager,
+        redirect_key: RedirectKey,
+    ) -> reqwest_middleware::ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    redirect_key,
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn original() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let _guards = mock_redirect(&mock_server, 1, 1).await;
+        let latest = Url::parse(&format!("{}/latest", &mock_server.uri()))?;
+        let target = Url::parse(&format!("{}/v2", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = client(&manager, RedirectKey::Original);
+
+        // Cold pass to load cache
+        client.get(latest.clone()).send().await?;
+
+        // The final response is stored under the requested url
+        let (res, _) = manager.get(GET, &latest).await?.unwrap();
+        assert_eq!(res.url, target);
+        assert!(manager.get(GET, &target).await?.is_none());
+
+        // Hot pass to make sure the expect response was returned
+        let res = client.get(latest).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn final_url() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let _guards = mock_redirect(&mock_server, 2, 2).await;
+        let latest = Url::parse(&format!("{}/latest", &mock_server.uri()))?;
+        let target = Url::parse(&format!("{}/v2", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = client(&manager, RedirectKey::Final);
+
+        // Cold pass to load cache
+        client.get(latest.clone()).send().await?;
+
+        // The final response is stored under the final url only
+        assert!(manager.get(GET, &latest).await?.is_none());
+        assert!(manager.get(GET, &target).await?.is_some());
+
+        // Requesting the final url directly is served from cache
+        let res = client.get(target).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+
+        // Requesting the original url follows the redirect again
+        let res = client.get(latest).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn both() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let _guards = mock_redirect(&mock_server, 1, 1).await;
+        let latest = Url::parse(&format!("{}/latest", &mock_server.uri()))?;
+        let target = Url::parse(&format!("{}/v2", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = client(&manager, RedirectKey::Both);
+
+        // Cold pass to load cache
+        client.get(latest.clone()).send().await?;
+
+        // The final response is stored under the final url with an alias
+        let (alias, _) = manager.get(GET, &latest).await?.unwrap();
+        assert!(alias.body.is_empty());
+        let (res, _) = manager.get(GET, &target).await?.unwrap();
+        assert_eq!(res.body, TEST_BODY);
+
+        // Both urls are served from cache
+        let res = client.get(latest).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.url(), &target);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        let res = client.get(target).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod request_builder_ext {
+    use crate::*;
+
+    use http_cache_reqwest::{Cache, CacheRequestBuilderExt};
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(manager: &MokaManager) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn cache_key() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        // Cold pass to load cache under the custom key
+        client.get(format!("{}one", url)).cache_key("shared").send().await?;
+        let data =
+            manager.get(GET, &Url::parse(&format!("{}one", url))?).await?;
+        assert!(data.is_none());
+
+        // A different url with the same key is served from the cache
+        let res = client
+            .get(format!("{}two", url))
+            .cache_key("shared")
+            .send()
+            .await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cache_bust() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 3);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let one = Url::parse(&format!("{}/one", &mock_server.uri()))?;
+        let two = Url::parse(&format!("{}/two", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        // Cold passes to load cache
+        client.get(one.clone()).send().await?;
+        client.get(two.clone()).send().await?;
+
+        // Busting from another request removes the stored responses
+        client
+            .post(format!("{}/three", &mock_server.uri()))
+            .cache_bust([one.clone()])
+            .cache_bust([two.clone()])
+            .send()
+            .await?;
+        assert!(manager.get(GET, &one).await?.is_none());
+        assert!(manager.get(GET, &two).await?.is_none());
+
+        // To verify our endpoint receives the request rather than a cache hit
+        let res = client.get(one).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cache_key_and_bust() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let one = Url::parse(&format!("{}/one", &mock_server.uri()))?;
+        let two = Url::parse(&format!("{}/two", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        // Cold pass to load cache
+        client.get(one.clone()).send().await?;
+
+        // Both controls apply to the one request
+        client
+            .get(two)
+            .cache_key("two")
+            .cache_bust([one.clone()])
+            .send()
+            .await?;
+        assert!(manager.get(GET, &one).await?.is_none());
+        let key_url = Url::parse("urn:http-cache:two")?;
+        assert!(manager.get(GET, &key_url).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_cache() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        // Cold pass to load cache
+        client.get(url.clone()).send().await?;
+
+        // To verify our endpoint receives the request rather than a cache hit
+        let res = client.get(url).no_cache().send().await?;
+        assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod overridden_cache_options {
+    use crate::*;
+
+    use http_cache_reqwest::{Cache, CacheRequestBuilderExt};
+    use reqwest::Client;
+    use reqwest_middleware::ClientBuilder;
+    use task_local_extensions::Extensions;
+
+    #[tokio::test]
+    async fn private_cache() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PRIVATE, TEST_BODY, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let shared = Url::parse(&format!("{}/shared", &mock_server.uri()))?;
+        let private = Url::parse(&format!("{}/private", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+
+        // Construct reqwest client with shared cache semantics
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // A private response can't be stored by the shared cache
+        client.get(shared.clone()).send().await?;
+        assert!(manager.get(GET, &shared).await?.is_none());
+
+        // Unless the request asks for private cache semantics
+        let options = CacheOptions { shared: false, ..Default::default() };
+        let mut extensions = Extensions::new();
+        extensions.insert(options);
+        let req = client.get(private.clone()).build()?;
+        client.execute_with_extensions(req, &mut extensions).await?;
+        assert!(extensions.get::<CacheOptions>().is_none());
+        assert!(manager.get(GET, &private).await?.is_some());
+
+        // Hot pass to make sure the stored policy keeps the override
+        let res = client.get(private).cache_options(options).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod empty_body {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::ClientBuilder;
+
+    async fn round_trip(status: u16) -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, b"", status, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+
+        // Construct reqwest client with cache defaults
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // Cold pass to load cache
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.status(), status);
+
+        // Try to load cached object
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.unwrap().0.body.is_empty());
+
+        // Hot pass to make sure the empty response is replayed as is
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.status(), status);
+        if status != 204 {
+            assert_eq!(res.headers().get("content-length").unwrap(), "0");
+        }
+        assert_eq!(res.content_length(), Some(0));
+        assert!(res.bytes().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_content() -> anyhow::Result<()> {
+        round_trip(204).await
+    }
+
+    #[tokio::test]
+    async fn empty_ok() -> anyhow::Result<()> {
+        round_trip(200).await
+    }
+}
+
+#[cfg(test)]
+mod partial_content {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_partial_mock(cache_control_val: &str, expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .insert_header("cache-control", cache_control_val)
+                    .insert_header("content-range", "bytes 0-1/4")
+                    .set_body_bytes(&TEST_BODY[..2]),
+            )
+            .expect(expect)
+    }
+
+    fn build_client(manager: &MokaManager) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn full_request() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_partial_mock(CACHEABLE_PUBLIC, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        // The partial response is passed through without being stored
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.status(), 206);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        assert_eq!(res.bytes().await?, &TEST_BODY[..2]);
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_none());
+
+        // The second request goes to the origin again
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revalidation() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+        let m_206 = build_partial_mock(MUST_REVALIDATE, 1);
+        let mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        // Cold pass to load cache
+        client.get(url.clone()).send().await?;
+
+        drop(mock_guard);
+
+        let _mock_guard = mock_server.register_as_scoped(m_206).await;
+
+        // The partial response doesn't replace the stored one
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.status(), 206);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        let (stored, _) = data.unwrap();
+        assert_eq!(stored.status, 200);
+        assert_eq!(stored.body, TEST_BODY);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cookie_policy {
+    use crate::*;
+
+    use http_cache_reqwest::{Cache, CookiePolicy};
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    // Cacheable, but not explicitly for shared caches
+    const CACHEABLE: &str = "max-age=86400";
+
+    fn build_client(
+        cookie_policy: CookiePolicy,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    cookie_policy,
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    fn build_set_cookie_mock(cache_control_val: &str, expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", cache_control_val)
+                    .insert_header("set-cookie", "session=abc")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(expect)
+    }
+
+    #[tokio::test]
+    async fn ignore() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE, TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CookiePolicy::Ignore, &manager);
+
+        // Cookies don't keep the response from being stored and served
+        client.get(url.clone()).header("cookie", "session=abc").send().await?;
+        let res =
+            client.get(url).header("cookie", "session=abc").send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_cookie_not_stored() -> anyhow::Result<()> {
+        for policy in
+            [CookiePolicy::BypassOnRequestCookie, CookiePolicy::BypassOnEither]
+        {
+            let mock_server = MockServer::start().await;
+            let m = build_mock(CACHEABLE, TEST_BODY, 200, 2);
+            let _mock_guard = mock_server.register_as_scoped(m).await;
+            let url = format!("{}/", &mock_server.uri());
+            let manager = MokaManager::default();
+            let client = build_client(policy, &manager);
+
+            // Remote request but should not cache
+            client
+                .get(url.clone())
+                .header("cookie", "session=abc")
+                .send()
+                .await?;
+            let data = manager.get(GET, &Url::parse(&url)?).await?;
+            assert!(data.is_none());
+
+            // To verify our endpoint receives the request rather than a cache hit
+            let res = client.get(url).send().await?;
+            assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_cookie_not_served() -> anyhow::Result<()> {
+        for policy in
+            [CookiePolicy::BypassOnRequestCookie, CookiePolicy::BypassOnEither]
+        {
+            let mock_server = MockServer::start().await;
+            let m = build_mock(CACHEABLE, TEST_BODY, 200, 2);
+            let _mock_guard = mock_server.register_as_scoped(m).await;
+            let url = format!("{}/", &mock_server.uri());
+            let manager = MokaManager::default();
+            let client = build_client(policy, &manager);
+
+            // Cold pass to load cache
+            client.get(url.clone()).send().await?;
+            let data = manager.get(GET, &Url::parse(&url)?).await?;
+            assert!(data.is_some());
+
+            // The stored response isn't served to a request with cookies
+            let res = client
+                .get(url.clone())
+                .header("cookie", "session=abc")
+                .send()
+                .await?;
+            assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+            assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_cookie_not_stored() -> anyhow::Result<()> {
+        for policy in
+            [CookiePolicy::BypassOnSetCookie, CookiePolicy::BypassOnEither]
+        {
+            let mock_server = MockServer::start().await;
+            let m = build_set_cookie_mock(CACHEABLE, 2);
+            let _mock_guard = mock_server.register_as_scoped(m).await;
+            let url = format!("{}/", &mock_server.uri());
+            let manager = MokaManager::default();
+            let client = build_client(policy, &manager);
+
+            // Remote request but should not cache
+            client.get(url.clone()).send().await?;
+            let data = manager.get(GET, &Url::parse(&url)?).await?;
+            assert!(data.is_none());
+
+            let res = client.get(url).send().await?;
+            assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_cookie_policy_allows_request_cookie() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE, TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CookiePolicy::BypassOnSetCookie, &manager);
+
+        // Only a `Set-Cookie` on the response bypasses the cache
+        client.get(url.clone()).header("cookie", "session=abc").send().await?;
+        let res =
+            client.get(url).header("cookie", "session=abc").send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn explicitly_shared() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_set_cookie_mock(CACHEABLE_PUBLIC, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CookiePolicy::BypassOnEither, &manager);
+
+        // `public` allows the response to be stored and served regardless
+        client.get(url.clone()).header("cookie", "session=abc").send().await?;
+        let res =
+            client.get(url).header("cookie", "session=abc").send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod set_cookie_policy {
+    use crate::*;
+
+    use http_cache_reqwest::{Cache, SetCookiePolicy};
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        set_cookie_policy: Option<SetCookiePolicy>,
+        shared: bool,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    cache_options: Some(CacheOptions {
+                        shared,
+                        ..Default::default()
+                    }),
+                    set_cookie_policy,
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    // Cacheable, but not explicitly for shared caches
+    fn build_set_cookie_mock(expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", "max-age=86400")
+                    .insert_header("set-cookie", "session=abc")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(expect)
+    }
+
+    #[tokio::test]
+    async fn strip_by_default_for_shared_caches() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_set_cookie_mock(1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(None, true, &manager);
+
+        // The user the cookie was meant for still gets it
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.headers().get("set-cookie").unwrap(), "session=abc");
+        let (stored, _) = manager.get(GET, &Url::parse(&url)?).await?.unwrap();
+        assert!(!stored.headers.contains_key("set-cookie"));
+
+        // Nobody else does
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert!(res.headers().get("set-cookie").is_none());
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bypass() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_set_cookie_mock(2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client =
+            build_client(Some(SetCookiePolicy::Bypass), true, &manager);
+
+        client.get(url.clone()).send().await?;
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_none());
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        assert_eq!(res.headers().get("set-cookie").unwrap(), "session=abc");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_by_default_for_private_caches() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_set_cookie_mock(1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(None, false, &manager);
+
+        client.get(url.clone()).send().await?;
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.headers().get("set-cookie").unwrap(), "session=abc");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_in_shared_caches() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_set_cookie_mock(2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(Some(SetCookiePolicy::Store), true, &manager);
+
+        // Stored with its cookie, which leaves it stale for a shared cache
+        client.get(url.clone()).send().await?;
+        let (stored, _) = manager.get(GET, &Url::parse(&url)?).await?.unwrap();
+        assert_eq!(stored.headers["set-cookie"], "session=abc");
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod missing_body {
+    use crate::*;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use http_cache_reqwest::Cache;
+    use http_cache_semantics::CachePolicy;
+    use reqwest::Client;
+    use reqwest_middleware::ClientBuilder;
+    use wiremock::matchers::header_exists;
+
+    // Loses the stored body on the next lookup once `evict` is set, as if
+    // it was evicted from the backend while the entry was in use
+    #[derive(Debug, Clone, Default)]
+    struct EvictingManager {
+        inner: MokaManager,
+        evict: Arc<AtomicBool>,
+    }
+
+    #[async_trait::async_trait]
+    impl CacheManager for EvictingManager {
+        async fn get(
+            &self,
+            method: &str,
+            url: &Url,
+        ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+            let mut data = self.inner.get(method, url).await?;
+            if self.evict.swap(false, Ordering::SeqCst) {
+                if let Some((res, _)) = data.as_mut() {
+                    res.body.clear();
+                }
+            }
+            Ok(data)
+        }
+
+        async fn put(
+            &self,
+            method: &str,
+            url: &Url,
+            res: HttpResponse,
+            policy: CachePolicy,
+        ) -> Result<HttpResponse> {
+            self.inner.put(method, url, res, policy).await
+        }
+
+        async fn delete(&self, method: &str, url: &Url) -> Result<()> {
+            self.inner.delete(method, url).await
+        }
+    }
+
+    #[tokio::test]
+    async fn refetches_instead_of_revalidating() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m_304 = Mock::given(method(GET))
+            .and(header_exists("if-none-match"))
+            .respond_with(ResponseTemplate::new(304))
+            .with_priority(1)
+            .expect(0);
+        let m = Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", MUST_REVALIDATE)
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(2);
+        let _mock_guard_304 = mock_server.register_as_scoped(m_304).await;
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = EvictingManager::default();
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // Cold pass to load cache
+        client.get(url.clone()).send().await?;
+
+        // The stale entry comes back without its body, so the full response
+        // is fetched rather than a `304` that would leave nothing to serve
+        manager.evict.store(true, Ordering::SeqCst);
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+
+        // Make sure the full response was stored again
+        let data = manager.inner.get(GET, &Url::parse(&url)?).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn passes_through_not_modified() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m_304 = Mock::given(method(GET))
+            .and(header_exists("if-none-match"))
+            .respond_with(ResponseTemplate::new(304))
+            .with_priority(1)
+            .expect(1);
+        let m = Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", MUST_REVALIDATE)
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(1);
+        let _mock_guard_304 = mock_server.register_as_scoped(m_304).await;
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = EvictingManager::default();
+        let client = ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build();
+
+        // Cold pass to load cache
+        client.get(url.clone()).send().await?;
+
+        // A `304` to the caller's own validators is theirs to handle, the
+        // empty stored response isn't served in its place
+        manager.evict.store(true, Ordering::SeqCst);
+        let res = client
+            .get(url.clone())
+            .header("if-none-match", "\"v1\"")
+            .send()
+            .await?;
+        assert_eq!(res.status(), 304);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+        // The stored response is left as it was
+        let data = manager.inner.get(GET, &Url::parse(&url)?).await?;
+        assert_eq!(data.unwrap().0.body, TEST_BODY);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cache_status {
+    use crate::*;
+
+    use http_cache_reqwest::{Cache, CacheStatusHeader};
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        status: CacheStatusHeader,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    cache_status_header: Some(status),
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    fn cache_status(res: &reqwest::Response) -> &str {
+        res.headers().get(CACHE_STATUS).unwrap().to_str().unwrap()
+    }
+
+    // Takes out the `ttl` parameter, which depends on how long the test
+    // took, after checking it's within a few seconds of a day
+    fn without_ttl(status: &str) -> String {
+        let start = status.find("; ttl=").unwrap();
+        let rest = &status[start + 6..];
+        let end = rest.find(';').unwrap_or(rest.len());
+        let ttl: u64 = rest[..end].parse().unwrap();
+        assert!((86390..=86400).contains(&ttl), "{}", status);
+        format!("{}{}", &status[..start], &rest[end..])
+    }
+
+    #[tokio::test]
+    async fn forwarded_miss_then_hit() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client =
+            build_client(CacheStatusHeader::new("http-cache"), &manager);
+
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(
+            without_ttl(cache_status(&res)),
+            "http-cache; fwd=uri-miss; fwd-status=200; stored"
+        );
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+        let res = client.get(url).send().await?;
+        assert_eq!(without_ttl(cache_status(&res)), "http-cache; hit");
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn key_and_replaced_x_cache() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let status = CacheStatusHeader {
+            name: "edge cache".to_string(),
+            include_key: true,
+            replace_x_cache: true,
+        };
+        let client = build_client(status, &manager);
+
+        client.get(url.clone()).send().await?;
+        let res = client.get(url.clone()).send().await?;
+        // Names that aren't tokens are sent as strings
+        let expected = format!("\"edge cache\"; hit; key=\"GET:{}\"", url);
+        assert_eq!(without_ttl(cache_status(&res)), expected);
+        assert!(res.headers().get(XCACHE).is_none());
+        assert!(res.headers().get(XCACHELOOKUP).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn appended_to_upstream_entries() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", "no-store")
+                    .insert_header(CACHE_STATUS, "OriginCache; hit")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client =
+            build_client(CacheStatusHeader::new("http-cache"), &manager);
+
+        let res = client.get(url).send().await?;
+        assert_eq!(
+            cache_status(&res),
+            "OriginCache; hit, http-cache; fwd=uri-miss; fwd-status=200"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bypassed_method() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client =
+            build_client(CacheStatusHeader::new("http-cache"), &manager);
+
+        let res = client.post(url).send().await?;
+        assert_eq!(cache_status(&res), "http-cache; fwd=method");
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+}
+
+mod surrogate_control {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+    use wiremock::matchers::header;
+
+    fn build_client(
+        token: Option<&str>,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    surrogate_token: token.map(str::to_string),
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    // Client-facing caching headers that would never allow a hit
+    fn build_mock(surrogate_control: &str, expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .and(header("surrogate-capability", "edge=\"Surrogate/1.0\""))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", "public, max-age=0")
+                    .insert_header("surrogate-control", surrogate_control)
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(expect)
+    }
+
+    #[tokio::test]
+    async fn max_age_overrides_cache_control() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock("max-age=600", 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(Some("edge"), &manager);
+
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        assert!(res.headers().get("surrogate-control").is_none());
+        assert_eq!(
+            res.headers().get("cache-control").unwrap(),
+            "public, max-age=0"
+        );
+
+        // Fresh by the surrogate's lifetime, the client-facing one has
+        // already run out
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert!(res.headers().get("surrogate-control").is_none());
+        assert_eq!(
+            res.headers().get("cache-control").unwrap(),
+            "public, max-age=0"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn targeted_directives_take_precedence() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(
+            "max-age=600, no-store;edge, content=\"ESI/1.0\";other",
+            2,
+        );
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(Some("edge"), &manager);
+
+        // The `no-store` aimed at this cache keeps it from storing at all,
+        // even though `Cache-Control` would allow it
+        client.get(url.clone()).send().await?;
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_none());
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ignored_without_token() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("surrogate-control", "no-store")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(None, &manager);
+
+        // Passed through as any other header, `Cache-Control` decides
+        client.get(url.clone()).send().await?;
+        let requests = mock_server.received_requests().await.unwrap();
+        let mut names = requests[0].headers.keys();
+        assert!(!names.any(|name| name.as_str() == "surrogate-capability"));
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.headers().get("surrogate-control").unwrap(), "no-store");
+        Ok(())
+    }
+}
+
+mod gone {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        mode: CacheMode,
+        gone_statuses: &[u16],
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    gone_statuses: gone_statuses.to_vec(),
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    // Stores a response that's stale straight away, then answers further
+    // requests with the status
+    async fn stale_then(
+        mock_server: &MockServer,
+        manager: &MokaManager,
+        gone_statuses: &[u16],
+        status: u16,
+    ) -> anyhow::Result<reqwest::Response> {
+        let url = format!("{}/", &mock_server.uri());
+        let client = build_client(CacheMode::Default, gone_statuses, manager);
+        let m = build_mock("public, max-age=0", TEST_BODY, 200, 1);
+        let mock_guard = mock_server.register_as_scoped(m).await;
+        client.get(url.clone()).send().await?;
+        assert!(manager.get(GET, &Url::parse(&url)?).await?.is_some());
+        drop(mock_guard);
+        Mock::given(method(GET))
+            .respond_with(ResponseTemplate::new(status))
+            .mount(mock_server)
+            .await;
+        Ok(client.get(url).send().await?)
+    }
+
+    #[tokio::test]
+    async fn removed_on_not_found_and_gone() -> anyhow::Result<()> {
+        for status in [404, 410] {
+            let mock_server = MockServer::start().await;
+            let url = format!("{}/", &mock_server.uri());
+            let manager = MokaManager::default();
+            let res = stale_then(&mock_server, &manager, &[], status).await?;
+            assert_eq!(res.status(), status);
+            assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+            assert!(manager.get(GET, &Url::parse(&url)?).await?.is_none());
+
+            // So the deleted content isn't served even when forced
+            let client = build_client(CacheMode::ForceCache, &[], &manager);
+            let res = client.get(url).send().await?;
+            assert_eq!(res.status(), status);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn removed_on_configured_status() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let res = stale_then(&mock_server, &manager, &[451], 451).await?;
+        assert_eq!(res.status(), 451);
+        assert!(manager.get(GET, &Url::parse(&url)?).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn kept_on_server_error() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+
+        // Even when listed, that's when the stored response is most useful
+        let res = stale_then(&mock_server, &manager, &[503], 503).await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert!(manager.get(GET, &Url::parse(&url)?).await?.is_some());
+        Ok(())
+    }
+}
+
+mod timing {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<PhaseTimings>>);
+
+    impl CacheObserver for Recorder {
+        fn on_timing(&self, timings: &PhaseTimings) {
+            self.0.lock().unwrap().push(*timings);
+        }
+    }
+
+    fn build_client(
+        recorder: &Arc<Recorder>,
+        max_total_budget: Option<Duration>,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    observer: Some(recorder.clone()),
+                    max_total_budget,
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    fn build_slow_mock(expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .set_body_bytes(TEST_BODY)
+                    .set_delay(Duration::from_millis(50)),
+            )
+            .expect(expect)
+    }
+
+    #[tokio::test]
+    async fn phases_are_reported() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let _mock_guard =
+            mock_server.register_as_scoped(build_slow_mock(1)).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let recorder = Arc::new(Recorder::default());
+        let client = build_client(&recorder, None, &manager);
+
+        client.get(url.clone()).send().await?;
+        client.get(url).send().await?;
+        let timings = recorder.0.lock().unwrap().clone();
+        assert_eq!(timings.len(), 2);
+
+        let miss = timings[0];
+        assert!(miss.origin >= Duration::from_millis(50));
+        assert!(miss.total >= miss.lookup + miss.origin + miss.store);
+        assert!(!miss.store_skipped);
+
+        // The hit never waits on the origin or stores anything
+        let hit = timings[1];
+        assert_eq!(hit.origin, Duration::ZERO);
+        assert_eq!(hit.store, Duration::ZERO);
+        assert!(hit.total >= hit.lookup);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_skipped_over_budget() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let _mock_guard =
+            mock_server.register_as_scoped(build_slow_mock(2)).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let recorder = Arc::new(Recorder::default());
+        let client =
+            build_client(&recorder, Some(Duration::from_millis(10)), &manager);
+
+        // The response is still served, just not stored
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        let data = manager.get(GET, &Url::parse(&url)?).await?;
+        assert!(data.is_none());
+        let res = client.get(url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+        let timings = recorder.0.lock().unwrap().clone();
+        assert!(timings.iter().all(|t| t.store_skipped));
+        assert!(timings.iter().all(|t| t.store == Duration::ZERO));
+        Ok(())
+    }
+}
+
+mod stored_policy {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use http_cache_semantics::CachePolicy;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(manager: &MokaManager) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn unread_headers_are_dropped() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("x-large", "x".repeat(4096).as_str())
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        client.get(&url).header("x-request", "value").send().await?;
+        let (_, policy) = manager.get(GET, &Url::parse(&url)?).await?.unwrap();
+        let policy = serde_json::to_string(&policy)?;
+        assert!(!policy.contains("x-large"));
+        assert!(!policy.contains("x-request"));
+        assert!(policy.contains(CACHEABLE_PUBLIC));
+
+        // The stored response still has every header
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.headers().get("x-large").unwrap().len(), 4096);
+        Ok(())
+    }
+
+    // Compacting reaches into the private fields of the serialized policy and
+    // keeps the policy whole when it can't find them, which the tests above
+    // would only show as headers left behind
+    #[test]
+    fn policy_layout_is_understood() -> anyhow::Result<()> {
+        let req = http::Request::get("http://example.com/")
+            .header("x-request", "value")
+            .body(())?;
+        let res = http::Response::builder()
+            .header("cache-control", CACHEABLE_PUBLIC)
+            .body(())?;
+        let policy = CachePolicy::new(&req, &res);
+        let value = serde_json::to_value(&policy)?;
+        let req = value.get("req").and_then(|req| req.as_object());
+        assert!(req.unwrap().contains_key("x-request"));
+        let res = value.get("res").and_then(|res| res.as_object());
+        assert!(res.unwrap().contains_key("cache-control"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vary_and_authorization_survive() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("vary", "accept-language")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        client
+            .get(&url)
+            .header("accept-language", "en")
+            .header("authorization", "Bearer secret")
+            .send()
+            .await?;
+        let (_, policy) = manager.get(GET, &Url::parse(&url)?).await?.unwrap();
+        let policy = serde_json::to_string(&policy)?;
+        assert!(policy.contains("accept-language"));
+        assert!(policy.contains("authorization"));
+        assert!(!policy.contains("secret"));
+
+        let res = client
+            .get(&url)
+            .header("accept-language", "en")
+            .header("authorization", "Bearer secret")
+            .send()
+            .await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+
+        // A different language still doesn't match the stored response
+        let res = client
+            .get(&url)
+            .header("accept-language", "fr")
+            .header("authorization", "Bearer secret")
+            .send()
+            .await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+}
+
+mod negative {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        negative_ttl: Option<Duration>,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    negative_ttl,
+                    negative_statuses: vec![400],
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn expires_on_the_negative_ttl() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        // The origin would have it fresh for a day
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 404, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(Some(Duration::from_secs(1)), &manager);
+
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), 404);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), 404);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), 404);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+        // Stored again for another negative TTL
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn outlives_a_shorter_origin_lifetime() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = Mock::given(method(GET))
+            .respond_with(ResponseTemplate::new(451))
+            .expect(1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(Some(Duration::from_secs(60)), &manager);
+
+        client.get(&url).send().await?;
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), 451);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn configured_status_is_cached() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 400, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(Some(Duration::from_secs(60)), &manager);
+
+        client.get(&url).send().await?;
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), 400);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn not_cached_when_forbidden_or_disabled() -> anyhow::Result<()> {
+        for (cache_control, negative_ttl) in [
+            ("no-store", Some(Duration::from_secs(60))),
+            ("max-age=86400, private", Some(Duration::from_secs(60))),
+            (CACHEABLE_PUBLIC, None),
+        ] {
+            let mock_server = MockServer::start().await;
+            let m = build_mock(cache_control, TEST_BODY, 404, 2);
+            let _mock_guard = mock_server.register_as_scoped(m).await;
+            let url = format!("{}/", &mock_server.uri());
+            let manager = MokaManager::default();
+            let client = build_client(negative_ttl, &manager);
+
+            client.get(&url).send().await?;
+            assert!(manager.get(GET, &Url::parse(&url)?).await?.is_none());
+            let res = client.get(&url).send().await?;
+            assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        }
+        Ok(())
+    }
+}
+
+mod accept_encoding {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        normalize: Option<&[&str]>,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        let normalize_accept_encoding = normalize
+            .map(|codings| codings.iter().map(|c| c.to_string()).collect());
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    normalize_accept_encoding,
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    fn build_vary_mock(expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("vary", "accept-encoding")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(expect)
+    }
+
+    // Sends requests with each `Accept-Encoding` value in turn, returning
+    // the `x-cache` header of each response
+    async fn send_all(
+        client: &ClientWithMiddleware,
+        url: &str,
+        values: &[&str],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut statuses = Vec::new();
+        for value in values {
+            let res = client
+                .get(url)
+                .header("accept-encoding", *value)
+                .send()
+                .await?;
+            statuses.push(res.headers()[XCACHE].to_str()?.to_string());
+        }
+        Ok(statuses)
+    }
+
+    #[tokio::test]
+    async fn equivalent_values_share_an_entry() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let _mock_guard =
+            mock_server.register_as_scoped(build_vary_mock(1)).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(Some(&["br", "gzip", "deflate"]), &manager);
+
+        let values = [
+            "gzip, deflate, br",
+            "br;q=1.0, gzip;q=0.8, deflate, identity",
+            "gzip, *;q=0.5, compress;q=0",
+        ];
+        assert_eq!(send_all(&client, &url, &values).await?, [MISS, HIT, HIT]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn different_codings_still_vary() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let _mock_guard =
+            mock_server.register_as_scoped(build_vary_mock(2)).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(Some(&["br", "gzip"]), &manager);
+
+        let values = ["gzip, br", "gzip, br;q=0"];
+        assert_eq!(send_all(&client, &url, &values).await?, [MISS, MISS]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exact_match_by_default() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let _mock_guard =
+            mock_server.register_as_scoped(build_vary_mock(2)).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(None, &manager);
+
+        let values = ["gzip, br", "br, gzip"];
+        assert_eq!(send_all(&client, &url, &values).await?, [MISS, MISS]);
+        Ok(())
+    }
+}
+
+mod retry_after {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        honor_retry_after: bool,
+        max_variants_per_url: Option<usize>,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    honor_retry_after,
+                    retry_after_jitter: Duration::from_secs(5),
+                    max_variants_per_url,
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    // Stores a response that's stale straight away, then has the origin
+    // answer with a `503` and the `Retry-After`, expected that many times
+    async fn unavailable(
+        mock_server: &MockServer,
+        client: &ClientWithMiddleware,
+        retry_after: &str,
+        expected: u64,
+    ) -> anyhow::Result<()> {
+        let url = format!("{}/", &mock_server.uri());
+        let m = build_mock("public, max-age=0", TEST_BODY, 200, 1);
+        let mock_guard = mock_server.register_as_scoped(m).await;
+        client.get(&url).send().await?;
+        drop(mock_guard);
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(503)
+                    .insert_header("retry-after", retry_after),
+            )
+            .expect(expected)
+            .mount(mock_server)
+            .await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stale_is_served_until_it_passes() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(true, None, &manager);
+        unavailable(&mock_server, &client, "30", 1).await?;
+
+        for _ in 0..3 {
+            let res = client.get(&url).send().await?;
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+            assert!(!res.headers().contains_key("x-cache-retry-at"));
+            assert_eq!(res.bytes().await?, TEST_BODY);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn http_date_in_the_past_holds_nothing_back() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(true, None, &manager);
+        let past = "Wed, 21 Oct 2015 07:28:00 GMT";
+        unavailable(&mock_server, &client, past, 2).await?;
+
+        for _ in 0..2 {
+            let res = client.get(&url).send().await?;
+            assert_eq!(res.bytes().await?, TEST_BODY);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ignored_by_default() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(false, None, &manager);
+        unavailable(&mock_server, &client, "30", 2).await?;
+
+        for _ in 0..2 {
+            let res = client.get(&url).send().await?;
+            assert_eq!(res.bytes().await?, TEST_BODY);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn variants_stored_apart_are_held_back() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(true, Some(4), &manager);
+        let m = Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", "public, max-age=0")
+                    .insert_header("vary", "accept-language")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(1);
+        let mock_guard = mock_server.register_as_scoped(m).await;
+        client.get(&url).header("accept-language", "en").send().await?;
+        drop(mock_guard);
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(503).insert_header("retry-after", "30"),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..3 {
+            let res =
+                client.get(&url).header("accept-language", "en").send().await?;
+            assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+            assert_eq!(res.bytes().await?, TEST_BODY);
+        }
+        Ok(())
+    }
+}
+
+mod query_param_policy {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        query_param_policy: QueryParamPolicy,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: MokaManager::default(),
+                options: HttpCacheOptions {
+                    query_param_policy,
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    // Requests each path in turn, returning the `x-cache` of each response
+    async fn x_cache(
+        client: &ClientWithMiddleware,
+        mock_server: &MockServer,
+        paths: &[&str],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut statuses = Vec::new();
+        for path in paths {
+            let url = format!("{}{}", &mock_server.uri(), path);
+            let res = client.get(url).send().await?;
+            statuses.push(res.headers()[XCACHE].to_str()?.to_string());
+        }
+        Ok(statuses)
+    }
+
+    #[tokio::test]
+    async fn dropped_params_are_ignored() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 3);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let policy = QueryParamPolicy::Drop(vec!["utm_*".into()]);
+        let client = build_client(policy);
+
+        let paths = [
+            "/?v=1",
+            "/?v=1&utm_source=x",
+            "/?utm_medium=y&v=1",
+            "/?v=2",
+            "/",
+            "/?utm_source=x",
+        ];
+        let statuses = x_cache(&client, &mock_server, &paths).await?;
+        assert_eq!(statuses, [MISS, HIT, HIT, MISS, MISS, HIT]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_kept_params_partition() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let client = build_client(QueryParamPolicy::Keep(vec!["v".into()]));
+
+        let paths = ["/?v=1", "/?v=2", "/?v=2&session=abc", "/?ref=a&v=1"];
+        let statuses = x_cache(&client, &mock_server, &paths).await?;
+        assert_eq!(statuses, [MISS, MISS, HIT, HIT]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn every_param_counts_by_default() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let client = build_client(QueryParamPolicy::default());
+
+        let paths = ["/?v=1", "/?v=1&utm_source=x", "/?v=1"];
+        let statuses = x_cache(&client, &mock_server, &paths).await?;
+        assert_eq!(statuses, [MISS, MISS, HIT]);
+        Ok(())
+    }
+}
+
+mod cacheability_override {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+    use std::sync::Arc;
+
+    const ERROR_ENVELOPE: &[u8] = br#"{"error":"database unavailable"}"#;
+
+    fn build_client(manager: &MokaManager) -> ClientWithMiddleware {
+        // Errors come back as `200`, and `no-store` is stored anyway
+        let cacheability = |res: &HttpResponse| {
+            if res.body.starts_with(br#"{"error""#) {
+                Some(false)
+            } else if res.headers.get("cache-control").map(String::as_str)
+                == Some("no-store")
+            {
+                Some(true)
+            } else {
+                None
+            }
+        };
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    cacheability_override: Some(Arc::new(cacheability)),
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn error_payload_is_skipped() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, ERROR_ENVELOPE, 200, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        for _ in 0..2 {
+            let res = client.get(&url).send().await?;
+            assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+            assert_eq!(res.bytes().await?, ERROR_ENVELOPE);
+        }
+        assert!(manager.get(GET, &Url::parse(&url)?).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn error_payload_leaves_the_stored_response() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+        let m = build_mock("public, max-age=0", TEST_BODY, 200, 1);
+        let mock_guard = mock_server.register_as_scoped(m).await;
+        client.get(&url).send().await?;
+        drop(mock_guard);
+
+        let m = build_mock(CACHEABLE_PUBLIC, ERROR_ENVELOPE, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes().await?, TEST_BODY);
+        let (stored, _) = manager.get(GET, &Url::parse(&url)?).await?.unwrap();
+        assert_eq!(stored.body, TEST_BODY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forced_response_is_stored() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock("no-store", TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+
+        client.get(&url).send().await?;
+        assert!(manager.get(GET, &Url::parse(&url)?).await?.is_some());
+        Ok(())
+    }
+}
+
+mod only_if_cached_directive {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        mode: CacheMode,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn hit_is_served() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        build_client(CacheMode::Default, &manager).get(&url).send().await?;
+
+        // Even by a mode that would otherwise go to the origin
+        for mode in [CacheMode::Default, CacheMode::NoCache, CacheMode::NoStore]
+        {
+            let res = build_client(mode, &manager)
+                .get(&url)
+                .header("cache-control", "max-age=60, only-if-cached")
+                .send()
+                .await?;
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+            assert_eq!(res.bytes().await?, TEST_BODY);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn miss_and_stale_get_a_gateway_timeout() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock("public, max-age=0", TEST_BODY, 200, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+        let stale = format!("{}/stale", &mock_server.uri());
+        client.get(&stale).send().await?;
+
+        let missing = format!("{}/missing", &mock_server.uri());
+        for url in [missing, stale] {
+            let res = client
+                .get(&url)
+                .header("cache-control", "only-if-cached")
+                .send()
+                .await?;
+            assert_eq!(res.status(), 504);
+            assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        }
+        Ok(())
+    }
+}
+
+mod server_errors {
+    use crate::*;
+
+    use http_cache_reqwest::Cache;
+    use reqwest::Client;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    fn build_client(
+        cache_server_errors: bool,
+        manager: &MokaManager,
+    ) -> ClientWithMiddleware {
+        ClientBuilder::new(Client::new())
+            .with(Cache(HttpCache {
+                mode: CacheMode::ForceCache,
+                manager: manager.clone(),
+                options: HttpCacheOptions {
+                    cache_server_errors,
+                    min_ttl: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+            }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn never_stored_by_default() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 500, 2);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(false, &manager);
+
+        for _ in 0..2 {
+            let res = client.get(&url).send().await?;
+            assert_eq!(res.status(), 500);
+            assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        }
+        assert!(manager.get(GET, &Url::parse(&url)?).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stored_when_opted_in() -> anyhow::Result<()> {
+        let mock_server = MockServer::start().await;
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 500, 1);
+        let _mock_guard = mock_server.register_as_scoped(m).await;
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(true, &manager);
+
+        client.get(&url).send().await?;
+        let res = client.get(&url).send().await?;
+        assert_eq!(res.status(), 500);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+use crate::*;
+
+use async_std::task::block_on;
+use http_cache_reqwest::blocking::Cache;
+use reqwest::blocking::{Client, Request};
+
+fn build_client(mode: CacheMode, manager: &MokaManager) -> Cache<MokaManager> {
+    Cache::new(
+        Client::new(),
+        HttpCache {
+            mode,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        },
+    )
+}
+
+#[test]
+fn default_mode() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+    // Try to load cached object
+    let data = block_on(manager.get(GET, &Url::parse(&url)?))?;
+    assert!(data.is_some());
+
+    // Hot pass to make sure the expect response was returned
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.bytes()?, TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn no_store_mode() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::NoStore, &manager);
+
+    // Remote request but should not cache
+    client.get(&url)?;
+
+    // Try to load cached object
+    let data = block_on(manager.get(GET, &Url::parse(&url)?))?;
+    assert!(data.is_none());
+
+    // To verify our endpoint receives the request rather than a cache hit
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    assert_eq!(res.bytes()?, TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn revalidation_304() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+    let m_304 = Mock::given(method(GET))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(1);
+    let mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get(&url)?;
+
+    drop(mock_guard);
+
+    let _mock_guard = block_on(mock_server.register_as_scoped(m_304));
+
+    // Hot pass to make sure revalidation request was sent
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.bytes()?, TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn delete_after_non_get_head_method_request() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m_get = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let m_post = Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201).set_body_bytes("created"))
+        .expect(1);
+    let _mock_guard_get = block_on(mock_server.register_as_scoped(m_get));
+    let _mock_guard_post = block_on(mock_server.register_as_scoped(m_post));
+    let url = Url::parse(&format!("{}/", &mock_server.uri()))?;
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get(url.clone())?;
+    assert!(block_on(manager.get(GET, &url))?.is_some());
+
+    // Post request to make sure the cache object at the same resource was deleted
+    client.execute(Request::new(reqwest::Method::POST, url.clone()))?;
+    assert!(block_on(manager.get(GET, &url))?.is_none());
+    Ok(())
+}
+
+#[test]
+fn non_utf8_headers() -> anyhow::Result<()> {
+    let url = serve_raw(
+        b"HTTP/1.1 200 OK\r\n\
+          Cache-Control: max-age=86400, public\r\n\
+          X-Name: caf\xe9\r\n\
+          Content-Length: 4\r\n\
+          \r\n\
+          test",
+    )?;
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    let res = client.get(&url)?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.bytes()?, TEST_BODY);
+    let (stored, _) = block_on(manager.get(GET, &Url::parse(&url)?))?.unwrap();
+    assert_eq!(stored.headers.get("x-name").unwrap(), "caf\u{fffd}");
+
+    // The stored response is served without going back to the server
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    Ok(())
+}
+
+#[cfg(test)]
+mod only_if_cached_mode {
+    use super::*;
+
+    #[test]
+    fn miss() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 0);
+        let _mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::OnlyIfCached, &manager);
+
+        // Should result in a cache miss and no remote request
+        let res = client.get(&url)?;
+        assert_eq!(res.status(), 504);
+        assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod revalidate {
+    use super::*;
+    use wiremock::matchers::header;
+
+    fn build_etag_mock(body: &[u8], expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_bytes(body),
+            )
+            .expect(expect)
+    }
+
+    #[test]
+    fn not_modified() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_etag_mock(TEST_BODY, 1);
+        let m_304 = Mock::given(method(GET))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(
+                ResponseTemplate::new(304)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("etag", "\"v1\""),
+            )
+            .expect(1);
+        let mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+
+        // Cold pass to load cache
+        client.get(&url)?;
+
+        drop(mock_guard);
+
+        let _mock_guard = block_on(mock_server.register_as_scoped(m_304));
+
+        // The conditional request is sent even though the response is fresh
+        let status = client.revalidate(reqwest::Method::GET, &url)?;
+        assert_eq!(status, CacheLookupStatus::NotModified);
+
+        // Hot pass to make sure the stored response is still served
+        let res = client.get(&url)?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes()?, TEST_BODY);
+        Ok(())
+    }
+
+    #[test]
+    fn modified() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_etag_mock(TEST_BODY, 1);
+        let m_200 = build_etag_mock(b"updated", 1);
+        let mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+
+        // Cold pass to load cache
+        client.get(&url)?;
+
+        drop(mock_guard);
+
+        let _mock_guard = block_on(mock_server.register_as_scoped(m_200));
+
+        // The origin sends a new response which replaces the stored one
+        let status = client.revalidate(reqwest::Method::GET, &url)?;
+        assert_eq!(status, CacheLookupStatus::Modified);
+
+        // Hot pass to make sure the new response was stored
+        let res = client.get(&url)?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.bytes()?, &b"updated"[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_stored() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_etag_mock(TEST_BODY, 1);
+        let _mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+
+        let status = client.revalidate(reqwest::Method::GET, &url)?;
+        assert_eq!(status, CacheLookupStatus::Fetched);
+
+        // Try to load cached object
+        let data = block_on(manager.get(GET, &Url::parse(&url)?))?;
+        assert!(data.is_some());
+        Ok(())
+    }
+}
@@ -18,7 +18,7 @@ async fn default_mode() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::Default,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Cold pass to load cache
@@ -51,7 +51,13 @@ async fn default_mode_with_options() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::Default,
         manager: manager.clone(),
-        options: Some(CacheOptions { shared: false, ..Default::default() }),
+        options: HttpCacheOptions {
+            cache_options: Some(CacheOptions {
+                shared: false,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
     }));
 
     // Cold pass to load cache
@@ -80,7 +86,7 @@ async fn default_mode_no_cache_response() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::Default,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Cold pass to load cache
@@ -120,7 +126,7 @@ async fn removes_warning() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::Default,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Cold pass to load cache
@@ -154,7 +160,7 @@ async fn no_store_mode() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::NoStore,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Remote request but should not cache
@@ -184,7 +190,7 @@ async fn no_cache_mode() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::NoCache,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Remote request and should cache
@@ -216,7 +222,7 @@ async fn force_cache_mode() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::ForceCache,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Should result in a cache miss and a remote request
@@ -253,7 +259,7 @@ async fn delete_after_non_get_head_method_request() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::Default,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Cold pass to load cache
@@ -274,6 +280,34 @@ async fn delete_after_non_get_head_method_request() -> surf::Result<()> {
     Ok(())
 }
 
+#[async_std::test]
+async fn non_get_head_body_is_sent() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m_post = Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201).set_body_bytes("created"))
+        .expect(1);
+    let _mock_guard_post = mock_server.register_as_scoped(m_post).await;
+    let url = format!("{}/", &mock_server.uri());
+    let mut req_post = Request::new(Method::Post, Url::parse(&url)?);
+    req_post.set_body("posted body");
+
+    // Construct Surf client with cache defaults
+    let client = Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: MokaManager::default(),
+        options: HttpCacheOptions::default(),
+    }));
+
+    let res = client.send(req_post).await?;
+    assert_eq!(res.status(), 201);
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+
+    // Make sure the body reached the endpoint
+    let received = mock_server.received_requests().await.unwrap();
+    assert_eq!(received[0].body, b"posted body");
+    Ok(())
+}
+
 #[async_std::test]
 async fn revalidation_304() -> surf::Result<()> {
     let mock_server = MockServer::start().await;
@@ -290,7 +324,7 @@ async fn revalidation_304() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::Default,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Cold pass to load cache
@@ -328,7 +362,7 @@ async fn revalidation_200() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::Default,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Cold pass to load cache
@@ -368,7 +402,7 @@ async fn revalidation_500() -> surf::Result<()> {
     let client = Client::new().with(Cache(HttpCache {
         mode: CacheMode::Default,
         manager: manager.clone(),
-        options: None,
+        options: HttpCacheOptions::default(),
     }));
 
     // Cold pass to load cache
@@ -410,7 +444,7 @@ mod only_if_cached_mode {
         let client = Client::new().with(Cache(HttpCache {
             mode: CacheMode::OnlyIfCached,
             manager: manager.clone(),
-            options: None,
+            options: HttpCacheOptions::default(),
         }));
 
         // Should result in a cache miss and no remote request
@@ -437,7 +471,7 @@ mod only_if_cached_mode {
         let client = Client::new().with(Cache(HttpCache {
             mode: CacheMode::Default,
             manager: manager.clone(),
-            options: None,
+            options: HttpCacheOptions::default(),
         }));
 
         // Cold pass to load the cache
@@ -453,7 +487,7 @@ mod only_if_cached_mode {
         let client = Client::new().with(Cache(HttpCache {
             mode: CacheMode::OnlyIfCached,
             manager: manager.clone(),
-            options: None,
+            options: HttpCacheOptions::default(),
         }));
 
         // Should result in a cache hit and no remote request
@@ -464,3 +498,509 @@ mod only_if_cached_mode {
         Ok(())
     }
 }
+
+#[async_std::test]
+async fn accumulates_upstream_age() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("age", "100")
+                .set_body_bytes(TEST_BODY),
+        )
+        .expect(1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let req = Request::new(Method::Get, Url::parse(&url)?);
+
+    // Construct Surf client with cache defaults
+    let client = Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions::default(),
+    }));
+
+    // Cold pass to load cache
+    let res = client.send(req.clone()).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+    assert_eq!(res.header("age").unwrap(), "100");
+
+    // Hot pass should report the upstream age plus the time held locally
+    let res = client.send(req.clone()).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+    let age: u64 = res.header("age").unwrap().as_str().parse()?;
+    assert!(age >= 100);
+
+    async_std::task::sleep(Duration::from_millis(1100)).await;
+
+    // The reported age should keep growing while the response is held
+    let res = client.send(req).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+    let later_age: u64 = res.header("age").unwrap().as_str().parse()?;
+    assert!(later_age > age);
+    Ok(())
+}
+
+#[async_std::test]
+async fn max_ttl_caps_freshness() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock("max-age=31536000, public", TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let req = Request::new(Method::Get, Url::parse(&url)?);
+
+    // Construct Surf client with a freshness ceiling
+    let client = Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions {
+            max_ttl: Some(Duration::from_secs(1)),
+            ..Default::default()
+        },
+    }));
+
+    // Cold pass to load cache
+    let res = client.send(req.clone()).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+
+    // Still fresh within the ceiling
+    let res = client.send(req.clone()).await?;
+    assert_eq!(res.header(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+
+    async_std::task::sleep(Duration::from_millis(1100)).await;
+
+    // Stale once the ceiling is reached, even though max-age is a year
+    let mut res = client.send(req).await?;
+    assert_eq!(res.body_bytes().await?, TEST_BODY);
+    assert_eq!(res.header(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+    Ok(())
+}
+
+#[async_std::test]
+async fn min_ttl_holds_freshness() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock("max-age=1, public", TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let req = Request::new(Method::Get, Url::parse(&url)?);
+
+    // Construct Surf client with a freshness floor
+    let client = Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions {
+            min_ttl: Some(Duration::from_secs(2)),
+            ..Default::default()
+        },
+    }));
+
+    // Cold pass to load cache
+    let res = client.send(req.clone()).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+
+    async_std::task::sleep(Duration::from_millis(1100)).await;
+
+    // Past the origin max-age but still within the floor
+    let mut res = client.send(req.clone()).await?;
+    assert_eq!(res.body_bytes().await?, TEST_BODY);
+    assert_eq!(res.header(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+
+    async_std::task::sleep(Duration::from_millis(1000)).await;
+
+    // Stale once the floor has passed
+    let res = client.send(req).await?;
+    assert_eq!(res.header(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+    Ok(())
+}
+
+#[async_std::test]
+async fn min_ttl_ignores_no_cache() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock("no-cache", TEST_BODY, 200, 2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let req = Request::new(Method::Get, Url::parse(&url)?);
+
+    // Construct Surf client with a freshness floor
+    let client = Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions {
+            min_ttl: Some(Duration::from_secs(60)),
+            ..Default::default()
+        },
+    }));
+
+    // Cold pass to load cache
+    client.send(req.clone()).await?;
+
+    // The floor must not override no-cache
+    let res = client.send(req).await?;
+    assert_eq!(res.header(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+    Ok(())
+}
+
+#[async_std::test]
+async fn peek() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = Url::parse(&format!("{}/", &mock_server.uri()))?;
+    let manager = MokaManager::default();
+    let req = Request::new(Method::Get, url.clone());
+    let cache = HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions::default(),
+    };
+
+    // Nothing has been stored yet
+    assert!(cache.peek(GET, &url).await?.is_none());
+
+    // Construct Surf client with cache defaults
+    let client = Client::new().with(Cache(cache.clone()));
+
+    // Cold pass to load cache
+    client.send(req).await?;
+
+    // The stored response is stale and peeking must not revalidate it
+    let (res, is_fresh) = cache.peek(GET, &url).await?.unwrap();
+    assert_eq!(res.body, TEST_BODY);
+    assert!(!is_fresh);
+
+    let fresh_url = Url::parse(&format!("{}/fresh", &mock_server.uri()))?;
+    let req = http::Request::get(fresh_url.as_str()).body(())?;
+    let res = http::Response::builder()
+        .header(CACHE_CONTROL.as_str(), CACHEABLE_PUBLIC)
+        .status(200)
+        .body(())?;
+    let policy = http_cache_semantics::CachePolicy::new(&req, &res);
+    let http_res = HttpResponse {
+        body: Bytes::from_static(TEST_BODY),
+        headers: HashMap::default(),
+        trailers: HashMap::new(),
+        status: 200,
+        url: fresh_url.clone(),
+        version: HttpVersion::Http11,
+    };
+    manager.put(GET, &fresh_url, http_res, policy).await?;
+    let (_, is_fresh) = cache.peek(GET, &fresh_url).await?.unwrap();
+    assert!(is_fresh);
+    Ok(())
+}
+
+#[cfg(test)]
+mod non_http_url {
+    use super::*;
+
+    // Stands in for a client that handles custom schemes itself
+    struct Terminal;
+
+    #[surf::utils::async_trait]
+    impl surf::middleware::Middleware for Terminal {
+        async fn handle(
+            &self,
+            req: Request,
+            _client: Client,
+            _next: Next<'_>,
+        ) -> surf::Result<surf::Response> {
+            let mut res = http_types::Response::new(200);
+            res.set_body(req.url().as_str());
+            Ok(res.into())
+        }
+    }
+
+    fn build_client(manager: &MokaManager) -> Client {
+        Client::new()
+            .with(Cache(HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            }))
+            .with(Terminal)
+    }
+
+    #[async_std::test]
+    async fn custom_scheme() -> surf::Result<()> {
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+        let url = Url::parse("unix:///tmp/app.sock")?;
+
+        // The request is forwarded rather than turned into an error
+        let mut res =
+            client.send(Request::new(Method::Get, url.clone())).await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.body_string().await?, url.as_str());
+        assert!(res.header(XCACHE).is_none());
+
+        // Nothing was stored
+        let data = manager.get(GET, &url).await?;
+        assert!(data.is_none());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn unconvertible_url() -> surf::Result<()> {
+        let manager = MokaManager::default();
+        let client = build_client(&manager);
+        // Too long to be represented as an `http::Uri`
+        let url = Url::parse(&format!(
+            "http://example.com/?q={}",
+            "a".repeat(u16::MAX as usize)
+        ))?;
+
+        // The request is forwarded rather than turned into an error
+        let mut res =
+            client.send(Request::new(Method::Get, url.clone())).await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.body_string().await?, url.as_str());
+
+        // Nothing was stored
+        let data = manager.get(GET, &url).await?;
+        assert!(data.is_none());
+        Ok(())
+    }
+}
+
+#[async_std::test]
+async fn no_content() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, b"", 204, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let req = Request::new(Method::Get, Url::parse(&url)?);
+
+    // Construct Surf client with cache defaults
+    let client = Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions::default(),
+    }));
+
+    // Cold pass to load cache
+    client.send(req.clone()).await?;
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.unwrap().0.body.is_empty());
+
+    // Hot pass to make sure the empty response is replayed as is
+    let mut res = client.send(req).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+    assert_eq!(res.status(), 204);
+    assert_eq!(res.len(), Some(0));
+    assert!(res.body_bytes().await?.is_empty());
+    Ok(())
+}
+
+#[async_std::test]
+async fn memory_manager() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MemoryManager::default();
+    let req = Request::new(Method::Get, Url::parse(&url)?);
+
+    // Construct Surf client with the in-memory manager
+    let client = Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions::default(),
+    }));
+
+    // Cold pass to load cache
+    client.send(req.clone()).await?;
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_some());
+
+    // Hot pass to make sure the expect response was returned
+    let mut res = client.send(req).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), HIT);
+    assert_eq!(res.body_bytes().await?, TEST_BODY);
+    Ok(())
+}
+
+#[async_std::test]
+async fn partial_content_not_stored() -> surf::Result<()> {
+    let mock_server = MockServer::start().await;
+    let m = Mock::given(method(GET))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("cache-control", CACHEABLE_PUBLIC)
+                .insert_header("content-range", "bytes 0-1/4")
+                .set_body_bytes(&TEST_BODY[..2]),
+        )
+        .expect(2);
+    let _mock_guard = mock_server.register_as_scoped(m).await;
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let req = Request::new(Method::Get, Url::parse(&url)?);
+
+    // Construct Surf client with cache defaults
+    let client = Client::new().with(Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: manager.clone(),
+        options: HttpCacheOptions::default(),
+    }));
+
+    // The partial response is passed through without being stored
+    let mut res = client.send(req.clone()).await?;
+    assert_eq!(res.status(), 206);
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+    assert_eq!(res.body_bytes().await?, &TEST_BODY[..2]);
+    let data = manager.get(GET, &Url::parse(&url)?).await?;
+    assert!(data.is_none());
+
+    // The second request goes to the origin again
+    let res = client.send(req).await?;
+    assert_eq!(res.header(XCACHE).unwrap(), MISS);
+    Ok(())
+}
+
+#[cfg(test)]
+mod redirect {
+    use super::*;
+    use surf::middleware::Redirect;
+    use wiremock::matchers::path;
+
+    async fn mock_redirect(mock_server: &MockServer, old: u64, new: u64) {
+        let location = format!("{}/new", &mock_server.uri());
+        Mock::given(method(GET))
+            .and(path("/old"))
+            .respond_with(
+                ResponseTemplate::new(301)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("location", location.as_str()),
+            )
+            .expect(old)
+            .mount(mock_server)
+            .await;
+        Mock::given(method(GET))
+            .and(path("/new"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(new)
+            .mount(mock_server)
+            .await;
+    }
+
+    fn build_cache(
+        manager: &MokaManager,
+        cache_redirects: bool,
+    ) -> Cache<MokaManager> {
+        Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions { cache_redirects, ..Default::default() },
+        })
+    }
+
+    #[async_std::test]
+    async fn cache_before_redirect() -> surf::Result<()> {
+        let mock_server = MockServer::start().await;
+        // Redirect probes both urls before sending the request on
+        mock_redirect(&mock_server, 1, 2).await;
+        let old = Url::parse(&format!("{}/old", &mock_server.uri()))?;
+        let new = Url::parse(&format!("{}/new", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = Client::new()
+            .with(build_cache(&manager, false))
+            .with(Redirect::default());
+
+        // Cold pass to load cache
+        client.send(Request::new(Method::Get, old.clone())).await?;
+
+        // The final response is stored under the requested url
+        assert!(manager.get(GET, &old).await?.is_some());
+        assert!(manager.get(GET, &new).await?.is_none());
+
+        // Hot pass skips the redirect entirely
+        let mut res = client.send(Request::new(Method::Get, old)).await?;
+        assert_eq!(res.header(XCACHE).unwrap(), HIT);
+        assert_eq!(res.body_bytes().await?, TEST_BODY);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn redirect_before_cache() -> surf::Result<()> {
+        let mock_server = MockServer::start().await;
+        // Redirect probes both urls on each pass, the cache only sees /new
+        mock_redirect(&mock_server, 2, 3).await;
+        let old = Url::parse(&format!("{}/old", &mock_server.uri()))?;
+        let new = Url::parse(&format!("{}/new", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = Client::new()
+            .with(Redirect::default())
+            .with(build_cache(&manager, false));
+
+        // Cold pass to load cache
+        client.send(Request::new(Method::Get, old.clone())).await?;
+
+        // The final response is stored under the final url
+        assert!(manager.get(GET, &old).await?.is_none());
+        assert!(manager.get(GET, &new).await?.is_some());
+
+        // Hot pass to make sure the final response was served from cache
+        let mut res = client.send(Request::new(Method::Get, old)).await?;
+        assert_eq!(res.header(XCACHE).unwrap(), HIT);
+        assert_eq!(res.body_bytes().await?, TEST_BODY);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn cache_redirects() -> surf::Result<()> {
+        let mock_server = MockServer::start().await;
+        mock_redirect(&mock_server, 1, 0).await;
+        let old = Url::parse(&format!("{}/old", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = Client::new().with(build_cache(&manager, true));
+
+        // Cold pass to load cache
+        let res = client.send(Request::new(Method::Get, old.clone())).await?;
+        assert_eq!(res.status(), 301);
+
+        // Try to load cached object
+        let data = manager.get(GET, &old).await?;
+        assert_eq!(data.unwrap().0.status, 301);
+
+        // Hot pass to make sure the redirect itself was served from cache
+        let res = client.send(Request::new(Method::Get, old)).await?;
+        assert_eq!(res.header(XCACHE).unwrap(), HIT);
+        assert_eq!(res.status(), 301);
+        assert!(res.header("location").is_some());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn redirects_not_cached_by_default() -> surf::Result<()> {
+        let mock_server = MockServer::start().await;
+        mock_redirect(&mock_server, 2, 0).await;
+        let old = Url::parse(&format!("{}/old", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = Client::new().with(build_cache(&manager, false));
+
+        // Both passes reach the origin
+        client.send(Request::new(Method::Get, old.clone())).await?;
+        assert!(manager.get(GET, &old).await?.is_none());
+        let res = client.send(Request::new(Method::Get, old)).await?;
+        assert_eq!(res.header(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+}
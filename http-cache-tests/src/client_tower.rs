@@ -0,0 +1,663 @@
+use crate::*;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use bytes::Bytes;
+use http1::{Request, Response};
+use http_body_util::{BodyExt, Full};
+use http_cache_tower::HttpCacheLayer;
+use tower::{service_fn, Layer, Service, ServiceExt};
+
+type Body = Full<Bytes>;
+
+// Builds a mock origin answering every request with the same response,
+// along with the count of requests it received
+fn build_origin(
+    cache_control: &'static str,
+) -> (
+    impl Service<
+            Request<Body>,
+            Response = Response<Body>,
+            Error = std::convert::Infallible,
+            Future = impl Send,
+        > + Clone
+        + Send
+        + 'static,
+    Arc<AtomicUsize>,
+) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let service = service_fn(move |req: Request<Body>| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        // The mode override never reaches the origin
+        assert!(req.extensions().get::<CacheMode>().is_none());
+        async move {
+            Ok(Response::builder()
+                .header("cache-control", cache_control)
+                .body(Full::new(Bytes::from_static(TEST_BODY)))
+                .unwrap())
+        }
+    });
+    (service, calls)
+}
+
+fn build_layer(
+    mode: CacheMode,
+    manager: &MokaManager,
+) -> HttpCacheLayer<MokaManager> {
+    HttpCacheLayer::new(HttpCache {
+        mode,
+        manager: manager.clone(),
+        options: HttpCacheOptions::default(),
+    })
+}
+
+fn get(url: &str) -> anyhow::Result<Request<Body>> {
+    Ok(Request::get(url).body(Full::default())?)
+}
+
+#[tokio::test]
+async fn default_mode() -> anyhow::Result<()> {
+    let url = "http://example.com/";
+    let manager = MokaManager::default();
+    let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+    let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+    // Cold pass to load cache
+    let res = service.clone().oneshot(get(url)?).await?;
+    assert_eq!(res.headers()[XCACHE], MISS);
+
+    // Try to load cached object
+    let data = manager.get(GET, &Url::parse(url)?).await?;
+    assert!(data.is_some());
+
+    // Hot pass to make sure the expect response was returned
+    let res = service.oneshot(get(url)?).await?;
+    assert_eq!(res.headers()[XCACHE], HIT);
+    assert_eq!(res.into_body().collect().await?.to_bytes(), TEST_BODY);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn no_store_mode() -> anyhow::Result<()> {
+    let url = "http://example.com/";
+    let manager = MokaManager::default();
+    let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+    let service = build_layer(CacheMode::NoStore, &manager).layer(origin);
+
+    for _ in 0..2 {
+        let res = service.clone().oneshot(get(url)?).await?;
+        assert_eq!(res.headers()[XCACHE], MISS);
+        assert_eq!(res.into_body().collect().await?.to_bytes(), TEST_BODY);
+    }
+
+    // Make sure nothing was stored
+    let data = manager.get(GET, &Url::parse(url)?).await?;
+    assert!(data.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn no_cache_mode() -> anyhow::Result<()> {
+    let url = "http://example.com/";
+    let manager = MokaManager::default();
+    let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+    let service = build_layer(CacheMode::NoCache, &manager).layer(origin);
+
+    // Cold pass to load cache
+    service.clone().oneshot(get(url)?).await?;
+    let data = manager.get(GET, &Url::parse(url)?).await?;
+    assert!(data.is_some());
+
+    // The stored response is always revalidated with the origin
+    let res = service.oneshot(get(url)?).await?;
+    assert_eq!(res.headers()[XCACHELOOKUP], HIT);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn must_revalidate() -> anyhow::Result<()> {
+    let url = "http://example.com/";
+    let manager = MokaManager::default();
+    let (origin, calls) = build_origin(MUST_REVALIDATE);
+    let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+    // Cold pass to load cache
+    service.clone().oneshot(get(url)?).await?;
+
+    // The stale response is fetched again
+    let res = service.oneshot(get(url)?).await?;
+    assert_eq!(res.headers()[XCACHELOOKUP], HIT);
+    assert_eq!(res.headers()[XCACHE], MISS);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_after_non_get_head_method_request() -> anyhow::Result<()> {
+    let url = "http://example.com/";
+    let manager = MokaManager::default();
+    let (origin, _) = build_origin(CACHEABLE_PUBLIC);
+    let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+    // Cold pass to load cache
+    service.clone().oneshot(get(url)?).await?;
+    let data = manager.get(GET, &Url::parse(url)?).await?;
+    assert!(data.is_some());
+
+    // Post request to make sure the cache object at the same resource was deleted
+    let req = Request::post(url).body(Full::new(Bytes::from_static(b"x")))?;
+    service.oneshot(req).await?;
+    let data = manager.get(GET, &Url::parse(url)?).await?;
+    assert!(data.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn relative_uri() -> anyhow::Result<()> {
+    let manager = MokaManager::default();
+    let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+    let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+    // Without an absolute url there's nothing to key by
+    for _ in 0..2 {
+        let res = service.clone().oneshot(get("/")?).await?;
+        assert_eq!(res.headers()[XCACHE], MISS);
+        assert_eq!(res.into_body().collect().await?.to_bytes(), TEST_BODY);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[cfg(test)]
+mod overridden_cache_mode {
+    use super::*;
+
+    #[tokio::test]
+    async fn reload() -> anyhow::Result<()> {
+        let url = "http://example.com/";
+        let manager = MokaManager::default();
+        let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+        let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+        // Cold pass to load cache
+        service.clone().oneshot(get(url)?).await?;
+
+        // The override skips the stored response
+        let mut req = get(url)?;
+        req.extensions_mut().insert(CacheMode::Reload);
+        let res = service.oneshot(req).await?;
+        assert_eq!(res.headers()[XCACHE], MISS);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_if_cached_miss() -> anyhow::Result<()> {
+        let url = "http://example.com/";
+        let manager = MokaManager::default();
+        let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+        let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+        let mut req = get(url)?;
+        req.extensions_mut().insert(CacheMode::OnlyIfCached);
+        let res = service.oneshot(req).await?;
+        assert_eq!(res.status(), 504);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod head_with_body {
+    use super::*;
+
+    #[tokio::test]
+    async fn body_is_dropped() -> anyhow::Result<()> {
+        let url = "http://example.com/";
+        let manager = MokaManager::default();
+        // The mock origin sends its body for HEAD requests too
+        let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+        let service = build_layer(CacheMode::Default, &manager).layer(origin);
+        let head = || Request::head(url).body(Full::default());
+
+        // Cold pass to load cache
+        let res = service.clone().oneshot(head()?).await?;
+        assert!(res.into_body().collect().await?.to_bytes().is_empty());
+
+        // Make sure the stored response has no body
+        let data = manager.get("HEAD", &Url::parse(url)?).await?;
+        assert!(data.unwrap().0.body.is_empty());
+
+        // Hot pass to make sure the replayed response has no body
+        let res = service.clone().oneshot(head()?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.into_body().collect().await?.to_bytes().is_empty());
+
+        // A GET is stored separately and keeps its body
+        service.clone().oneshot(get(url)?).await?;
+        let res = service.oneshot(get(url)?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert_eq!(res.into_body().collect().await?.to_bytes(), TEST_BODY);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stored_body_is_not_replayed() -> anyhow::Result<()> {
+        let url = Url::parse("http://example.com/")?;
+        let manager = MokaManager::default();
+        let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+        let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+        // An entry stored with a body, as older versions did
+        let req = http::Request::head(url.as_str()).body(())?;
+        let res = http::Response::builder()
+            .status(200)
+            .header("cache-control", CACHEABLE_PUBLIC)
+            .body(TEST_BODY.to_vec())?;
+        let policy = http_cache_semantics::CachePolicy::new(&req, &res);
+        let stored = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers: HashMap::from([(
+                "cache-control".to_string(),
+                CACHEABLE_PUBLIC.to_string(),
+            )]),
+            trailers: HashMap::new(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        manager.put("HEAD", &url, stored, policy).await?;
+
+        let req = Request::head(url.as_str()).body(Full::default())?;
+        let res = service.oneshot(req).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.into_body().collect().await?.to_bytes().is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod stored_headers {
+    use super::*;
+
+    // Builds a mock origin answering with the extra headers
+    fn build_header_origin(
+        extra: &'static [(&'static str, &'static str)],
+    ) -> impl Service<
+        Request<Body>,
+        Response = Response<Body>,
+        Error = std::convert::Infallible,
+        Future = impl Send,
+    > + Clone
+           + Send
+           + 'static {
+        service_fn(move |_: Request<Body>| async move {
+            let mut res = Response::builder()
+                .header("cache-control", CACHEABLE_PUBLIC)
+                .header("content-type", "text/plain")
+                .header("etag", "\"v1\"");
+            for (name, value) in extra {
+                res = res.header(*name, *value);
+            }
+            Ok(res.body(Full::new(Bytes::from_static(TEST_BODY))).unwrap())
+        })
+    }
+
+    fn build_options_layer(
+        options: HttpCacheOptions,
+        manager: &MokaManager,
+    ) -> HttpCacheLayer<MokaManager> {
+        HttpCacheLayer::new(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options,
+        })
+    }
+
+    #[tokio::test]
+    async fn denied_headers_are_not_replayed() -> anyhow::Result<()> {
+        let url = "http://example.com/";
+        let manager = MokaManager::default();
+        let origin = build_header_origin(&[
+            ("set-cookie", "session=abc"),
+            ("x-debug", "node-7"),
+        ]);
+        let options = HttpCacheOptions {
+            store_header_denylist: vec![
+                "Set-Cookie".to_string(),
+                "X-Debug".to_string(),
+            ],
+            ..Default::default()
+        };
+        let service = build_options_layer(options, &manager).layer(origin);
+
+        // The response from the origin keeps every header
+        let res = service.clone().oneshot(get(url)?).await?;
+        assert_eq!(res.headers()["set-cookie"], "session=abc");
+        assert_eq!(res.headers()["x-debug"], "node-7");
+
+        // Make sure the denied headers weren't stored
+        let (stored, _) = manager.get(GET, &Url::parse(url)?).await?.unwrap();
+        assert!(!stored.headers.contains_key("set-cookie"));
+        assert!(!stored.headers.contains_key("x-debug"));
+
+        // Hot pass to make sure they aren't replayed either
+        let res = service.oneshot(get(url)?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.headers().get("set-cookie").is_none());
+        assert!(res.headers().get("x-debug").is_none());
+        assert_eq!(res.headers()["content-type"], "text/plain");
+        assert_eq!(res.into_body().collect().await?.to_bytes(), TEST_BODY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn allowlist_keeps_required_headers() -> anyhow::Result<()> {
+        let url = "http://example.com/";
+        let manager = MokaManager::default();
+        let origin =
+            build_header_origin(&[("x-kept", "1"), ("x-dropped", "1")]);
+        let options = HttpCacheOptions {
+            store_header_allowlist: Some(vec!["X-Kept".to_string()]),
+            ..Default::default()
+        };
+        let service = build_options_layer(options, &manager).layer(origin);
+
+        // Cold pass to load cache
+        service.clone().oneshot(get(url)?).await?;
+
+        // Only the listed headers and those the cache relies on are stored
+        let (stored, _) = manager.get(GET, &Url::parse(url)?).await?.unwrap();
+        assert!(stored.headers.contains_key("x-kept"));
+        assert!(!stored.headers.contains_key("x-dropped"));
+        assert_eq!(stored.headers["content-type"], "text/plain");
+        assert_eq!(stored.headers["etag"], "\"v1\"");
+        assert_eq!(stored.headers["cache-control"], CACHEABLE_PUBLIC);
+
+        let res = service.oneshot(get(url)?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.headers().get("x-dropped").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hop_by_hop_headers_are_stripped() -> anyhow::Result<()> {
+        let url = "http://example.com/";
+        let manager = MokaManager::default();
+        let origin = build_header_origin(&[
+            ("connection", "keep-alive, x-internal"),
+            ("keep-alive", "timeout=5"),
+            ("x-internal", "1"),
+            ("x-public", "1"),
+        ]);
+        let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+        // Cold pass to load cache
+        service.clone().oneshot(get(url)?).await?;
+
+        // The hop-by-hop headers, and those named by `Connection`, are
+        // stripped even without any lists set
+        let (stored, _) = manager.get(GET, &Url::parse(url)?).await?.unwrap();
+        assert!(!stored.headers.contains_key("connection"));
+        assert!(!stored.headers.contains_key("keep-alive"));
+        assert!(!stored.headers.contains_key("x-internal"));
+        assert!(stored.headers.contains_key("x-public"));
+
+        let res = service.oneshot(get(url)?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.headers().get("x-internal").is_none());
+        assert!(res.headers().get("keep-alive").is_none());
+        assert_eq!(res.headers()["x-public"], "1");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod hop_by_hop {
+    use super::*;
+
+    #[tokio::test]
+    async fn stripped_from_stored_entries_on_replay() -> anyhow::Result<()> {
+        let url = Url::parse("http://example.com/")?;
+        let manager = MokaManager::default();
+        let (origin, calls) = build_origin(CACHEABLE_PUBLIC);
+        let service =
+            build_layer(CacheMode::ForceCache, &manager).layer(origin);
+
+        // An entry stored with its connection headers, as older versions did
+        let headers = HashMap::from([
+            ("cache-control".to_string(), CACHEABLE_PUBLIC.to_string()),
+            ("connection".to_string(), "keep-alive, x-trace".to_string()),
+            ("keep-alive".to_string(), "timeout=5".to_string()),
+            ("x-trace".to_string(), "abc".to_string()),
+        ]);
+        let req = http::Request::get(url.as_str()).body(())?;
+        let mut res = http::Response::builder().status(200);
+        for (name, value) in &headers {
+            res = res.header(name.as_str(), value.as_str());
+        }
+        let policy =
+            http_cache_semantics::CachePolicy::new(&req, &res.body(())?);
+        let stored = HttpResponse {
+            body: Bytes::from_static(TEST_BODY),
+            headers,
+            trailers: HashMap::new(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        };
+        manager.put(GET, &url, stored, policy).await?;
+
+        let res = service.oneshot(get(url.as_str())?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.headers().get("connection").is_none());
+        assert!(res.headers().get("keep-alive").is_none());
+        assert!(res.headers().get("x-trace").is_none());
+        assert_eq!(res.into_body().collect().await?.to_bytes(), TEST_BODY);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod server {
+    use super::*;
+
+    use http_cache_tower::server::ServerCacheLayer;
+
+    fn build_server_layer(
+        manager: &MokaManager,
+    ) -> ServerCacheLayer<MokaManager> {
+        ServerCacheLayer::new(HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        })
+    }
+
+    // A request as a server receives it, with just a path and a `Host`
+    fn incoming(method: &str) -> anyhow::Result<Request<Body>> {
+        Ok(Request::builder()
+            .method(method)
+            .uri("/items?page=1")
+            .header("host", "example.com")
+            .body(Full::default())?)
+    }
+
+    const KEY_URL: &str = "http://example.com/items?page=1";
+
+    #[tokio::test]
+    async fn fresh_response_is_served_with_age() -> anyhow::Result<()> {
+        let manager = MokaManager::default();
+        let (handler, calls) = build_origin("max-age=60");
+        let service = build_server_layer(&manager).layer(handler);
+
+        // Cold pass to load cache
+        let res = service.clone().oneshot(incoming("GET")?).await?;
+        assert_eq!(res.headers()[XCACHE], MISS);
+
+        // The response is keyed by the host and path
+        let data = manager.get(GET, &Url::parse(KEY_URL)?).await?;
+        assert!(data.is_some());
+
+        // Hot pass to make sure the handler isn't called again
+        let res = service.oneshot(incoming("GET")?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        assert!(res.headers().contains_key("age"));
+        assert_eq!(res.into_body().collect().await?.to_bytes(), TEST_BODY);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn private_response_is_not_stored() -> anyhow::Result<()> {
+        let manager = MokaManager::default();
+        let (handler, calls) = build_origin(CACHEABLE_PRIVATE);
+        let service = build_server_layer(&manager).layer(handler);
+
+        // The cache is shared, so private responses always reach the handler
+        for _ in 0..2 {
+            let res = service.clone().oneshot(incoming("GET")?).await?;
+            assert_eq!(res.headers()[XCACHE], MISS);
+        }
+        let data = manager.get(GET, &Url::parse(KEY_URL)?).await?;
+        assert!(data.is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authorized_request_is_not_stored() -> anyhow::Result<()> {
+        let manager = MokaManager::default();
+        let (handler, calls) = build_origin("max-age=60");
+        let service = build_server_layer(&manager).layer(handler);
+
+        let mut req = incoming("GET")?;
+        req.headers_mut().insert("authorization", "Bearer abc".parse()?);
+        service.clone().oneshot(req).await?;
+        let data = manager.get(GET, &Url::parse(KEY_URL)?).await?;
+        assert!(data.is_none());
+
+        // Unless the response explicitly allows it
+        let (handler, _) = build_origin(CACHEABLE_PUBLIC);
+        let service = build_server_layer(&manager).layer(handler);
+        let mut req = incoming("GET")?;
+        req.headers_mut().insert("authorization", "Bearer abc".parse()?);
+        service.oneshot(req).await?;
+        let data = manager.get(GET, &Url::parse(KEY_URL)?).await?;
+        assert!(data.is_some());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unsafe_method_invalidates() -> anyhow::Result<()> {
+        let manager = MokaManager::default();
+        let (handler, calls) = build_origin("max-age=60");
+        let service = build_server_layer(&manager).layer(handler);
+
+        // Cold pass to load cache
+        service.clone().oneshot(incoming("GET")?).await?;
+        let data = manager.get(GET, &Url::parse(KEY_URL)?).await?;
+        assert!(data.is_some());
+
+        // The post reaches the handler and removes the stored response
+        let res = service.clone().oneshot(incoming("POST")?).await?;
+        assert_eq!(res.headers()[XCACHE], MISS);
+        let data = manager.get(GET, &Url::parse(KEY_URL)?).await?;
+        assert!(data.is_none());
+
+        let res = service.oneshot(incoming("GET")?).await?;
+        assert_eq!(res.headers()[XCACHE], MISS);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_host_passes_through() -> anyhow::Result<()> {
+        let manager = MokaManager::default();
+        let (handler, calls) = build_origin("max-age=60");
+        let service = build_server_layer(&manager).layer(handler);
+
+        // Without a host there's nothing to key by
+        for _ in 0..2 {
+            let res = service.clone().oneshot(get("/items")?).await?;
+            assert_eq!(res.headers()[XCACHE], MISS);
+            assert_eq!(res.into_body().collect().await?.to_bytes(), TEST_BODY);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod trailers {
+    use super::*;
+
+    use http1::HeaderMap;
+
+    #[tokio::test]
+    async fn replayed_on_hit() -> anyhow::Result<()> {
+        let url = "http://example.com/";
+        let manager = MokaManager::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let origin = service_fn(move |_: Request<Body>| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", "0".parse().unwrap());
+                let body = Full::new(Bytes::from_static(TEST_BODY))
+                    .with_trailers(async move { Some(Ok(trailers)) });
+                Ok::<_, std::convert::Infallible>(
+                    Response::builder()
+                        .header("cache-control", CACHEABLE_PUBLIC)
+                        .body(body)
+                        .unwrap(),
+                )
+            }
+        });
+        let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+        // Cold pass to load cache, the trailers reach the caller
+        let res = service.clone().oneshot(get(url)?).await?;
+        let collected = res.into_body().collect().await?;
+        assert_eq!(collected.trailers().unwrap()["grpc-status"], "0");
+
+        // Make sure they were stored alongside the body
+        let (stored, _) = manager.get(GET, &Url::parse(url)?).await?.unwrap();
+        assert_eq!(stored.trailers["grpc-status"], "0");
+
+        // Hot pass to make sure they're replayed after the body
+        let res = service.oneshot(get(url)?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        let collected = res.into_body().collect().await?;
+        assert_eq!(collected.trailers().unwrap()["grpc-status"], "0");
+        assert_eq!(collected.to_bytes(), TEST_BODY);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn absent_without_trailers() -> anyhow::Result<()> {
+        let url = "http://example.com/";
+        let manager = MokaManager::default();
+        let (origin, _) = build_origin(CACHEABLE_PUBLIC);
+        let service = build_layer(CacheMode::Default, &manager).layer(origin);
+
+        service.clone().oneshot(get(url)?).await?;
+        let res = service.oneshot(get(url)?).await?;
+        assert_eq!(res.headers()[XCACHE], HIT);
+        let collected = res.into_body().collect().await?;
+        assert!(collected.trailers().is_none());
+        Ok(())
+    }
+}
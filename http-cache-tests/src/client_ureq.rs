@@ -0,0 +1,403 @@
+use crate::*;
+
+use async_std::task::block_on;
+use http_cache_ureq::Cache;
+
+fn build_client(mode: CacheMode, manager: &MokaManager) -> Cache<MokaManager> {
+    Cache::new(
+        ureq::Agent::new(),
+        HttpCache {
+            mode,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        },
+    )
+}
+
+#[test]
+fn default_mode() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+
+    // Try to load cached object
+    let data = block_on(manager.get(GET, &Url::parse(&url)?))?;
+    assert!(data.is_some());
+
+    // Hot pass to make sure the expect response was returned
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.body(), TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn no_store_mode() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::NoStore, &manager);
+
+    // Remote request but should not cache
+    client.get(&url)?;
+
+    // Try to load cached object
+    let data = block_on(manager.get(GET, &Url::parse(&url)?))?;
+    assert!(data.is_none());
+
+    // To verify our endpoint receives the request rather than a cache hit
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    assert_eq!(res.body(), TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn revalidation_304() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+    let m_304 = Mock::given(method(GET))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(1);
+    let mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get(&url)?;
+
+    drop(mock_guard);
+
+    let _mock_guard = block_on(mock_server.register_as_scoped(m_304));
+
+    // Hot pass to make sure revalidation request was sent
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.body(), TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn delete_after_non_get_head_method_request() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m_get = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 1);
+    let m_post = Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(201).set_body_bytes("created"))
+        .expect(1);
+    let _mock_guard_get = block_on(mock_server.register_as_scoped(m_get));
+    let _mock_guard_post = block_on(mock_server.register_as_scoped(m_post));
+    let url = Url::parse(&format!("{}/", &mock_server.uri()))?;
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get(url.as_str())?;
+    assert!(block_on(manager.get(GET, &url))?.is_some());
+
+    // Post request to make sure the cache object at the same resource was deleted
+    client.request("POST", url.as_str())?;
+    assert!(block_on(manager.get(GET, &url))?.is_none());
+    Ok(())
+}
+
+#[test]
+fn error_status_is_a_response() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 404, 1);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // The status is returned rather than a ureq error
+    let res = client.get(&url)?;
+    assert_eq!(res.status(), 404);
+    assert_eq!(res.body(), TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn force_cache_mode() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(MUST_REVALIDATE, TEST_BODY, 200, 1);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::ForceCache, &manager);
+
+    // Cold pass to load cache
+    client.get(&url)?;
+
+    // The stale response is served without revalidating
+    let res = client.get(&url)?;
+    assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), HIT);
+    assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+    assert_eq!(res.body(), TEST_BODY);
+    Ok(())
+}
+
+#[test]
+fn overridden_cache_mode() -> anyhow::Result<()> {
+    let mock_server = block_on(MockServer::start());
+    let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 2);
+    let _mock_guard = block_on(mock_server.register_as_scoped(m));
+    let url = format!("{}/", &mock_server.uri());
+    let manager = MokaManager::default();
+    let client = build_client(CacheMode::Default, &manager);
+
+    // Cold pass to load cache
+    client.get(&url)?;
+
+    // The override skips the stored response
+    let mut req = http::Request::get(&url).body(Vec::new())?;
+    req.extensions_mut().insert(CacheMode::Reload);
+    let res = client.execute(req)?;
+    assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+    Ok(())
+}
+
+#[cfg(test)]
+mod only_if_cached_mode {
+    use super::*;
+
+    #[test]
+    fn miss() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_mock(CACHEABLE_PUBLIC, TEST_BODY, 200, 0);
+        let _mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::OnlyIfCached, &manager);
+
+        // Should result in a cache miss and no remote request
+        let res = client.get(&url)?;
+        assert_eq!(res.status(), 504);
+        assert_eq!(res.headers().get(XCACHELOOKUP).unwrap(), MISS);
+        assert_eq!(res.headers().get(XCACHE).unwrap(), MISS);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod revalidate {
+    use super::*;
+    use wiremock::matchers::header;
+
+    fn build_etag_mock(body: &[u8], expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_bytes(body),
+            )
+            .expect(expect)
+    }
+
+    #[test]
+    fn not_modified() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_etag_mock(TEST_BODY, 1);
+        let m_304 = Mock::given(method(GET))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(
+                ResponseTemplate::new(304)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .insert_header("etag", "\"v1\""),
+            )
+            .expect(1);
+        let mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+
+        // Cold pass to load cache
+        client.get(&url)?;
+
+        drop(mock_guard);
+
+        let _mock_guard = block_on(mock_server.register_as_scoped(m_304));
+
+        // The conditional request is sent even though the response is fresh
+        let status = client.revalidate(GET, &url)?;
+        assert_eq!(status, CacheLookupStatus::NotModified);
+
+        // Hot pass to make sure the stored response is still served
+        let res = client.get(&url)?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.body(), TEST_BODY);
+        Ok(())
+    }
+
+    #[test]
+    fn modified() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_etag_mock(TEST_BODY, 1);
+        let m_200 = build_etag_mock(b"updated", 1);
+        let mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+
+        // Cold pass to load cache
+        client.get(&url)?;
+
+        drop(mock_guard);
+
+        let _mock_guard = block_on(mock_server.register_as_scoped(m_200));
+
+        // The origin sends a new response which replaces the stored one
+        let status = client.revalidate(GET, &url)?;
+        assert_eq!(status, CacheLookupStatus::Modified);
+
+        // Hot pass to make sure the new response was stored
+        let res = client.get(&url)?;
+        assert_eq!(res.headers().get(XCACHE).unwrap(), HIT);
+        assert_eq!(res.body(), b"updated");
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_stored() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_etag_mock(TEST_BODY, 1);
+        let _mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = format!("{}/", &mock_server.uri());
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+
+        let status = client.revalidate(GET, &url)?;
+        assert_eq!(status, CacheLookupStatus::Fetched);
+
+        // Try to load cached object
+        let data = block_on(manager.get(GET, &Url::parse(&url)?))?;
+        assert!(data.is_some());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod refresh {
+    use super::*;
+    use wiremock::matchers::{header, path};
+
+    // A response with a tenth of its ten second lifetime left
+    fn build_expiring_mock(route: &str, expect: u64) -> Mock {
+        Mock::given(method(GET))
+            .and(path(route))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", "public, max-age=10")
+                    .insert_header("age", "9")
+                    .insert_header("etag", "\"v1\"")
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(expect)
+    }
+
+    fn build_cache(manager: &MokaManager) -> HttpCache<MokaManager> {
+        HttpCache {
+            mode: CacheMode::Default,
+            manager: manager.clone(),
+            options: HttpCacheOptions::default(),
+        }
+    }
+
+    #[test]
+    fn refreshes_only_expiring() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let expiring = build_expiring_mock("/expiring", 1);
+        let fresh = Mock::given(method(GET))
+            .and(path("/fresh"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", CACHEABLE_PUBLIC)
+                    .set_body_bytes(TEST_BODY),
+            )
+            .expect(1);
+        let m_304 = Mock::given(method(GET))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(
+                ResponseTemplate::new(304)
+                    .insert_header("cache-control", "public, max-age=10")
+                    .insert_header("age", "0")
+                    .insert_header("etag", "\"v1\""),
+            )
+            .expect(1);
+        let mock_guard = block_on(mock_server.register_as_scoped(expiring));
+        let _fresh_guard = block_on(mock_server.register_as_scoped(fresh));
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+        let urls = ["expiring", "fresh", "missing"]
+            .iter()
+            .map(|p| Url::parse(&format!("{}/{}", &mock_server.uri(), p)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        client.get(urls[0].as_str())?;
+        client.get(urls[1].as_str())?;
+        drop(mock_guard);
+        let _mock_guard = block_on(mock_server.register_as_scoped(m_304));
+
+        let refresher = Refresher::new(urls.clone());
+        let cache = build_cache(&manager);
+        let client = &client;
+        let results =
+            block_on(refresher.refresh(&cache, move |url| async move {
+                client.revalidate(GET, url.as_str())
+            }));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, urls[0]);
+        assert_eq!(
+            results[0].1.as_ref().unwrap(),
+            &CacheLookupStatus::NotModified
+        );
+
+        // The refreshed response has its whole lifetime ahead again
+        assert!(!block_on(cache.is_expiring(GET, &urls[0], 0.1))?);
+        Ok(())
+    }
+
+    #[test]
+    fn failure_backs_off_and_keeps_entry() -> anyhow::Result<()> {
+        let mock_server = block_on(MockServer::start());
+        let m = build_expiring_mock("/", 1);
+        let m_500 = Mock::given(method(GET))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1);
+        let mock_guard = block_on(mock_server.register_as_scoped(m));
+        let url = Url::parse(&format!("{}/", &mock_server.uri()))?;
+        let manager = MokaManager::default();
+        let client = build_client(CacheMode::Default, &manager);
+        client.get(url.as_str())?;
+        drop(mock_guard);
+        let _mock_guard = block_on(mock_server.register_as_scoped(m_500));
+
+        let refresher = Refresher::new(vec![url.clone()]);
+        let cache = build_cache(&manager);
+        let client = &client;
+        let revalidate =
+            move |url: Url| async move { client.revalidate(GET, url.as_str()) };
+        let results = block_on(refresher.refresh(&cache, revalidate));
+        assert_eq!(results[0].1.as_ref().unwrap(), &CacheLookupStatus::Failed);
+        assert!(block_on(manager.get(GET, &url))?.is_some());
+
+        // Backed off, so the origin isn't asked again
+        let results = block_on(refresher.refresh(&cache, revalidate));
+        assert!(results.is_empty());
+        let res = client.get(url.as_str())?;
+        assert_eq!(res.body(), TEST_BODY);
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Middleware answering from a queue of canned responses, implemented both
+//! through the boxed [`Middleware`] and the [`UnboxedMiddleware`] trait so
+//! the two can be compared without any client or network in the way.
+use bytes::Bytes;
+use http::request;
+use http_cache::{
+    now, CacheMode, CacheOptions, HttpResponse, HttpVersion, Middleware,
+    Result, UnboxedMiddleware,
+};
+use http_cache_semantics::CachePolicy;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+use url::Url;
+
+/// The responses still to be served and the number of fetches so far
+#[derive(Debug, Clone, Default)]
+pub struct Origin {
+    responses: Arc<Mutex<VecDeque<HttpResponse>>>,
+    fetches: Arc<Mutex<usize>>,
+}
+
+impl Origin {
+    /// Queues a `200` response with the `cache-control` value and body
+    pub fn push(&self, url: &Url, cache_control: &str, body: &[u8]) {
+        let mut headers = HashMap::new();
+        headers.insert("cache-control".to_string(), cache_control.to_string());
+        self.responses.lock().unwrap().push_back(HttpResponse {
+            body: Bytes::copy_from_slice(body),
+            headers,
+            trailers: HashMap::new(),
+            status: 200,
+            url: url.clone(),
+            version: HttpVersion::Http11,
+        });
+    }
+
+    /// The number of times the origin was fetched from
+    pub fn fetches(&self) -> usize {
+        *self.fetches.lock().unwrap()
+    }
+
+    fn fetch(&self) -> HttpResponse {
+        *self.fetches.lock().unwrap() += 1;
+        self.responses.lock().unwrap().pop_front().expect("no response queued")
+    }
+}
+
+/// A GET request for the url, answered by the origin
+#[derive(Debug)]
+pub struct FakeRequest {
+    pub url: Url,
+    pub mode: Option<CacheMode>,
+    pub origin: Origin,
+    parts: request::Parts,
+}
+
+impl FakeRequest {
+    pub fn new(url: &Url, mode: Option<CacheMode>, origin: &Origin) -> Self {
+        let parts = request::Builder::new()
+            .uri(url.as_str())
+            .body(())
+            .expect("valid request")
+            .into_parts()
+            .0;
+        Self { url: url.clone(), mode, origin: origin.clone(), parts }
+    }
+
+    fn parts(&self) -> request::Parts {
+        let mut parts = request::Builder::new()
+            .method(self.parts.method.clone())
+            .uri(self.parts.uri.clone())
+            .body(())
+            .expect("valid request")
+            .into_parts()
+            .0;
+        parts.headers = self.parts.headers.clone();
+        parts
+    }
+}
+
+/// Fetches through the boxed [`Middleware`] trait
+#[derive(Debug)]
+pub struct Boxed(pub FakeRequest);
+
+/// Fetches through the [`UnboxedMiddleware`] trait
+#[derive(Debug)]
+pub struct Unboxed(pub FakeRequest);
+
+// The methods both traits have in common
+macro_rules! request_methods {
+    () => {
+        fn is_method_get_head(&self) -> bool {
+            true
+        }
+        fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+            Ok(CachePolicy::new_options(
+                &self.0.parts(),
+                &response.parts()?,
+                now(),
+                CacheOptions::default(),
+            ))
+        }
+        fn policy_with_options(
+            &self,
+            response: &HttpResponse,
+            options: CacheOptions,
+        ) -> Result<CachePolicy> {
+            Ok(CachePolicy::new_options(
+                &self.0.parts(),
+                &response.parts()?,
+                now(),
+                options,
+            ))
+        }
+        fn update_headers(&mut self, parts: &request::Parts) -> Result<()> {
+            for (name, value) in parts.headers.iter() {
+                self.0.parts.headers.insert(name.clone(), value.clone());
+            }
+            Ok(())
+        }
+        fn force_no_cache(&mut self) -> Result<()> {
+            self.0.parts.headers.insert(
+                http::header::CACHE_CONTROL,
+                http::HeaderValue::from_static("no-cache"),
+            );
+            Ok(())
+        }
+        fn parts(&self) -> Result<request::Parts> {
+            Ok(self.0.parts())
+        }
+        fn url(&self) -> Result<Url> {
+            Ok(self.0.url.clone())
+        }
+        fn method(&self) -> Result<String> {
+            Ok("GET".to_string())
+        }
+        fn overridden_cache_mode(&self) -> Option<CacheMode> {
+            self.0.mode
+        }
+    };
+}
+
+#[async_trait::async_trait]
+impl Middleware for Boxed {
+    request_methods!();
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        Ok(self.0.origin.fetch())
+    }
+}
+
+impl UnboxedMiddleware for Unboxed {
+    request_methods!();
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        Ok(self.0.origin.fetch())
+    }
+}
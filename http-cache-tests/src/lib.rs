@@ -1,15 +1,50 @@
 #![allow(unused_imports, dead_code)]
 
+#[cfg(test)]
+mod client_awc;
+
+#[cfg(test)]
+mod client_curl;
+
+#[cfg(test)]
+mod client_http_client;
+
+#[cfg(test)]
+mod client_isahc;
+
 #[cfg(test)]
 mod client_surf;
 
 #[cfg(test)]
 mod client_reqwest;
 
+#[cfg(test)]
+mod client_reqwest_blocking;
+
+#[cfg(test)]
+mod client_tower;
+
+#[cfg(test)]
+mod client_ureq;
+
+#[cfg(test)]
+mod unboxed_middleware;
+
+pub mod fake;
+
+use bytes::Bytes;
 use http::{header::CACHE_CONTROL, StatusCode};
 use http_cache::*;
 use http_types::{headers::HeaderValue, Method, Version};
-use std::{collections::HashMap, convert::TryInto, str::FromStr};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    io::{Read, Write},
+    net::TcpListener,
+    str::FromStr,
+    thread,
+    time::Duration,
+};
 use url::Url;
 use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
 
@@ -28,6 +63,38 @@ pub(crate) fn build_mock(
         .expect(expect)
 }
 
+// Runs the background work of a cache on async-std
+pub(crate) struct AsyncStd;
+
+impl Spawner for AsyncStd {
+    fn spawn(&self, task: futures::future::BoxFuture<'static, ()>) {
+        async_std::task::spawn(task);
+    }
+    fn sleep(
+        &self,
+        duration: Duration,
+    ) -> futures::future::BoxFuture<'static, ()> {
+        Box::pin(async_std::task::sleep(duration))
+    }
+}
+
+// Answers a single request with the raw response, returning the url
+pub(crate) fn serve_raw(response: &'static [u8]) -> anyhow::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut req = Vec::new();
+        let mut buf = [0; 1024];
+        while !req.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            req.extend_from_slice(&buf[..n]);
+        }
+        stream.write_all(response).unwrap();
+    });
+    Ok(url)
+}
+
 const GET: &str = "GET";
 
 const TEST_BODY: &[u8] = b"test";
@@ -50,8 +117,9 @@ mod http_cache_tests {
     fn response_methods_work() -> anyhow::Result<()> {
         let url = Url::from_str("http://example.com")?;
         let mut res = HttpResponse {
-            body: TEST_BODY.to_vec(),
+            body: Bytes::from_static(TEST_BODY),
             headers: HashMap::default(),
+            trailers: HashMap::new(),
             status: 200,
             url: url.clone(),
             version: HttpVersion::Http11,
@@ -135,6 +203,27 @@ mod http_cache_tests {
         Ok(())
     }
 
+    #[test]
+    fn hashed_cache_keys() -> anyhow::Result<()> {
+        let options =
+            HttpCacheOptions { hash_keys: true, ..Default::default() };
+        let long = format!("http://example.com/?q={}", "a".repeat(4096));
+        let key = options.cache_key(GET, &Url::parse(&long)?);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, options.cache_key(GET, &Url::parse(&long)?));
+        assert_ne!(
+            key,
+            options.cache_key(GET, &Url::parse(&format!("{}b", long))?)
+        );
+        assert_ne!(key, options.cache_key("HEAD", &Url::parse(&long)?));
+        let readable = HttpCacheOptions::default();
+        assert_eq!(
+            readable.cache_key(GET, &Url::parse("http://example.com/")?),
+            "GET:http://example.com/"
+        );
+        Ok(())
+    }
+
     #[cfg(test)]
     mod managers {
         use crate::*;
@@ -146,8 +235,9 @@ mod http_cache_tests {
             let url = Url::parse("http://example.com")?;
             let manager = CACacheManager::default();
             let http_res = HttpResponse {
-                body: TEST_BODY.to_vec(),
+                body: Bytes::from_static(TEST_BODY),
                 headers: Default::default(),
+                trailers: HashMap::new(),
                 status: 200,
                 url: url.clone(),
                 version: HttpVersion::Http11,
@@ -172,6 +262,489 @@ mod http_cache_tests {
             Ok(())
         }
 
+        #[async_std::test]
+        async fn cacache_put_stream() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/large")?;
+            let manager = CACacheManager {
+                path: "./http-cacache-put-stream".into(),
+                ..Default::default()
+            };
+            let http_res = HttpResponse {
+                body: Bytes::new(),
+                headers: Default::default(),
+                trailers: HashMap::new(),
+                status: 200,
+                url: url.clone(),
+                version: HttpVersion::Http11,
+            };
+            let req = http::Request::get("http://example.com/large").body(())?;
+            let res = http::Response::builder().status(200).body(())?;
+            let policy = CachePolicy::new(&req, &res);
+            // 4 MiB body delivered in 64 KiB chunks
+            let chunks: Vec<std::io::Result<Vec<u8>>> =
+                (0..64u8).map(|i| Ok(vec![i; 64 * 1024])).collect();
+            manager
+                .put_stream(
+                    GET,
+                    &url,
+                    http_res,
+                    policy,
+                    futures::stream::iter(chunks),
+                )
+                .await?;
+            let (cached, _) = manager.get(GET, &url).await?.unwrap();
+            assert_eq!(cached.status, 200);
+            assert_eq!(cached.body.len(), 64 * 64 * 1024);
+            for (i, chunk) in cached.body.chunks(64 * 1024).enumerate() {
+                assert!(chunk.iter().all(|b| *b == i as u8));
+            }
+            manager.delete(GET, &url).await?;
+            assert!(manager.get(GET, &url).await?.is_none());
+            manager.clear().await?;
+            Ok(())
+        }
+
+        fn format_entry(
+            url: &Url,
+        ) -> anyhow::Result<(HttpResponse, CachePolicy)> {
+            let http_res = HttpResponse {
+                body: Bytes::from_static(TEST_BODY),
+                headers: HashMap::from([(
+                    "cache-control".to_string(),
+                    CACHEABLE_PUBLIC.to_string(),
+                )]),
+                trailers: HashMap::new(),
+                status: 200,
+                url: url.clone(),
+                version: HttpVersion::Http11,
+            };
+            let req = http::Request::get(url.as_str()).body(())?;
+            let policy = CachePolicy::new(&req, &http_res.parts()?);
+            Ok((http_res, policy))
+        }
+
+        #[async_std::test]
+        async fn cacache_serialization_formats() -> anyhow::Result<()> {
+            let path = "./http-cacache-formats".to_string();
+            for format in
+                [SerializationFormat::Bincode, SerializationFormat::Json]
+            {
+                let url = Url::parse(&format!(
+                    "http://example.com/{}",
+                    format!("{:?}", format).to_lowercase()
+                ))?;
+                let manager = CACacheManager { path: path.clone(), format };
+                let (http_res, policy) = format_entry(&url)?;
+                manager.put(GET, &url, http_res.clone(), policy).await?;
+                let (res, _) = manager.get(GET, &url).await?.unwrap();
+                assert_eq!(res.body, TEST_BODY);
+                assert_eq!(res.headers, http_res.headers);
+
+                // Entries stay readable after switching formats
+                let other =
+                    CACacheManager { path: path.clone(), ..Default::default() };
+                assert!(other.get(GET, &url).await?.is_some());
+                manager.delete(GET, &url).await?;
+            }
+
+            // The JSON entry is plain JSON on disk
+            let url = Url::parse("http://example.com/plain-json")?;
+            let manager =
+                CACacheManager { path, format: SerializationFormat::Json };
+            let (http_res, policy) = format_entry(&url)?;
+            manager.put(GET, &url, http_res, policy).await?;
+            let raw = cacache::read(&manager.path, format!("{}:{}", GET, url))
+                .await?;
+            let value: serde_json::Value = serde_json::from_slice(&raw)?;
+            assert_eq!(value["response"]["status"], 200);
+            manager.clear().await?;
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn cacache_concurrent_puts() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/contended")?;
+            let manager = CACacheManager {
+                path: "./http-cacache-concurrent".into(),
+                ..Default::default()
+            };
+            let mut tasks = Vec::new();
+            for writer in 0..8u8 {
+                let manager = manager.clone();
+                let url = url.clone();
+                tasks.push(async_std::task::spawn(async move {
+                    let (mut http_res, policy) = format_entry(&url)?;
+                    for _ in 0..10 {
+                        http_res.body = Bytes::from(vec![writer; 256 * 1024]);
+                        manager
+                            .put(GET, &url, http_res.clone(), policy.clone())
+                            .await?;
+                    }
+                    anyhow::Ok(())
+                }));
+            }
+            for _ in 0..8 {
+                let manager = manager.clone();
+                let url = url.clone();
+                tasks.push(async_std::task::spawn(async move {
+                    for _ in 0..20 {
+                        // Always a miss or one whole body, never an error
+                        if let Some((res, _)) = manager.get(GET, &url).await? {
+                            assert_eq!(res.body.len(), 256 * 1024);
+                            assert!(res.body.iter().all(|b| *b == res.body[0]));
+                        }
+                    }
+                    anyhow::Ok(())
+                }));
+            }
+            for task in tasks {
+                task.await?;
+            }
+            assert!(manager.get(GET, &url).await?.is_some());
+            manager.clear().await?;
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn cacache_corrupt_content_is_a_miss() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/corrupt")?;
+            let manager = CACacheManager {
+                path: "./http-cacache-corrupt".into(),
+                ..Default::default()
+            };
+            let (http_res, policy) = format_entry(&url)?;
+            manager.put(GET, &url, http_res.clone(), policy.clone()).await?;
+            let key = format!("{}:{}", GET, url);
+            let integrity = cacache::metadata(&manager.path, &key)
+                .await?
+                .unwrap()
+                .integrity;
+
+            // Overwrite every content file, as a torn write would leave it
+            let mut dirs = vec![std::path::PathBuf::from(&manager.path)];
+            while let Some(dir) = dirs.pop() {
+                for entry in std::fs::read_dir(dir)? {
+                    let path = entry?.path();
+                    if path.is_dir() {
+                        dirs.push(path);
+                    } else if path.to_string_lossy().contains("content-v2") {
+                        std::fs::write(path, b"truncated")?;
+                    }
+                }
+            }
+            assert!(manager.get(GET, &url).await?.is_none());
+            assert!(!cacache::exists(&manager.path, &integrity).await);
+
+            // A later put makes the entry readable again
+            manager.put(GET, &url, http_res, policy).await?;
+            let (res, _) = manager.get(GET, &url).await?.unwrap();
+            assert_eq!(res.body, TEST_BODY);
+            manager.clear().await?;
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn moka_serialization_formats() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com")?;
+            for format in
+                [SerializationFormat::Bincode, SerializationFormat::Json]
+            {
+                let manager = MokaManager { format, ..Default::default() };
+                let (http_res, policy) = format_entry(&url)?;
+                manager.put(GET, &url, http_res.clone(), policy).await?;
+                let (res, _) = manager.get(GET, &url).await?.unwrap();
+                assert_eq!(res.body, TEST_BODY);
+                assert_eq!(res.headers, http_res.headers);
+
+                // Entries stay readable after switching formats
+                let other = MokaManager {
+                    format: SerializationFormat::default(),
+                    ..manager
+                };
+                assert!(other.get(GET, &url).await?.is_some());
+            }
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn cacache_trailers() -> anyhow::Result<()> {
+            let path = "./http-cacache-trailers".to_string();
+            let url = Url::parse("http://example.com/")?;
+            let manager = CACacheManager { path, ..Default::default() };
+            let (mut http_res, policy) = format_entry(&url)?;
+            http_res
+                .trailers
+                .insert("grpc-status".to_string(), "0".to_string());
+            manager.put(GET, &url, http_res, policy.clone()).await?;
+            let (res, _) = manager.get(GET, &url).await?.unwrap();
+            assert_eq!(res.trailers["grpc-status"], "0");
+
+            // Entries written before responses had trailers stay readable,
+            // with the layout bincode gave them then
+            let (http_res, _) = format_entry(&url)?;
+            let legacy = bincode::serialize(&(
+                (
+                    http_res.body.to_vec(),
+                    &http_res.headers,
+                    http_res.status,
+                    &http_res.url,
+                    http_res.version,
+                ),
+                &policy,
+            ))?;
+            let key = format!("{}:{}", GET, url);
+            cacache::write(&manager.path, &key, &legacy).await?;
+            let (res, _) = manager.get(GET, &url).await?.unwrap();
+            assert_eq!(res.body, TEST_BODY);
+            assert!(res.trailers.is_empty());
+            let mut writer = cacache::WriteOpts::new()
+                .metadata(serde_json::json!({ "format": "bincode" }))
+                .open(&manager.path, &key)
+                .await?;
+            futures::AsyncWriteExt::write_all(&mut writer, &legacy).await?;
+            writer.commit().await?;
+            let (res, _) = manager.get(GET, &url).await?.unwrap();
+            assert_eq!(res.headers, http_res.headers);
+            manager.clear().await?;
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn moka_shares_bodies() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com")?;
+            let manager = MokaManager::default();
+            let (http_res, policy) = format_entry(&url)?;
+            manager.put(GET, &url, http_res, policy).await?;
+
+            // Every hit hands out the same buffer rather than a copy
+            let (first, _) = manager.get(GET, &url).await?.unwrap();
+            let (second, _) = manager.get(GET, &url).await?.unwrap();
+            assert_eq!(first.body, TEST_BODY);
+            assert_eq!(first.body.as_ptr(), second.body.as_ptr());
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn moka_stale_grace() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com")?;
+            let manager = MokaManager::with_stale_grace(
+                MokaCache::new(42),
+                Duration::from_secs(1),
+            );
+            let (mut http_res, _) = format_entry(&url)?;
+            http_res
+                .headers
+                .insert("cache-control".to_string(), "max-age=1".to_string());
+            let req = http::Request::get(url.as_str()).body(())?;
+            let policy = CachePolicy::new(&req, &http_res.parts()?);
+            manager.put(GET, &url, http_res, policy).await?;
+
+            // Kept through the grace period after going stale
+            async_std::task::sleep(Duration::from_millis(1500)).await;
+            assert!(manager.get(GET, &url).await?.is_some());
+
+            // Then removed from memory altogether
+            async_std::task::sleep(Duration::from_millis(1000)).await;
+            assert!(manager.get(GET, &url).await?.is_none());
+            assert_eq!(manager.cache.entry_count(), 0);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn moka_stale_grace_sweep() -> anyhow::Result<()> {
+            let manager = MokaManager::with_stale_grace(
+                MokaCache::new(42),
+                Duration::from_secs(1),
+            );
+            let expiring = Url::parse("http://example.com/expiring")?;
+            let (mut http_res, _) = format_entry(&expiring)?;
+            http_res
+                .headers
+                .insert("cache-control".to_string(), "max-age=1".to_string());
+            let req = http::Request::get(expiring.as_str()).body(())?;
+            let policy = CachePolicy::new(&req, &http_res.parts()?);
+            manager.put(GET, &expiring, http_res, policy).await?;
+            let kept = Url::parse("http://example.com/kept")?;
+            let (http_res, policy) = format_entry(&kept)?;
+            manager.put(GET, &kept, http_res, policy).await?;
+            manager
+                .sweep_expired(Arc::new(AsyncStd), Duration::from_millis(100));
+
+            // Removed without being looked up again
+            async_std::task::sleep(Duration::from_millis(1500)).await;
+            assert_eq!(manager.cache.entry_count(), 2);
+            async_std::task::sleep(Duration::from_millis(1000)).await;
+            assert_eq!(manager.cache.entry_count(), 1);
+            assert!(manager.get(GET, &kept).await?.is_some());
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn moka_pinned_entries_survive_eviction() -> anyhow::Result<()> {
+            let manager = MokaManager::new(MokaCache::new(2));
+            let critical = Url::parse("http://example.com/critical")?;
+            let (http_res, policy) = format_entry(&critical)?;
+            manager.put(GET, &critical, http_res, policy).await?;
+            manager.pin(GET, &critical).await?;
+
+            // Far past the capacity
+            for i in 0..20 {
+                let url = Url::parse(&format!("http://example.com/{}", i))?;
+                let (http_res, policy) = format_entry(&url)?;
+                manager.put(GET, &url, http_res, policy).await?;
+            }
+            assert!(manager.get(GET, &critical).await?.is_some());
+            let mut unpinned = 0;
+            for i in 0..20 {
+                let url = Url::parse(&format!("http://example.com/{}", i))?;
+                if manager.get(GET, &url).await?.is_some() {
+                    unpinned += 1;
+                }
+            }
+            assert!(unpinned <= 2);
+
+            // Still replaced when revalidated
+            let (mut http_res, policy) = format_entry(&critical)?;
+            http_res.body = Bytes::from_static(b"updated");
+            manager.put(GET, &critical, http_res, policy).await?;
+            let (res, _) = manager.get(GET, &critical).await?.unwrap();
+            assert_eq!(res.body, "updated");
+            assert!(manager.keys().await?.contains(&(GET.into(), critical)));
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn moka_unpinned_entries_rejoin_the_cache() -> anyhow::Result<()>
+        {
+            let manager = MokaManager::default();
+            let url = Url::parse("http://example.com/")?;
+            // Pinned before anything is stored
+            manager.pin(GET, &url).await?;
+            let (http_res, policy) = format_entry(&url)?;
+            manager.put(GET, &url, http_res, policy).await?;
+            assert_eq!(manager.cache.entry_count(), 0);
+
+            manager.unpin(GET, &url).await?;
+            assert_eq!(manager.cache.entry_count(), 1);
+            assert!(manager.get(GET, &url).await?.is_some());
+            manager.delete(GET, &url).await?;
+            assert!(manager.get(GET, &url).await?.is_none());
+            Ok(())
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+        async fn moka_pin_keeps_concurrent_put() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            for _ in 0..2000 {
+                let manager = MokaManager::default();
+                let (http_res, policy) = format_entry(&url)?;
+                manager.put(GET, &url, http_res, policy).await?;
+                let pin = {
+                    let (manager, url) = (manager.clone(), url.clone());
+                    tokio::spawn(async move { manager.pin(GET, &url).await })
+                };
+                let put = {
+                    let (manager, url) = (manager.clone(), url.clone());
+                    let (mut http_res, policy) = format_entry(&url)?;
+                    http_res.body = Bytes::from_static(b"updated");
+                    tokio::spawn(async move {
+                        manager.put(GET, &url, http_res, policy).await
+                    })
+                };
+                pin.await??;
+                put.await??;
+                // Whichever ran first, the write isn't lost
+                let (res, _) = manager.get(GET, &url).await?.unwrap();
+                assert_eq!(res.body, "updated");
+            }
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn cacache_managers_are_isolated() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let root = std::env::temp_dir().join("http-cacache-isolated");
+            let (public, restricted) = (
+                CACacheManager::new(root.join("public")),
+                CACacheManager::new(root.join("restricted")),
+            );
+            let (mut http_res, policy) = format_entry(&url)?;
+            public.put(GET, &url, http_res.clone(), policy.clone()).await?;
+            http_res.body = Bytes::from_static(b"restricted");
+            restricted.put(GET, &url, http_res, policy).await?;
+            let (res, _) = public.get(GET, &url).await?.unwrap();
+            assert_eq!(res.body, TEST_BODY);
+            let (res, _) = restricted.get(GET, &url).await?.unwrap();
+            assert_eq!(res.body, b"restricted".as_ref());
+
+            public.delete(GET, &url).await?;
+            assert!(public.get(GET, &url).await?.is_none());
+            assert!(restricted.get(GET, &url).await?.is_some());
+            restricted.clear().await?;
+            assert!(restricted.get(GET, &url).await?.is_none());
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn cacache_health_check() -> anyhow::Result<()> {
+            let path = "./http-cacache-health".to_string();
+            let manager = CACacheManager { path, ..Default::default() };
+            manager.health_check().await?;
+            // Nothing is left behind
+            assert!(manager.keys().await?.is_empty());
+
+            // A file stands where the cache directory should be
+            let path = "./http-cacache-health-broken".to_string();
+            std::fs::write(&path, b"")?;
+            let manager = CACacheManager { path, ..Default::default() };
+            let cache = HttpCache {
+                mode: CacheMode::Default,
+                manager,
+                options: HttpCacheOptions::default(),
+            };
+            assert!(cache.health_check().await.is_err());
+            Ok(())
+        }
+
+        #[test]
+        fn bodies_serialize_like_vecs() -> anyhow::Result<()> {
+            // Entries stored while the body was a `Vec<u8>` stay readable
+            let url = Url::parse("http://example.com")?;
+            let (http_res, _) = format_entry(&url)?;
+            let json = serde_json::to_value(&http_res)?;
+            assert_eq!(json["body"], serde_json::json!(TEST_BODY.to_vec()));
+            let res: HttpResponse = serde_json::from_value(json)?;
+            assert_eq!(res.body, TEST_BODY);
+            Ok(())
+        }
+
+        #[test]
+        fn stores_serialize_reproducibly() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com")?;
+            let names: Vec<String> =
+                (0..32).map(|i| format!("x-header-{}", i)).collect();
+            // Each map has its own hasher, so its own iteration order
+            let build = |names: &mut dyn Iterator<Item = &String>| {
+                let mut http_res = format_entry(&url).unwrap().0;
+                for name in names {
+                    http_res.headers.insert(name.clone(), name.clone());
+                    http_res.trailers.insert(name.clone(), name.clone());
+                }
+                http_res
+            };
+            let first = build(&mut names.iter());
+            let second = build(&mut names.iter().rev());
+            assert_eq!(
+                serde_json::to_vec(&first)?,
+                serde_json::to_vec(&second)?
+            );
+            assert_eq!(
+                bincode::serialize(&first)?,
+                bincode::serialize(&second)?
+            );
+            Ok(())
+        }
+
         #[async_std::test]
         async fn moka() -> anyhow::Result<()> {
             // Added to test custom Debug impl
@@ -182,8 +755,46 @@ mod http_cache_tests {
             let url = Url::parse("http://example.com")?;
             let manager = Arc::new(MokaManager::default());
             let http_res = HttpResponse {
-                body: TEST_BODY.to_vec(),
+                body: Bytes::from_static(TEST_BODY),
+                headers: Default::default(),
+                trailers: HashMap::new(),
+                status: 200,
+                url: url.clone(),
+                version: HttpVersion::Http11,
+            };
+            let req = http::Request::get("http://example.com").body(())?;
+            let res = http::Response::builder()
+                .status(200)
+                .body(TEST_BODY.to_vec())?;
+            let policy = CachePolicy::new(&req, &res);
+            manager.put(GET, &url, http_res.clone(), policy.clone()).await?;
+            let data = manager.get(GET, &url).await?;
+            assert!(data.is_some());
+            assert_eq!(data.unwrap().0.body, TEST_BODY);
+            manager.delete(GET, &url).await?;
+            let data = manager.get(GET, &url).await?;
+            assert!(data.is_none());
+
+            manager.put(GET, &url, http_res, policy).await?;
+            manager.clear().await?;
+            let data = manager.get(GET, &url).await?;
+            assert!(data.is_none());
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn memory() -> anyhow::Result<()> {
+            // Added to test custom Debug impl
+            assert_eq!(
+                format!("{:?}", MemoryManager::default()),
+                "MemoryManager { .. }",
+            );
+            let url = Url::parse("http://example.com")?;
+            let manager = MemoryManager::default();
+            let http_res = HttpResponse {
+                body: Bytes::from_static(TEST_BODY),
                 headers: Default::default(),
+                trailers: HashMap::new(),
                 status: 200,
                 url: url.clone(),
                 version: HttpVersion::Http11,
@@ -207,5 +818,1845 @@ mod http_cache_tests {
             assert!(data.is_none());
             Ok(())
         }
+
+        // Stores a few responses, then purges them by prefix and by glob
+        async fn purge_entries<T: CacheManager + Clone>(
+            manager: T,
+        ) -> anyhow::Result<()> {
+            let cache = HttpCache {
+                mode: CacheMode::Default,
+                manager: manager.clone(),
+                options: HttpCacheOptions::default(),
+            };
+            let stored = [
+                (GET, "http://example.com/products/1"),
+                ("HEAD", "http://example.com/products/1"),
+                (GET, "http://example.com/products/2"),
+                (GET, "http://example.com/about"),
+                (GET, "http://example.org/about"),
+            ];
+            for (method, url) in stored {
+                let url = Url::parse(url)?;
+                let (http_res, policy) = format_entry(&url)?;
+                manager.put(method, &url, http_res, policy).await?;
+            }
+            assert_eq!(manager.keys().await?.len(), 5);
+
+            let purged =
+                cache.purge_prefix("http://example.com/products/").await?;
+            assert_eq!(purged, 3);
+            let mut keys: Vec<String> = manager
+                .keys()
+                .await?
+                .into_iter()
+                .map(|(method, url)| format!("{}:{}", method, url))
+                .collect();
+            keys.sort();
+            assert_eq!(
+                keys,
+                [
+                    "GET:http://example.com/about",
+                    "GET:http://example.org/about"
+                ]
+            );
+
+            assert_eq!(cache.purge_glob("http://*.c?m/*").await?, 1);
+            let keys = manager.keys().await?;
+            assert_eq!(keys.len(), 1);
+            assert_eq!(keys[0].1.as_str(), "http://example.org/about");
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn cacache_purge() -> anyhow::Result<()> {
+            let manager = CACacheManager {
+                path: "./http-cacache-purge".into(),
+                ..Default::default()
+            };
+            // Left over if an earlier run failed
+            manager.clear().await.ok();
+            purge_entries(manager.clone()).await?;
+            manager.clear().await?;
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn moka_purge() -> anyhow::Result<()> {
+            purge_entries(MokaManager::default()).await
+        }
+
+        #[async_std::test]
+        async fn memory_purge() -> anyhow::Result<()> {
+            purge_entries(MemoryManager::default()).await
+        }
+    }
+
+    mod test_utils {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, ManagerCall, ManagerOperation, MockManager,
+            Script, ScriptedMiddleware,
+        };
+        use http_cache_semantics::CachePolicy;
+
+        #[async_std::test]
+        async fn miss_then_hit() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                200,
+                &[("cache-control", CACHEABLE_PUBLIC)],
+                TEST_BODY,
+            ));
+
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.headers.get(XCACHE).unwrap(), MISS);
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.headers.get(XCACHE).unwrap(), HIT);
+            assert_eq!(res.body, TEST_BODY);
+            assert_eq!(script.fetches(), 1);
+
+            // Consulted both times, written only once
+            let calls = manager.calls();
+            assert_eq!(calls.len(), 3);
+            assert!(
+                matches!(&calls[0], ManagerCall::Get(m, u) if m == GET && u == &url)
+            );
+            assert!(
+                matches!(&calls[1], ManagerCall::Put(_, _, res) if res.status == 200)
+            );
+            assert!(matches!(&calls[2], ManagerCall::Get(..)));
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn canned_entry_is_revalidated() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let stored = response(
+                &url,
+                200,
+                &[("cache-control", "public, max-age=0"), ("etag", "\"v1\"")],
+                TEST_BODY,
+            );
+            let req = http::Request::get(url.as_str()).body(())?;
+            let policy = CachePolicy::new(&req, &stored.parts()?);
+            manager.insert(GET, &url, stored, policy);
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            let script = Script::default();
+            script.respond(response(&url, 304, &[("etag", "\"v1\"")], b""));
+
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.status, 200);
+            assert_eq!(res.body, TEST_BODY);
+            let sent = script.requests();
+            assert_eq!(sent[0].get("if-none-match").unwrap(), "\"v1\"");
+            assert_eq!(manager.puts(), 1);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn failures_are_injected() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            let script = Script::default();
+
+            manager.fail(ManagerOperation::Get);
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await;
+            assert!(res.is_err());
+            assert_eq!(script.fetches(), 0);
+            manager.recover(ManagerOperation::Get);
+
+            // Nothing stored to fall back on when the origin fails
+            script.fail();
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await;
+            assert!(res.is_err());
+            assert_eq!(script.remaining(), 0);
+            assert_eq!(manager.puts(), 0);
+            Ok(())
+        }
+    }
+
+    mod get_or_fetch {
+        use crate::*;
+        use http_cache::test_utils::{build_cache, response, MockManager};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Answers with the response, counting the calls
+        async fn fetch(
+            count: &AtomicUsize,
+            cache_control: &str,
+        ) -> http_cache::Result<HttpResponse> {
+            count.fetch_add(1, Ordering::SeqCst);
+            // Long enough for concurrent calls to pile up behind it
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            let url = Url::parse("grpc://example.com/Products/Get")?;
+            Ok(response(
+                &url,
+                200,
+                &[("cache-control", cache_control)],
+                TEST_BODY,
+            ))
+        }
+
+        #[async_std::test]
+        async fn fetches_only_on_a_miss() -> anyhow::Result<()> {
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            let count = AtomicUsize::new(0);
+
+            let res = cache
+                .get_or_fetch("product:1", || fetch(&count, CACHEABLE_PUBLIC))
+                .await?;
+            assert_eq!(res.headers.get(XCACHE).unwrap(), MISS);
+            let res = cache
+                .get_or_fetch("product:1", || fetch(&count, CACHEABLE_PUBLIC))
+                .await?;
+            assert_eq!(res.headers.get(XCACHE).unwrap(), HIT);
+            assert_eq!(res.body, TEST_BODY);
+            assert_eq!(count.load(Ordering::SeqCst), 1);
+
+            // Neither stored nor served from the cache
+            for _ in 0..2 {
+                cache
+                    .get_or_fetch("product:2", || fetch(&count, "no-store"))
+                    .await?;
+            }
+            assert_eq!(count.load(Ordering::SeqCst), 3);
+            assert_eq!(manager.puts(), 1);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn stale_is_served_when_the_fetcher_fails() -> anyhow::Result<()>
+        {
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            let count = AtomicUsize::new(0);
+            cache
+                .get_or_fetch("product:1", || {
+                    fetch(&count, "public, max-age=0")
+                })
+                .await?;
+
+            let res = cache
+                .get_or_fetch("product:1", || async {
+                    Err(CacheError::General(anyhow::anyhow!("unavailable")))
+                })
+                .await?;
+            assert_eq!(res.body, TEST_BODY);
+            assert_eq!(res.warning_code(), Some(111));
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn concurrent_calls_are_coalesced() -> anyhow::Result<()> {
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            let single_flight = SingleFlight::new();
+            let count = AtomicUsize::new(0);
+
+            let calls = (0..5).map(|_| {
+                single_flight.get_or_fetch(&cache, "product:1", || {
+                    fetch(&count, CACHEABLE_PUBLIC)
+                })
+            });
+            for res in futures::future::join_all(calls).await {
+                assert_eq!(res?.body, TEST_BODY);
+            }
+            assert_eq!(count.load(Ordering::SeqCst), 1);
+            assert_eq!(manager.puts(), 1);
+            Ok(())
+        }
+    }
+
+    mod validator_preference {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use http_cache_semantics::CachePolicy;
+
+        const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+        // Revalidates a stale entry with the validators under the
+        // preference, returning the headers of the conditional request
+        async fn revalidate(
+            preference: ValidatorPreference,
+            validators: &[(&str, &str)],
+        ) -> anyhow::Result<http::HeaderMap> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let mut headers = vec![("cache-control", "public, max-age=0")];
+            headers.extend_from_slice(validators);
+            let stored = response(&url, 200, &headers, TEST_BODY);
+            let req = http::Request::get(url.as_str()).body(())?;
+            let policy = CachePolicy::new(&req, &stored.parts()?);
+            manager.insert(GET, &url, stored, policy);
+            let options = HttpCacheOptions {
+                validator_preference: preference,
+                ..Default::default()
+            };
+            let cache = build_cache(&manager, options);
+            let script = Script::default();
+            script.respond(response(&url, 304, &[], b""));
+
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.body, TEST_BODY);
+            Ok(script.requests().remove(0))
+        }
+
+        #[async_std::test]
+        async fn each_preference_sends_its_headers() -> anyhow::Result<()> {
+            let both = [("etag", "\"v1\""), ("last-modified", LAST_MODIFIED)];
+            let cases = [
+                (ValidatorPreference::Both, true, true),
+                (ValidatorPreference::ETagOnly, true, false),
+                (ValidatorPreference::LastModifiedOnly, false, true),
+            ];
+            for (preference, etag, last_modified) in cases {
+                let sent = revalidate(preference, &both).await?;
+                assert_eq!(sent.contains_key("if-none-match"), etag);
+                assert_eq!(
+                    sent.contains_key("if-modified-since"),
+                    last_modified
+                );
+            }
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn a_lone_validator_is_always_sent() -> anyhow::Result<()> {
+            let sent = revalidate(
+                ValidatorPreference::ETagOnly,
+                &[("last-modified", LAST_MODIFIED)],
+            )
+            .await?;
+            assert_eq!(sent.get("if-modified-since").unwrap(), LAST_MODIFIED);
+            let sent = revalidate(
+                ValidatorPreference::LastModifiedOnly,
+                &[("etag", "\"v1\"")],
+            )
+            .await?;
+            assert_eq!(sent.get("if-none-match").unwrap(), "\"v1\"");
+            Ok(())
+        }
+    }
+
+    mod fallback_response {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use std::sync::Arc;
+
+        const OFFLINE: &[u8] = b"offline";
+
+        fn options(fallback: Arc<dyn FallbackResponse>) -> HttpCacheOptions {
+            HttpCacheOptions {
+                fallback_response: Some(fallback),
+                ..Default::default()
+            }
+        }
+
+        // Answers with a `503` naming the page that couldn't be fetched
+        struct Unavailable;
+
+        impl FallbackResponse for Unavailable {
+            fn respond(
+                &self,
+                _parts: &http::request::Parts,
+                url: &Url,
+            ) -> HttpResponse {
+                response(url, 503, &[], url.path().as_bytes())
+            }
+        }
+
+        #[async_std::test]
+        async fn served_when_the_origin_is_down() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let page = response(&url, 200, &[], OFFLINE);
+            let cache = build_cache(&manager, options(Arc::new(page)));
+            let script = Script::default();
+            script.fail();
+
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.status, 200);
+            assert_eq!(res.body, OFFLINE);
+            assert_eq!(res.headers.get(XCACHE).unwrap(), MISS);
+            // Nor is it stored
+            assert_eq!(manager.puts(), 0);
+
+            let url = Url::parse("http://example.com/products")?;
+            let cache = build_cache(&manager, options(Arc::new(Unavailable)));
+            script.fail();
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.status, 503);
+            assert_eq!(res.body, "/products");
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn stale_is_preferred() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let page = response(&url, 200, &[], OFFLINE);
+            let cache = build_cache(&manager, options(Arc::new(page)));
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                200,
+                &[("cache-control", "public, max-age=0")],
+                TEST_BODY,
+            ));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+
+            script.fail();
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.body, TEST_BODY);
+            Ok(())
+        }
+    }
+
+    mod stale_entries {
+        use crate::*;
+        use http_cache::test_utils::{build_cache, response, MockManager};
+        use http_cache_semantics::CachePolicy;
+
+        // Stores a fresh and a stale response
+        fn stored_cache() -> anyhow::Result<(HttpCache<MockManager>, Url)> {
+            let manager = MockManager::default();
+            for (path, cache_control) in
+                [("/fresh", CACHEABLE_PUBLIC), ("/stale", "public, max-age=0")]
+            {
+                let url = Url::parse("http://example.com")?.join(path)?;
+                let res = response(
+                    &url,
+                    200,
+                    &[("cache-control", cache_control)],
+                    TEST_BODY,
+                );
+                let req = http::Request::get(url.as_str()).body(())?;
+                let policy = CachePolicy::new(&req, &res.parts()?);
+                manager.insert(GET, &url, res, policy);
+            }
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            Ok((cache, Url::parse("http://example.com/stale")?))
+        }
+
+        #[async_std::test]
+        async fn only_stale_are_listed() -> anyhow::Result<()> {
+            let (cache, stale) = stored_cache()?;
+            let listed = cache.list_stale().await?;
+            assert_eq!(listed.len(), 1);
+            let (key, meta) = &listed[0];
+            assert_eq!(key, "GET:http://example.com/stale");
+            assert_eq!(meta.method, GET);
+            assert_eq!(meta.url, stale);
+            assert_eq!(meta.status, 200);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn only_stale_are_evicted() -> anyhow::Result<()> {
+            let (cache, stale) = stored_cache()?;
+            assert_eq!(cache.evict_stale().await?, 1);
+            assert!(cache.manager.entry(GET, &stale).is_none());
+            let fresh = Url::parse("http://example.com/fresh")?;
+            assert!(cache.manager.entry(GET, &fresh).is_some());
+            assert!(cache.list_stale().await?.is_empty());
+            Ok(())
+        }
+    }
+
+    mod etag_comparison {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use http_cache_semantics::CachePolicy;
+
+        // Stores a response with the entity tag, fresh or stale
+        fn stored_cache(
+            url: &Url,
+            etag: &str,
+            cache_control: &str,
+        ) -> anyhow::Result<HttpCache<MockManager>> {
+            let manager = MockManager::default();
+            let stored = response(
+                url,
+                200,
+                &[("cache-control", cache_control), ("etag", etag)],
+                TEST_BODY,
+            );
+            let req = http::Request::get(url.as_str()).body(())?;
+            let policy = CachePolicy::new(&req, &stored.parts()?);
+            manager.insert(GET, url, stored, policy);
+            Ok(build_cache(&manager, HttpCacheOptions::default()))
+        }
+
+        fn request(
+            url: &Url,
+            script: &Script,
+            headers: &[(&'static str, &'static str)],
+        ) -> anyhow::Result<ScriptedMiddleware> {
+            let mut middleware = ScriptedMiddleware::new(GET, url, script)?;
+            for (name, value) in headers {
+                middleware
+                    .headers_mut()
+                    .insert(*name, http::HeaderValue::from_static(value));
+            }
+            Ok(middleware)
+        }
+
+        #[async_std::test]
+        async fn if_none_match_compares_weakly() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            for (stored, sent) in [
+                ("W/\"v1\"", "\"v1\""),
+                ("\"v1\"", "W/\"v1\""),
+                ("W/\"v1\"", "\"v0\", W/\"v1\""),
+                ("\"v1\"", "*"),
+            ] {
+                let cache = stored_cache(&url, stored, CACHEABLE_PUBLIC)?;
+                let script = Script::default();
+                let req = request(&url, &script, &[("if-none-match", sent)])?;
+                let res = cache.run(req).await?;
+                assert_eq!(res.status, 304, "{} against {}", sent, stored);
+                assert!(res.body.is_empty());
+                assert_eq!(res.headers.get("etag").unwrap(), stored);
+                assert_eq!(script.fetches(), 0);
+            }
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn if_none_match_without_a_match_gets_the_body(
+        ) -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = stored_cache(&url, "W/\"v1\"", CACHEABLE_PUBLIC)?;
+            let script = Script::default();
+            let req = request(&url, &script, &[("if-none-match", "\"v2\"")])?;
+            let res = cache.run(req).await?;
+            assert_eq!(res.status, 200);
+            assert_eq!(res.body, TEST_BODY);
+            assert_eq!(script.fetches(), 0);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn weak_revalidation_304_refreshes_the_entry(
+        ) -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = stored_cache(&url, "\"v1\"", "public, max-age=0")?;
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                304,
+                &[("etag", "W/\"v1\""), ("cache-control", CACHEABLE_PUBLIC)],
+                b"",
+            ));
+            let res = cache.run(request(&url, &script, &[])?).await?;
+            assert_eq!(res.status, 200);
+            assert_eq!(res.body, TEST_BODY);
+            assert_eq!(
+                res.headers.get("cache-control").unwrap(),
+                CACHEABLE_PUBLIC
+            );
+            let sent = script.requests();
+            assert_eq!(sent[0].get("if-none-match").unwrap(), "\"v1\"");
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn weak_etag_never_satisfies_if_range() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = stored_cache(&url, "W/\"v1\"", CACHEABLE_PUBLIC)?;
+            for if_range in ["W/\"v1\"", "\"v1\""] {
+                let script = Script::default();
+                let req = request(
+                    &url,
+                    &script,
+                    &[("range", "bytes=0-1"), ("if-range", if_range)],
+                )?;
+                let res = cache.run(req).await?;
+                // The full stored response is the answer to a failed
+                // `If-Range`
+                assert_eq!(res.status, 200, "{}", if_range);
+                assert_eq!(res.body, TEST_BODY);
+                assert_eq!(script.fetches(), 0);
+            }
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn strong_if_range_match_fetches_the_range() -> anyhow::Result<()>
+        {
+            let url = Url::parse("http://example.com/")?;
+            let cache = stored_cache(&url, "\"v1\"", CACHEABLE_PUBLIC)?;
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                206,
+                &[("content-range", "bytes 0-1/4"), ("etag", "\"v1\"")],
+                b"te",
+            ));
+            let req = request(
+                &url,
+                &script,
+                &[("range", "bytes=0-1"), ("if-range", "\"v1\"")],
+            )?;
+            let res = cache.run(req).await?;
+            assert_eq!(res.status, 206);
+            assert_eq!(res.body, b"te".as_ref());
+            assert_eq!(script.fetches(), 1);
+            assert_eq!(script.requests()[0].get("if-range").unwrap(), "\"v1\"");
+
+            // The partial response doesn't replace the stored one
+            let (stored, _) = cache.manager.entry(GET, &url).unwrap();
+            assert_eq!(stored.status, 200);
+            assert_eq!(stored.body, TEST_BODY);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn if_range_mismatch_serves_the_stored_response(
+        ) -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = stored_cache(&url, "\"v2\"", CACHEABLE_PUBLIC)?;
+            let script = Script::default();
+            let req = request(
+                &url,
+                &script,
+                &[("range", "bytes=0-1"), ("if-range", "\"v1\"")],
+            )?;
+            let res = cache.run(req).await?;
+            assert_eq!(res.status, 200);
+            assert_eq!(res.body, TEST_BODY);
+            assert_eq!(script.fetches(), 0);
+            Ok(())
+        }
+    }
+
+    mod vary_variants {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+
+        fn options(
+            max_variants_per_url: Option<usize>,
+            variant_overflow: VariantOverflow,
+        ) -> HttpCacheOptions {
+            HttpCacheOptions {
+                max_variants_per_url,
+                variant_overflow,
+                ..Default::default()
+            }
+        }
+
+        // Requests the url as the user agent, answered by a response varying
+        // on it when the cache doesn't have one. Returns whether the origin
+        // was contacted.
+        async fn fetch(
+            cache: &HttpCache<MockManager>,
+            user_agent: &str,
+        ) -> anyhow::Result<bool> {
+            fetch_in(cache, user_agent, None).await
+        }
+
+        async fn fetch_in(
+            cache: &HttpCache<MockManager>,
+            user_agent: &str,
+            mode: Option<CacheMode>,
+        ) -> anyhow::Result<bool> {
+            let url = Url::parse("http://example.com/")?;
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                200,
+                &[("cache-control", CACHEABLE_PUBLIC), ("vary", "User-Agent")],
+                user_agent.as_bytes(),
+            ));
+            let mut middleware = ScriptedMiddleware::new(GET, &url, &script)?;
+            middleware
+                .headers_mut()
+                .insert("user-agent", http::HeaderValue::from_str(user_agent)?);
+            middleware.mode = mode;
+            let res = cache.run(middleware).await?;
+            assert_eq!(res.body, user_agent.as_bytes());
+            Ok(script.fetches() == 1)
+        }
+
+        // Counts the stored records that aren't index records
+        async fn stored_variants(
+            cache: &HttpCache<MockManager>,
+        ) -> anyhow::Result<usize> {
+            Ok(cache
+                .manager
+                .keys()
+                .await?
+                .iter()
+                .filter(|(_, url)| url.scheme() == "urn")
+                .count())
+        }
+
+        #[async_std::test]
+        async fn variants_are_stored_apart() -> anyhow::Result<()> {
+            let cache = build_cache(
+                &MockManager::default(),
+                options(Some(4), VariantOverflow::EvictOldest),
+            );
+            for agent in ["a", "b", "c"] {
+                assert!(fetch(&cache, agent).await?);
+            }
+            for agent in ["a", "b", "c"] {
+                assert!(!fetch(&cache, agent).await?, "{}", agent);
+            }
+            assert_eq!(stored_variants(&cache).await?, 3);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn oldest_variants_are_evicted() -> anyhow::Result<()> {
+            let cache = build_cache(
+                &MockManager::default(),
+                options(Some(3), VariantOverflow::EvictOldest),
+            );
+            for n in 0..20 {
+                assert!(fetch(&cache, &format!("agent-{}", n)).await?);
+                assert!(stored_variants(&cache).await? <= 3);
+            }
+            assert_eq!(stored_variants(&cache).await?, 3);
+            for n in 17..20 {
+                assert!(!fetch(&cache, &format!("agent-{}", n)).await?);
+            }
+            assert!(fetch(&cache, "agent-0").await?);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn storing_again_makes_a_variant_recent() -> anyhow::Result<()> {
+            let cache = build_cache(
+                &MockManager::default(),
+                options(Some(2), VariantOverflow::EvictOldest),
+            );
+            assert!(fetch(&cache, "a").await?);
+            assert!(fetch(&cache, "b").await?);
+            // Storing `a` again leaves `b` the oldest
+            assert!(fetch_in(&cache, "a", Some(CacheMode::Reload)).await?);
+            assert!(fetch(&cache, "c").await?);
+            assert!(!fetch(&cache, "a").await?);
+            assert!(!fetch(&cache, "c").await?);
+            assert!(fetch(&cache, "b").await?);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn deleting_removes_every_variant() -> anyhow::Result<()> {
+            let cache = build_cache(
+                &MockManager::default(),
+                options(Some(4), VariantOverflow::EvictOldest),
+            );
+            for agent in ["a", "b", "c"] {
+                assert!(fetch(&cache, agent).await?);
+            }
+            cache.delete(GET, &Url::parse("http://example.com/")?).await?;
+            assert!(cache.manager.keys().await?.is_empty());
+            assert!(fetch(&cache, "a").await?);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn new_variants_are_rejected() -> anyhow::Result<()> {
+            let cache = build_cache(
+                &MockManager::default(),
+                options(Some(2), VariantOverflow::Reject),
+            );
+            for n in 0..10 {
+                assert!(fetch(&cache, &format!("agent-{}", n)).await?);
+            }
+            assert_eq!(stored_variants(&cache).await?, 2);
+            assert!(!fetch(&cache, "agent-0").await?);
+            assert!(!fetch(&cache, "agent-1").await?);
+            assert!(fetch(&cache, "agent-2").await?);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn without_a_bound_variants_replace_each_other(
+        ) -> anyhow::Result<()> {
+            let cache = build_cache(
+                &MockManager::default(),
+                options(None, VariantOverflow::default()),
+            );
+            for agent in ["a", "b", "a"] {
+                assert!(fetch(&cache, agent).await?);
+            }
+            assert_eq!(cache.manager.keys().await?.len(), 1);
+            Ok(())
+        }
+    }
+
+    mod fresh_until {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use http_cache_semantics::CachePolicy;
+
+        const FRESH_UNTIL: &str = "x-cache-fresh-until";
+
+        // Stores the response with the headers through the cache, returning
+        // it as stored
+        async fn store(
+            cache: &HttpCache<MockManager>,
+            headers: &[(&str, &str)],
+        ) -> anyhow::Result<HttpResponse> {
+            let url = Url::parse("http://example.com/")?;
+            let script = Script::default();
+            script.respond(response(&url, 200, headers, TEST_BODY));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            Ok(cache.manager.entry(GET, &url).unwrap().0)
+        }
+
+        // Requests the url with the headers, returning the response and
+        // whether the origin was contacted
+        async fn fetch(
+            cache: &HttpCache<MockManager>,
+            headers: &[(&'static str, &'static str)],
+        ) -> anyhow::Result<(HttpResponse, bool)> {
+            let url = Url::parse("http://example.com/")?;
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                200,
+                &[("cache-control", CACHEABLE_PUBLIC)],
+                TEST_BODY,
+            ));
+            let mut middleware = ScriptedMiddleware::new(GET, &url, &script)?;
+            for (name, value) in headers {
+                middleware
+                    .headers_mut()
+                    .insert(*name, http::HeaderValue::from_static(value));
+            }
+            let res = cache.run(middleware).await?;
+            Ok((res, script.fetches() == 1))
+        }
+
+        #[async_std::test]
+        async fn fresh_hits_use_the_recorded_time() -> anyhow::Result<()> {
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            let stored =
+                store(&cache, &[("cache-control", CACHEABLE_PUBLIC)]).await?;
+            let until: u64 = stored.headers[FRESH_UNTIL].parse()?;
+            let now = std::time::UNIX_EPOCH.elapsed()?.as_secs();
+            assert!(until > now + 86000 && until <= now + 86400);
+
+            let (res, fetched) = fetch(&cache, &[]).await?;
+            assert!(!fetched);
+            assert_eq!(res.body, TEST_BODY);
+            assert_eq!(res.headers["age"], "0");
+            assert!(res.headers.contains_key("date"));
+            assert!(!res.headers.contains_key(FRESH_UNTIL));
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn request_directives_still_apply() -> anyhow::Result<()> {
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            store(&cache, &[("cache-control", CACHEABLE_PUBLIC)]).await?;
+            let (_, fetched) =
+                fetch(&cache, &[("cache-control", "no-cache")]).await?;
+            assert!(fetched);
+            let (_, fetched) = fetch(&cache, &[("pragma", "no-cache")]).await?;
+            assert!(fetched);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn options_set_since_storing_still_apply() -> anyhow::Result<()> {
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            store(&cache, &[("cache-control", CACHEABLE_PUBLIC)]).await?;
+            let capped = build_cache(
+                &manager,
+                HttpCacheOptions {
+                    max_ttl: Some(Duration::from_secs(0)),
+                    ..Default::default()
+                },
+            );
+            let (_, fetched) = fetch(&capped, &[]).await?;
+            assert!(fetched);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn entries_without_the_time_use_the_policy() -> anyhow::Result<()>
+        {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let stored = response(
+                &url,
+                200,
+                &[("cache-control", CACHEABLE_PUBLIC)],
+                TEST_BODY,
+            );
+            let req = http::Request::get(url.as_str()).body(())?;
+            let policy = CachePolicy::new(&req, &stored.parts()?);
+            manager.insert(GET, &url, stored, policy);
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            let (res, fetched) = fetch(&cache, &[]).await?;
+            assert!(!fetched);
+            assert_eq!(res.body, TEST_BODY);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn policy_decides_what_the_time_cant() -> anyhow::Result<()> {
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, HttpCacheOptions::default());
+            for headers in [
+                &[("cache-control", "public, max-age=0")][..],
+                &[("cache-control", CACHEABLE_PUBLIC), ("vary", "accept")],
+                &[
+                    ("cache-control", "public"),
+                    ("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+                ],
+            ] {
+                let stored = store(&cache, headers).await?;
+                assert!(!stored.headers.contains_key(FRESH_UNTIL));
+            }
+            Ok(())
+        }
+    }
+
+    mod body_sizes {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Debug, Default)]
+        struct Histogram(Mutex<HashMap<(Option<u64>, bool), usize>>);
+
+        impl CacheObserver for Histogram {
+            fn on_timing(&self, _timings: &PhaseTimings) {}
+            fn on_body_size(&self, size: &BodySize) {
+                *self
+                    .0
+                    .lock()
+                    .unwrap()
+                    .entry((size.bucket, size.stored))
+                    .or_default() += 1;
+            }
+        }
+
+        #[async_std::test]
+        async fn sizes_are_bucketed() -> anyhow::Result<()> {
+            let histogram = Arc::new(Histogram::default());
+            let options = HttpCacheOptions {
+                observer: Some(histogram.clone()),
+                ..Default::default()
+            };
+            let cache = build_cache(&MockManager::default(), options);
+            let cases = [
+                (0, CACHEABLE_PUBLIC),
+                (1024, CACHEABLE_PUBLIC),
+                (1025, CACHEABLE_PUBLIC),
+                (1025, "no-store"),
+                (3000, "no-store"),
+                (20 << 20, CACHEABLE_PUBLIC),
+            ];
+            for (n, (len, cache_control)) in cases.iter().enumerate() {
+                let url = Url::parse("http://example.com")?
+                    .join(&format!("/{}", n))?;
+                let script = Script::default();
+                script.respond(response(
+                    &url,
+                    200,
+                    &[("cache-control", cache_control)],
+                    &vec![b'x'; *len],
+                ));
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            }
+            // A hit fetches nothing to report
+            let url = Url::parse("http://example.com/0")?;
+            let script = Script::default();
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(script.fetches(), 0);
+
+            let counts = histogram.0.lock().unwrap().clone();
+            let expected = HashMap::from([
+                ((Some(1024), true), 2),
+                ((Some(4096), true), 1),
+                ((Some(4096), false), 2),
+                ((None, true), 1),
+            ]);
+            assert_eq!(counts, expected);
+            Ok(())
+        }
+    }
+
+    mod heuristic_freshness {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+
+        // Modified an hour before it was sent
+        const DATE: &str = "Wed, 21 Oct 2015 08:28:00 GMT";
+        const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+        fn heuristic_cache(max: Option<Duration>) -> HttpCache<MockManager> {
+            let options = HttpCacheOptions {
+                heuristic_freshness: Some(HeuristicFreshness {
+                    factor: 0.1,
+                    max,
+                }),
+                ..Default::default()
+            };
+            build_cache(&MockManager::default(), options)
+        }
+
+        // Requests the url twice, the origin answering with the headers,
+        // returning whether the second request was a hit
+        async fn hits(
+            cache: &HttpCache<MockManager>,
+            headers: &[(&str, &str)],
+        ) -> anyhow::Result<bool> {
+            let url = Url::parse("http://example.com/")?;
+            let script = Script::default();
+            let mut all =
+                vec![("date", DATE), ("last-modified", LAST_MODIFIED)];
+            all.extend_from_slice(headers);
+            script.respond(response(&url, 200, &all, TEST_BODY));
+            script.respond(response(&url, 200, &all, TEST_BODY));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            Ok(script.fetches() == 1)
+        }
+
+        #[async_std::test]
+        async fn a_tenth_of_an_hour_is_six_minutes() -> anyhow::Result<()> {
+            let cache = heuristic_cache(None);
+            assert!(hits(&cache, &[]).await?);
+            let url = Url::parse("http://example.com/")?;
+            let (_, policy) = cache.manager.entry(GET, &url).unwrap();
+            let ttl = policy.time_to_live(now());
+            assert!(ttl > Duration::from_secs(350), "{:?}", ttl);
+            assert!(ttl <= Duration::from_secs(360), "{:?}", ttl);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn the_cap_shortens_heuristic_freshness() -> anyhow::Result<()> {
+            let older = [("age", "120")];
+            assert!(hits(&heuristic_cache(None), &older).await?);
+            let capped = heuristic_cache(Some(Duration::from_secs(60)));
+            assert!(!hits(&capped, &older).await?);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn explicit_freshness_isnt_capped() -> anyhow::Result<()> {
+            let capped = heuristic_cache(Some(Duration::from_secs(60)));
+            let headers = [("age", "120"), ("cache-control", "max-age=600")];
+            assert!(hits(&capped, &headers).await?);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn no_store_is_respected() -> anyhow::Result<()> {
+            let cache = heuristic_cache(None);
+            assert!(!hits(&cache, &[("cache-control", "no-store")]).await?);
+            assert_eq!(cache.manager.puts(), 0);
+            Ok(())
+        }
+    }
+
+    mod interim_and_non_authoritative {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use std::sync::Arc;
+
+        const TRANSFORMED: &str = "214 proxy \"Transformation applied\"";
+
+        #[async_std::test]
+        async fn interim_responses_are_never_stored() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let always = |_: &HttpResponse| Some(true);
+            let cache = build_cache(
+                &MockManager::default(),
+                HttpCacheOptions {
+                    cacheability_override: Some(Arc::new(always)),
+                    negative_ttl: Some(Duration::from_secs(60)),
+                    negative_statuses: vec![103],
+                    ..Default::default()
+                },
+            );
+            for status in [100, 103] {
+                let script = Script::default();
+                script.respond(response(
+                    &url,
+                    status,
+                    &[("cache-control", CACHEABLE_PUBLIC)],
+                    b"",
+                ));
+                let res = cache
+                    .run(ScriptedMiddleware::new(GET, &url, &script)?)
+                    .await?;
+                assert_eq!(res.status, status);
+            }
+            assert_eq!(cache.manager.puts(), 0);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn non_authoritative_round_trips() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = build_cache(
+                &MockManager::default(),
+                HttpCacheOptions::default(),
+            );
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                203,
+                &[
+                    ("cache-control", CACHEABLE_PUBLIC),
+                    ("warning", TRANSFORMED),
+                ],
+                TEST_BODY,
+            ));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(script.fetches(), 1);
+            assert_eq!(res.status, 203);
+            assert_eq!(res.body, TEST_BODY);
+            // The cache transforms nothing itself, so the warning is the
+            // one the response came with
+            assert_eq!(res.headers["warning"], TRANSFORMED);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn revalidation_keeps_the_transformation_warning(
+        ) -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = build_cache(
+                &MockManager::default(),
+                HttpCacheOptions::default(),
+            );
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                203,
+                &[
+                    ("cache-control", "public, max-age=0"),
+                    ("etag", "\"v1\""),
+                    ("warning", TRANSFORMED),
+                ],
+                TEST_BODY,
+            ));
+            script.respond(response(&url, 304, &[("etag", "\"v1\"")], b""));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(script.fetches(), 2);
+            assert_eq!(res.status, 203);
+            assert_eq!(res.body, TEST_BODY);
+            assert_eq!(res.headers["warning"], TRANSFORMED);
+            Ok(())
+        }
+    }
+
+    mod content_length_mismatch {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+
+        fn options(
+            content_length_mismatch: ContentLengthMismatch,
+        ) -> HttpCacheOptions {
+            HttpCacheOptions { content_length_mismatch, ..Default::default() }
+        }
+
+        // Declares more than the body it arrives with, as when the
+        // connection was cut short
+        fn truncated(url: &Url, cache_control: &str) -> HttpResponse {
+            response(
+                url,
+                200,
+                &[("cache-control", cache_control), ("content-length", "100")],
+                TEST_BODY,
+            )
+        }
+
+        #[async_std::test]
+        async fn stored_as_is_by_default() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = build_cache(
+                &MockManager::default(),
+                options(ContentLengthMismatch::default()),
+            );
+            let script = Script::default();
+            script.respond(truncated(&url, CACHEABLE_PUBLIC));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            let (stored, _) = cache.manager.entry(GET, &url).unwrap();
+            assert_eq!(stored.headers["content-length"], "100");
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn rejected_on_a_miss() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = build_cache(
+                &MockManager::default(),
+                options(ContentLengthMismatch::Reject),
+            );
+            let script = Script::default();
+            script.respond(truncated(&url, CACHEABLE_PUBLIC));
+            let err = cache
+                .run(ScriptedMiddleware::new(GET, &url, &script)?)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                CacheError::ContentLengthMismatch { declared: 100, actual }
+                    if actual == TEST_BODY.len() as u64
+            ));
+            assert_eq!(cache.manager.puts(), 0);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn rejected_revalidation_serves_stale() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = build_cache(
+                &MockManager::default(),
+                options(ContentLengthMismatch::Reject),
+            );
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                200,
+                &[("cache-control", "public, max-age=0")],
+                b"stored",
+            ));
+            script.respond(truncated(&url, CACHEABLE_PUBLIC));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(script.fetches(), 2);
+            assert_eq!(res.body, b"stored".as_ref());
+            assert_eq!(res.warning_code(), Some(111));
+            assert_eq!(cache.manager.puts(), 1);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn corrected_before_storing() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache = build_cache(
+                &MockManager::default(),
+                options(ContentLengthMismatch::Correct),
+            );
+            let script = Script::default();
+            script.respond(truncated(&url, CACHEABLE_PUBLIC));
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            let len = TEST_BODY.len().to_string();
+            assert_eq!(res.headers["content-length"], len);
+            let (stored, _) = cache.manager.entry(GET, &url).unwrap();
+            assert_eq!(stored.headers["content-length"], len);
+            assert_eq!(stored.body, TEST_BODY);
+            Ok(())
+        }
+    }
+
+    mod cache_lock {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use std::{
+            sync::{Arc, Mutex},
+            time::SystemTime,
+        };
+
+        // Leases shared by the caches of a test, as a store shared by a
+        // fleet would hold them
+        #[derive(Debug, Default)]
+        struct MemoryLock {
+            leases: Mutex<HashMap<String, SystemTime>>,
+            waits: Mutex<usize>,
+        }
+
+        #[async_trait::async_trait]
+        impl CacheLock for MemoryLock {
+            async fn try_acquire(
+                &self,
+                key: &str,
+                ttl: Duration,
+            ) -> Result<bool> {
+                let mut leases = self.leases.lock().unwrap();
+                let now = SystemTime::now();
+                if matches!(leases.get(key), Some(until) if now < *until) {
+                    return Ok(false);
+                }
+                leases.insert(key.to_string(), now + ttl);
+                Ok(true)
+            }
+
+            async fn release(&self, key: &str) -> Result<()> {
+                self.leases.lock().unwrap().remove(key);
+                Ok(())
+            }
+
+            async fn wait(&self, _key: &str) -> Result<()> {
+                *self.waits.lock().unwrap() += 1;
+                async_std::task::sleep(Duration::from_millis(5)).await;
+                Ok(())
+            }
+        }
+
+        // The options of a cache of the fleet, sharing the leases
+        fn options(lock: &Arc<MemoryLock>) -> HttpCacheOptions {
+            HttpCacheOptions {
+                cache_lock: Some(lock.clone()),
+                ..Default::default()
+            }
+        }
+
+        // Takes a while to hear back from the origin, so the requests of a
+        // test overlap
+        fn request(url: &Url, script: &Script) -> Result<ScriptedMiddleware> {
+            let mut middleware = ScriptedMiddleware::new(GET, url, script)?;
+            middleware.delay = Duration::from_millis(50);
+            Ok(middleware)
+        }
+
+        #[async_std::test]
+        async fn cold_key_is_fetched_once() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let lock = Arc::new(MemoryLock::default());
+            let first = build_cache(&manager, options(&lock));
+            let second = build_cache(&manager, options(&lock));
+            let script = Script::default();
+            script.respond(response(
+                &url,
+                200,
+                &[("cache-control", CACHEABLE_PUBLIC)],
+                TEST_BODY,
+            ));
+            let (a, b) = futures::join!(
+                first.run(request(&url, &script)?),
+                second.run(request(&url, &script)?)
+            );
+            assert_eq!(a?.body, TEST_BODY);
+            assert_eq!(b?.body, TEST_BODY);
+            assert_eq!(script.fetches(), 1);
+            assert!(*lock.waits.lock().unwrap() > 0);
+            assert!(lock.leases.lock().unwrap().is_empty());
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn waiting_cache_fetches_what_wasnt_stored() -> anyhow::Result<()>
+        {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let lock = Arc::new(MemoryLock::default());
+            let first = build_cache(&manager, options(&lock));
+            let second = build_cache(&manager, options(&lock));
+            let script = Script::default();
+            for _ in 0..2 {
+                script.respond(response(
+                    &url,
+                    200,
+                    &[("cache-control", "no-store")],
+                    TEST_BODY,
+                ));
+            }
+            let (a, b) = futures::join!(
+                first.run(request(&url, &script)?),
+                second.run(request(&url, &script)?)
+            );
+            assert_eq!(a?.body, TEST_BODY);
+            assert_eq!(b?.body, TEST_BODY);
+            assert_eq!(script.fetches(), 2);
+            assert_eq!(manager.puts(), 0);
+            assert!(lock.leases.lock().unwrap().is_empty());
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn lease_is_released_when_the_fetch_fails() -> anyhow::Result<()>
+        {
+            let url = Url::parse("http://example.com/")?;
+            let lock = Arc::new(MemoryLock::default());
+            let cache = build_cache(&MockManager::default(), options(&lock));
+            let script = Script::default();
+            script.fail();
+            assert!(cache.run(request(&url, &script)?).await.is_err());
+            assert!(lock.leases.lock().unwrap().is_empty());
+            Ok(())
+        }
+    }
+
+    mod request_context {
+        use crate::*;
+        use http::header::{HeaderValue, ACCEPT_LANGUAGE, USER_AGENT};
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use std::collections::BTreeMap;
+
+        fn options(
+            store_request_context: bool,
+            max_variants_per_url: Option<usize>,
+        ) -> HttpCacheOptions {
+            HttpCacheOptions {
+                store_request_context,
+                max_variants_per_url,
+                ..Default::default()
+            }
+        }
+
+        fn varied(url: &Url) -> HttpResponse {
+            response(
+                url,
+                200,
+                &[
+                    ("cache-control", CACHEABLE_PUBLIC),
+                    ("vary", "Accept-Language"),
+                ],
+                TEST_BODY,
+            )
+        }
+
+        fn request(
+            url: &Url,
+            script: &Script,
+            language: &'static str,
+        ) -> Result<ScriptedMiddleware> {
+            let mut middleware = ScriptedMiddleware::new(GET, url, script)?;
+            let headers = middleware.headers_mut();
+            headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static(language));
+            headers.insert(USER_AGENT, HeaderValue::from_static("tests"));
+            Ok(middleware)
+        }
+
+        fn context(language: &str) -> RequestContext {
+            RequestContext {
+                method: GET.to_string(),
+                headers: BTreeMap::from([(
+                    "accept-language".to_string(),
+                    language.to_string(),
+                )]),
+            }
+        }
+
+        #[async_std::test]
+        async fn varied_headers_are_kept() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache =
+                build_cache(&MockManager::default(), options(true, None));
+            let script = Script::default();
+            script.respond(varied(&url));
+            let res = cache.run(request(&url, &script, "en")?).await?;
+            assert!(!res.headers.contains_key("x-cache-request"));
+            let meta = cache.manager.metadata(GET, &url).await?.unwrap();
+            assert_eq!(meta.status, 200);
+            assert_eq!(meta.request, Some(context("en")));
+            // Nor is it served from the cache
+            let res = cache.run(request(&url, &script, "en")?).await?;
+            assert_eq!(res.headers[XCACHE], HIT);
+            assert!(!res.headers.contains_key("x-cache-request"));
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn each_variant_keeps_its_own() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache =
+                build_cache(&MockManager::default(), options(true, Some(4)));
+            let script = Script::default();
+            for language in ["en", "fr"] {
+                script.respond(varied(&url));
+                cache.run(request(&url, &script, language)?).await?;
+            }
+            let mut contexts = Vec::new();
+            for (method, key_url) in cache.manager.keys().await? {
+                let meta = cache.manager.metadata(&method, &key_url).await?;
+                contexts.extend(meta.and_then(|meta| meta.request));
+            }
+            contexts.sort_by(|a, b| a.headers.cmp(&b.headers));
+            assert_eq!(contexts, vec![context("en"), context("fr")]);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn not_kept_by_default() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let cache =
+                build_cache(&MockManager::default(), options(false, None));
+            let script = Script::default();
+            script.respond(varied(&url));
+            cache.run(request(&url, &script, "en")?).await?;
+            let meta = cache.manager.metadata(GET, &url).await?.unwrap();
+            assert_eq!(meta.request, None);
+            Ok(())
+        }
+    }
+
+    mod write_behind {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, ManagerOperation, MockManager, Script,
+            ScriptedMiddleware,
+        };
+        use std::{
+            sync::{Arc, Mutex},
+            time::Instant,
+        };
+
+        const STORE_DELAY: Duration = Duration::from_millis(300);
+
+        // Records the urls whose store failed
+        #[derive(Default)]
+        struct StoreErrors(Mutex<Vec<Url>>);
+
+        impl CacheObserver for StoreErrors {
+            fn on_timing(&self, _timings: &PhaseTimings) {}
+            fn on_store_error(
+                &self,
+                _method: &str,
+                url: &Url,
+                _error: &CacheError,
+            ) {
+                self.0.lock().unwrap().push(url.clone());
+            }
+        }
+
+        fn options(
+            manager: &MockManager,
+            observer: Option<Arc<dyn CacheObserver>>,
+        ) -> HttpCacheOptions {
+            HttpCacheOptions {
+                background: Some(Background::new(AsyncStd, manager.clone())),
+                write_behind: true,
+                observer,
+                ..Default::default()
+            }
+        }
+
+        fn cacheable(url: &Url) -> HttpResponse {
+            response(
+                url,
+                200,
+                &[("cache-control", CACHEABLE_PUBLIC)],
+                TEST_BODY,
+            )
+        }
+
+        #[async_std::test]
+        async fn response_returns_before_the_store() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            manager.delay_puts(STORE_DELAY);
+            let cache = build_cache(&manager, options(&manager, None));
+            let script = Script::default();
+            script.respond(cacheable(&url));
+
+            let started = Instant::now();
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert!(started.elapsed() < STORE_DELAY);
+            assert_eq!(res.body, TEST_BODY);
+            assert!(manager.entry(GET, &url).is_none());
+
+            // The store finishes on its own
+            async_std::task::sleep(STORE_DELAY * 2).await;
+            assert!(manager.entry(GET, &url).is_some());
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.headers.get(XCACHE).unwrap(), HIT);
+            assert_eq!(script.fetches(), 1);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn failed_store_is_reported() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            manager.fail(ManagerOperation::Put);
+            let errors = Arc::new(StoreErrors::default());
+            let cache =
+                build_cache(&manager, options(&manager, Some(errors.clone())));
+            let script = Script::default();
+            script.respond(cacheable(&url));
+
+            // The request doesn't fail, as the store comes after it
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.status, 200);
+            async_std::task::sleep(Duration::from_millis(100)).await;
+            assert_eq!(*errors.0.lock().unwrap(), vec![url]);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn shutdown_waits_for_pending_stores() -> anyhow::Result<()> {
+            let manager = MockManager::default();
+            manager.delay_puts(STORE_DELAY);
+            let cache = build_cache(&manager, options(&manager, None));
+            let script = Script::default();
+            let urls: Vec<Url> = (0..4)
+                .map(|n| Url::parse(&format!("http://example.com/{}", n)))
+                .collect::<std::result::Result<_, _>>()?;
+            for url in &urls {
+                script.respond(cacheable(url));
+                cache.run(ScriptedMiddleware::new(GET, url, &script)?).await?;
+            }
+            let background = cache.options.background.as_ref().unwrap();
+            assert_eq!(background.pending(), urls.len());
+
+            cache.shutdown(Duration::from_secs(5)).await?;
+            assert_eq!(background.pending(), 0);
+            for url in &urls {
+                assert!(manager.entry(GET, url).is_some());
+            }
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn shutdown_gives_up_at_the_timeout() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            manager.delay_puts(Duration::from_secs(2));
+            let cache = build_cache(&manager, options(&manager, None));
+            let script = Script::default();
+            script.respond(cacheable(&url));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+
+            let started = Instant::now();
+            let shutdown = cache.shutdown(Duration::from_millis(50)).await;
+            assert!(started.elapsed() < Duration::from_secs(1));
+            assert!(matches!(
+                shutdown,
+                Err(CacheError::ShutdownTimeout { pending: 1 })
+            ));
+            assert!(manager.entry(GET, &url).is_none());
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn shutdown_without_background() -> anyhow::Result<()> {
+            let cache =
+                build_cache(&MockManager::default(), Default::default());
+            cache.shutdown(Duration::ZERO).await?;
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn stored_inline_without_background() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let options =
+                HttpCacheOptions { write_behind: true, ..Default::default() };
+            let cache = build_cache(&manager, options);
+            let script = Script::default();
+            script.respond(cacheable(&url));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert!(manager.entry(GET, &url).is_some());
+            Ok(())
+        }
+    }
+
+    mod revalidation_deadline {
+        use crate::*;
+        use http_cache::test_utils::{
+            build_cache, response, MockManager, Script, ScriptedMiddleware,
+        };
+        use http_cache_semantics::CachePolicy;
+        use std::time::Instant;
+
+        const DEADLINE: Duration = Duration::from_millis(100);
+
+        const ORIGIN_DELAY: Duration = Duration::from_millis(400);
+
+        fn options(manager: &MockManager) -> HttpCacheOptions {
+            HttpCacheOptions {
+                background: Some(Background::new(AsyncStd, manager.clone())),
+                revalidation_deadline: Some(DEADLINE),
+                ..Default::default()
+            }
+        }
+
+        // Stores a stale response carrying the cache control and an etag
+        fn store_stale(
+            manager: &MockManager,
+            url: &Url,
+            cache_control: &str,
+        ) -> anyhow::Result<()> {
+            let headers = [
+                ("cache-control", cache_control),
+                ("etag", "\"v1\""),
+                ("date", "Wed, 21 Oct 2015 07:28:00 GMT"),
+            ];
+            let stored = response(url, 200, &headers, b"stale");
+            let req = http::Request::get(url.as_str()).body(())?;
+            let policy = CachePolicy::new(&req, &stored.parts()?);
+            manager.insert(GET, url, stored, policy);
+            Ok(())
+        }
+
+        fn slow_request(
+            url: &Url,
+            script: &Script,
+        ) -> anyhow::Result<ScriptedMiddleware> {
+            let mut middleware = ScriptedMiddleware::new(GET, url, script)?;
+            middleware.delay = ORIGIN_DELAY;
+            Ok(middleware)
+        }
+
+        fn fresh(url: &Url) -> HttpResponse {
+            let headers =
+                [("cache-control", CACHEABLE_PUBLIC), ("etag", "\"v2\"")];
+            response(url, 200, &headers, b"fresh")
+        }
+
+        #[async_std::test]
+        async fn stale_is_served_at_the_deadline() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            store_stale(&manager, &url, "max-age=0")?;
+            let cache = build_cache(&manager, options(&manager));
+            let script = Script::default();
+            script.respond(fresh(&url));
+
+            let started = Instant::now();
+            let res = cache.run(slow_request(&url, &script)?).await?;
+            assert!(started.elapsed() < ORIGIN_DELAY);
+            assert_eq!(res.body, b"stale"[..]);
+            assert_eq!(res.warning_code(), Some(110));
+            assert_eq!(res.headers.get(XCACHE).unwrap(), HIT);
+
+            // The revalidation finishes in the background
+            cache.shutdown(Duration::from_secs(5)).await?;
+            let (stored, _) = manager.entry(GET, &url).unwrap();
+            assert_eq!(stored.body, b"fresh"[..]);
+            assert_eq!(script.fetches(), 1);
+            let res = cache.run(slow_request(&url, &script)?).await?;
+            assert_eq!(res.body, b"fresh"[..]);
+            assert_eq!(res.warning_code(), None);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn answer_within_the_deadline_is_served() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            store_stale(&manager, &url, "max-age=0")?;
+            let cache = build_cache(&manager, options(&manager));
+            let script = Script::default();
+            script.respond(fresh(&url));
+
+            let res =
+                cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+            assert_eq!(res.body, b"fresh"[..]);
+            assert_eq!(res.warning_code(), None);
+            assert_eq!(manager.entry(GET, &url).unwrap().0.body, b"fresh"[..]);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn must_revalidate_is_waited_on() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            store_stale(&manager, &url, "max-age=0, must-revalidate")?;
+            let cache = build_cache(&manager, options(&manager));
+            let script = Script::default();
+            script.respond(fresh(&url));
+
+            let started = Instant::now();
+            let res = cache.run(slow_request(&url, &script)?).await?;
+            assert!(started.elapsed() >= ORIGIN_DELAY);
+            assert_eq!(res.body, b"fresh"[..]);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn background_revalidations_are_bounded() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            store_stale(&manager, &url, "max-age=0")?;
+            let options = HttpCacheOptions {
+                max_background_revalidations: Some(2),
+                ..options(&manager)
+            };
+            let cache = build_cache(&manager, options);
+            let script = Script::default();
+            for _ in 0..10 {
+                script.respond(fresh(&url));
+            }
+
+            let requests = (0..10)
+                .map(|_| Ok(cache.run(slow_request(&url, &script)?)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let started = Instant::now();
+            for res in futures::future::join_all(requests).await {
+                let res = res?;
+                assert_eq!(res.body, b"stale"[..]);
+                assert_eq!(res.warning_code(), Some(110));
+            }
+            assert!(started.elapsed() < ORIGIN_DELAY);
+
+            // Only the revalidations that got a slot were sent
+            cache.shutdown(Duration::from_secs(5)).await?;
+            assert_eq!(script.fetches(), 2);
+            assert_eq!(script.max_in_flight(), 2);
+            Ok(())
+        }
+
+        #[async_std::test]
+        async fn fresh_response_spawns_nothing() -> anyhow::Result<()> {
+            let url = Url::parse("http://example.com/")?;
+            let manager = MockManager::default();
+            let cache = build_cache(&manager, options(&manager));
+            let script = Script::default();
+            script.respond(fresh(&url));
+            cache.run(ScriptedMiddleware::new(GET, &url, &script)?).await?;
+
+            let res = cache.run(slow_request(&url, &script)?).await?;
+            assert_eq!(res.headers.get(XCACHE).unwrap(), HIT);
+            let background = cache.options.background.as_ref().unwrap();
+            assert_eq!(background.pending(), 0);
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,78 @@
+use crate::fake::{Boxed, FakeRequest, Origin, Unboxed};
+use crate::*;
+
+use http_cache::UnboxedMiddleware;
+
+// What the client sees of a response
+type Seen = (u16, String, String, Bytes);
+
+fn seen(res: HttpResponse) -> Seen {
+    (
+        res.status,
+        res.headers[XCACHE].clone(),
+        res.headers[XCACHELOOKUP].clone(),
+        res.body,
+    )
+}
+
+// Runs the same requests through a fresh cache, wrapping each in the
+// middleware built by `wrap`
+async fn scenario<M: UnboxedMiddleware>(
+    wrap: impl Fn(FakeRequest) -> M,
+) -> anyhow::Result<(Vec<Seen>, usize)> {
+    let url = Url::parse("http://example.com/")?;
+    let origin = Origin::default();
+    origin.push(&url, CACHEABLE_PUBLIC, TEST_BODY);
+    origin.push(&url, CACHEABLE_PUBLIC, b"reloaded");
+    origin.push(&url, MUST_REVALIDATE, b"revalidated");
+    let cache = HttpCache {
+        mode: CacheMode::Default,
+        manager: MemoryManager::default(),
+        options: HttpCacheOptions::default(),
+    };
+    let mut responses = Vec::new();
+    for mode in [
+        None,
+        None,
+        Some(CacheMode::Reload),
+        Some(CacheMode::NoCache),
+        Some(CacheMode::OnlyIfCached),
+    ] {
+        let req = FakeRequest::new(&url, mode, &origin);
+        responses.push(seen(cache.run(wrap(req)).await?));
+    }
+    Ok((responses, origin.fetches()))
+}
+
+#[async_std::test]
+async fn parity_with_boxed() -> anyhow::Result<()> {
+    let boxed = scenario(Boxed).await?;
+    let unboxed = scenario(Unboxed).await?;
+    assert_eq!(boxed, unboxed);
+
+    // Make sure the scenario covered both hits and misses
+    let (responses, fetches) = unboxed;
+    assert_eq!(fetches, 3);
+    let hits: Vec<_> = responses.iter().map(|r| r.1 == HIT).collect();
+    assert_eq!(hits, [false, true, false, false, true]);
+    assert_eq!(responses[4].3, &b"revalidated"[..]);
+    Ok(())
+}
+
+#[async_std::test]
+async fn no_cache_request() -> anyhow::Result<()> {
+    let url = Url::parse("http://example.com/")?;
+    let origin = Origin::default();
+    origin.push(&url, CACHEABLE_PUBLIC, TEST_BODY);
+    let cache = HttpCache {
+        mode: CacheMode::NoStore,
+        manager: MemoryManager::default(),
+        options: HttpCacheOptions::default(),
+    };
+    let middleware = Unboxed(FakeRequest::new(&url, None, &origin));
+    assert!(!cache.can_cache_request(&middleware));
+    let res = cache.run(middleware).await?;
+    assert_eq!(res.headers[XCACHE], MISS);
+    assert!(cache.manager.get(GET, &url).await?.is_none());
+    Ok(())
+}
@@ -0,0 +1,507 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The tower middleware implementation for http-cache. [`HttpCacheLayer`]
+//! wraps any client [`Service`] taking an [`http::Request`] and returning an
+//! [`http::Response`], such as those built with hyper-util or tonic's
+//! transport.
+//! ```no_run
+//! use bytes::Bytes;
+//! use http::{Request, Response};
+//! use http_body_util::{Empty, Full};
+//! use http_cache_tower::{CACacheManager, CacheMode, HttpCache, HttpCacheLayer, HttpCacheOptions};
+//! use tower::{service_fn, ServiceBuilder, ServiceExt};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//!     // Stands in for the client service doing the actual requests
+//!     let client = service_fn(|_req: Request<Empty<Bytes>>| async {
+//!         Ok::<_, std::convert::Infallible>(Response::new(Full::new(Bytes::from("hello"))))
+//!     });
+//!     let service = ServiceBuilder::new()
+//!         .layer(HttpCacheLayer::new(HttpCache {
+//!             mode: CacheMode::Default,
+//!             manager: CACacheManager::default(),
+//!             options: HttpCacheOptions::default(),
+//!         }))
+//!         .service(client);
+//!     let req = Request::get("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching")
+//!         .body(Empty::new())?;
+//!     service.oneshot(req).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Response bodies are buffered so they can be stored, the service responds
+//! with a [`CacheBody`] either way, which replays the response's trailers
+//! after its data. Requests the cache can't be keyed by, as
+//! their uri isn't an absolute http(s) url, are passed through uncached. The
+//! [`CacheMode`] can be overridden for a single request by adding it as an
+//! extension, which is removed before the request is sent on.
+//!
+//! To cache the responses of your own service instead, such as an axum
+//! router, see [`server`].
+use anyhow::anyhow;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use http::{
+    header::{HeaderName, CACHE_CONTROL},
+    request, HeaderMap, HeaderValue, Method, Request, Response, Version,
+};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use http_cache::{
+    now, CacheError, CacheManager, HitOrMiss, HttpVersion, Middleware, Result,
+    CACHE_STATUS, XCACHE, XCACHELOOKUP,
+};
+use http_cache_semantics::CachePolicy;
+use tower::{Layer, Service, ServiceExt};
+use url::Url;
+
+pub use http_cache::{
+    CacheMode, CacheOptions, CacheStatusHeader, CookiePolicy, HttpCache,
+    HttpCacheOptions, HttpResponse, RedirectKey, SetCookiePolicy,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
+
+#[cfg(feature = "manager-memory")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-memory")))]
+pub use http_cache::MemoryManager;
+
+pub mod server;
+
+/// The error type of the wrapped service and its response bodies
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Applies the cache to the wrapped service
+pub struct HttpCacheLayer<T: CacheManager> {
+    /// The cache that requests are run through
+    pub cache: Arc<HttpCache<T>>,
+}
+
+impl<T: CacheManager> HttpCacheLayer<T> {
+    /// Creates a new layer running requests through the cache
+    pub fn new(cache: HttpCache<T>) -> Self {
+        Self { cache: Arc::new(cache) }
+    }
+}
+
+impl<T: CacheManager> Clone for HttpCacheLayer<T> {
+    fn clone(&self) -> Self {
+        Self { cache: self.cache.clone() }
+    }
+}
+
+impl<T: CacheManager> fmt::Debug for HttpCacheLayer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpCacheLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S, T: CacheManager> Layer<S> for HttpCacheLayer<T> {
+    type Service = HttpCacheService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpCacheService { inner, cache: self.cache.clone() }
+    }
+}
+
+/// The service produced by [`HttpCacheLayer`]
+pub struct HttpCacheService<S, T: CacheManager> {
+    inner: S,
+    cache: Arc<HttpCache<T>>,
+}
+
+impl<S: Clone, T: CacheManager> Clone for HttpCacheService<S, T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), cache: self.cache.clone() }
+    }
+}
+
+impl<S, T: CacheManager> fmt::Debug for HttpCacheService<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpCacheService").finish_non_exhaustive()
+    }
+}
+
+/// Implements ['Middleware'] for tower services
+pub(crate) struct TowerMiddleware<S, B> {
+    pub parts: request::Parts,
+    pub url: Url,
+    pub mode: Option<CacheMode>,
+    // Only ever accessed mutably, the locks make the middleware `Sync`
+    // without requiring the service and body to be
+    pub body: Mutex<Option<B>>,
+    pub inner: Mutex<S>,
+}
+
+// Converts headers between the http versions used by the service and the
+// cache
+fn to_cache_headers(headers: &HeaderMap) -> Result<http02::HeaderMap> {
+    let mut converted = http02::HeaderMap::new();
+    for (name, value) in headers {
+        converted.append(
+            http02::header::HeaderName::from_bytes(name.as_str().as_bytes())?,
+            http02::HeaderValue::from_bytes(value.as_bytes())?,
+        );
+    }
+    Ok(converted)
+}
+
+fn general<E>(e: E) -> CacheError
+where
+    E: Into<BoxError>,
+{
+    CacheError::General(anyhow!(e.into()))
+}
+
+fn version_from(version: Version) -> Result<HttpVersion> {
+    Ok(match version {
+        Version::HTTP_09 => HttpVersion::Http09,
+        Version::HTTP_10 => HttpVersion::Http10,
+        Version::HTTP_11 => HttpVersion::Http11,
+        Version::HTTP_2 => HttpVersion::H2,
+        Version::HTTP_3 => HttpVersion::H3,
+        _ => return Err(CacheError::BadVersion),
+    })
+}
+
+fn version_into(version: HttpVersion) -> Result<Version> {
+    Ok(match version {
+        HttpVersion::Http09 => Version::HTTP_09,
+        HttpVersion::Http10 => Version::HTTP_10,
+        HttpVersion::Http11 => Version::HTTP_11,
+        HttpVersion::H2 => Version::HTTP_2,
+        HttpVersion::H3 => Version::HTTP_3,
+        _ => return Err(CacheError::BadVersion),
+    })
+}
+
+/// The body of responses from the cache services, the buffered data
+/// followed by any trailers the response had
+#[derive(Debug, Default)]
+pub struct CacheBody {
+    data: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl CacheBody {
+    /// Creates a body from the data and trailers
+    pub fn new(data: Bytes, trailers: Option<HeaderMap>) -> Self {
+        Self { data: Some(data).filter(|d| !d.is_empty()), trailers }
+    }
+}
+
+impl Body for CacheBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Infallible>>> {
+        let frame = match self.data.take() {
+            Some(data) => Frame::data(data),
+            None => match self.trailers.take() {
+                Some(trailers) => Frame::trailers(trailers),
+                None => return Poll::Ready(None),
+            },
+        };
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none() && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.as_ref().map_or(0, |d| d.len() as u64))
+    }
+}
+
+// Sends the request through the wrapped service and buffers the response,
+// keeping its trailers
+async fn send<S, B, ResBody>(
+    inner: &mut S,
+    req: Request<B>,
+) -> Result<(http::response::Parts, Bytes, Option<HeaderMap>)>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Error: Into<BoxError>,
+    ResBody: Body,
+    ResBody::Error: Into<BoxError>,
+{
+    let res = inner.ready().await.map_err(general)?.call(req).await;
+    let (parts, body) = res.map_err(general)?.into_parts();
+    let collected = body.collect().await.map_err(general)?;
+    let trailers = collected.trailers().cloned();
+    Ok((parts, collected.to_bytes(), trailers))
+}
+
+// Converts trailers to and from the map they're stored as
+fn to_stored(headers: &HeaderMap) -> Result<HashMap<String, String>> {
+    let mut stored = HashMap::new();
+    for header in headers.iter() {
+        stored.insert(
+            header.0.as_str().to_owned(),
+            header.1.to_str().map_err(general)?.to_owned(),
+        );
+    }
+    Ok(stored)
+}
+
+fn from_stored(stored: HashMap<String, String>) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for header in stored {
+        headers.insert(
+            HeaderName::from_str(header.0.as_str()).map_err(general)?,
+            HeaderValue::from_str(header.1.as_str()).map_err(general)?,
+        );
+    }
+    Ok(headers)
+}
+
+#[async_trait::async_trait]
+impl<S, B, ResBody> Middleware for TowerMiddleware<S, B>
+where
+    S: Service<Request<B>, Response = Response<ResBody>> + Send,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    B: Send,
+    ResBody: Body + Send,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    fn is_method_get_head(&self) -> bool {
+        self.parts.method == Method::GET || self.parts.method == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            CacheOptions::default(),
+        ))
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &http02::request::Parts) -> Result<()> {
+        for header in parts.headers.iter() {
+            self.parts.headers.insert(
+                HeaderName::from_bytes(header.0.as_str().as_bytes())
+                    .map_err(general)?,
+                HeaderValue::from_bytes(header.1.as_bytes())
+                    .map_err(general)?,
+            );
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.parts
+            .headers
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        Ok(())
+    }
+    fn parts(&self) -> Result<http02::request::Parts> {
+        let mut converted = http02::request::Builder::new()
+            .method(self.parts.method.as_str())
+            .uri(self.url.as_str())
+            .body(())?;
+        *converted.headers_mut() = to_cache_headers(&self.parts.headers)?;
+        Ok(converted.into_parts().0)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.parts.method.as_str().to_string())
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        self.mode
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        // The body can only be sent once, which is all the cache needs
+        let body = self.body.get_mut().map_err(|e| general(e.to_string()))?;
+        let body = body.take().ok_or(CacheError::BadRequest)?;
+        let req = Request::from_parts(self.parts.clone(), body);
+        let inner = self.inner.get_mut().map_err(|e| general(e.to_string()))?;
+        let (parts, body, trailers) = send(inner, req).await?;
+        Ok(HttpResponse {
+            body,
+            headers: to_stored(&parts.headers)?,
+            trailers: match trailers {
+                Some(trailers) => to_stored(&trailers)?,
+                None => HashMap::new(),
+            },
+            status: parts.status.as_u16(),
+            url: self.url.clone(),
+            version: version_from(parts.version)?,
+        })
+    }
+}
+
+// Converts an [`HttpResponse`] to a buffered [`Response`]
+fn convert_response(response: HttpResponse) -> Result<Response<CacheBody>> {
+    let trailers = match response.trailers.is_empty() {
+        true => None,
+        false => Some(from_stored(response.trailers)?),
+    };
+    let mut ret_res = Response::builder()
+        .status(response.status)
+        .version(version_into(response.version)?)
+        .body(CacheBody::new(response.body, trailers))
+        .map_err(general)?;
+    for header in response.headers {
+        ret_res.headers_mut().insert(
+            HeaderName::from_str(header.0.as_str()).map_err(general)?,
+            HeaderValue::from_str(header.1.as_str()).map_err(general)?,
+        );
+    }
+    Ok(ret_res)
+}
+
+// Marks a response that wasn't looked up in the cache as a miss
+fn add_miss_headers(res: &mut Response<CacheBody>) -> Result<()> {
+    let miss =
+        HeaderValue::from_str(&HitOrMiss::MISS.to_string()).map_err(general)?;
+    res.headers_mut().insert(XCACHE, miss.clone());
+    res.headers_mut().insert(XCACHELOOKUP, miss);
+    Ok(())
+}
+
+// Determines the url a client request is keyed by, which requires its uri
+// to be an absolute http(s) url
+fn client_url(parts: &request::Parts) -> Option<Url> {
+    match Url::parse(&parts.uri.to_string()) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+            Some(url)
+        }
+        _ => None,
+    }
+}
+
+// Runs the request through the cache, keyed by the url `key_url` finds for
+// it
+pub(crate) async fn handle<S, T, B, ResBody>(
+    cache: Arc<HttpCache<T>>,
+    mut inner: S,
+    req: Request<B>,
+    key_url: fn(&request::Parts) -> Option<Url>,
+) -> Result<Response<CacheBody>>
+where
+    S: Service<Request<B>, Response = Response<ResBody>> + Send,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: CacheManager,
+    B: Send,
+    ResBody: Body + Send,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    let (mut parts, body) = req.into_parts();
+    let mode = parts.extensions.remove::<CacheMode>();
+    let url = match key_url(&parts) {
+        Some(url) => url,
+        // Nothing to key the response by, send the request on as is
+        None => {
+            let req = Request::from_parts(parts, body);
+            let (parts, body, trailers) = send(&mut inner, req).await?;
+            let mut res =
+                Response::from_parts(parts, CacheBody::new(body, trailers));
+            add_miss_headers(&mut res)?;
+            return Ok(res);
+        }
+    };
+    let middleware = TowerMiddleware {
+        parts,
+        url,
+        mode,
+        body: Mutex::new(Some(body)),
+        inner: Mutex::new(inner),
+    };
+    if !cache.can_cache_request(&middleware) {
+        cache.run_no_cache(&middleware).await?;
+        let headers = cache.bypass_headers(&middleware);
+        let mut middleware = middleware;
+        let mut res = convert_response(middleware.remote_fetch().await?)?;
+        for (name, value) in headers {
+            let value = HeaderValue::from_str(&value).map_err(general)?;
+            if name == CACHE_STATUS {
+                res.headers_mut().append(name, value);
+            } else {
+                res.headers_mut().insert(name, value);
+            }
+        }
+        return Ok(res);
+    }
+    convert_response(cache.run(middleware).await?)
+}
+
+impl<S, T, B, ResBody> Service<Request<B>> for HttpCacheService<S, T>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: CacheManager,
+    B: Send + 'static,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<CacheBody>;
+    type Error = CacheError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.inner.poll_ready(cx).map_err(general)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Hand the service that was driven to readiness to the request,
+        // leaving a fresh clone in its place
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(handle(self.cache.clone(), inner, req, client_url))
+    }
+}
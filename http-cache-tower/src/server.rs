@@ -0,0 +1,164 @@
+//! The cache in front of your own service, such as an axum router, acting
+//! as a shared cache for the responses its handlers produce. The layer
+//! follows the same caching rules as on the client side.
+//! ```no_run
+//! use bytes::Bytes;
+//! use http::{Request, Response};
+//! use http_body_util::{Empty, Full};
+//! use http_cache_tower::server::ServerCacheLayer;
+//! use http_cache_tower::{CACacheManager, CacheMode, HttpCache, HttpCacheOptions};
+//! use tower::{service_fn, ServiceBuilder, ServiceExt};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//!     // Stands in for the handlers producing the responses
+//!     let app = service_fn(|_req: Request<Empty<Bytes>>| async {
+//!         let res = Response::builder()
+//!             .header("cache-control", "max-age=60")
+//!             .body(Full::new(Bytes::from("hello")))?;
+//!         Ok::<_, http::Error>(res)
+//!     });
+//!     let service = ServiceBuilder::new()
+//!         .layer(ServerCacheLayer::new(HttpCache {
+//!             mode: CacheMode::Default,
+//!             manager: CACacheManager::default(),
+//!             options: HttpCacheOptions::default(),
+//!         }))
+//!         .service(app);
+//!     let req = Request::get("/").header("host", "example.com").body(Empty::new())?;
+//!     service.oneshot(req).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Requests to a server usually carry just a path, so responses are keyed
+//! by the `Host` header and the path, as `http://{host}{path}`. Requests
+//! without a `Host` header, or with an absolute uri, are keyed by that uri.
+//! Requests that can be keyed by neither are passed to the service
+//! uncached.
+//!
+//! The cache is shared by default, as [`CacheOptions::shared`] is, so
+//! responses marked `private` aren't stored and neither are responses to
+//! requests carrying `Authorization` unless they explicitly allow it.
+//! Fresh responses are served with an `Age` header, stale ones are
+//! revalidated with the service, and requests with unsafe methods remove
+//! the stored response for their url.
+//!
+//! The service fails with a [`CacheError`], so with axum it has to be
+//! wrapped in a `HandleErrorLayer`.
+//!
+//! [`CacheOptions::shared`]: crate::CacheOptions::shared
+//! [`CacheError`]: http_cache::CacheError
+use crate::{general, handle, BoxError, CacheBody};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http::{header::HOST, request, Request, Response};
+use http_body::Body;
+use http_cache::{CacheError, CacheManager, HttpCache, Result};
+use tower::{Layer, Service};
+use url::Url;
+
+/// Applies the cache to the responses of the wrapped server
+pub struct ServerCacheLayer<T: CacheManager> {
+    /// The cache that requests are run through
+    pub cache: Arc<HttpCache<T>>,
+}
+
+impl<T: CacheManager> ServerCacheLayer<T> {
+    /// Creates a new layer caching the responses of the service
+    pub fn new(cache: HttpCache<T>) -> Self {
+        Self { cache: Arc::new(cache) }
+    }
+}
+
+impl<T: CacheManager> Clone for ServerCacheLayer<T> {
+    fn clone(&self) -> Self {
+        Self { cache: self.cache.clone() }
+    }
+}
+
+impl<T: CacheManager> fmt::Debug for ServerCacheLayer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServerCacheLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S, T: CacheManager> Layer<S> for ServerCacheLayer<T> {
+    type Service = ServerCacheService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ServerCacheService { inner, cache: self.cache.clone() }
+    }
+}
+
+/// The service produced by [`ServerCacheLayer`]
+pub struct ServerCacheService<S, T: CacheManager> {
+    inner: S,
+    cache: Arc<HttpCache<T>>,
+}
+
+impl<S: Clone, T: CacheManager> Clone for ServerCacheService<S, T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), cache: self.cache.clone() }
+    }
+}
+
+impl<S, T: CacheManager> fmt::Debug for ServerCacheService<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServerCacheService").finish_non_exhaustive()
+    }
+}
+
+// Determines the url a request to the server is keyed by, from its `Host`
+// header and path or failing that its absolute uri
+fn server_url(parts: &request::Parts) -> Option<Url> {
+    let url = match parts.headers.get(HOST).and_then(|h| h.to_str().ok()) {
+        Some(host) => {
+            let path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+            Url::parse(&format!("http://{}{}", host, path)).ok()?
+        }
+        None => Url::parse(&parts.uri.to_string()).ok()?,
+    };
+    match url.scheme() {
+        "http" | "https" => Some(url),
+        _ => None,
+    }
+}
+
+impl<S, T, B, ResBody> Service<Request<B>> for ServerCacheService<S, T>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: CacheManager,
+    B: Send + 'static,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<CacheBody>;
+    type Error = CacheError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.inner.poll_ready(cx).map_err(general)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Hand the service that was driven to readiness to the request,
+        // leaving a fresh clone in its place
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(handle(self.cache.clone(), inner, req, server_url))
+    }
+}
@@ -0,0 +1,267 @@
+#![forbid(unsafe_code, future_incompatible)]
+#![deny(
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    nonstandard_style,
+    unused_qualifications,
+    unused_import_braces,
+    unused_extern_crates,
+    trivial_casts,
+    trivial_numeric_casts
+)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+//! The ureq client implementation for http-cache. [`Cache`] wraps a
+//! [`ureq::Agent`], requests are run through the cache on the calling thread.
+//! ```no_run
+//! use http_cache_ureq::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+//!
+//! fn main() -> http_cache::Result<()> {
+//!     let client = Cache::new(
+//!         ureq::Agent::new(),
+//!         HttpCache {
+//!             mode: CacheMode::Default,
+//!             manager: CACacheManager::default(),
+//!             options: HttpCacheOptions::default(),
+//!         },
+//!     );
+//!     let res = client.get("https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching")?;
+//!     println!("{}", String::from_utf8_lossy(res.body()));
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Responses are returned as an [`http::Response`] with the body read into a
+//! `Vec<u8>`. Error statuses are responses like any other rather than a
+//! [`ureq::Error`]. The [`CacheMode`] can be overridden for a single request
+//! by passing it to [`Cache::execute`] as an extension.
+use anyhow::anyhow;
+use std::{collections::HashMap, io::Read};
+
+use http::{
+    header::{HeaderName, CACHE_CONTROL},
+    request::{self, Parts},
+    HeaderMap, HeaderValue, Method, Request, Response,
+};
+use http_cache::{
+    now, CacheError, CacheLookupStatus, CacheManager, HttpVersion, Middleware,
+    Result, CACHE_STATUS,
+};
+use http_cache_semantics::CachePolicy;
+use ureq::Agent;
+use url::Url;
+
+pub use http_cache::{
+    CacheMode, CacheOptions, CacheStatusHeader, CookiePolicy, HttpCache,
+    HttpCacheOptions, HttpResponse, RedirectKey, SetCookiePolicy,
+};
+
+#[cfg(feature = "manager-cacache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
+pub use http_cache::CACacheManager;
+
+#[cfg(feature = "manager-moka")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+pub use http_cache::{MokaCache, MokaCacheBuilder, MokaManager};
+
+#[cfg(feature = "manager-memory")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-memory")))]
+pub use http_cache::MemoryManager;
+
+/// Wraps a [`ureq::Agent`] so that requests consult the cache
+#[derive(Debug)]
+pub struct Cache<T: CacheManager> {
+    /// The agent used to fetch upstream resources
+    pub agent: Agent,
+    /// The cache that requests are run through
+    pub cache: HttpCache<T>,
+}
+
+/// Implements ['Middleware'] for ureq
+pub(crate) struct UreqMiddleware<'a> {
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub mode: Option<CacheMode>,
+    pub agent: &'a Agent,
+}
+
+impl UreqMiddleware<'_> {
+    // Sends the request with the agent, whatever the response status
+    fn send(&self) -> Result<HttpResponse> {
+        let mut req = self.agent.request_url(self.method.as_str(), &self.url);
+        for (name, value) in self.headers.iter() {
+            req = req.set(name.as_str(), value.to_str()?);
+        }
+        let res = match req.send_bytes(&self.body) {
+            Ok(r) | Err(ureq::Error::Status(_, r)) => r,
+            Err(e) => return Err(CacheError::General(anyhow!(e))),
+        };
+        let mut headers = HashMap::new();
+        for name in res.headers_names() {
+            if let Some(value) = res.header(&name) {
+                headers.insert(name, value.to_owned());
+            }
+        }
+        let url = Url::parse(res.get_url())?;
+        let status = res.status();
+        let version = match res.http_version() {
+            "HTTP/0.9" => HttpVersion::Http09,
+            "HTTP/1.0" => HttpVersion::Http10,
+            "HTTP/1.1" => HttpVersion::Http11,
+            "HTTP/2.0" => HttpVersion::H2,
+            "HTTP/3.0" => HttpVersion::H3,
+            _ => return Err(CacheError::BadVersion),
+        };
+        let mut body = Vec::new();
+        if let Err(e) = res.into_reader().read_to_end(&mut body) {
+            return Err(CacheError::General(anyhow!(e)));
+        }
+        Ok(HttpResponse {
+            body: body.into(),
+            headers,
+            trailers: HashMap::new(),
+            status,
+            url,
+            version,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for UreqMiddleware<'_> {
+    fn is_method_get_head(&self) -> bool {
+        self.method == Method::GET || self.method == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        self.policy_with_options(response, CacheOptions::default())
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.parts()?,
+            &response.parts()?,
+            now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &Parts) -> Result<()> {
+        for header in parts.headers.iter() {
+            self.headers.insert(header.0.clone(), header.1.clone());
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.headers.insert(CACHE_CONTROL, HeaderValue::from_str("no-cache")?);
+        Ok(())
+    }
+    fn parts(&self) -> Result<Parts> {
+        let mut converted = request::Builder::new()
+            .method(self.method.as_str())
+            .uri(self.url.as_str())
+            .body(())?;
+        *converted.headers_mut() = self.headers.clone();
+        Ok(converted.into_parts().0)
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.method.as_ref().to_string())
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        self.mode
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        self.send()
+    }
+}
+
+// Converts an [`HttpResponse`] to an [`http::Response`]
+fn convert_response(response: HttpResponse) -> Result<Response<Vec<u8>>> {
+    let mut ret_res = Response::builder()
+        .status(response.status)
+        .version(response.version.into())
+        .body(response.body.to_vec())?;
+    for header in response.headers {
+        ret_res.headers_mut().insert(
+            HeaderName::from_bytes(header.0.as_bytes())?,
+            HeaderValue::from_str(header.1.as_str())?,
+        );
+    }
+    Ok(ret_res)
+}
+
+impl<T: CacheManager> Cache<T> {
+    /// Creates a new cache client
+    pub fn new(agent: Agent, cache: HttpCache<T>) -> Self {
+        Self { agent, cache }
+    }
+
+    /// Sends a GET request to the url, consulting the cache
+    pub fn get(&self, url: &str) -> Result<Response<Vec<u8>>> {
+        self.request("GET", url)
+    }
+
+    /// Sends a request with the method to the url, consulting the cache
+    pub fn request(
+        &self,
+        method: &str,
+        url: &str,
+    ) -> Result<Response<Vec<u8>>> {
+        let req = Request::builder().method(method).uri(url).body(Vec::new())?;
+        self.execute(req)
+    }
+
+    /// Executes the request, consulting the cache. A [`CacheMode`] extension
+    /// on the request overrides the mode for this request only.
+    pub fn execute(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+        let (parts, body) = req.into_parts();
+        let middleware = UreqMiddleware {
+            url: Url::parse(&parts.uri.to_string())?,
+            method: parts.method,
+            headers: parts.headers,
+            body,
+            mode: parts.extensions.get::<CacheMode>().copied(),
+            agent: &self.agent,
+        };
+        if !self.cache.can_cache_request(&middleware) {
+            futures_executor::block_on(self.cache.run_no_cache(&middleware))?;
+            let headers = self.cache.bypass_headers(&middleware);
+            let mut res = convert_response(middleware.send()?)?;
+            for (name, value) in headers {
+                let value = HeaderValue::from_str(&value)?;
+                if name == CACHE_STATUS {
+                    res.headers_mut().append(name, value);
+                } else {
+                    res.headers_mut().insert(name, value);
+                }
+            }
+            return Ok(res);
+        }
+        let res = futures_executor::block_on(self.cache.run(middleware))?;
+        convert_response(res)
+    }
+
+    /// Issues a conditional request for the stored response, refreshing or
+    /// replacing it, see [`HttpCache::revalidate`]
+    pub fn revalidate(
+        &self,
+        method: &str,
+        url: &str,
+    ) -> Result<CacheLookupStatus> {
+        let middleware = UreqMiddleware {
+            method: Method::from_bytes(method.as_bytes())?,
+            url: Url::parse(url)?,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            mode: None,
+            agent: &self.agent,
+        };
+        futures_executor::block_on(self.cache.revalidate(middleware))
+    }
+}
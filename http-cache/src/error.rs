@@ -53,6 +53,10 @@ pub enum CacheError {
     #[error(transparent)]
     #[diagnostic(code(http_cache::bincode))]
     Bincode(#[from] Box<bincode::ErrorKind>),
+    /// Error from serde_json
+    #[error(transparent)]
+    #[diagnostic(code(http_cache::serde_json))]
+    SerdeJson(#[from] serde_json::Error),
     /// There was an error parsing the HTTP request version
     #[error("Unknown HTTP version")]
     #[diagnostic(code(http_cache::bad_version))]
@@ -61,6 +65,35 @@ pub enum CacheError {
     #[error("Error parsing header value")]
     #[diagnostic(code(http_cache::bad_header))]
     BadHeader,
+    /// A stored entry was written in a serialization format this version
+    /// doesn't know
+    #[error("Unknown serialization format")]
+    #[diagnostic(code(http_cache::unknown_format))]
+    UnknownFormat,
+    /// The cache manager can't list the records it holds
+    #[error("The cache manager can't list its keys")]
+    #[diagnostic(code(http_cache::keys_unsupported))]
+    KeysUnsupported,
+    /// A response from the origin has a body of a different length than
+    /// its `Content-Length` declares
+    #[error(
+        "Content-Length declared {declared} bytes but the body has {actual}"
+    )]
+    #[diagnostic(code(http_cache::content_length_mismatch))]
+    ContentLengthMismatch {
+        /// The length `Content-Length` declares
+        declared: u64,
+        /// The length of the body
+        actual: u64,
+    },
+    /// Work handed to the background was still running when
+    /// [`HttpCache::shutdown`](crate::HttpCache::shutdown) gave up on it
+    #[error("{pending} background tasks were still running at shutdown")]
+    #[diagnostic(code(http_cache::shutdown_timeout))]
+    ShutdownTimeout {
+        /// The number of tasks that hadn't finished
+        pending: usize,
+    },
     /// There was an error parsing the HTTP request
     #[error(
         "Request object is not cloneable. Are you passing a streaming body?"
@@ -20,21 +20,59 @@
 //! The following features are available. By default `manager-cacache` is enabled.
 //!
 //! - `manager-cacache` (default): enable [cacache](https://github.com/zkat/cacache-rs),
-//! a high-performance disk cache, backend manager.
+//!   a high-performance disk cache, backend manager.
 //! - `manager-moka` (disabled): enable [moka](https://github.com/moka-rs/moka),
-//! a high-performance in-memory cache, backend manager.
+//!   a high-performance in-memory cache, backend manager.
+//! - `manager-memory` (disabled): enable a simple `HashMap` backed in-memory
+//!   manager, which unlike the others also works on `wasm32-unknown-unknown`.
+//! - `unboxed-middleware` (disabled): add `UnboxedMiddleware`, a variant of
+//!   `Middleware` that doesn't box the future returned by `remote_fetch`.
+//!   Requires Rust 1.75 or newer.
+//! - `local-middleware` (disabled): add `LocalMiddleware`, a variant of
+//!   `UnboxedMiddleware` without the `Send` bounds, for clients whose
+//!   futures aren't `Send`. Implies `unboxed-middleware`, and so requires
+//!   Rust 1.75 or newer.
 //! - `with-http-types` (disabled): enable [http-types](https://github.com/http-rs/http-types)
-//! type conversion support
+//!   type conversion support
+//! - `test-utils` (disabled): add the `test_utils` module, with a
+//!   `CacheManager` that records its calls and a `Middleware` answering from
+//!   scripted responses, for testing code built on the cache.
 mod error;
 mod managers;
 
+#[cfg(feature = "test-utils")]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-utils")))]
+pub mod test_utils;
+
 use std::{
-    collections::HashMap, convert::TryFrom, fmt, str::FromStr, time::SystemTime,
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    convert::TryFrom,
+    fmt,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
 };
 
-use http::{header::CACHE_CONTROL, request, response, StatusCode};
+use bytes::Bytes;
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture, Either},
+    lock::Mutex as AsyncMutex,
+    stream, StreamExt,
+};
+use http::{
+    header::{
+        ACCEPT_ENCODING, AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH,
+        COOKIE, DATE, ETAG, HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+        PRAGMA, RANGE, RETRY_AFTER, SET_COOKIE,
+    },
+    request, response, HeaderValue, StatusCode,
+};
 use http_cache_semantics::{AfterResponse, BeforeRequest, CachePolicy};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 use url::Url;
 
 pub use error::{CacheError, Result};
@@ -43,18 +81,45 @@ pub use error::{CacheError, Result};
 pub use managers::cacache::CACacheManager;
 
 #[cfg(feature = "manager-moka")]
-pub use managers::moka::MokaManager;
+pub use managers::moka::{MokaEntry, MokaManager, PinnedEntries};
+
+#[cfg(feature = "manager-memory")]
+pub use managers::memory::MemoryManager;
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+pub use managers::SerializationFormat;
 
 // Exposing the moka cache for convenience, renaming to avoid naming conflicts
 #[cfg(feature = "manager-moka")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
 pub use moka::future::{Cache as MokaCache, CacheBuilder as MokaCacheBuilder};
 
+/// Returns the current time. `SystemTime::now` panics on
+/// `wasm32-unknown-unknown`, so the time is read from JavaScript's `Date`
+/// there instead. Middleware implementations should build their policies
+/// with it rather than with [`CachePolicy::new`], which reads the clock
+/// itself.
+pub fn now() -> SystemTime {
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        SystemTime::UNIX_EPOCH
+            + Duration::from_millis(js_sys::Date::now() as u64)
+    }
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        SystemTime::now()
+    }
+}
+
 // Custom headers used to indicate cache status (hit or miss)
 /// `x-cache` header: Value will be HIT if the response was served from cache, MISS if not
 pub const XCACHE: &str = "x-cache";
 /// `x-cache-lookup` header: Value will be HIT if a response existed in cache, MISS if not
 pub const XCACHELOOKUP: &str = "x-cache-lookup";
+/// `cache-status` header: The structured cache status of
+/// [RFC 9211](https://www.rfc-editor.org/rfc/rfc9211), added when
+/// [`HttpCacheOptions::cache_status_header`] is set
+pub const CACHE_STATUS: &str = "cache-status";
 
 /// Represents a basic cache status
 /// Used in the custom headers `x-cache` and `x-cache-lookup`
@@ -99,10 +164,18 @@ pub enum HttpVersion {
 /// A basic generic type that represents an HTTP response
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct HttpResponse {
-    /// HTTP response body
-    pub body: Vec<u8>,
-    /// HTTP response headers
+    /// HTTP response body. Clones share the same buffer, so replaying a
+    /// stored response doesn't copy its body.
+    #[serde(with = "body_serde")]
+    pub body: Bytes,
+    /// HTTP response headers. They serialize ordered by name, so two stores
+    /// of the same response are byte for byte the same.
+    #[serde(serialize_with = "sorted_headers")]
     pub headers: HashMap<String, String>,
+    /// HTTP response trailers, sent after the body. Empty unless the origin
+    /// sent some and the client exposes them.
+    #[serde(default, serialize_with = "sorted_headers")]
+    pub trailers: HashMap<String, String>,
     /// HTTP response status code
     pub status: u16,
     /// HTTP response url
@@ -111,6 +184,34 @@ pub struct HttpResponse {
     pub version: HttpVersion,
 }
 
+// Serializes bodies as a sequence of bytes, like a `Vec<u8>`, so entries
+// stored before bodies were `Bytes` can still be read
+pub(crate) mod body_serde {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        body: &Bytes,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(body.iter())
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Bytes, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Bytes::from)
+    }
+}
+
+// Serializes a header map ordered by name rather than in the map's
+// arbitrary order
+fn sorted_headers<S: Serializer>(
+    headers: &HashMap<String, String>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(headers.iter().collect::<BTreeMap<_, _>>())
+}
+
 impl HttpResponse {
     /// Returns `http::response::Parts`
     pub fn parts(&self) -> Result<response::Parts> {
@@ -118,10 +219,13 @@ impl HttpResponse {
             response::Builder::new().status(self.status).body(())?;
         {
             let headers = converted.headers_mut();
-            for header in &self.headers {
+            // In order, so the header maps of policies built from the parts
+            // serialize the same
+            let sorted: BTreeMap<_, _> = self.headers.iter().collect();
+            for header in sorted {
                 headers.insert(
                     http::header::HeaderName::from_str(header.0.as_str())?,
-                    http::HeaderValue::from_str(header.1.as_str())?,
+                    HeaderValue::from_str(header.1.as_str())?,
                 );
             }
         }
@@ -154,7 +258,7 @@ impl HttpResponse {
                 code,
                 url.host().expect("Invalid URL"),
                 message,
-                httpdate::fmt_http_date(SystemTime::now())
+                httpdate::fmt_http_date(now())
             ),
         );
     }
@@ -169,7 +273,7 @@ impl HttpResponse {
         for header in parts.headers.iter() {
             self.headers.insert(
                 header.0.as_str().to_string(),
-                header.1.to_str()?.to_string(),
+                String::from_utf8_lossy(header.1.as_bytes()).into_owned(),
             );
         }
         Ok(())
@@ -213,6 +317,93 @@ pub trait CacheManager: Send + Sync + 'static {
     ) -> Result<HttpResponse>;
     /// Attempts to remove a record from cache.
     async fn delete(&self, method: &str, url: &Url) -> Result<()>;
+    /// Lists the method and url of every stored record, as they were
+    /// passed to [`put`](CacheManager::put). Managers that can't enumerate
+    /// their records return [`CacheError::KeysUnsupported`], which is what
+    /// the default does.
+    async fn keys(&self) -> Result<Vec<(String, Url)>> {
+        Err(CacheError::KeysUnsupported)
+    }
+    /// Keeps the record for the method and url from ever being evicted,
+    /// including one stored after this is called. It can still be replaced
+    /// when revalidated, or removed with [`delete`](CacheManager::delete).
+    /// Managers that never evict do nothing, which is what the default
+    /// does. [`CACacheManager`] is one of them: cacache does no size-based
+    /// eviction, so pinning has no effect there.
+    async fn pin(&self, _method: &str, _url: &Url) -> Result<()> {
+        Ok(())
+    }
+    /// Lets the record for the method and url be evicted again. Like
+    /// [`pin`](CacheManager::pin), this does nothing for managers that
+    /// never evict.
+    async fn unpin(&self, _method: &str, _url: &Url) -> Result<()> {
+        Ok(())
+    }
+    /// Checks that the backend can actually be written to and read from,
+    /// for readiness probes, returning the error it reports when it can't.
+    /// Managers backed by storage outside the process write, read and
+    /// remove a sentinel record. The default, for managers that keep their
+    /// records in memory, always succeeds.
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Describes the record stored for the method and url, including the
+    /// request it was fetched for when that was kept with
+    /// [`HttpCacheOptions::store_request_context`]. The default reads the
+    /// whole record with [`get`](CacheManager::get).
+    async fn metadata(
+        &self,
+        method: &str,
+        url: &Url,
+    ) -> Result<Option<CacheEntryMeta>> {
+        Ok(self.get(method, url).await?.map(|(res, policy)| {
+            CacheEntryMeta::new(method, res, &policy, now())
+        }))
+    }
+}
+
+// A shared manager stores into the one it holds, which is how the
+// background revalidates through the manager a `Background` was given
+#[async_trait::async_trait]
+impl<T: CacheManager + ?Sized> CacheManager for Arc<T> {
+    async fn get(
+        &self,
+        method: &str,
+        url: &Url,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        (**self).get(method, url).await
+    }
+    async fn put(
+        &self,
+        method: &str,
+        url: &Url,
+        res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        (**self).put(method, url, res, policy).await
+    }
+    async fn delete(&self, method: &str, url: &Url) -> Result<()> {
+        (**self).delete(method, url).await
+    }
+    async fn keys(&self) -> Result<Vec<(String, Url)>> {
+        (**self).keys().await
+    }
+    async fn pin(&self, method: &str, url: &Url) -> Result<()> {
+        (**self).pin(method, url).await
+    }
+    async fn unpin(&self, method: &str, url: &Url) -> Result<()> {
+        (**self).unpin(method, url).await
+    }
+    async fn health_check(&self) -> Result<()> {
+        (**self).health_check().await
+    }
+    async fn metadata(
+        &self,
+        method: &str,
+        url: &Url,
+    ) -> Result<Option<CacheEntryMeta>> {
+        (**self).metadata(method, url).await
+    }
 }
 
 /// Describes the functionality required for interfacing with HTTP client middleware
@@ -238,10 +429,291 @@ pub trait Middleware: Send {
     fn url(&self) -> Result<Url>;
     /// Attempts to determine the request method
     fn method(&self) -> Result<String>;
+    /// Returns the cache mode set for this request, if any, overriding
+    /// the mode configured on [`HttpCache`]. Clients that can't set one per
+    /// request leave it to the default, which returns `None`.
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        None
+    }
+    /// Returns the cache key set for this request, if any, which the
+    /// response is stored under in place of the one derived from the url.
+    /// The default returns `None`.
+    fn overridden_cache_key(&self) -> Option<String> {
+        None
+    }
+    /// Returns the cache options set for this request, if any, overriding
+    /// the options configured on [`HttpCache`]. The default returns `None`.
+    fn overridden_cache_options(&self) -> Option<CacheOptions> {
+        None
+    }
+    /// Returns a copy of the request that borrows nothing from the client,
+    /// so that a revalidation past [`HttpCacheOptions::revalidation_deadline`]
+    /// can finish in the background. The default returns `None`, for
+    /// clients whose requests can't outlive the call, whose revalidations
+    /// are waited on whatever the deadline.
+    fn detach(&self) -> Option<Box<dyn Middleware + Sync>> {
+        None
+    }
     /// Attempts to fetch an upstream resource and return an [`HttpResponse`]
     async fn remote_fetch(&mut self) -> Result<HttpResponse>;
 }
 
+// What `Middleware::detach` returns is driven like any other request
+#[async_trait::async_trait]
+impl<'a> Middleware for Box<dyn Middleware + Sync + 'a> {
+    fn is_method_get_head(&self) -> bool {
+        (**self).is_method_get_head()
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        (**self).policy(response)
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        (**self).policy_with_options(response, options)
+    }
+    fn update_headers(&mut self, parts: &request::Parts) -> Result<()> {
+        (**self).update_headers(parts)
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        (**self).force_no_cache()
+    }
+    fn parts(&self) -> Result<request::Parts> {
+        (**self).parts()
+    }
+    fn url(&self) -> Result<Url> {
+        (**self).url()
+    }
+    fn method(&self) -> Result<String> {
+        (**self).method()
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        (**self).overridden_cache_mode()
+    }
+    fn overridden_cache_key(&self) -> Option<String> {
+        (**self).overridden_cache_key()
+    }
+    fn overridden_cache_options(&self) -> Option<CacheOptions> {
+        (**self).overridden_cache_options()
+    }
+    fn detach(&self) -> Option<Box<dyn Middleware + Sync>> {
+        (**self).detach()
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        (**self).remote_fetch().await
+    }
+}
+
+/// A variant of [`Middleware`] whose `remote_fetch` returns its future as is
+/// rather than boxing it, which saves an allocation per fetch. Every
+/// [`Middleware`] implements it as well, so either can be passed to
+/// [`HttpCache`]. Only types implementing this trait directly save the
+/// allocation, as a [`Middleware`] driven through it still returns the
+/// future its own `remote_fetch` boxed. Requires Rust 1.75 or newer.
+#[cfg(feature = "unboxed-middleware")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unboxed-middleware")))]
+pub trait UnboxedMiddleware: Send {
+    /// See [`Middleware::is_method_get_head`]
+    fn is_method_get_head(&self) -> bool;
+    /// See [`Middleware::policy`]
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy>;
+    /// See [`Middleware::policy_with_options`]
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy>;
+    /// See [`Middleware::update_headers`]
+    fn update_headers(&mut self, parts: &request::Parts) -> Result<()>;
+    /// See [`Middleware::force_no_cache`]
+    fn force_no_cache(&mut self) -> Result<()>;
+    /// See [`Middleware::parts`]
+    fn parts(&self) -> Result<request::Parts>;
+    /// See [`Middleware::url`]
+    fn url(&self) -> Result<Url>;
+    /// See [`Middleware::method`]
+    fn method(&self) -> Result<String>;
+    /// See [`Middleware::overridden_cache_mode`]
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        None
+    }
+    /// See [`Middleware::overridden_cache_key`]
+    fn overridden_cache_key(&self) -> Option<String> {
+        None
+    }
+    /// See [`Middleware::overridden_cache_options`]
+    fn overridden_cache_options(&self) -> Option<CacheOptions> {
+        None
+    }
+    /// See [`Middleware::detach`]
+    fn detach(&self) -> Option<Box<dyn Middleware + Sync>> {
+        None
+    }
+    /// See [`Middleware::remote_fetch`]
+    fn remote_fetch(
+        &mut self,
+    ) -> impl Future<Output = Result<HttpResponse>> + Send;
+}
+
+#[cfg(feature = "unboxed-middleware")]
+impl<T: Middleware> UnboxedMiddleware for T {
+    fn is_method_get_head(&self) -> bool {
+        Middleware::is_method_get_head(self)
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        Middleware::policy(self, response)
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Middleware::policy_with_options(self, response, options)
+    }
+    fn update_headers(&mut self, parts: &request::Parts) -> Result<()> {
+        Middleware::update_headers(self, parts)
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        Middleware::force_no_cache(self)
+    }
+    fn parts(&self) -> Result<request::Parts> {
+        Middleware::parts(self)
+    }
+    fn url(&self) -> Result<Url> {
+        Middleware::url(self)
+    }
+    fn method(&self) -> Result<String> {
+        Middleware::method(self)
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        Middleware::overridden_cache_mode(self)
+    }
+    fn overridden_cache_key(&self) -> Option<String> {
+        Middleware::overridden_cache_key(self)
+    }
+    fn overridden_cache_options(&self) -> Option<CacheOptions> {
+        Middleware::overridden_cache_options(self)
+    }
+    fn detach(&self) -> Option<Box<dyn Middleware + Sync>> {
+        Middleware::detach(self)
+    }
+    fn remote_fetch(
+        &mut self,
+    ) -> impl Future<Output = Result<HttpResponse>> + Send {
+        Middleware::remote_fetch(self)
+    }
+}
+
+/// A variant of [`UnboxedMiddleware`] without the `Send` bounds, for clients
+/// whose futures can't be sent between threads, such as those running on a
+/// single-threaded runtime. The futures of [`HttpCache`] are still `Send`
+/// whenever the middleware passed to it is. Every [`UnboxedMiddleware`],
+/// and so every [`Middleware`], implements it as well. Requires Rust 1.75 or
+/// newer.
+#[cfg(feature = "local-middleware")]
+#[cfg_attr(docsrs, doc(cfg(feature = "local-middleware")))]
+pub trait LocalMiddleware {
+    /// See [`Middleware::is_method_get_head`]
+    fn is_method_get_head(&self) -> bool;
+    /// See [`Middleware::policy`]
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy>;
+    /// See [`Middleware::policy_with_options`]
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy>;
+    /// See [`Middleware::update_headers`]
+    fn update_headers(&mut self, parts: &request::Parts) -> Result<()>;
+    /// See [`Middleware::force_no_cache`]
+    fn force_no_cache(&mut self) -> Result<()>;
+    /// See [`Middleware::parts`]
+    fn parts(&self) -> Result<request::Parts>;
+    /// See [`Middleware::url`]
+    fn url(&self) -> Result<Url>;
+    /// See [`Middleware::method`]
+    fn method(&self) -> Result<String>;
+    /// See [`Middleware::overridden_cache_mode`]
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        None
+    }
+    /// See [`Middleware::overridden_cache_key`]
+    fn overridden_cache_key(&self) -> Option<String> {
+        None
+    }
+    /// See [`Middleware::overridden_cache_options`]
+    fn overridden_cache_options(&self) -> Option<CacheOptions> {
+        None
+    }
+    /// See [`Middleware::detach`]
+    fn detach(&self) -> Option<Box<dyn Middleware + Sync>> {
+        None
+    }
+    /// See [`Middleware::remote_fetch`]
+    fn remote_fetch(&mut self) -> impl Future<Output = Result<HttpResponse>>;
+}
+
+#[cfg(feature = "local-middleware")]
+impl<T: UnboxedMiddleware> LocalMiddleware for T {
+    fn is_method_get_head(&self) -> bool {
+        UnboxedMiddleware::is_method_get_head(self)
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        UnboxedMiddleware::policy(self, response)
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        UnboxedMiddleware::policy_with_options(self, response, options)
+    }
+    fn update_headers(&mut self, parts: &request::Parts) -> Result<()> {
+        UnboxedMiddleware::update_headers(self, parts)
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        UnboxedMiddleware::force_no_cache(self)
+    }
+    fn parts(&self) -> Result<request::Parts> {
+        UnboxedMiddleware::parts(self)
+    }
+    fn url(&self) -> Result<Url> {
+        UnboxedMiddleware::url(self)
+    }
+    fn method(&self) -> Result<String> {
+        UnboxedMiddleware::method(self)
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        UnboxedMiddleware::overridden_cache_mode(self)
+    }
+    fn overridden_cache_key(&self) -> Option<String> {
+        UnboxedMiddleware::overridden_cache_key(self)
+    }
+    fn overridden_cache_options(&self) -> Option<CacheOptions> {
+        UnboxedMiddleware::overridden_cache_options(self)
+    }
+    fn detach(&self) -> Option<Box<dyn Middleware + Sync>> {
+        UnboxedMiddleware::detach(self)
+    }
+    fn remote_fetch(&mut self) -> impl Future<Output = Result<HttpResponse>> {
+        UnboxedMiddleware::remote_fetch(self)
+    }
+}
+
+// The trait the cache drives requests through, the most relaxed one
+// available covers every kind of middleware
+#[cfg(feature = "local-middleware")]
+use LocalMiddleware as Driver;
+#[cfg(not(feature = "unboxed-middleware"))]
+use Middleware as Driver;
+#[cfg(all(
+    feature = "unboxed-middleware",
+    not(feature = "local-middleware")
+))]
+use UnboxedMiddleware as Driver;
+
 /// Similar to [make-fetch-happen cache options](https://github.com/npm/make-fetch-happen#--optscache).
 /// Passed in when the [`HttpCache`] struct is being built.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -328,6 +800,325 @@ impl From<HttpVersion> for http_types::Version {
     }
 }
 
+// Checks for the directives that a freshness floor must never override
+fn forbids_min_ttl(headers: &http::HeaderMap) -> bool {
+    headers.get_all(CACHE_CONTROL).iter().any(|val| {
+        val.to_str().map_or(false, |val| {
+            let val = val.to_lowercase();
+            val.contains("no-cache") || val.contains("no-store")
+        })
+    })
+}
+
+// Checks whether the response states how long it's fresh for, rather than
+// leaving it to a heuristic
+fn has_explicit_expiry(headers: &HashMap<String, String>) -> bool {
+    headers.contains_key("expires")
+        || headers.get(CACHE_CONTROL.as_str()).map_or(false, |val| {
+            val.split(',').any(|directive| {
+                let name = directive.split('=').next().unwrap_or_default();
+                let name = name.trim().to_lowercase();
+                name == "max-age" || name == "s-maxage"
+            })
+        })
+}
+
+// Checks whether the response is only fresh by a heuristic over its
+// `Last-Modified`
+fn is_heuristic(headers: &HashMap<String, String>) -> bool {
+    headers.contains_key("last-modified") && !has_explicit_expiry(headers)
+}
+
+// Checks for the `only-if-cached` request directive
+fn requests_only_if_cached(headers: &http::HeaderMap) -> bool {
+    headers.get_all(CACHE_CONTROL).iter().any(|val| {
+        val.to_str().map_or(false, |val| {
+            val.split(',')
+                .any(|d| d.trim().eq_ignore_ascii_case("only-if-cached"))
+        })
+    })
+}
+
+// Compares two entity tags. Weak comparison ignores the `W/` prefix, while
+// under strong comparison a weak tag never matches, not even itself
+// (https://tools.ietf.org/html/rfc7232#section-2.3.2)
+fn etags_match(a: &str, b: &str, weak: bool) -> bool {
+    let (a, b) = (a.trim(), b.trim());
+    if weak {
+        a.strip_prefix("W/").unwrap_or(a) == b.strip_prefix("W/").unwrap_or(b)
+    } else {
+        !a.starts_with("W/") && a == b
+    }
+}
+
+// Checks the request's `If-None-Match` against the stored response's
+// entity tag, which is compared weakly
+// (https://tools.ietf.org/html/rfc7232#section-3.2)
+fn if_none_match_matches(
+    headers: &http::HeaderMap,
+    res: &HttpResponse,
+) -> bool {
+    let etag = match res.headers.get(ETAG.as_str()) {
+        Some(etag) => etag,
+        None => return false,
+    };
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .any(|tag| tag.trim() == "*" || etags_match(tag, etag, true))
+}
+
+// Checks whether the request asks for a range of the stored response, an
+// `If-Range` naming its entity tag under strong comparison. Otherwise the
+// full stored response is what the request asks for.
+// (https://tools.ietf.org/html/rfc7233#section-3.2)
+fn wants_stored_range(headers: &http::HeaderMap, res: &HttpResponse) -> bool {
+    if !headers.contains_key(RANGE) {
+        return false;
+    }
+    let if_range = headers.get(IF_RANGE).and_then(|val| val.to_str().ok());
+    match (if_range, res.headers.get(ETAG.as_str())) {
+        (Some(tag), Some(etag)) => etags_match(tag, etag, false),
+        _ => false,
+    }
+}
+
+// The stored response as a `304 Not Modified`, for a request whose own
+// validators it matches
+fn not_modified(mut res: HttpResponse) -> HttpResponse {
+    res.status = 304;
+    res.body = Bytes::new();
+    res.headers.remove(CONTENT_LENGTH.as_str());
+    res
+}
+
+// The response to a request that may only be answered from the cache when
+// nothing usable is stored
+fn gateway_timeout(url: Url) -> HttpResponse {
+    // ENOTCACHED
+    let mut res = HttpResponse {
+        body: Bytes::from_static(b"GatewayTimeout"),
+        headers: HashMap::default(),
+        trailers: HashMap::new(),
+        status: 504,
+        url,
+        version: HttpVersion::Http11,
+    };
+    res.cache_status(HitOrMiss::MISS);
+    res.cache_lookup_status(HitOrMiss::MISS);
+    res
+}
+
+// Determines if the headers explicitly allow storing the response in a
+// shared cache
+fn allows_shared(headers: &http::HeaderMap) -> bool {
+    headers.get_all(CACHE_CONTROL).iter().any(|val| {
+        val.to_str().map_or(false, |val| {
+            let val = val.to_lowercase();
+            val.contains("public") || val.contains("s-maxage")
+        })
+    })
+}
+
+// Determines if a stored response lost the body its `content-length` says
+// it has, so it can neither be served nor revalidated
+fn is_body_missing(method: &str, res: &HttpResponse) -> bool {
+    method != "HEAD"
+        && res.body.is_empty()
+        && res
+            .headers
+            .get(CONTENT_LENGTH.as_str())
+            .and_then(|len| len.trim().parse::<u64>().ok())
+            .map_or(false, |len| len > 0)
+}
+
+// Removes the hop-by-hop headers, along with any named by `Connection`
+// (https://tools.ietf.org/html/rfc7230#section-6.1)
+fn strip_hop_by_hop(headers: &mut HashMap<String, String>) {
+    let connection: Vec<String> = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, val)| val.split(','))
+        .map(|name| name.trim().to_lowercase())
+        .collect();
+    headers.retain(|name, _| {
+        let name = name.to_lowercase();
+        !HOP_BY_HOP.contains(&name.as_str()) && !connection.contains(&name)
+    });
+}
+
+// Determines if the header name is `Set-Cookie`, whatever its case
+fn is_set_cookie(name: &str) -> bool {
+    name.eq_ignore_ascii_case(SET_COOKIE.as_str())
+}
+
+// Drops the headers the policy never reads back from the copies of the
+// request and response it holds, which otherwise take up most of a stored
+// entry. `Authorization` only matters for being there, so its value goes as
+// well. `Accept-Encoding` is normalized to the `accept_encodings` when they
+// are given. The serialized layout doesn't change, so entries stored before
+// load as they are.
+//
+// The `res` and `req` fields are private to http-cache-semantics, which is
+// why it is held to one minor version. Should they move, the policy is kept
+// whole, and the `stored_policy` tests fail.
+fn compact_policy(
+    policy: CachePolicy,
+    accept_encodings: Option<&[String]>,
+) -> CachePolicy {
+    let mut value = match serde_json::to_value(&policy) {
+        Ok(value) => value,
+        Err(_) => return policy,
+    };
+    let mut varies = Vec::new();
+    if let Some(res) = value.get_mut("res").and_then(|res| res.as_object_mut())
+    {
+        res.retain(|name, _| POLICY_RESPONSE_HEADERS.contains(&name.as_str()));
+        let vary = match res.get("vary") {
+            Some(serde_json::Value::String(vary)) => vec![vary.as_str()],
+            Some(serde_json::Value::Array(vary)) => {
+                vary.iter().filter_map(|vary| vary.as_str()).collect()
+            }
+            _ => Vec::new(),
+        };
+        varies = vary
+            .iter()
+            .flat_map(|vary| vary.split(','))
+            .map(|name| name.trim().to_lowercase())
+            .collect();
+    }
+    if let Some(req) = value.get_mut("req").and_then(|req| req.as_object_mut())
+    {
+        req.retain(|name, _| {
+            POLICY_REQUEST_HEADERS.contains(&name.as_str())
+                || varies.contains(name)
+        });
+        if let Some(auth) = req.get_mut(AUTHORIZATION.as_str()) {
+            *auth = serde_json::Value::String(String::new());
+        }
+        let encoding = req.get_mut(ACCEPT_ENCODING.as_str());
+        if let (Some(encoding), Some(supported)) = (encoding, accept_encodings)
+        {
+            let value = match &*encoding {
+                serde_json::Value::Array(values) => values
+                    .iter()
+                    .filter_map(|value| value.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                value => value.as_str().unwrap_or_default().to_string(),
+            };
+            *encoding = serde_json::Value::String(normalize_accept_encoding(
+                &value, supported,
+            ));
+        }
+    }
+    serde_json::from_value(value).unwrap_or(policy)
+}
+
+// Reduces an `Accept-Encoding` value to the `supported` codings it accepts,
+// in the order they're listed there, so that equivalent values compare
+// equal. `*` stands for every coding the value doesn't list itself.
+fn normalize_accept_encoding(value: &str, supported: &[String]) -> String {
+    let mut listed = HashMap::new();
+    let mut wildcard = false;
+    for item in value.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or_default().trim().to_lowercase();
+        let q = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .and_then(|(_, q)| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match coding.as_str() {
+            "" => {}
+            "*" => wildcard = q > 0.0,
+            _ => {
+                listed.insert(coding, q > 0.0);
+            }
+        }
+    }
+    let accepted: Vec<String> = supported
+        .iter()
+        .map(|coding| coding.to_lowercase())
+        .filter(|coding| listed.get(coding).copied().unwrap_or(wildcard))
+        .collect();
+    if accepted.is_empty() {
+        "identity".to_string()
+    } else {
+        accepted.join(", ")
+    }
+}
+
+// The `Surrogate-Control` directives that apply to this cache
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct SurrogateControl {
+    max_age: Option<Duration>,
+    no_store: bool,
+}
+
+// Parses the `Surrogate-Control` directives that apply to the device token,
+// those targeted at it replacing the untargeted ones. Returns `None` when
+// none of the directives bear on storage or freshness.
+fn surrogate_control(
+    headers: &HashMap<String, String>,
+    token: &str,
+) -> Option<SurrogateControl> {
+    let value = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(SURROGATE_CONTROL))
+        .map(|(_, value)| value)?;
+    let mut untargeted = SurrogateControl::default();
+    let mut targeted: Option<SurrogateControl> = None;
+    for directive in split_unquoted(value, ',') {
+        let mut parts = split_unquoted(directive, ';').into_iter();
+        let directive = parts.next().unwrap_or_default();
+        let control = match parts.next() {
+            None => &mut untargeted,
+            Some(target) if target.trim().eq_ignore_ascii_case(token) => {
+                targeted.get_or_insert_with(SurrogateControl::default)
+            }
+            Some(_) => continue,
+        };
+        let (name, arg) = match directive.split_once('=') {
+            Some((name, arg)) => (name.trim(), Some(arg.trim())),
+            None => (directive.trim(), None),
+        };
+        match name.to_lowercase().as_str() {
+            "max-age" => {
+                // Anything after a `+` is how long the response may still
+                // be served stale on errors, it doesn't add to freshness
+                let secs = arg.and_then(|arg| arg.split('+').next());
+                if let Some(secs) = secs.and_then(|s| s.parse().ok()) {
+                    control.max_age = Some(Duration::from_secs(secs));
+                }
+            }
+            "no-store" => control.no_store = true,
+            _ => {}
+        }
+    }
+    let control = targeted.unwrap_or(untargeted);
+    (control != SurrogateControl::default()).then(|| control)
+}
+
+// Splits the value on the separator, except where it's inside quotes
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == sep && !quoted {
+            parts.push(&value[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
 /// Options struct provided by
 /// [`http-cache-semantics`](https://github.com/kornelski/rusty-http-cache-semantics).
 pub use http_cache_semantics::CacheOptions;
@@ -342,143 +1133,3159 @@ pub struct HttpCache<T: CacheManager> {
     /// as the backend has been provided, see [`CACacheManager`].
     pub manager: T,
     /// Override the default cache options.
-    pub options: Option<CacheOptions>,
+    pub options: HttpCacheOptions,
 }
 
-#[allow(dead_code)]
-impl<T: CacheManager> HttpCache<T> {
-    /// Attempts to run the passed middleware along with the cache
-    pub async fn run(
-        &self,
-        mut middleware: impl Middleware,
-    ) -> Result<HttpResponse> {
-        let is_cacheable = middleware.is_method_get_head()
-            && self.mode != CacheMode::NoStore
-            && self.mode != CacheMode::Reload;
-        if !is_cacheable {
-            return self.remote_fetch(&mut middleware).await;
-        }
-        let method = middleware.method()?.to_uppercase();
-        let url = middleware.url()?;
-        if let Some(store) = self.manager.get(&method, &url).await? {
-            let (mut res, policy) = store;
-            res.cache_lookup_status(HitOrMiss::HIT);
-            if let Some(warning_code) = res.warning_code() {
-                // https://tools.ietf.org/html/rfc7234#section-4.3.4
-                //
-                // If a stored response is selected for update, the cache MUST:
-                //
-                // * delete any warning header fields in the stored response with
-                //   warn-code 1xx (see Section 5.5);
-                //
-                // * retain any warning header fields in the stored response with
-                //   warn-code 2xx;
-                //
-                if (100..200).contains(&warning_code) {
-                    res.remove_warning();
-                }
-            }
+/// Computes the key a request is stored under at runtime, for example to
+/// collapse query parameters or alias legacy paths to canonical ones.
+#[async_trait::async_trait]
+pub trait KeyRewriter: Send + Sync {
+    /// Returns the cache key for the request
+    async fn rewrite(&self, parts: &request::Parts) -> String;
+}
 
-            match self.mode {
-                CacheMode::Default => {
-                    self.conditional_fetch(middleware, res, policy).await
-                }
-                CacheMode::NoCache => {
-                    middleware.force_no_cache()?;
-                    let mut res = self.remote_fetch(&mut middleware).await?;
-                    res.cache_lookup_status(HitOrMiss::HIT);
-                    Ok(res)
-                }
-                CacheMode::ForceCache | CacheMode::OnlyIfCached => {
-                    //   112 Disconnected operation
-                    // SHOULD be included if the cache is intentionally disconnected from
-                    // the rest of the network for a period of time.
-                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
-                    res.add_warning(
-                        &res.url.clone(),
-                        112,
+/// Decides whether a response is stored in place of the caching rules, for
+/// responses the application knows better about, such as a `200` whose body
+/// is an error. Closures taking the response implement it.
+pub trait CacheabilityOverride: Send + Sync {
+    /// Returns `Some(true)` to store the response, `Some(false)` to never
+    /// store it, or `None` to leave it to the caching rules. A partial or
+    /// an interim `1xx` response is never stored either way.
+    fn is_cacheable(&self, res: &HttpResponse) -> Option<bool>;
+}
+
+impl<F> CacheabilityOverride for F
+where
+    F: Fn(&HttpResponse) -> Option<bool> + Send + Sync,
+{
+    fn is_cacheable(&self, res: &HttpResponse) -> Option<bool> {
+        self(res)
+    }
+}
+
+/// A lease on a cache key shared by every cache holding it, such as the
+/// instances of a fleet storing their responses in one place, so only one
+/// of them fetches a response that's not stored yet from the origin. See
+/// [`HttpCacheOptions::cache_lock`].
+///
+/// Implementations are backed by a store every instance reaches, such as
+/// the one their responses are kept in. Keys are the request method and
+/// url joined by a colon. A lease must only be taken by one caller at a
+/// time across all instances, and must lapse on its own once the TTL is
+/// up, so a crashed holder doesn't block the key for good. An error from
+/// [`try_acquire`](CacheLock::try_acquire) or [`wait`](CacheLock::wait)
+/// fails the request, while one from [`release`](CacheLock::release) is
+/// ignored and the lease left to lapse.
+#[async_trait::async_trait]
+pub trait CacheLock: Send + Sync {
+    /// Takes the lease on the key unless it's held already, returning
+    /// whether it was taken. It lapses after the TTL should it never be
+    /// released.
+    async fn try_acquire(&self, key: &str, ttl: Duration) -> Result<bool>;
+    /// Releases the lease on the key taken by this cache
+    async fn release(&self, key: &str) -> Result<()>;
+    /// Waits for the holder of the lease on the key to make progress before
+    /// the cache looks for its response again, such as by sleeping briefly
+    /// or until the lease is released
+    async fn wait(&self, key: &str) -> Result<()>;
+}
+
+/// Builds the response served for a request that can't be answered any
+/// other way, with nothing stored for it and the origin unreachable, such
+/// as an offline page. A plain [`HttpResponse`] is served as is.
+pub trait FallbackResponse: Send + Sync {
+    /// Returns the response for the request to the url
+    fn respond(&self, parts: &request::Parts, url: &Url) -> HttpResponse;
+}
+
+impl FallbackResponse for HttpResponse {
+    fn respond(&self, _parts: &request::Parts, _url: &Url) -> HttpResponse {
+        self.clone()
+    }
+}
+
+/// Runs the work the cache hands off to finish after a request is answered
+/// on the executor the client runs on, see [`Background`]
+pub trait Spawner: Send + Sync {
+    /// Runs the task to completion, such as with `tokio::spawn`
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+    /// Returns a future that completes once the duration has passed, such
+    /// as `tokio::time::sleep`
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+// Counts the tasks handed to the spawner that haven't finished, how many
+// of them are revalidations, and who is waiting for there to be none
+#[derive(Default)]
+struct Pending {
+    count: usize,
+    revalidations: usize,
+    idle: Vec<oneshot::Sender<()>>,
+}
+
+// Held by a task for as long as it exists, so it stops being counted once
+// it finishes or the executor drops it
+struct PendingGuard(Arc<Mutex<Pending>>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let mut pending = self.0.lock().unwrap();
+        pending.count -= 1;
+        if pending.count == 0 {
+            for idle in pending.idle.drain(..) {
+                idle.send(()).ok();
+            }
+        }
+    }
+}
+
+// Held by a revalidation running in the background, so its slot is free
+// again once it finishes or the executor drops it, see
+// `max_background_revalidations`
+struct RevalidationPermit(Arc<Mutex<Pending>>);
+
+impl Drop for RevalidationPermit {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().revalidations -= 1;
+    }
+}
+
+/// Where the cache runs the work it hands off, see
+/// [`HttpCacheOptions::background`]. The work can't borrow the cache, so it
+/// writes through a manager of its own, which should share its storage with
+/// [`HttpCache::manager`], such as a clone of it.
+///
+/// The tasks handed off are counted until they finish, so that
+/// [`HttpCache::shutdown`] can wait for them. Clones count the same tasks.
+#[derive(Clone)]
+pub struct Background {
+    spawner: Arc<dyn Spawner>,
+    manager: Arc<dyn CacheManager>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl Background {
+    /// Runs the work with the spawner, writing through the manager
+    pub fn new(
+        spawner: impl Spawner + 'static,
+        manager: impl CacheManager,
+    ) -> Self {
+        Self {
+            spawner: Arc::new(spawner),
+            manager: Arc::new(manager),
+            pending: Arc::default(),
+        }
+    }
+
+    /// Returns the number of tasks handed off that haven't finished yet
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().count
+    }
+
+    // Takes a slot for a revalidation, unless there are `limit` running
+    // already
+    fn try_revalidate(
+        &self,
+        limit: Option<usize>,
+    ) -> Option<RevalidationPermit> {
+        let mut pending = self.pending.lock().unwrap();
+        if limit.map_or(false, |limit| pending.revalidations >= limit) {
+            return None;
+        }
+        pending.revalidations += 1;
+        Some(RevalidationPermit(self.pending.clone()))
+    }
+
+    // Hands the task to the spawner, counting it until it's done
+    fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        self.pending.lock().unwrap().count += 1;
+        let guard = PendingGuard(self.pending.clone());
+        self.spawner.spawn(Box::pin(async move {
+            let _guard = guard;
+            task.await;
+        }));
+    }
+
+    // Waits for every task handed off to finish, including those handed
+    // off meanwhile
+    async fn idle(&self) {
+        let (idle, done) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.count == 0 {
+                return;
+            }
+            pending.idle.push(idle);
+        }
+        done.await.ok();
+    }
+
+    // Stores the response on a task of the spawner, reporting a failure to
+    // the observer as there's no request left to fail
+    fn put(
+        &self,
+        method: &str,
+        url: &Url,
+        res: HttpResponse,
+        policy: CachePolicy,
+        observer: Option<Arc<dyn CacheObserver>>,
+    ) {
+        let manager = self.manager.clone();
+        let (method, url) = (method.to_string(), url.clone());
+        self.spawn(async move {
+            if let Err(e) = manager.put(&method, &url, res, policy).await {
+                if let Some(observer) = observer {
+                    observer.on_store_error(&method, &url, &e);
+                }
+            }
+        });
+    }
+}
+
+impl fmt::Debug for Background {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Background")
+            .field("pending", &self.pending())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Time spent in each phase of answering a request, reported to a
+/// [`CacheObserver`]. Phases that didn't happen are zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    /// Looking the request up in the cache manager
+    pub lookup: Duration,
+    /// Waiting on the origin, including conditional requests
+    pub origin: Duration,
+    /// Storing the response in the cache manager
+    pub store: Duration,
+    /// The whole request, from the cache's point of view
+    pub total: Duration,
+    /// Whether storing the response was skipped, as the request had gone
+    /// over [`HttpCacheOptions::max_total_budget`]
+    pub store_skipped: bool,
+}
+
+/// The upper bounds, in bytes, of the buckets the sizes in [`BodySize`] are
+/// sorted into, from 1 KiB to 16 MiB. Larger bodies fall in a last bucket
+/// without a bound.
+pub const BODY_SIZE_BUCKETS: &[u64] = &[
+    1 << 10,
+    4 << 10,
+    16 << 10,
+    64 << 10,
+    256 << 10,
+    1 << 20,
+    4 << 20,
+    16 << 20,
+];
+
+/// The size of the body of a response fetched from the origin, reported to
+/// a [`CacheObserver`] along with whether the response was stored. Bodies
+/// of `304` responses aren't reported, nothing new was fetched for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodySize {
+    /// The length of the body in bytes
+    pub bytes: u64,
+    /// The upper bound of the bucket of [`BODY_SIZE_BUCKETS`] the length
+    /// falls in, which it's at most, or `None` when it's over every bound
+    pub bucket: Option<u64>,
+    /// Whether the response was stored rather than skipped
+    pub stored: bool,
+}
+
+impl BodySize {
+    fn new(bytes: u64, stored: bool) -> Self {
+        let bucket =
+            BODY_SIZE_BUCKETS.iter().copied().find(|bound| bytes <= *bound);
+        Self { bytes, bucket, stored }
+    }
+}
+
+/// Receives reports about each request the cache answers, for example to
+/// track latency objectives.
+pub trait CacheObserver: Send + Sync {
+    /// Called with the time each phase took, once the request is answered
+    fn on_timing(&self, timings: &PhaseTimings);
+    /// Called with the size of the body the origin answered with, for
+    /// example to keep a histogram of the sizes stored and skipped
+    fn on_body_size(&self, _size: &BodySize) {}
+    /// Called when a store written behind fails, see
+    /// [`HttpCacheOptions::write_behind`]
+    fn on_store_error(&self, _method: &str, _url: &Url, _error: &CacheError) {}
+}
+
+// Records the phase timings of a single request
+struct Timer {
+    started: SystemTime,
+    timings: PhaseTimings,
+}
+
+impl Timer {
+    fn new() -> Self {
+        Self { started: now(), timings: PhaseTimings::default() }
+    }
+
+    // Returns the time passed since `start`, zero if the clock went back
+    fn since(start: SystemTime) -> Duration {
+        now().duration_since(start).unwrap_or_default()
+    }
+
+    // Fills in the total and hands the timings to the observer, if any
+    fn finish(mut self, observer: Option<&Arc<dyn CacheObserver>>) {
+        self.timings.total = Self::since(self.started);
+        if let Some(observer) = observer {
+            observer.on_timing(&self.timings);
+        }
+    }
+}
+
+/// Refreshes stored responses shortly before they go stale, so that
+/// requests for them never wait on the origin. The cache doesn't spawn
+/// tasks of its own, [`Refresher::refresh`] is meant to be called
+/// periodically from a task on the executor the client runs on.
+#[derive(Debug)]
+pub struct Refresher {
+    /// The urls of the `GET` responses kept refreshed
+    pub urls: Vec<Url>,
+    /// Refreshes a stored response once less than this fraction of its
+    /// freshness lifetime remains, `0.1` refreshes it in the last tenth
+    pub lookahead: f64,
+    /// How many refreshes may be in flight at once
+    pub concurrency: usize,
+    /// How long a url is left alone after its refresh failed, doubling
+    /// with each further failure in a row
+    pub backoff: Duration,
+    // Failures in a row and when to try again, by url
+    failures: Mutex<HashMap<Url, (u32, SystemTime)>>,
+}
+
+impl Refresher {
+    /// Creates a refresher for the urls, refreshing in the last tenth of
+    /// the freshness lifetime, four at a time, backing off for 30 seconds
+    /// after a failure
+    pub fn new(urls: Vec<Url>) -> Self {
+        Self {
+            urls,
+            lookahead: 0.1,
+            concurrency: 4,
+            backoff: Duration::from_secs(30),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Revalidates every stored response that's within the lookahead of
+    /// going stale through `revalidate`, which should issue the request
+    /// through the client's usual cache, as with the `revalidate` methods
+    /// of the client crates. Returns the outcome for each url refreshed.
+    ///
+    /// An error or [`CacheLookupStatus::Failed`] backs the url off, the
+    /// stored response is kept as it is. Urls with nothing stored aren't
+    /// fetched.
+    pub async fn refresh<T, F, Fut>(
+        &self,
+        cache: &HttpCache<T>,
+        revalidate: F,
+    ) -> Vec<(Url, Result<CacheLookupStatus>)>
+    where
+        T: CacheManager,
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = Result<CacheLookupStatus>>,
+    {
+        let started = now();
+        let due: Vec<Url> = {
+            let failures = self.failures.lock().unwrap();
+            self.urls
+                .iter()
+                .filter(|url| {
+                    failures.get(*url).map_or(true, |(_, at)| started >= *at)
+                })
+                .cloned()
+                .collect()
+        };
+        let revalidate = &revalidate;
+        let results: Vec<_> = stream::iter(due)
+            .map(|url| async move {
+                let status = match cache
+                    .is_expiring("GET", &url, self.lookahead)
+                    .await
+                {
+                    Ok(false) => return None,
+                    Ok(true) => revalidate(url.clone()).await,
+                    Err(e) => Err(e),
+                };
+                Some((url, status))
+            })
+            .buffer_unordered(self.concurrency.max(1))
+            .filter_map(|result| async { result })
+            .collect()
+            .await;
+        let mut failures = self.failures.lock().unwrap();
+        for (url, status) in &results {
+            match status {
+                Ok(status) if *status != CacheLookupStatus::Failed => {
+                    failures.remove(url);
+                }
+                _ => {
+                    let count = failures.get(url).map_or(0, |(n, _)| *n) + 1;
+                    let backoff = self.backoff * 2u32.pow((count - 1).min(6));
+                    failures.insert(url.clone(), (count, now() + backoff));
+                }
+            }
+        }
+        results
+    }
+}
+
+/// Coalesces concurrent [`HttpCache::get_or_fetch`] calls for the same key,
+/// so that one of them runs its fetcher while the others wait and are then
+/// answered with the response it stored. When the response can't be
+/// stored, the waiting calls run their own fetchers in turn.
+#[derive(Debug, Default)]
+pub struct SingleFlight {
+    // Held by the call fetching for the key, by key
+    in_flight: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl SingleFlight {
+    /// Creates a coalescer with nothing in flight
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls [`HttpCache::get_or_fetch`], once the calls for the same key
+    /// made before this one have finished
+    pub async fn get_or_fetch<T, F, Fut>(
+        &self,
+        cache: &HttpCache<T>,
+        key: &str,
+        fetcher: F,
+    ) -> Result<HttpResponse>
+    where
+        T: CacheManager,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<HttpResponse>>,
+    {
+        let flight = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let res = {
+            let _turn = flight.lock().await;
+            cache.get_or_fetch(key, fetcher).await
+        };
+        let mut in_flight = self.in_flight.lock().unwrap();
+        // Only the map holds it otherwise when no other call is waiting
+        if Arc::strong_count(&flight) == 2 {
+            in_flight.remove(key);
+        }
+        res
+    }
+}
+
+/// A stored response as listed by [`HttpCache::list_stale`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntryMeta {
+    /// The method the response was stored for
+    pub method: String,
+    /// The url of the response
+    pub url: Url,
+    /// The status of the response
+    pub status: u16,
+    /// How old the response is, including any upstream `Age`
+    pub age: Duration,
+    /// The request the response was fetched for, when it was kept
+    pub request: Option<RequestContext>,
+}
+
+impl CacheEntryMeta {
+    fn new(
+        method: &str,
+        res: HttpResponse,
+        policy: &CachePolicy,
+        now: SystemTime,
+    ) -> Self {
+        let request = res
+            .headers
+            .get(REQUEST_CONTEXT)
+            .and_then(|context| serde_json::from_str(context).ok());
+        Self {
+            method: method.to_string(),
+            url: res.url,
+            status: res.status,
+            age: policy.age(now),
+            request,
+        }
+    }
+}
+
+/// The request a stored response was fetched for, as kept with
+/// [`HttpCacheOptions::store_request_context`], for finding out why a
+/// url ended up with the variants it has
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestContext {
+    /// The method of the request
+    pub method: String,
+    /// The request headers named by the response's `Vary`, which are what
+    /// its variant is told apart by, with lowercase names. Headers the
+    /// request didn't carry are left out, as are values that aren't
+    /// visible ASCII.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl RequestContext {
+    // Captures the request headers the response varies on
+    fn new(
+        method: &str,
+        req_headers: &http::HeaderMap,
+        res: &HttpResponse,
+    ) -> Self {
+        let mut headers = BTreeMap::new();
+        for name in vary_names(&res.headers).unwrap_or_default() {
+            let values: Option<Vec<&str>> = req_headers
+                .get_all(name.as_str())
+                .iter()
+                .map(|value| value.to_str().ok())
+                .collect();
+            match values {
+                Some(values) if !values.is_empty() => {
+                    headers.insert(name, values.join(", "));
+                }
+                _ => {}
+            }
+        }
+        Self { method: method.to_string(), headers }
+    }
+}
+
+/// Options for configuring the cache behavior of [`HttpCache`].
+#[derive(Clone, Default)]
+pub struct HttpCacheOptions {
+    /// Override the default cache options provided by
+    /// [`http-cache-semantics`](https://github.com/kornelski/rusty-http-cache-semantics).
+    pub cache_options: Option<CacheOptions>,
+    /// Caps how long a stored response is considered fresh, regardless of
+    /// the `max-age` or `Expires` the origin declared. Once a response is
+    /// older than this it is revalidated as if it were stale.
+    pub max_ttl: Option<Duration>,
+    /// Keeps a stored response fresh for at least this long, even if the
+    /// origin declared a shorter `max-age`. Responses or requests carrying
+    /// `no-cache` or `no-store` are never held by the floor. A response with
+    /// `must-revalidate` is held fresh until the floor passes, after which it
+    /// must be revalidated before it can be served again. [`max_ttl`]
+    /// takes precedence when both are set.
+    ///
+    /// [`max_ttl`]: HttpCacheOptions::max_ttl
+    pub min_ttl: Option<Duration>,
+    /// Sets how long responses carrying `Last-Modified` but no explicit
+    /// freshness are fresh for, see [`HeuristicFreshness`]. When `None`,
+    /// the `cache_heuristic` of the [`cache_options`] applies without a cap.
+    ///
+    /// [`cache_options`]: HttpCacheOptions::cache_options
+    pub heuristic_freshness: Option<HeuristicFreshness>,
+    /// Determines which url a response is stored under when the request
+    /// was redirected.
+    pub redirect_key: RedirectKey,
+    /// Stores redirect responses themselves, so that a client which
+    /// follows redirects outside of the cache can replay the hop. Whether
+    /// a redirect is stored, and for how long, is still decided by its
+    /// caching headers.
+    pub cache_redirects: bool,
+    /// Stores `5xx` responses unless their caching headers forbid it. Most
+    /// server errors are stale as soon as they're stored, so outside of
+    /// modes like [`CacheMode::ForceCache`] they're only served while
+    /// [`min_ttl`] holds them fresh. Otherwise server errors are never
+    /// stored, whatever the mode, except as a negatively cached status
+    /// listed in [`negative_statuses`]. A stored response the origin answers a
+    /// revalidation of with an error that can't be stored is still served.
+    ///
+    /// [`min_ttl`]: HttpCacheOptions::min_ttl
+    /// [`negative_statuses`]: HttpCacheOptions::negative_statuses
+    pub cache_server_errors: bool,
+    /// Hands the manager a fixed-length SHA-256 hash of the cache key
+    /// instead of the full url, which keeps keys for very long urls
+    /// manageable. Stored responses still carry the original url.
+    pub hash_keys: bool,
+    /// Computes the key responses are stored under in place of the one
+    /// derived from the url. A cache key set on the request itself still
+    /// takes precedence.
+    pub key_rewriter: Option<Arc<dyn KeyRewriter>>,
+    /// Determines which query parameters are part of the key derived from
+    /// the url, see [`QueryParamPolicy`]
+    pub query_param_policy: QueryParamPolicy,
+    /// Keeps responses carrying `Vary` apart by the request headers they
+    /// vary on, storing up to this many variants per url. Once a url has as
+    /// many, a new variant is handled as [`variant_overflow`] says. With
+    /// `Some(0)` such responses aren't stored at all. When `None`, each url
+    /// holds a single response, which a response for another variant
+    /// replaces.
+    ///
+    /// [`variant_overflow`]: HttpCacheOptions::variant_overflow
+    pub max_variants_per_url: Option<usize>,
+    /// Determines what happens to a new variant of a url already holding
+    /// [`max_variants_per_url`] of them, see [`VariantOverflow`]
+    ///
+    /// [`max_variants_per_url`]: HttpCacheOptions::max_variants_per_url
+    pub variant_overflow: VariantOverflow,
+    /// Keeps the method of the request each response was fetched for, and
+    /// the request headers named by its `Vary`, with the stored response.
+    /// They're read back through [`CacheManager::metadata`], to find out
+    /// why a url is stored as more variants than expected. Responses
+    /// stored by [`HttpCache::get_or_fetch`] have no request to keep.
+    pub store_request_context: bool,
+    /// Determines whether requests carrying a `Cookie` header or responses
+    /// carrying `Set-Cookie` bypass the cache, see [`CookiePolicy`].
+    pub cookie_policy: CookiePolicy,
+    /// Determines how a response carrying `Set-Cookie` is stored, see
+    /// [`SetCookiePolicy`]. When `None`, it's [`SetCookiePolicy::Strip`]
+    /// unless [`cache_options`] makes this a private cache, in which case
+    /// it's [`SetCookiePolicy::Store`].
+    ///
+    /// [`cache_options`]: HttpCacheOptions::cache_options
+    pub set_cookie_policy: Option<SetCookiePolicy>,
+    /// Statuses that, when revalidating a stored response, mean it no longer
+    /// exists at the origin, in addition to `404` and `410`. The stored
+    /// response is removed and the new response is passed on. Server
+    /// errors are ignored here, since a stored response is meant to stand
+    /// in for the origin while it's failing.
+    pub gone_statuses: Vec<u16>,
+    /// Stores error responses with the statuses in [`negative_statuses`]
+    /// and keeps them fresh for this long, in place of whatever lifetime
+    /// the origin gave them. [`max_ttl`] and [`min_ttl`] don't apply to
+    /// them. A response the origin marked `no-store`, or `private` in a
+    /// shared cache, is still not stored. When `None`, error responses are
+    /// never stored.
+    ///
+    /// [`negative_statuses`]: HttpCacheOptions::negative_statuses
+    /// [`max_ttl`]: HttpCacheOptions::max_ttl
+    /// [`min_ttl`]: HttpCacheOptions::min_ttl
+    pub negative_ttl: Option<Duration>,
+    /// Statuses that are cached for [`negative_ttl`], in addition to `404`,
+    /// `410` and `451`.
+    ///
+    /// [`negative_ttl`]: HttpCacheOptions::negative_ttl
+    pub negative_statuses: Vec<u16>,
+    /// Receives the phase timings of each request that goes through
+    /// [`HttpCache::run`] or [`HttpCache::revalidate`].
+    pub observer: Option<Arc<dyn CacheObserver>>,
+    /// Skips storing the response once the request has already taken this
+    /// long, so that the store never adds to a request that's over its
+    /// latency budget. The response is still served, it just isn't cached.
+    pub max_total_budget: Option<Duration>,
+    /// Stores only the response headers named here, compared without
+    /// regard to case. `Content-Type`, `Content-Length`, the validators and
+    /// the headers that decide freshness are always kept. When `None`,
+    /// every header is stored.
+    pub store_header_allowlist: Option<Vec<String>>,
+    /// Response headers that are never stored, such as `Set-Cookie` or
+    /// internal debugging headers, compared without regard to case. This
+    /// takes precedence over [`store_header_allowlist`].
+    ///
+    /// Hop-by-hop headers, like `Connection` and `Transfer-Encoding` and any
+    /// named by `Connection`, are never stored whatever the lists hold.
+    ///
+    /// [`store_header_allowlist`]: HttpCacheOptions::store_header_allowlist
+    pub store_header_denylist: Vec<String>,
+    /// Adds the structured `Cache-Status` header to responses, see
+    /// [`CacheStatusHeader`].
+    pub cache_status_header: Option<CacheStatusHeader>,
+    /// Identifies the cache as a surrogate with this device token, as
+    /// described by the [Edge Architecture](https://www.w3.org/TR/edge-arch/)
+    /// specification. Requests to the origin then announce the token in a
+    /// `Surrogate-Capability` header, and the `max-age` and `no-store`
+    /// directives of a response's `Surrogate-Control` header take
+    /// precedence over its `Cache-Control` in deciding whether and for how
+    /// long it is stored. Directives targeted at the token, as in
+    /// `max-age=600;edge`, replace the untargeted ones. `Surrogate-Control`
+    /// is removed from the responses served. When `None`, it is treated as
+    /// any other header.
+    pub surrogate_token: Option<String>,
+    /// Normalizes the request's `Accept-Encoding` to the content codings
+    /// listed here that it accepts, such as `["br", "gzip"]`, before it's
+    /// matched against a stored response that varies on it. Differently
+    /// written but equivalent values then share one stored response rather
+    /// than replacing each other. The header sent to the origin is left as
+    /// is. When `None`, values have to match exactly.
+    pub normalize_accept_encoding: Option<Vec<String>>,
+    /// Holds requests back from the origin after it answered a
+    /// revalidation with `503 Service Unavailable` and a `Retry-After`,
+    /// serving the stale response until the time it named has passed
+    /// rather than asking again. Responses that must be revalidated are
+    /// always sent on. When unset, `Retry-After` is ignored.
+    pub honor_retry_after: bool,
+    /// Lengthens each `Retry-After` cooldown by a random amount up to this,
+    /// so the requests held back don't all return to the origin at once.
+    pub retry_after_jitter: Duration,
+    /// Determines which conditional headers are sent to revalidate a stored
+    /// response that has both validators, see [`ValidatorPreference`].
+    pub validator_preference: ValidatorPreference,
+    /// Serves this in place of the error when nothing is stored for a
+    /// request and the origin can't be reached, see [`FallbackResponse`].
+    /// A stale response is still preferred whenever one may be served.
+    pub fallback_response: Option<Arc<dyn FallbackResponse>>,
+    /// Decides whether each response from the origin is stored before the
+    /// caching rules do, see [`CacheabilityOverride`]. A new response to a
+    /// revalidation that's ruled out leaves the stored one to be served as
+    /// if the origin had failed.
+    pub cacheability_override: Option<Arc<dyn CacheabilityOverride>>,
+    /// Determines what happens to a response from the origin whose body
+    /// isn't as long as its `Content-Length` says, such as one cut short,
+    /// before it's stored, see [`ContentLengthMismatch`]
+    pub content_length_mismatch: ContentLengthMismatch,
+    /// Takes a lease on the key of a request with nothing stored for it
+    /// before fetching it from the origin, see [`CacheLock`]. A request
+    /// finding the lease held waits for the response its holder stores,
+    /// and fetches it itself once the lease is free again with still
+    /// nothing stored. Stale responses are revalidated without a lease.
+    pub cache_lock: Option<Arc<dyn CacheLock>>,
+    /// How long a lease taken through [`cache_lock`] lasts should it not be
+    /// released, 30 seconds when `None`
+    ///
+    /// [`cache_lock`]: HttpCacheOptions::cache_lock
+    pub cache_lock_ttl: Option<Duration>,
+    /// Where the work handed off to finish after a request is answered
+    /// runs, see [`Background`]. Nothing is handed off unless an option
+    /// asks for it, such as [`write_behind`].
+    ///
+    /// [`write_behind`]: HttpCacheOptions::write_behind
+    pub background: Option<Background>,
+    /// Stores responses on a task of the [`background`] once they're handed
+    /// back, so a slow backend doesn't add to the request. A store that
+    /// fails is reported to the [`observer`]. A variant and the index
+    /// record listing it are written by separate tasks, so a request coming
+    /// in between the two misses. Has no effect without a background.
+    ///
+    /// [`background`]: HttpCacheOptions::background
+    /// [`observer`]: HttpCacheOptions::observer
+    pub write_behind: bool,
+    /// Serves a stale response once revalidating it has taken this long,
+    /// with a `110` warning, leaving the revalidation to finish on the
+    /// [`background`] and update the cache. A response with
+    /// `must-revalidate` is always waited on, as are revalidations through
+    /// a client whose middleware can't be detached, see
+    /// [`Middleware::detach`]. Has no effect without a background.
+    ///
+    /// [`background`]: HttpCacheOptions::background
+    pub revalidation_deadline: Option<Duration>,
+    /// The most revalidations left to finish on the [`background`] at once,
+    /// see [`revalidation_deadline`]. A stale response that would start one
+    /// more is served as is, with a `110` warning, without sending the
+    /// conditional request at all. Unlimited when `None`.
+    ///
+    /// [`background`]: HttpCacheOptions::background
+    /// [`revalidation_deadline`]: HttpCacheOptions::revalidation_deadline
+    pub max_background_revalidations: Option<usize>,
+}
+
+impl fmt::Debug for HttpCacheOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpCacheOptions")
+            .field("cache_options", &self.cache_options)
+            .field("max_ttl", &self.max_ttl)
+            .field("min_ttl", &self.min_ttl)
+            .field("heuristic_freshness", &self.heuristic_freshness)
+            .field("redirect_key", &self.redirect_key)
+            .field("cache_redirects", &self.cache_redirects)
+            .field("cache_server_errors", &self.cache_server_errors)
+            .field("hash_keys", &self.hash_keys)
+            .field(
+                "key_rewriter",
+                &self.key_rewriter.as_ref().map(|_| "KeyRewriter { .. }"),
+            )
+            .field("query_param_policy", &self.query_param_policy)
+            .field("max_variants_per_url", &self.max_variants_per_url)
+            .field("variant_overflow", &self.variant_overflow)
+            .field("store_request_context", &self.store_request_context)
+            .field("cookie_policy", &self.cookie_policy)
+            .field("set_cookie_policy", &self.set_cookie_policy)
+            .field("gone_statuses", &self.gone_statuses)
+            .field("negative_ttl", &self.negative_ttl)
+            .field("negative_statuses", &self.negative_statuses)
+            .field(
+                "observer",
+                &self.observer.as_ref().map(|_| "CacheObserver { .. }"),
+            )
+            .field("max_total_budget", &self.max_total_budget)
+            .field("store_header_allowlist", &self.store_header_allowlist)
+            .field("store_header_denylist", &self.store_header_denylist)
+            .field("cache_status_header", &self.cache_status_header)
+            .field("surrogate_token", &self.surrogate_token)
+            .field("normalize_accept_encoding", &self.normalize_accept_encoding)
+            .field("honor_retry_after", &self.honor_retry_after)
+            .field("retry_after_jitter", &self.retry_after_jitter)
+            .field("validator_preference", &self.validator_preference)
+            .field(
+                "fallback_response",
+                &self
+                    .fallback_response
+                    .as_ref()
+                    .map(|_| "FallbackResponse { .. }"),
+            )
+            .field(
+                "cacheability_override",
+                &self
+                    .cacheability_override
+                    .as_ref()
+                    .map(|_| "CacheabilityOverride { .. }"),
+            )
+            .field("content_length_mismatch", &self.content_length_mismatch)
+            .field(
+                "cache_lock",
+                &self.cache_lock.as_ref().map(|_| "CacheLock { .. }"),
+            )
+            .field("cache_lock_ttl", &self.cache_lock_ttl)
+            .field("background", &self.background)
+            .field("write_behind", &self.write_behind)
+            .field("revalidation_deadline", &self.revalidation_deadline)
+            .field(
+                "max_background_revalidations",
+                &self.max_background_revalidations,
+            )
+            .finish()
+    }
+}
+
+impl HttpCacheOptions {
+    /// Returns the key a response for the method and url is stored under.
+    /// This is the human-readable `METHOD:url` key, or its hex encoded
+    /// SHA-256 hash when [`hash_keys`] is set.
+    ///
+    /// [`hash_keys`]: HttpCacheOptions::hash_keys
+    pub fn cache_key(&self, method: &str, url: &Url) -> String {
+        let url = self.query_param_policy.apply(url);
+        self.hashed(format!("{}:{}", method.to_uppercase(), url))
+    }
+
+    // Applies the factor of `heuristic_freshness`, when set, to the options
+    // policies are built with
+    fn with_heuristic(
+        &self,
+        options: Option<CacheOptions>,
+    ) -> Option<CacheOptions> {
+        match self.heuristic_freshness {
+            Some(heuristic) => Some(CacheOptions {
+                cache_heuristic: heuristic.factor,
+                ..options.unwrap_or_default()
+            }),
+            None => options,
+        }
+    }
+
+    // Hashes the key when `hash_keys` is set
+    fn hashed(&self, key: String) -> String {
+        if self.hash_keys {
+            hex::encode(Sha256::digest(key.as_bytes()))
+        } else {
+            key
+        }
+    }
+
+    // The `Set-Cookie` policy that applies, `Strip` for shared caches and
+    // `Store` for private ones unless one was configured
+    fn set_cookie_policy(&self) -> SetCookiePolicy {
+        self.set_cookie_policy.unwrap_or_else(|| {
+            if self.cache_options.map_or(true, |options| options.shared) {
+                SetCookiePolicy::Strip
+            } else {
+                SetCookiePolicy::Store
+            }
+        })
+    }
+
+    // Removes `Set-Cookie` when the policy strips it
+    fn strip_set_cookie(&self, headers: &mut HashMap<String, String>) {
+        if self.set_cookie_policy() == SetCookiePolicy::Strip {
+            headers.retain(|name, _| !is_set_cookie(name));
+        }
+    }
+
+    // Removes the response headers that mustn't be stored, the hop-by-hop
+    // ones, `Set-Cookie` when it's stripped and those ruled out by the
+    // allowlist and denylist
+    fn filter_stored_headers(&self, headers: &mut HashMap<String, String>) {
+        strip_hop_by_hop(headers);
+        self.strip_set_cookie(headers);
+        let listed = |list: &[String], name: &str| {
+            list.iter().any(|listed| listed.eq_ignore_ascii_case(name))
+        };
+        headers.retain(|name, _| {
+            let name = name.to_lowercase();
+            if listed(&self.store_header_denylist, &name) {
+                return false;
+            }
+            match &self.store_header_allowlist {
+                Some(allowlist) => {
+                    ALWAYS_STORED.contains(&name.as_str())
+                        || listed(allowlist, &name)
+                }
+                None => true,
+            }
+        });
+    }
+}
+
+/// Determines which url a redirected response is stored under.
+/// Only applies when the client reports the final url of the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKey {
+    /// Store the response under the url that was requested.
+    Original,
+    /// Store the response under the final url after redirects.
+    Final,
+    /// Store the response under the final url after redirects, along with
+    /// a small alias entry under the requested url that points at it.
+    Both,
+}
+
+impl Default for RedirectKey {
+    fn default() -> Self {
+        Self::Original
+    }
+}
+
+/// Determines whether cookies make a request or response bypass the cache,
+/// which keeps responses personalized for one user from being served to
+/// another by a shared cache. A response whose `Cache-Control` explicitly
+/// allows shared caching with `public` or `s-maxage` is cached regardless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookiePolicy {
+    /// Cookies don't affect caching.
+    Ignore,
+    /// A request carrying a `Cookie` header is neither served from the
+    /// cache nor has its response stored.
+    BypassOnRequestCookie,
+    /// A response carrying a `Set-Cookie` header isn't stored.
+    BypassOnSetCookie,
+    /// Applies both [`BypassOnRequestCookie`] and [`BypassOnSetCookie`].
+    ///
+    /// [`BypassOnRequestCookie`]: CookiePolicy::BypassOnRequestCookie
+    /// [`BypassOnSetCookie`]: CookiePolicy::BypassOnSetCookie
+    BypassOnEither,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
+/// Determines which query parameters are part of the key a response is
+/// stored under. Parameters left out don't partition the cache, so with
+/// `utm_source` dropped `/?utm_source=x` is answered with the response stored
+/// for `/`. Names may use the `*` and `?` wildcards, as in `utm_*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryParamPolicy {
+    /// Every parameter is part of the key, the default.
+    KeepAll,
+    /// Parameters with these names are left out of the key.
+    Drop(Vec<String>),
+    /// Only parameters with these names are part of the key.
+    Keep(Vec<String>),
+}
+
+impl Default for QueryParamPolicy {
+    fn default() -> Self {
+        Self::KeepAll
+    }
+}
+
+impl QueryParamPolicy {
+    // Removes the parameters that aren't part of the key from the url
+    fn apply(&self, url: &Url) -> Url {
+        let (names, keep) = match self {
+            Self::KeepAll => return url.clone(),
+            Self::Drop(names) => (names, false),
+            Self::Keep(names) => (names, true),
+        };
+        let mut url = url.clone();
+        if url.query().is_none() {
+            return url;
+        }
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(name, _)| {
+                names.iter().any(|pattern| glob_matches(pattern, name)) == keep
+            })
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        url
+    }
+}
+
+/// The freshness given to a response that carries `Last-Modified` but
+/// neither `Expires` nor a `max-age`, a fraction of the time between its
+/// `Date` and `Last-Modified`. A response modified an hour before it was
+/// sent is fresh for six minutes with a `factor` of `0.1`. Responses that
+/// can't be stored, such as those with `no-store`, still aren't.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeuristicFreshness {
+    /// The fraction of the time since the response was last modified that
+    /// it's fresh for
+    pub factor: f32,
+    /// The longest the heuristic keeps a response fresh for, if there's a
+    /// limit. [`HttpCacheOptions::max_ttl`] still applies when it's lower.
+    pub max: Option<Duration>,
+}
+
+/// Determines what happens to a new variant of a url that already holds
+/// [`max_variants_per_url`](HttpCacheOptions::max_variants_per_url) of them.
+/// A variant that is already stored can always be replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariantOverflow {
+    /// Remove the variant stored the longest ago to make room, the default.
+    EvictOldest,
+    /// Leave the stored variants be and don't store the new one.
+    Reject,
+}
+
+impl Default for VariantOverflow {
+    fn default() -> Self {
+        Self::EvictOldest
+    }
+}
+
+/// Determines what happens to a response that would be stored when its body
+/// is a different length than its `Content-Length` declares. Responses to
+/// `HEAD` requests and responses without a body are never checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentLengthMismatch {
+    /// Store the response as it is, the default.
+    Store,
+    /// Treat the fetch as failed with
+    /// [`CacheError::ContentLengthMismatch`]. A stale response is served in
+    /// its place when one may be, as when the origin can't be reached.
+    Reject,
+    /// Set `Content-Length` to the length of the body and store it.
+    Correct,
+}
+
+impl Default for ContentLengthMismatch {
+    fn default() -> Self {
+        Self::Store
+    }
+}
+
+/// Determines what happens to a response carrying `Set-Cookie` when it's
+/// stored. A shared cache replaying one user's cookie to everyone else is
+/// almost never what's wanted. The response handed back for the request
+/// that fetched it always keeps its `Set-Cookie`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCookiePolicy {
+    /// Store the response without its `Set-Cookie` headers, which also
+    /// keeps them from making the response stale for a shared cache. The
+    /// default for shared caches.
+    Strip,
+    /// Don't store a response carrying `Set-Cookie` at all.
+    Bypass,
+    /// Store the response as is, leaving `Set-Cookie` to the caching rules.
+    /// The default for private caches.
+    Store,
+}
+
+/// Determines which conditional headers a revalidation sends when the
+/// stored response has both an `ETag` and a `Last-Modified`. Sending both is
+/// what RFC 9110 recommends, but some origins misbehave when they get both.
+/// A response with only one of the validators is always revalidated with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidatorPreference {
+    /// Send both `If-None-Match` and `If-Modified-Since`, the default.
+    Both,
+    /// Send only `If-None-Match`.
+    ETagOnly,
+    /// Send only `If-Modified-Since`.
+    LastModifiedOnly,
+}
+
+impl Default for ValidatorPreference {
+    fn default() -> Self {
+        Self::Both
+    }
+}
+
+/// Configures the `Cache-Status` header of
+/// [RFC 9211](https://www.rfc-editor.org/rfc/rfc9211), which reports
+/// whether the response was served from the cache, why the request was
+/// forwarded otherwise, and how long the response stays fresh. The entry
+/// is appended after any the origin or stored response already carried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheStatusHeader {
+    /// Identifies this cache in the header, for example `http-cache`
+    pub name: String,
+    /// Adds the `key` parameter holding the key the response is stored
+    /// under
+    pub include_key: bool,
+    /// Leaves the `x-cache` and `x-cache-lookup` headers off the response,
+    /// so that `Cache-Status` is the only report of the cache's decision
+    pub replace_x_cache: bool,
+}
+
+impl CacheStatusHeader {
+    /// Creates an entry for the named cache, with no `key` parameter and
+    /// alongside the `x-cache` headers
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), include_key: false, replace_x_cache: false }
+    }
+
+    // Serializes the entry for the outcome, less the `key` parameter
+    fn entry(&self, outcome: Outcome, ttl: Option<u64>) -> String {
+        let mut entry = sf_item(&self.name);
+        match outcome {
+            Outcome::Hit => entry.push_str("; hit"),
+            Outcome::Forward { reason, status, stored } => {
+                entry.push_str("; fwd=");
+                entry.push_str(reason);
+                if let Some(status) = status {
+                    entry.push_str(&format!("; fwd-status={}", status));
+                }
+                if stored {
+                    entry.push_str("; stored");
+                }
+            }
+            Outcome::Generated => {}
+        }
+        if let Some(ttl) = ttl {
+            entry.push_str(&format!("; ttl={}", ttl));
+        }
+        entry
+    }
+}
+
+// How the cache answered a request, as reported by `Cache-Status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    // Served from the cache without contacting the origin
+    Hit,
+    // Sent on to the origin for `reason`, the `fwd` parameter, along with
+    // the status the origin answered with and whether it was stored
+    Forward { reason: &'static str, status: Option<u16>, stored: bool },
+    // Answered by the cache itself, with neither a stored response nor
+    // the origin
+    Generated,
+}
+
+// Serializes a structured field item as a token where it's a valid one,
+// and as a quoted string otherwise
+// (https://www.rfc-editor.org/rfc/rfc8941#section-4.1.7)
+fn sf_item(value: &str) -> String {
+    let is_token = value.chars().enumerate().all(|(i, c)| {
+        c.is_ascii_alphabetic()
+            || c == '*'
+            || (i > 0
+                && (c.is_ascii_alphanumeric()
+                    || "!#$%&'+-.^_`|~:/".contains(c)))
+    }) && !value.is_empty();
+    if is_token {
+        value.to_string()
+    } else {
+        sf_string(value)
+    }
+}
+
+// Serializes a structured field string, dropping characters it can't hold
+// (https://www.rfc-editor.org/rfc/rfc8941#section-4.1.6)
+fn sf_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars().filter(|c| (' '..='~').contains(c)) {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The outcome of looking up a stored response, as reported by
+/// [`HttpCache::revalidate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheLookupStatus {
+    /// The stored response was fresh and served without contacting the
+    /// origin.
+    Fresh,
+    /// The origin answered `304 Not Modified`, the stored response's
+    /// freshness was refreshed.
+    NotModified,
+    /// The origin sent a new response, which replaced the stored one.
+    Modified,
+    /// Nothing was stored, the response was fetched from the origin.
+    Fetched,
+    /// The origin couldn't revalidate the stored response, it was kept as
+    /// is.
+    Failed,
+    /// The origin reported the stored response no longer exists, it was
+    /// removed, see [`HttpCacheOptions::gone_statuses`].
+    Gone,
+}
+
+// Matches the text against a glob pattern, where `*` matches any run of
+// characters and `?` any single one
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text it has matched up to
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the `*` take one more character and retry
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Parses `Retry-After`, in delta-seconds or as an HTTP-date, into the time
+// the origin asked not to be retried before
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<SystemTime> {
+    let value = value.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => now.checked_add(Duration::from_secs(secs)),
+        Err(_) => httpdate::parse_http_date(value).ok(),
+    }
+}
+
+// A random duration up to the maximum
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+// The time until which the response is fresh by its policy alone, recorded
+// with it so that fresh hits can be served without consulting the policy.
+// It's left out when the request or the response could change how the
+// policy judges a later request, such as responses that vary, those whose
+// lifetime is a heuristic over a day long, and requests naming a host.
+fn fresh_until(
+    middleware: &impl Driver,
+    res: &HttpResponse,
+    policy: &CachePolicy,
+) -> Result<Option<SystemTime>> {
+    let now = now();
+    let ttl = policy.time_to_live(now);
+    let lifetime = policy.age(now) + ttl;
+    if ttl.is_zero()
+        || res.headers.contains_key("vary")
+        || middleware.parts()?.headers.contains_key(HOST)
+        || (!has_explicit_expiry(&res.headers)
+            && lifetime > Duration::from_secs(24 * 3600))
+    {
+        return Ok(None);
+    }
+    Ok(Some(now + ttl))
+}
+
+// Builds the url standing in for a cache key that isn't a url
+fn key_url(key: &str) -> Result<Url> {
+    Ok(Url::parse(&format!("urn:http-cache:{}", key))?)
+}
+
+// Builds the url a variant of the response stored under the url is kept at
+fn variant_url(key_url: &Url, id: &str) -> Result<Url> {
+    self::key_url(&format!("variant:{}:{}", id, key_url))
+}
+
+// Returns the request headers a response varies on, in order, unless it
+// doesn't vary or varies on `*`
+fn vary_names(headers: &HashMap<String, String>) -> Option<Vec<String>> {
+    let vary = headers.get("vary")?;
+    let mut names: Vec<String> = vary
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if names.is_empty() || names.iter().any(|name| name == "*") {
+        return None;
+    }
+    names.sort();
+    names.dedup();
+    Some(names)
+}
+
+// Identifies the variant the request headers select among those varying on
+// the names
+fn variant_id(names: &[String], headers: &http::HeaderMap) -> String {
+    let mut hasher = Sha256::new();
+    for name in names {
+        let values: Vec<&str> = headers
+            .get_all(name.as_str())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        hasher.update(format!("{}:{}\n", name, values.join(", ")));
+    }
+    hex::encode(&hasher.finalize()[..8])
+}
+
+// Returns the url the variant for the request headers is stored under, when
+// the index record stored under the url lists it
+fn request_variant(
+    key_url: &Url,
+    index: &HttpResponse,
+    req_headers: &http::HeaderMap,
+) -> Result<Option<Url>> {
+    let names: Vec<String> = index
+        .headers
+        .get(VARIANT_VARY)
+        .map_or("", String::as_str)
+        .split(", ")
+        .map(str::to_string)
+        .collect();
+    let id = variant_id(&names, req_headers);
+    if variant_ids(index).contains(&id) {
+        Ok(Some(variant_url(key_url, &id)?))
+    } else {
+        Ok(None)
+    }
+}
+
+// Returns the ids of the variants an index record lists
+fn variant_ids(index: &HttpResponse) -> Vec<String> {
+    index.headers.get(VARIANT_IDS).map_or_else(Vec::new, |ids| {
+        ids.split_whitespace().map(str::to_string).collect()
+    })
+}
+
+// Header on alias entries holding the url of the stored response
+const CACHE_ALIAS: &str = "x-cache-alias";
+
+// Headers on the index records of urls whose variants are stored apart,
+// holding the ids of the variants from the least recently stored on, and
+// the request headers they vary on
+const VARIANT_IDS: &str = "x-cache-variants";
+const VARIANT_VARY: &str = "x-cache-vary";
+
+// Header on entries whose freshness only depends on their age, holding the
+// Unix time in seconds until which they're fresh
+const FRESH_UNTIL: &str = "x-cache-fresh-until";
+
+// Header on entries stored with `store_request_context`, holding the
+// request they were fetched for as JSON
+const REQUEST_CONTEXT: &str = "x-cache-request";
+
+// Header on entries the origin asked to be left alone for, holding the Unix
+// time in seconds until which they're served stale
+const RETRY_AT: &str = "x-cache-retry-at";
+
+// How long a lease on a key lasts unless `cache_lock_ttl` says otherwise
+const DEFAULT_LOCK_TTL: Duration = Duration::from_secs(30);
+
+// Caching directives meant only for surrogates, and the request header a
+// surrogate announces itself with (https://www.w3.org/TR/edge-arch/)
+const SURROGATE_CONTROL: &str = "surrogate-control";
+const SURROGATE_CAPABILITY: &str = "surrogate-capability";
+
+// Headers that only apply to a single connection, never stored or served
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// Response headers the stored policy reads back, when checking freshness,
+// revalidating, or refreshing the response after a 304
+const POLICY_RESPONSE_HEADERS: &[&str] = &[
+    "cache-control",
+    "pragma",
+    "expires",
+    "date",
+    "age",
+    "last-modified",
+    "etag",
+    "vary",
+    "set-cookie",
+    "warning",
+];
+
+// Request headers the stored policy reads back, along with any the response
+// varies on
+const POLICY_REQUEST_HEADERS: &[&str] =
+    &["cache-control", "pragma", "host", "authorization"];
+
+// Headers stored whatever the allowlist holds, since replaying or
+// revalidating the response depends on them
+const ALWAYS_STORED: &[&str] = &[
+    "content-type",
+    "content-length",
+    "content-encoding",
+    "etag",
+    "last-modified",
+    "cache-control",
+    "expires",
+    "date",
+    "age",
+    "vary",
+    SURROGATE_CONTROL,
+    XCACHE,
+    XCACHELOOKUP,
+];
+
+// What a revalidation found out about the stored response before it sent
+// the conditional request, for handling the answer
+struct Revalidation {
+    cached_res: HttpResponse,
+    policy: CachePolicy,
+    req_parts: request::Parts,
+    req_url: Url,
+    via_alias: bool,
+    force_stale: bool,
+    body_missing: bool,
+    now: SystemTime,
+}
+
+impl<T: CacheManager> HttpCache<T> {
+    /// Determines if the request should be handed off to the cache at all.
+    /// Requests that can't be cached can be sent without buffering the body.
+    /// A request carrying `only-if-cached` is always handed off, as only the
+    /// cache may answer it.
+    pub fn can_cache_request(&self, middleware: &impl Driver) -> bool {
+        middleware.is_method_get_head()
+            && (self.cache_mode(middleware) != CacheMode::NoStore
+                || middleware.parts().map_or(false, |parts| {
+                    requests_only_if_cached(&parts.headers)
+                }))
+    }
+
+    /// Determines the cache mode to use for the request, preferring the
+    /// mode overridden by the middleware
+    pub fn cache_mode(&self, middleware: &impl Driver) -> CacheMode {
+        middleware.overridden_cache_mode().unwrap_or(self.mode)
+    }
+
+    // Determines the cache options to create policies with, preferring the
+    // options overridden by the middleware
+    fn cache_options(&self, middleware: &impl Driver) -> Option<CacheOptions> {
+        let options = middleware
+            .overridden_cache_options()
+            .or(self.options.cache_options);
+        self.options.with_heuristic(options)
+    }
+
+    // The cap on how long the response is fresh for, `max_ttl` or the cap
+    // of the heuristic when it's what makes the response fresh, whichever
+    // is lower
+    fn max_ttl(&self, res: &HttpResponse) -> Option<Duration> {
+        let heuristic = self
+            .options
+            .heuristic_freshness
+            .and_then(|heuristic| heuristic.max)
+            .filter(|_| is_heuristic(&res.headers));
+        match (self.options.max_ttl, heuristic) {
+            (Some(max), Some(heuristic)) => Some(max.min(heuristic)),
+            (max, heuristic) => max.or(heuristic),
+        }
+    }
+
+    /// Runs the cache bookkeeping for a request that bypasses the cache,
+    /// such as invalidating the stored response for non GET/HEAD requests.
+    pub async fn run_no_cache(&self, middleware: &impl Driver) -> Result<()> {
+        if !middleware.is_method_get_head() {
+            let key_url = self.request_url(middleware, "GET").await?;
+            self.delete_entry("GET", &key_url).await.ok();
+        }
+        Ok(())
+    }
+
+    /// Returns the stored response for the method and url, if any, along
+    /// with whether it is currently fresh. This never contacts the origin,
+    /// so a stale response is returned as is rather than revalidated.
+    pub async fn peek(
+        &self,
+        method: &str,
+        url: &Url,
+    ) -> Result<Option<(HttpResponse, bool)>> {
+        let method = method.to_uppercase();
+        let key_url = self.manager_url(&method, url)?;
+        match self.lookup(&method, &key_url, &http::HeaderMap::new()).await? {
+            Some((mut res, policy, _)) => {
+                res.headers.remove(RETRY_AT);
+                res.headers.remove(FRESH_UNTIL);
+                res.headers.remove(REQUEST_CONTEXT);
+                let is_fresh = self.is_fresh(&res, &policy, now())?;
+                Ok(Some((res, is_fresh)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the response stored under the key while it's fresh,
+    /// otherwise calls `fetcher` for a new one and stores it when its
+    /// headers allow, as if it had come from the origin for a `GET` of its
+    /// url. This applies the cache's freshness and storage rules to
+    /// responses that don't come over HTTP, such as from a database or a
+    /// gRPC call. The stale response is served if the fetcher fails, unless
+    /// it must be revalidated. See [`SingleFlight`] to run one fetcher for
+    /// concurrent calls.
+    pub async fn get_or_fetch<F, Fut>(
+        &self,
+        key: &str,
+        fetcher: F,
+    ) -> Result<HttpResponse>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<HttpResponse>>,
+    {
+        let key_url = key_url(&self.options.hashed(key.to_string()))?;
+        let stale = match self
+            .lookup("GET", &key_url, &http::HeaderMap::new())
+            .await?
+        {
+            Some((mut res, policy, _)) => {
+                res.headers.remove(RETRY_AT);
+                res.headers.remove(FRESH_UNTIL);
+                res.headers.remove(REQUEST_CONTEXT);
+                if self.is_fresh(&res, &policy, now())? {
+                    res.cache_status(HitOrMiss::HIT);
+                    res.cache_lookup_status(HitOrMiss::HIT);
+                    return Ok(res);
+                }
+                Some(res)
+            }
+            None => None,
+        };
+        let lookup_status =
+            if stale.is_some() { HitOrMiss::HIT } else { HitOrMiss::MISS };
+        let mut res = match (fetcher().await, stale) {
+            (Ok(res), _) => res,
+            (Err(_), Some(mut stale)) if !stale.must_revalidate() => {
+                let url = stale.url.clone();
+                stale.add_warning(&url, 111, "Revalidation failed");
+                stale.cache_status(HitOrMiss::HIT);
+                stale.cache_lookup_status(HitOrMiss::HIT);
+                return Ok(stale);
+            }
+            (Err(e), _) => return Err(e),
+        };
+        let req_parts = request::Builder::new()
+            .uri(res.url.as_str())
+            .body(())?
+            .into_parts()
+            .0;
+        let policy = CachePolicy::new_options(
+            &req_parts,
+            &res.parts()?,
+            now(),
+            self.options
+                .with_heuristic(self.options.cache_options)
+                .unwrap_or_default(),
+        );
+        res.cache_status(HitOrMiss::MISS);
+        res.cache_lookup_status(lookup_status);
+        if policy.is_storable() && self.is_storable_status(res.status) {
+            let mut stored = res.clone();
+            self.options.filter_stored_headers(&mut stored.headers);
+            let policy = compact_policy(
+                policy,
+                self.options.normalize_accept_encoding.as_deref(),
+            );
+            self.put_record("GET", &key_url, stored, policy).await?;
+        }
+        Ok(res)
+    }
+
+    /// Waits for the work handed to the [`background`] to finish, such as
+    /// stores written behind, so that none of it is lost when the process
+    /// exits. Gives up once the timeout passes, returning
+    /// [`CacheError::ShutdownTimeout`] with the number of tasks still
+    /// running. Work handed off while waiting is waited for as well. A
+    /// clone of the cache shares its background, so one kept aside can
+    /// shut down the work of the one a client took. Returns at once without
+    /// a background.
+    ///
+    /// [`background`]: HttpCacheOptions::background
+    pub async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        let background = match &self.options.background {
+            Some(background) => background,
+            None => return Ok(()),
+        };
+        let idle = Box::pin(background.idle());
+        let timeout = background.spawner.sleep(timeout);
+        match future::select(idle, timeout).await {
+            Either::Left(_) => Ok(()),
+            Either::Right(_) => Err(CacheError::ShutdownTimeout {
+                pending: background.pending(),
+            }),
+        }
+    }
+
+    /// Checks that the manager's backend is reachable and writable, see
+    /// [`CacheManager::health_check`]
+    pub async fn health_check(&self) -> Result<()> {
+        self.manager.health_check().await
+    }
+
+    /// Keeps the stored response for the method and url from being evicted,
+    /// see [`CacheManager::pin`]
+    pub async fn pin(&self, method: &str, url: &Url) -> Result<()> {
+        let method = method.to_uppercase();
+        self.manager.pin(&method, &self.manager_url(&method, url)?).await
+    }
+
+    /// Lets the stored response for the method and url be evicted again
+    pub async fn unpin(&self, method: &str, url: &Url) -> Result<()> {
+        let method = method.to_uppercase();
+        self.manager.unpin(&method, &self.manager_url(&method, url)?).await
+    }
+
+    /// Removes the stored response for the method and url, if any
+    pub async fn delete(&self, method: &str, url: &Url) -> Result<()> {
+        let method = method.to_uppercase();
+        self.delete_entry(&method, &self.manager_url(&method, url)?).await
+    }
+
+    /// Removes every stored response whose url starts with the prefix, such
+    /// as `https://example.com/products/`, returning how many were removed.
+    /// This needs a manager that can list its [`keys`](CacheManager::keys).
+    pub async fn purge_prefix(&self, prefix: &str) -> Result<usize> {
+        self.purge(|url| url.starts_with(prefix)).await
+    }
+
+    /// Removes every stored response whose url matches the glob pattern,
+    /// where `*` matches any run of characters and `?` any single one,
+    /// returning how many were removed. This needs a manager that can list
+    /// its [`keys`](CacheManager::keys).
+    pub async fn purge_glob(&self, pattern: &str) -> Result<usize> {
+        self.purge(|url| glob_matches(pattern, url)).await
+    }
+
+    /// Lists the stored responses that have gone stale but are still held,
+    /// by the key the manager stores them under, such as
+    /// `GET:https://example.com/`. This needs a manager that can list its
+    /// [`keys`](CacheManager::keys).
+    pub async fn list_stale(&self) -> Result<Vec<(String, CacheEntryMeta)>> {
+        let now = now();
+        let mut stale = Vec::new();
+        for (method, key_url) in self.manager.keys().await? {
+            if let Some(meta) = self.stale_entry(&method, &key_url, now).await?
+            {
+                stale.push((format!("{}:{}", method, key_url), meta));
+            }
+        }
+        Ok(stale)
+    }
+
+    /// Removes every stored response that has gone stale, returning how
+    /// many were removed. This needs a manager that can list its
+    /// [`keys`](CacheManager::keys).
+    pub async fn evict_stale(&self) -> Result<usize> {
+        let now = now();
+        let mut evicted = 0;
+        for (method, key_url) in self.manager.keys().await? {
+            if self.stale_entry(&method, &key_url, now).await?.is_some() {
+                self.manager.delete(&method, &key_url).await?;
+                evicted += 1;
+            }
+        }
+        Ok(evicted)
+    }
+
+    // Describes the record stored under the method and url if it's a stale
+    // response. Alias records are left out, they only point at one.
+    async fn stale_entry(
+        &self,
+        method: &str,
+        key_url: &Url,
+        now: SystemTime,
+    ) -> Result<Option<CacheEntryMeta>> {
+        let (res, policy) = match self.manager.get(method, key_url).await? {
+            Some(store) => store,
+            None => return Ok(None),
+        };
+        if res.headers.contains_key(CACHE_ALIAS)
+            || res.headers.contains_key(VARIANT_IDS)
+            || self.is_fresh(&res, &policy, now)?
+        {
+            return Ok(None);
+        }
+        Ok(Some(CacheEntryMeta::new(method, res, &policy, now)))
+    }
+
+    // Removes the stored responses whose url matches. Records stored under
+    // a key rather than a url are matched on the url of their response.
+    async fn purge(&self, matches: impl Fn(&str) -> bool) -> Result<usize> {
+        let mut purged = 0;
+        for (method, key_url) in self.manager.keys().await? {
+            let url = if key_url.scheme() == "urn" {
+                match self.manager.get(&method, &key_url).await? {
+                    Some((res, _)) => res.url,
+                    None => continue,
+                }
+            } else {
+                key_url.clone()
+            };
+            if matches(url.as_str()) {
+                self.manager.delete(&method, &key_url).await?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    // Returns the url the manager stores the response under, which is a
+    // stand-in holding the hashed key when `hash_keys` is set
+    fn manager_url(&self, method: &str, url: &Url) -> Result<Url> {
+        if !self.options.hash_keys {
+            return Ok(self.options.query_param_policy.apply(url));
+        }
+        key_url(&self.options.cache_key(method, url))
+    }
+
+    // Returns the url the manager stores the response to the request under,
+    // preferring the cache key overridden by the middleware
+    async fn request_url(
+        &self,
+        middleware: &impl Driver,
+        method: &str,
+    ) -> Result<Url> {
+        if let Some(key) = middleware.overridden_cache_key() {
+            return key_url(&self.options.hashed(key));
+        }
+        match &self.options.key_rewriter {
+            Some(rewriter) => {
+                let key = rewriter.rewrite(&middleware.parts()?).await;
+                key_url(&self.options.hashed(key))
+            }
+            None => self.manager_url(method, &middleware.url()?),
+        }
+    }
+
+    // Returns the key the response to the request is stored under, as
+    // reported by `Cache-Status`
+    async fn request_key(
+        &self,
+        middleware: &impl Driver,
+        method: &str,
+    ) -> Result<String> {
+        if let Some(key) = middleware.overridden_cache_key() {
+            return Ok(self.options.hashed(key));
+        }
+        match &self.options.key_rewriter {
+            Some(rewriter) => {
+                let key = rewriter.rewrite(&middleware.parts()?).await;
+                Ok(self.options.hashed(key))
+            }
+            None => Ok(self.options.cache_key(method, &middleware.url()?)),
+        }
+    }
+
+    // Determines if the request is stored under a key that isn't its url
+    // as is
+    fn is_keyed(&self, middleware: &impl Driver) -> bool {
+        middleware.overridden_cache_key().is_some()
+            || self.options.key_rewriter.is_some()
+            || self.options.query_param_policy != QueryParamPolicy::KeepAll
+    }
+
+    // Pulls the stored response from the manager, following an alias entry
+    // to the response it points at. The returned flag is true when an alias
+    // was followed.
+    async fn lookup(
+        &self,
+        method: &str,
+        key_url: &Url,
+        req_headers: &http::HeaderMap,
+    ) -> Result<Option<(HttpResponse, CachePolicy, bool)>> {
+        let (res, policy) = match self.manager.get(method, key_url).await? {
+            Some(store) => store,
+            None => return Ok(None),
+        };
+        let found = match res.headers.get(CACHE_ALIAS) {
+            Some(target) => {
+                let target = self.manager_url(method, &Url::parse(target)?)?;
+                self.manager
+                    .get(method, &target)
+                    .await?
+                    .map(|(res, policy)| (res, policy, true))
+            }
+            None if res.headers.contains_key(VARIANT_IDS) => {
+                match request_variant(key_url, &res, req_headers)? {
+                    Some(variant) => self
+                        .manager
+                        .get(method, &variant)
+                        .await?
+                        .map(|(res, policy)| (res, policy, false)),
+                    None => None,
+                }
+            }
+            None => Some((res, policy, false)),
+        };
+        // Entries stored before HEAD bodies were dropped may still have one,
+        // and before hop-by-hop headers and `Set-Cookie` were stripped may
+        // still carry those
+        Ok(found.map(|(mut res, policy, via_alias)| {
+            if method == "HEAD" {
+                res.body.clear();
+            }
+            strip_hop_by_hop(&mut res.headers);
+            self.options.strip_set_cookie(&mut res.headers);
+            (res, policy, via_alias)
+        }))
+    }
+
+    // Stores the response under the url selected by `redirect_key`
+    async fn store(
+        &self,
+        middleware: &impl Driver,
+        mut res: HttpResponse,
+        policy: CachePolicy,
+        timer: &mut Timer,
+    ) -> Result<HttpResponse> {
+        let budget = self.options.max_total_budget;
+        if budget.map_or(false, |budget| Timer::since(timer.started) >= budget)
+        {
+            timer.timings.store_skipped = true;
+            return Ok(res);
+        }
+        let started = now();
+        let method = middleware.method()?.to_uppercase();
+        if method == "HEAD" {
+            // A response to HEAD has no body, whatever the origin sent
+            res.body.clear();
+        }
+        // The response is handed back with every header it arrived with,
+        // only the stored copy is filtered
+        let headers = res.headers.clone();
+        self.options.filter_stored_headers(&mut res.headers);
+        res.headers.remove(FRESH_UNTIL);
+        if let Some(until) = fresh_until(middleware, &res, &policy)? {
+            let secs = until
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            res.headers.insert(FRESH_UNTIL.to_string(), secs.to_string());
+        }
+        res.headers.remove(REQUEST_CONTEXT);
+        if self.options.store_request_context {
+            let context = RequestContext::new(
+                &method,
+                &middleware.parts()?.headers,
+                &res,
+            );
+            res.headers.insert(
+                REQUEST_CONTEXT.to_string(),
+                serde_json::to_string(&context)?,
+            );
+        }
+        let req_url = middleware.url()?;
+        let mut stored = if self.options.redirect_key == RedirectKey::Original
+            || res.url == req_url
+        {
+            let key_url = self.request_url(middleware, &method).await?;
+            let policy = compact_policy(
+                policy,
+                self.options.normalize_accept_encoding.as_deref(),
+            );
+            let req_headers = middleware.parts()?.headers;
+            let (res, stored) = self
+                .put_entry(&method, &key_url, &req_headers, res, policy)
+                .await?;
+            if !stored {
+                timer.timings.store_skipped = true;
+            }
+            res
+        } else {
+            self.store_redirected(middleware, &method, res, policy).await?
+        };
+        stored.headers = headers;
+        timer.timings.store += Timer::since(started);
+        Ok(stored)
+    }
+
+    // Puts the record, on a task of the background when stores are written
+    // behind, in which case the response is handed back as it is
+    async fn put_record(
+        &self,
+        method: &str,
+        url: &Url,
+        res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        match &self.options.background {
+            Some(background) if self.options.write_behind => {
+                let observer = self.options.observer.clone();
+                background.put(method, url, res.clone(), policy, observer);
+                Ok(res)
+            }
+            _ => self.manager.put(method, url, res, policy).await,
+        }
+    }
+
+    // Puts the response under the url, or under the url of its variant when
+    // variants are stored apart, adding it to the url's index record. The
+    // returned flag is false when the variant was turned away.
+    async fn put_entry(
+        &self,
+        method: &str,
+        key_url: &Url,
+        req_headers: &http::HeaderMap,
+        res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<(HttpResponse, bool)> {
+        let (limit, names) =
+            match (self.options.max_variants_per_url, vary_names(&res.headers))
+            {
+                (Some(limit), Some(names)) => (limit, names),
+                _ => {
+                    let res = self.put_record(method, key_url, res, policy);
+                    return Ok((res.await?, true));
+                }
+            };
+        if limit == 0 {
+            return Ok((res, false));
+        }
+        let vary = names.join(", ");
+        let id = variant_id(&names, req_headers);
+        let mut ids = match self.manager.get(method, key_url).await? {
+            Some((index, _))
+                if index.headers.get(VARIANT_VARY) == Some(&vary) =>
+            {
+                variant_ids(&index)
+            }
+            Some((index, _)) => {
+                // Told apart by other headers, so they can't be found anymore
+                for old in variant_ids(&index) {
+                    let old = variant_url(key_url, &old)?;
+                    self.manager.delete(method, &old).await?;
+                }
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        let known = ids.contains(&id);
+        ids.retain(|old| *old != id);
+        if ids.len() >= limit {
+            if !known
+                && self.options.variant_overflow == VariantOverflow::Reject
+            {
+                return Ok((res, false));
+            }
+            for old in ids.drain(..=ids.len() - limit) {
+                let old = variant_url(key_url, &old)?;
+                self.manager.delete(method, &old).await?;
+            }
+        }
+        ids.push(id);
+        let index = HttpResponse {
+            body: Bytes::new(),
+            headers: HashMap::from([
+                (VARIANT_IDS.to_string(), ids.join(" ")),
+                (VARIANT_VARY.to_string(), vary),
+            ]),
+            trailers: HashMap::new(),
+            status: res.status,
+            url: res.url.clone(),
+            version: res.version,
+        };
+        let variant = variant_url(key_url, ids.last().unwrap())?;
+        let res =
+            self.put_record(method, &variant, res, policy.clone()).await?;
+        self.put_record(method, key_url, index, policy).await?;
+        Ok((res, true))
+    }
+
+    // Removes the record stored under the url, along with the variants it
+    // lists when it's an index record
+    async fn delete_entry(&self, method: &str, key_url: &Url) -> Result<()> {
+        if self.options.max_variants_per_url.is_some() {
+            if let Some((index, _)) = self.manager.get(method, key_url).await? {
+                for id in variant_ids(&index) {
+                    let variant = variant_url(key_url, &id)?;
+                    self.manager.delete(method, &variant).await?;
+                }
+            }
+        }
+        self.manager.delete(method, key_url).await
+    }
+
+    // Stores the response of a redirected request under the final url,
+    // along with an alias at the original url for `RedirectKey::Both`
+    async fn store_redirected(
+        &self,
+        middleware: &impl Driver,
+        method: &str,
+        res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        // The stored policy has to match requests made for the final url
+        let final_url = res.url.clone();
+        let mut parts = middleware.parts()?;
+        parts.uri = final_url.as_str().parse()?;
+        let final_policy = match self.cache_options(middleware) {
+            Some(options) => {
+                CachePolicy::new_options(&parts, &res.parts()?, now(), options)
+            }
+            None => CachePolicy::new_options(
+                &parts,
+                &res.parts()?,
+                now(),
+                CacheOptions::default(),
+            ),
+        };
+        if self.options.redirect_key == RedirectKey::Both {
+            let alias = HttpResponse {
+                body: Bytes::new(),
+                headers: HashMap::from([(
+                    CACHE_ALIAS.to_string(),
+                    final_url.to_string(),
+                )]),
+                trailers: HashMap::new(),
+                status: res.status,
+                url: final_url.clone(),
+                version: res.version,
+            };
+            let key_url = self.request_url(middleware, method).await?;
+            let policy = compact_policy(
+                policy,
+                self.options.normalize_accept_encoding.as_deref(),
+            );
+            self.put_record(method, &key_url, alias, policy).await?;
+        }
+        let key_url = self.manager_url(method, &final_url)?;
+        let final_policy = compact_policy(
+            final_policy,
+            self.options.normalize_accept_encoding.as_deref(),
+        );
+        self.put_record(method, &key_url, res, final_policy).await
+    }
+
+    // Determines if the status of a revalidation means the stored response
+    // no longer exists at the origin
+    fn is_gone_status(&self, status: u16) -> bool {
+        match status {
+            404 | 410 => true,
+            500..=599 => false,
+            _ => self.options.gone_statuses.contains(&status),
+        }
+    }
+
+    // Determines if responses with the status can be stored, redirects
+    // only when `cache_redirects` is set and server errors only when
+    // `cache_server_errors` is
+    fn is_storable_status(&self, status: u16) -> bool {
+        match status {
+            // Interim responses only precede the one answering the request
+            100..=199 => false,
+            // A `203` is a copy transformed on the way, but still the
+            // response the request gets
+            200 | 203 | 204 => true,
+            // A partial body must never stand in for the full response
+            206 => false,
+            301 | 302 | 303 | 307 | 308 => self.options.cache_redirects,
+            500..=599 if self.options.cache_server_errors => true,
+            _ => self.negative_ttl(status).is_some(),
+        }
+    }
+
+    // Determines if the response can be stored, as the cacheability override
+    // decides or else by its status and what its headers allow
+    fn is_storable_response(
+        &self,
+        res: &HttpResponse,
+        is_storable: bool,
+    ) -> bool {
+        if res.status == 206 || (100..200).contains(&res.status) {
+            // Not even when forced
+            return false;
+        }
+        self.options
+            .cacheability_override
+            .as_ref()
+            .and_then(|cacheability| cacheability.is_cacheable(res))
+            .unwrap_or_else(|| {
+                is_storable && self.is_storable_status(res.status)
+            })
+    }
+
+    // Returns how long a response with the status is held fresh when it's
+    // negatively cached
+    fn negative_ttl(&self, status: u16) -> Option<Duration> {
+        let ttl = self.options.negative_ttl?;
+        let is_negative = matches!(status, 404 | 410 | 451)
+            || self.options.negative_statuses.contains(&status);
+        is_negative.then(|| ttl)
+    }
+
+    // Determines if neither the request nor the response forbids storing a
+    // negatively cached response or a server error, which the policy can't
+    // be asked about for every status
+    fn allows_negative(
+        &self,
+        middleware: &impl Driver,
+        res: &HttpResponse,
+    ) -> Result<bool> {
+        let shared = self.cache_options(middleware).map_or(true, |o| o.shared);
+        let forbids = |headers: &http::HeaderMap, private: bool| {
+            headers.get_all(CACHE_CONTROL).iter().any(|val| {
+                val.to_str().map_or(false, |val| {
+                    let val = val.to_lowercase();
+                    val.contains("no-store")
+                        || (private && val.contains("private"))
+                })
+            })
+        };
+        Ok(!forbids(&res.parts()?.headers, shared)
+            && !forbids(&middleware.parts()?.headers, false))
+    }
+
+    // Returns the `Surrogate-Control` directives of the response that apply
+    // to this cache, if it identifies as a surrogate
+    fn surrogate(&self, res: &HttpResponse) -> Option<SurrogateControl> {
+        let token = self.options.surrogate_token.as_ref()?;
+        surrogate_control(&res.headers, token)
+    }
+
+    // Returns how much longer the response stays fresh according to its
+    // policy or `Surrogate-Control`, adjusted by the configured `max_ttl`
+    // and `min_ttl`
+    fn ttl(
+        &self,
+        res: &HttpResponse,
+        policy: &CachePolicy,
+        now: SystemTime,
+    ) -> Result<Duration> {
+        let age = policy.age(now);
+        if let Some(negative) = self.negative_ttl(res.status) {
+            return Ok(negative.saturating_sub(age));
+        }
+        let mut ttl = match self.surrogate(res).and_then(|s| s.max_age) {
+            Some(max_age) => max_age.saturating_sub(age),
+            None => policy.time_to_live(now),
+        };
+        if let Some(min) = self.options.min_ttl {
+            if !forbids_min_ttl(&res.parts()?.headers) {
+                ttl = ttl.max(min.saturating_sub(age));
+            }
+        }
+        if let Some(max) = self.max_ttl(res) {
+            ttl = ttl.min(max.saturating_sub(age));
+        }
+        Ok(ttl)
+    }
+
+    // Determines if the stored response can be served for the request by
+    // its recorded freshness window alone, without consulting its policy
+    fn is_plainly_fresh(
+        &self,
+        middleware: &impl Driver,
+        req_parts: &request::Parts,
+        res: &HttpResponse,
+    ) -> Result<bool> {
+        let headers = &req_parts.headers;
+        Ok(!headers.contains_key(CACHE_CONTROL)
+            && !headers.contains_key(PRAGMA)
+            && !headers.contains_key(HOST)
+            && self.negative_ttl(res.status).is_none()
+            && self.surrogate(res).and_then(|s| s.max_age).is_none()
+            && !is_body_missing(&middleware.method()?.to_uppercase(), res))
+    }
+
+    // Determines if a stored response is fresh according to its policy or
+    // `Surrogate-Control`, adjusted by the configured `max_ttl` and `min_ttl`
+    fn is_fresh(
+        &self,
+        res: &HttpResponse,
+        policy: &CachePolicy,
+        now: SystemTime,
+    ) -> Result<bool> {
+        let age = policy.age(now);
+        if let Some(negative) = self.negative_ttl(res.status) {
+            return Ok(age < negative);
+        }
+        if self.max_ttl(res).map_or(false, |max| age >= max) {
+            return Ok(false);
+        }
+        let is_fresh = match self.surrogate(res).and_then(|s| s.max_age) {
+            Some(max_age) => age < max_age,
+            None => !policy.is_stale(now),
+        };
+        if is_fresh {
+            return Ok(true);
+        }
+        Ok(self.options.min_ttl.map_or(false, |min| age < min)
+            && !forbids_min_ttl(&res.parts()?.headers))
+    }
+
+    // Determines if the `cookie_policy` rules out serving or storing the
+    // response for the request
+    fn bypasses_cookies(
+        &self,
+        middleware: &impl Driver,
+        res: &HttpResponse,
+    ) -> Result<bool> {
+        if self.options.set_cookie_policy() == SetCookiePolicy::Bypass
+            && res.headers.keys().any(|name| is_set_cookie(name))
+        {
+            return Ok(true);
+        }
+        let policy = self.options.cookie_policy;
+        if policy == CookiePolicy::Ignore {
+            return Ok(false);
+        }
+        let res_headers = res.parts()?.headers;
+        if allows_shared(&res_headers) {
+            return Ok(false);
+        }
+        let on_request = matches!(
+            policy,
+            CookiePolicy::BypassOnRequestCookie | CookiePolicy::BypassOnEither
+        ) && middleware.parts()?.headers.contains_key(COOKIE);
+        let on_response = matches!(
+            policy,
+            CookiePolicy::BypassOnSetCookie | CookiePolicy::BypassOnEither
+        ) && res_headers.contains_key(SET_COOKIE);
+        Ok(on_request || on_response)
+    }
+
+    /// Returns the headers to add to the response to a request that
+    /// bypassed the cache, see [`can_cache_request`]. A `Cache-Status`
+    /// value should be appended after any the origin sent rather than
+    /// replace them.
+    ///
+    /// [`can_cache_request`]: HttpCache::can_cache_request
+    pub fn bypass_headers(
+        &self,
+        middleware: &impl Driver,
+    ) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        let status = self.options.cache_status_header.as_ref();
+        if !status.map_or(false, |status| status.replace_x_cache) {
+            headers.push((XCACHE, HitOrMiss::MISS.to_string()));
+            headers.push((XCACHELOOKUP, HitOrMiss::MISS.to_string()));
+        }
+        if let Some(status) = status {
+            let reason = if middleware.is_method_get_head() {
+                "bypass"
+            } else {
+                "method"
+            };
+            let outcome =
+                Outcome::Forward { reason, status: None, stored: false };
+            headers.push((CACHE_STATUS, status.entry(outcome, None)));
+        }
+        headers
+    }
+
+    /// Attempts to run the passed middleware along with the cache
+    pub async fn run(
+        &self,
+        mut middleware: impl Driver,
+    ) -> Result<HttpResponse> {
+        let mut timer = Timer::new();
+        let (mut res, outcome) =
+            self.answer(&mut middleware, &mut timer).await?;
+        if let (Some(observer), Outcome::Forward { status, stored, .. }) =
+            (&self.options.observer, outcome)
+        {
+            if status.map_or(false, |status| status != 304) {
+                let bytes = res.body.len() as u64;
+                observer.on_body_size(&BodySize::new(bytes, stored));
+            }
+        }
+        if let Some(status) = &self.options.cache_status_header {
+            self.report_status(status, &middleware, &mut res, outcome).await?;
+        }
+        // Left on by modes that serve the stored response as is
+        res.headers.remove(RETRY_AT);
+        res.headers.remove(FRESH_UNTIL);
+        res.headers.remove(REQUEST_CONTEXT);
+        if self.options.surrogate_token.is_some() {
+            // The directives were meant for this cache alone
+            res.headers.retain(|name, _| {
+                !name.eq_ignore_ascii_case(SURROGATE_CONTROL)
+            });
+        }
+        timer.finish(self.options.observer.as_ref());
+        Ok(res)
+    }
+
+    // Answers the request from the cache or the origin, as the mode allows
+    async fn answer(
+        &self,
+        middleware: &mut impl Driver,
+        timer: &mut Timer,
+    ) -> Result<(HttpResponse, Outcome)> {
+        if middleware.is_method_get_head()
+            && requests_only_if_cached(&middleware.parts()?.headers)
+        {
+            // The client itself rules out contacting the origin, whatever
+            // the mode
+            return self.answer_only_if_cached(middleware, timer).await;
+        }
+        let mode = self.cache_mode(middleware);
+        let is_cacheable = middleware.is_method_get_head()
+            && mode != CacheMode::NoStore
+            && mode != CacheMode::Reload;
+        if !is_cacheable {
+            let reason = match mode {
+                _ if !middleware.is_method_get_head() => "method",
+                CacheMode::NoStore => "bypass",
+                _ => "request",
+            };
+            return self.forward(middleware, reason, timer).await;
+        }
+        let method = middleware.method()?.to_uppercase();
+        let key_url = self.request_url(middleware, &method).await?;
+        let req_headers = middleware.parts()?.headers;
+        let started = now();
+        let found = self.lookup(&method, &key_url, &req_headers).await?;
+        timer.timings.lookup += Timer::since(started);
+        let mut lease = None;
+        let found = match (found, &self.options.cache_lock) {
+            (None, Some(lock)) if mode != CacheMode::OnlyIfCached => {
+                let key = format!("{}:{}", method, key_url);
+                let found = self
+                    .await_lease(
+                        lock.as_ref(),
+                        &key,
+                        &method,
+                        &key_url,
+                        &req_headers,
+                        timer,
+                    )
+                    .await?;
+                if found.is_none() {
+                    lease = Some((lock, key));
+                }
+                found
+            }
+            (found, _) => found,
+        };
+        let store = match found {
+            Some(store) if self.bypasses_cookies(middleware, &store.0)? => None,
+            store => store,
+        };
+        if let Some(store) = store {
+            let (mut res, policy, via_alias) = store;
+            res.cache_lookup_status(HitOrMiss::HIT);
+            if let Some(warning_code) = res.warning_code() {
+                // https://tools.ietf.org/html/rfc7234#section-4.3.4
+                //
+                // If a stored response is selected for update, the cache MUST:
+                //
+                // * delete any warning header fields in the stored response with
+                //   warn-code 1xx (see Section 5.5);
+                //
+                // * retain any warning header fields in the stored response with
+                //   warn-code 2xx;
+                //
+                if (100..200).contains(&warning_code) {
+                    res.remove_warning();
+                }
+            }
+
+            match mode {
+                CacheMode::Default
+                    if wants_stored_range(&req_headers, &res) =>
+                {
+                    // The stored response can't be cut to the range, so the
+                    // origin is asked for it
+                    let (mut res, outcome) =
+                        self.forward(middleware, "partial", timer).await?;
+                    res.cache_lookup_status(HitOrMiss::HIT);
+                    Ok((res, outcome))
+                }
+                CacheMode::Default => {
+                    let (res, status) = self
+                        .conditional_fetch(
+                            middleware, res, policy, via_alias, false, timer,
+                        )
+                        .await?;
+                    let res = match status {
+                        CacheLookupStatus::Fresh
+                            if if_none_match_matches(&req_headers, &res) =>
+                        {
+                            not_modified(res)
+                        }
+                        _ => res,
+                    };
+                    let outcome = match status {
+                        CacheLookupStatus::Fresh => Outcome::Hit,
+                        CacheLookupStatus::NotModified => Outcome::Forward {
+                            reason: "stale",
+                            status: Some(304),
+                            stored: true,
+                        },
+                        CacheLookupStatus::Modified => Outcome::Forward {
+                            reason: "stale",
+                            status: Some(res.status),
+                            stored: true,
+                        },
+                        CacheLookupStatus::Gone => Outcome::Forward {
+                            reason: "stale",
+                            status: Some(res.status),
+                            stored: false,
+                        },
+                        _ => Outcome::Forward {
+                            reason: "stale",
+                            status: None,
+                            stored: false,
+                        },
+                    };
+                    Ok((res, outcome))
+                }
+                CacheMode::NoCache => {
+                    middleware.force_no_cache()?;
+                    let (mut res, outcome) =
+                        self.forward(middleware, "request", timer).await?;
+                    res.cache_lookup_status(HitOrMiss::HIT);
+                    Ok((res, outcome))
+                }
+                CacheMode::ForceCache | CacheMode::OnlyIfCached => {
+                    //   112 Disconnected operation
+                    // SHOULD be included if the cache is intentionally disconnected from
+                    // the rest of the network for a period of time.
+                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                    res.add_warning(
+                        &res.url.clone(),
+                        112,
                         "Disconnected operation",
                     );
+                    // The policy accounts for any upstream `Age` as well
+                    // as the time the response has been held locally.
+                    res.headers.insert(
+                        AGE.as_str().to_string(),
+                        policy.age(now()).as_secs().to_string(),
+                    );
                     res.cache_status(HitOrMiss::HIT);
-                    Ok(res)
+                    Ok((res, Outcome::Hit))
                 }
-                _ => self.remote_fetch(&mut middleware).await,
+                _ => self.forward(middleware, "request", timer).await,
             }
         } else {
-            match self.mode {
+            match mode {
                 CacheMode::OnlyIfCached => {
-                    // ENOTCACHED
-                    let mut res = HttpResponse {
-                        body: b"GatewayTimeout".to_vec(),
-                        headers: HashMap::default(),
-                        status: 504,
-                        url: middleware.url()?,
-                        version: HttpVersion::Http11,
-                    };
-                    res.cache_status(HitOrMiss::MISS);
-                    res.cache_lookup_status(HitOrMiss::MISS);
-                    Ok(res)
+                    Ok((gateway_timeout(middleware.url()?), Outcome::Generated))
+                }
+                _ => {
+                    let answered =
+                        self.forward(middleware, "uri-miss", timer).await;
+                    if let Some((lock, key)) = lease {
+                        // Left to lapse if it can't be released
+                        lock.release(&key).await.ok();
+                    }
+                    match (answered, &self.options.fallback_response) {
+                        (Err(_), Some(fallback)) => {
+                            let mut res = fallback.respond(
+                                &middleware.parts()?,
+                                &middleware.url()?,
+                            );
+                            res.cache_status(HitOrMiss::MISS);
+                            res.cache_lookup_status(HitOrMiss::MISS);
+                            Ok((res, Outcome::Generated))
+                        }
+                        (answered, _) => answered,
+                    }
                 }
-                _ => self.remote_fetch(&mut middleware).await,
             }
         }
     }
 
+    // Waits for the lease on the key of a request with nothing stored for it,
+    // returning what its holder stored meanwhile or `None` once it's taken
+    async fn await_lease(
+        &self,
+        lock: &dyn CacheLock,
+        key: &str,
+        method: &str,
+        key_url: &Url,
+        req_headers: &http::HeaderMap,
+        timer: &mut Timer,
+    ) -> Result<Option<(HttpResponse, CachePolicy, bool)>> {
+        let ttl = self.options.cache_lock_ttl.unwrap_or(DEFAULT_LOCK_TTL);
+        while !lock.try_acquire(key, ttl).await? {
+            lock.wait(key).await?;
+            let started = now();
+            let found = self.lookup(method, key_url, req_headers).await?;
+            timer.timings.lookup += Timer::since(started);
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(None)
+    }
+
+    // Answers a request carrying `only-if-cached` with the stored response
+    // while it's fresh and matches the request, or a `504` otherwise
+    async fn answer_only_if_cached(
+        &self,
+        middleware: &mut impl Driver,
+        timer: &mut Timer,
+    ) -> Result<(HttpResponse, Outcome)> {
+        let method = middleware.method()?.to_uppercase();
+        let key_url = self.request_url(middleware, &method).await?;
+        let started = now();
+        let found = self
+            .lookup(&method, &key_url, &middleware.parts()?.headers)
+            .await?;
+        timer.timings.lookup += Timer::since(started);
+        if let Some((mut res, policy, via_alias)) = found {
+            let mut req_parts = middleware.parts()?;
+            if via_alias || self.is_keyed(middleware) {
+                req_parts.uri = res.url.as_str().parse()?;
+            }
+            let now = now();
+            let matches = match policy.before_request(&req_parts, now) {
+                BeforeRequest::Fresh(_) => true,
+                BeforeRequest::Stale { matches, .. } => matches,
+            };
+            if matches
+                && self.is_fresh(&res, &policy, now)?
+                && !self.bypasses_cookies(middleware, &res)?
+            {
+                res.headers.insert(
+                    AGE.as_str().to_string(),
+                    policy.age(now).as_secs().to_string(),
+                );
+                res.cache_status(HitOrMiss::HIT);
+                res.cache_lookup_status(HitOrMiss::HIT);
+                return Ok((res, Outcome::Hit));
+            }
+        }
+        Ok((gateway_timeout(middleware.url()?), Outcome::Generated))
+    }
+
+    // Fetches the response from the origin for the `fwd` reason given
+    async fn forward(
+        &self,
+        middleware: &mut impl Driver,
+        reason: &'static str,
+        timer: &mut Timer,
+    ) -> Result<(HttpResponse, Outcome)> {
+        let (res, stored) = self.remote_fetch(middleware, timer).await?;
+        let status = Some(res.status);
+        Ok((res, Outcome::Forward { reason, status, stored }))
+    }
+
+    // Appends the `Cache-Status` entry for the outcome to the response
+    async fn report_status(
+        &self,
+        status: &CacheStatusHeader,
+        middleware: &impl Driver,
+        res: &mut HttpResponse,
+        outcome: Outcome,
+    ) -> Result<()> {
+        let is_held = match outcome {
+            Outcome::Hit => true,
+            Outcome::Forward { stored, .. } => stored,
+            Outcome::Generated => false,
+        };
+        let ttl = if is_held {
+            let policy = self.response_policy(middleware, res)?;
+            Some(self.ttl(res, &policy, now())?.as_secs())
+        } else {
+            None
+        };
+        let mut entry = status.entry(outcome, ttl);
+        if status.include_key && middleware.is_method_get_head() {
+            let method = middleware.method()?.to_uppercase();
+            let key = self.request_key(middleware, &method).await?;
+            entry.push_str(&format!("; key={}", sf_string(&key)));
+        }
+        let entry = match res.headers.get(CACHE_STATUS) {
+            Some(existing) => format!("{}, {}", existing, entry),
+            None => entry,
+        };
+        res.headers.insert(CACHE_STATUS.to_string(), entry);
+        if status.replace_x_cache {
+            res.headers.remove(XCACHE);
+            res.headers.remove(XCACHELOOKUP);
+        }
+        Ok(())
+    }
+
+    /// Issues a conditional request for the stored response using its
+    /// validators, even if it is still fresh. A `304` refreshes the stored
+    /// response's freshness and a `200` replaces it. When nothing is stored
+    /// the response is fetched and stored as usual.
+    pub async fn revalidate(
+        &self,
+        mut middleware: impl Driver,
+    ) -> Result<CacheLookupStatus> {
+        let mut timer = Timer::new();
+        let method = middleware.method()?.to_uppercase();
+        let key_url = self.request_url(&middleware, &method).await?;
+        let started = now();
+        let found = self
+            .lookup(&method, &key_url, &middleware.parts()?.headers)
+            .await?;
+        timer.timings.lookup += Timer::since(started);
+        let status = match found {
+            Some((res, policy, via_alias)) => {
+                self.conditional_fetch(
+                    &mut middleware,
+                    res,
+                    policy,
+                    via_alias,
+                    true,
+                    &mut timer,
+                )
+                .await?
+                .1
+            }
+            None => {
+                self.remote_fetch(&mut middleware, &mut timer).await?;
+                CacheLookupStatus::Fetched
+            }
+        };
+        timer.finish(self.options.observer.as_ref());
+        Ok(status)
+    }
+
+    /// Determines if the stored response for the method and url has less
+    /// than the `lookahead` fraction of its freshness lifetime left, or is
+    /// already stale. `false` when nothing is stored.
+    pub async fn is_expiring(
+        &self,
+        method: &str,
+        url: &Url,
+        lookahead: f64,
+    ) -> Result<bool> {
+        let method = method.to_uppercase();
+        let key_url = self.manager_url(&method, url)?;
+        match self.lookup(&method, &key_url, &http::HeaderMap::new()).await? {
+            Some((res, policy, _)) => {
+                let now = now();
+                let ttl = self.ttl(&res, &policy, now)?;
+                let lifetime = policy.age(now) + ttl;
+                Ok(ttl.is_zero()
+                    || ttl.as_secs_f64() < lifetime.as_secs_f64() * lookahead)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Fetches the response from the origin and stores it if it can be,
+    // the returned flag is set when it was stored
     async fn remote_fetch(
         &self,
-        middleware: &mut impl Middleware,
-    ) -> Result<HttpResponse> {
-        let mut res = middleware.remote_fetch().await?;
+        middleware: &mut impl Driver,
+        timer: &mut Timer,
+    ) -> Result<(HttpResponse, bool)> {
+        let mut res = self.fetch(middleware, timer).await?;
         res.cache_status(HitOrMiss::MISS);
         res.cache_lookup_status(HitOrMiss::MISS);
-        let policy = match self.options {
-            Some(options) => middleware.policy_with_options(&res, options)?,
-            None => middleware.policy(&res)?,
+        let policy = self.response_policy(middleware, &res)?;
+        let is_storable = match self.surrogate(&res) {
+            Some(surrogate) => !surrogate.no_store,
+            None if self.negative_ttl(res.status).is_some()
+                || (self.options.cache_server_errors
+                    && (500..600).contains(&res.status)) =>
+            {
+                self.allows_negative(middleware, &res)?
+            }
+            None => policy.is_storable(),
         };
         let is_get_head = middleware.is_method_get_head();
         let is_cacheable = is_get_head
-            && self.mode != CacheMode::NoStore
-            && self.mode != CacheMode::Reload
-            && res.status == 200
-            && policy.is_storable();
-        let url = middleware.url()?;
-        let method = middleware.method()?.to_uppercase();
+            && self.cache_mode(middleware) != CacheMode::NoStore
+            && self.is_storable_response(&res, is_storable)
+            && !self.bypasses_cookies(middleware, &res)?;
         if is_cacheable {
-            Ok(self.manager.put(&method, &url, res, policy).await?)
+            let method = middleware.method()?.to_uppercase();
+            let res = self.check_content_length(&method, res)?;
+            let res = self.store(middleware, res, policy, timer).await?;
+            Ok((res, !timer.timings.store_skipped))
         } else if !is_get_head {
-            self.manager.delete("GET", &url).await.ok();
-            Ok(res)
+            let key_url = self.request_url(middleware, "GET").await?;
+            self.delete_entry("GET", &key_url).await.ok();
+            Ok((res, false))
+        } else {
+            Ok((res, false))
+        }
+    }
+
+    // Sends the request to the origin, announcing the surrogate capability
+    // when the cache has a token
+    async fn fetch(
+        &self,
+        middleware: &mut impl Driver,
+        timer: &mut Timer,
+    ) -> Result<HttpResponse> {
+        if let Some(token) = &self.options.surrogate_token {
+            let capability = format!("{}=\"Surrogate/1.0\"", token);
+            let parts = request::Builder::new()
+                .header(SURROGATE_CAPABILITY, capability)
+                .body(())?
+                .into_parts()
+                .0;
+            middleware.update_headers(&parts)?;
+        }
+        let started = now();
+        let res = middleware.remote_fetch().await;
+        timer.timings.origin += Timer::since(started);
+        res
+    }
+
+    // Compares the body with the `Content-Length` it was sent with, handling
+    // a response that doesn't match as `content_length_mismatch` says
+    fn check_content_length(
+        &self,
+        method: &str,
+        mut res: HttpResponse,
+    ) -> Result<HttpResponse> {
+        let policy = self.options.content_length_mismatch;
+        if policy == ContentLengthMismatch::Store
+            || method == "HEAD"
+            || matches!(res.status, 100..=199 | 204 | 304)
+        {
+            return Ok(res);
+        }
+        let declared = match res
+            .headers
+            .get(CONTENT_LENGTH.as_str())
+            .and_then(|len| len.trim().parse::<u64>().ok())
+        {
+            Some(declared) => declared,
+            None => return Ok(res),
+        };
+        let actual = res.body.len() as u64;
+        if declared == actual {
+            return Ok(res);
+        }
+        match policy {
+            ContentLengthMismatch::Reject => {
+                Err(CacheError::ContentLengthMismatch { declared, actual })
+            }
+            _ => {
+                res.headers.insert(
+                    CONTENT_LENGTH.as_str().to_string(),
+                    actual.to_string(),
+                );
+                Ok(res)
+            }
+        }
+    }
+
+    // Creates the policy for the response to the request, as it will be
+    // stored
+    fn response_policy(
+        &self,
+        middleware: &impl Driver,
+        res: &HttpResponse,
+    ) -> Result<CachePolicy> {
+        let stripped;
+        let res = if self.options.set_cookie_policy() == SetCookiePolicy::Strip
+            && res.headers.keys().any(|name| is_set_cookie(name))
+        {
+            let mut copy = res.clone();
+            self.options.strip_set_cookie(&mut copy.headers);
+            stripped = copy;
+            &stripped
+        } else {
+            res
+        };
+        match self.cache_options(middleware) {
+            Some(options) => middleware.policy_with_options(res, options),
+            None => middleware.policy(res),
+        }
+    }
+
+    // The time the origin asked not to be retried before with a `503` and
+    // its `Retry-After`, when that's honored, lengthened by the jitter
+    fn retry_at(
+        &self,
+        res: &HttpResponse,
+        now: SystemTime,
+    ) -> Option<SystemTime> {
+        if !self.options.honor_retry_after || res.status != 503 {
+            return None;
+        }
+        let value = res.headers.get(RETRY_AFTER.as_str())?;
+        Some(
+            parse_retry_after(value, now)?
+                + jitter(self.options.retry_after_jitter),
+        )
+    }
+
+    // Marks the stored response to be served stale without asking the
+    // origin until the time
+    async fn hold_back(
+        &self,
+        middleware: &impl Driver,
+        via_alias: bool,
+        cached_res: &HttpResponse,
+        until: SystemTime,
+    ) -> Result<()> {
+        let method = middleware.method()?.to_uppercase();
+        let mut key_url = if via_alias {
+            self.manager_url(&method, &cached_res.url)?
         } else {
-            Ok(res)
+            self.request_url(middleware, &method).await?
+        };
+        // Read back as stored, the looked up response may have been altered
+        let mut stored = self.manager.get(&method, &key_url).await?;
+        if let Some((index, _)) = &stored {
+            if index.headers.contains_key(VARIANT_IDS) {
+                // The response is the variant the index record points at
+                let req_headers = middleware.parts()?.headers;
+                stored = match request_variant(&key_url, index, &req_headers)? {
+                    Some(variant) => {
+                        let found = self.manager.get(&method, &variant).await?;
+                        key_url = variant;
+                        found
+                    }
+                    None => None,
+                };
+            }
         }
+        if let Some((mut res, policy)) = stored {
+            let secs = until
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            res.headers.insert(RETRY_AT.to_string(), secs.to_string());
+            self.manager.put(&method, &key_url, res, policy).await?;
+        }
+        Ok(())
     }
 
     async fn conditional_fetch(
         &self,
-        mut middleware: impl Middleware,
+        middleware: &mut impl Driver,
         mut cached_res: HttpResponse,
-        mut policy: CachePolicy,
-    ) -> Result<HttpResponse> {
-        let before_req =
-            policy.before_request(&middleware.parts()?, SystemTime::now());
+        policy: CachePolicy,
+        via_alias: bool,
+        revalidate: bool,
+        timer: &mut Timer,
+    ) -> Result<(HttpResponse, CacheLookupStatus)> {
+        let now = now();
+        let retry_at = cached_res
+            .headers
+            .remove(RETRY_AT)
+            .and_then(|secs| secs.parse().ok())
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        let fresh_until = cached_res
+            .headers
+            .remove(FRESH_UNTIL)
+            .and_then(|secs| secs.parse().ok())
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        let mut req_parts = middleware.parts()?;
+        if !revalidate
+            && fresh_until.map_or(false, |until| now < until)
+            && self.is_plainly_fresh(middleware, &req_parts, &cached_res)?
+        {
+            // Still in the window recorded when it was stored, which nothing
+            // about the request or the options shortens
+            let age = policy.age(now);
+            if self.max_ttl(&cached_res).map_or(true, |max| age < max) {
+                cached_res.headers.insert(
+                    AGE.as_str().to_string(),
+                    age.as_secs().to_string(),
+                );
+                cached_res.headers.insert(
+                    DATE.as_str().to_string(),
+                    httpdate::fmt_http_date(now),
+                );
+                cached_res.cache_status(HitOrMiss::HIT);
+                cached_res.cache_lookup_status(HitOrMiss::HIT);
+                return Ok((cached_res, CacheLookupStatus::Fresh));
+            }
+        }
+        if via_alias || self.is_keyed(middleware) {
+            // The policy was stored for the url the alias points at, or
+            // for whichever url was first stored under the key
+            req_parts.uri = cached_res.url.as_str().parse()?;
+        }
+        let accept_encoding = req_parts.headers.get(ACCEPT_ENCODING).cloned();
+        if let (Some(value), Some(supported)) =
+            (&accept_encoding, &self.options.normalize_accept_encoding)
+        {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let normalized = normalize_accept_encoding(&value, supported);
+            req_parts
+                .headers
+                .insert(ACCEPT_ENCODING, HeaderValue::from_str(&normalized)?);
+        }
+        // A negatively cached response is fresh for the negative TTL alone,
+        // otherwise `Surrogate-Control` decides in place of the policy
+        let negative_ttl = self.negative_ttl(cached_res.status);
+        let override_fresh = match negative_ttl {
+            Some(ttl) => Some(policy.age(now) < ttl),
+            None => self
+                .surrogate(&cached_res)
+                .and_then(|s| s.max_age)
+                .map(|max_age| policy.age(now) < max_age),
+        };
+        let force_stale = revalidate
+            || (negative_ttl.is_none()
+                && self
+                    .max_ttl(&cached_res)
+                    .map_or(false, |max| policy.age(now) >= max))
+            || override_fresh == Some(false);
+        let min_ttl_holds = negative_ttl.is_none()
+            && self.options.min_ttl.map_or(false, |min| policy.age(now) < min)
+            && !forbids_min_ttl(&cached_res.parts()?.headers);
+        let held_fresh = !force_stale
+            && !forbids_min_ttl(&req_parts.headers)
+            && (override_fresh == Some(true) || min_ttl_holds);
+        if force_stale {
+            // Force the policy to treat the stored response as stale
+            req_parts
+                .headers
+                .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        }
+        let body_missing =
+            is_body_missing(&middleware.method()?.to_uppercase(), &cached_res);
+        let before_req = policy.before_request(&req_parts, now);
         match before_req {
+            // Without the stored body the conditional headers are left off so
+            // the full response is fetched to repopulate the cache
+            _ if body_missing => {}
             BeforeRequest::Fresh(parts) => {
+                // The policy holds every header of the original response
                 cached_res.update_headers(&parts)?;
+                self.options.filter_stored_headers(&mut cached_res.headers);
+                cached_res.cache_status(HitOrMiss::HIT);
+                cached_res.cache_lookup_status(HitOrMiss::HIT);
+                return Ok((cached_res, CacheLookupStatus::Fresh));
+            }
+            BeforeRequest::Stale { matches: true, .. } if held_fresh => {
+                cached_res.headers.insert(
+                    AGE.as_str().to_string(),
+                    policy.age(now).as_secs().to_string(),
+                );
                 cached_res.cache_status(HitOrMiss::HIT);
                 cached_res.cache_lookup_status(HitOrMiss::HIT);
-                return Ok(cached_res);
+                return Ok((cached_res, CacheLookupStatus::Fresh));
             }
-            BeforeRequest::Stale { request: parts, matches } => {
+            BeforeRequest::Stale { request: mut parts, matches } => {
+                if force_stale {
+                    // Don't send the directive we injected upstream
+                    parts.headers.remove(CACHE_CONTROL);
+                }
+                if let Some(value) = accept_encoding {
+                    // Nor the normalized `Accept-Encoding`
+                    parts.headers.insert(ACCEPT_ENCODING, value);
+                }
+                let has_both = parts.headers.contains_key(IF_NONE_MATCH)
+                    && parts.headers.contains_key(IF_MODIFIED_SINCE);
+                match self.options.validator_preference {
+                    _ if !has_both => {}
+                    ValidatorPreference::Both => {}
+                    ValidatorPreference::ETagOnly => {
+                        parts.headers.remove(IF_MODIFIED_SINCE);
+                    }
+                    ValidatorPreference::LastModifiedOnly => {
+                        parts.headers.remove(IF_NONE_MATCH);
+                    }
+                }
                 if matches {
                     middleware.update_headers(&parts)?;
                 }
             }
         }
         let req_url = middleware.url()?;
-        match middleware.remote_fetch().await {
+        if !revalidate && !body_missing && retry_at.map_or(false, |at| now < at)
+        {
+            // The origin asked not to be retried yet
+            cached_res.add_warning(&req_url, 111, "Revalidation failed");
+            cached_res.cache_status(HitOrMiss::HIT);
+            return Ok((cached_res, CacheLookupStatus::Failed));
+        }
+        let revalidation = Revalidation {
+            cached_res,
+            policy,
+            req_parts,
+            req_url,
+            via_alias,
+            force_stale,
+            body_missing,
+            now,
+        };
+        let deadline = self.options.revalidation_deadline;
+        if let (false, Some(deadline), Some(background)) =
+            (revalidate, deadline, &self.options.background)
+        {
+            if let Some(detached) =
+                self.detach_revalidation(middleware, &revalidation)
+            {
+                return self
+                    .revalidate_by_deadline(
+                        deadline,
+                        background,
+                        detached,
+                        revalidation,
+                    )
+                    .await;
+            }
+        }
+        let fetched = self.fetch(middleware, timer).await;
+        // Boxed, as its state inline makes the future of every request large
+        // enough to overflow the stack of a test thread in debug builds
+        Box::pin(self.finish_revalidation(
+            middleware,
+            revalidation,
+            fetched,
+            timer,
+        ))
+        .await
+    }
+
+    // Returns a copy of the request to revalidate the stored response with
+    // in the background. A response that must be revalidated, or is missing
+    // its body, can't be served stale in the meantime.
+    fn detach_revalidation(
+        &self,
+        middleware: &impl Driver,
+        revalidation: &Revalidation,
+    ) -> Option<Box<dyn Middleware + Sync>> {
+        if revalidation.body_missing
+            || revalidation.cached_res.must_revalidate()
+        {
+            return None;
+        }
+        middleware.detach()
+    }
+
+    // Sends the conditional request from the background, serving the
+    // stored response stale once the deadline passes without an answer,
+    // which the background then handles as a revalidation would. With as
+    // many revalidations running as allowed, it's served stale right away.
+    async fn revalidate_by_deadline(
+        &self,
+        deadline: Duration,
+        background: &Background,
+        mut detached: Box<dyn Middleware + Sync>,
+        revalidation: Revalidation,
+    ) -> Result<(HttpResponse, CacheLookupStatus)> {
+        let mut stale = revalidation.cached_res.clone();
+        let req_url = revalidation.req_url.clone();
+        let limit = self.options.max_background_revalidations;
+        let permit = match background.try_revalidate(limit) {
+            Some(permit) => permit,
+            None => {
+                stale.add_warning(&req_url, 110, "Response is stale");
+                stale.cache_status(HitOrMiss::HIT);
+                return Ok((stale, CacheLookupStatus::Failed));
+            }
+        };
+        let cache = HttpCache {
+            mode: self.mode,
+            manager: background.manager.clone(),
+            options: self.options.clone(),
+        };
+        let (answered, answer) = oneshot::channel();
+        background.spawn(async move {
+            let _permit = permit;
+            let mut timer = Timer::new();
+            let fetched = cache.fetch(&mut detached, &mut timer).await;
+            let revalidated = cache
+                .finish_revalidation(
+                    &mut detached,
+                    revalidation,
+                    fetched,
+                    &mut timer,
+                )
+                .await;
+            answered.send(revalidated).ok();
+        });
+        let timeout = background.spawner.sleep(deadline);
+        match future::select(answer, timeout).await {
+            Either::Left((Ok(revalidated), _)) => revalidated,
+            Either::Left((Err(_), _)) => {
+                // The executor dropped the revalidation
+                stale.add_warning(&req_url, 111, "Revalidation failed");
+                stale.cache_status(HitOrMiss::HIT);
+                Ok((stale, CacheLookupStatus::Failed))
+            }
+            Either::Right(_) => {
+                //   110 Response is stale
+                //   MUST be included whenever the returned response is stale.
+                // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                stale.add_warning(&req_url, 110, "Response is stale");
+                stale.cache_status(HitOrMiss::HIT);
+                Ok((stale, CacheLookupStatus::Failed))
+            }
+        }
+    }
+
+    // Handles the answer to the conditional request for the stored response
+    async fn finish_revalidation(
+        &self,
+        middleware: &mut impl Driver,
+        revalidation: Revalidation,
+        fetched: Result<HttpResponse>,
+        timer: &mut Timer,
+    ) -> Result<(HttpResponse, CacheLookupStatus)> {
+        let Revalidation {
+            mut cached_res,
+            mut policy,
+            mut req_parts,
+            req_url,
+            via_alias,
+            force_stale,
+            body_missing,
+            now,
+        } = revalidation;
+        let method = middleware.method()?.to_uppercase();
+        match fetched.and_then(|res| self.check_content_length(&method, res)) {
             Ok(mut cond_res) => {
                 let status = StatusCode::from_u16(cond_res.status)?;
-                if status.is_server_error() && cached_res.must_revalidate() {
+                if self.is_gone_status(cond_res.status) {
+                    // Nothing stored may be served for it anymore, even
+                    // under `ForceCache`
+                    let method = middleware.method()?.to_uppercase();
+                    let key_url = self.request_url(middleware, &method).await?;
+                    self.delete_entry(&method, &key_url).await?;
+                    if via_alias {
+                        let target =
+                            self.manager_url(&method, &cached_res.url)?;
+                        self.manager.delete(&method, &target).await?;
+                    }
+                    cond_res.cache_status(HitOrMiss::MISS);
+                    cond_res.cache_lookup_status(HitOrMiss::HIT);
+                    if self.negative_ttl(cond_res.status).is_some()
+                        && self.allows_negative(middleware, &cond_res)?
+                        && !self.bypasses_cookies(middleware, &cond_res)?
+                    {
+                        // It takes the place of what was stored, as a
+                        // negatively cached response
+                        let policy =
+                            self.response_policy(middleware, &cond_res)?;
+                        cond_res = self
+                            .store(middleware, cond_res, policy, timer)
+                            .await?;
+                    }
+                    Ok((cond_res, CacheLookupStatus::Gone))
+                } else if body_missing
+                    && !self.is_storable_response(&cond_res, true)
+                {
+                    // Nothing usable is stored to fall back on, such as a
+                    // `304` to validators the request carried itself
+                    cond_res.cache_status(HitOrMiss::MISS);
+                    cond_res.cache_lookup_status(HitOrMiss::HIT);
+                    Ok((cond_res, CacheLookupStatus::Failed))
+                } else if status.is_server_error()
+                    && cached_res.must_revalidate()
+                {
                     //   111 Revalidation failed
                     //   MUST be included if a cache returns a stale response
                     //   because an attempt to revalidate the response failed,
@@ -490,49 +4297,68 @@ impl<T: CacheManager> HttpCache<T> {
                         "Revalidation failed",
                     );
                     cached_res.cache_status(HitOrMiss::HIT);
-                    Ok(cached_res)
+                    Ok((cached_res, CacheLookupStatus::Failed))
                 } else if cond_res.status == 304 {
+                    if force_stale {
+                        req_parts.headers.remove(CACHE_CONTROL);
+                    }
                     let after_res = policy.after_response(
-                        &middleware.parts()?,
+                        &req_parts,
                         &cond_res.parts()?,
-                        SystemTime::now(),
+                        crate::now(),
                     );
                     match after_res {
                         AfterResponse::Modified(new_policy, parts)
                         | AfterResponse::NotModified(new_policy, parts) => {
                             policy = new_policy;
                             cached_res.update_headers(&parts)?;
+                            self.options
+                                .filter_stored_headers(&mut cached_res.headers);
                         }
                     }
                     cached_res.cache_status(HitOrMiss::HIT);
                     cached_res.cache_lookup_status(HitOrMiss::HIT);
-                    let method = middleware.method()?.to_uppercase();
                     let res = self
-                        .manager
-                        .put(&method, &req_url, cached_res, policy)
+                        .store(middleware, cached_res, policy, timer)
                         .await?;
-                    Ok(res)
-                } else if cond_res.status == 200 {
-                    let policy = match self.options {
-                        Some(options) => middleware
-                            .policy_with_options(&cond_res, options)?,
-                        None => middleware.policy(&cond_res)?,
-                    };
+                    Ok((res, CacheLookupStatus::NotModified))
+                } else if cond_res.status == 206 {
+                    // The partial body can't replace the stored response,
+                    // pass it through untouched as a miss
                     cond_res.cache_status(HitOrMiss::MISS);
                     cond_res.cache_lookup_status(HitOrMiss::HIT);
-                    let method = middleware.method()?.to_uppercase();
-                    let res = self
-                        .manager
-                        .put(&method, &req_url, cond_res, policy)
-                        .await?;
-                    Ok(res)
+                    Ok((cond_res, CacheLookupStatus::Failed))
+                } else if self.is_storable_response(&cond_res, true)
+                    && self.bypasses_cookies(middleware, &cond_res)?
+                {
+                    // The new response can't replace the stored one under
+                    // the cookie policy, pass it through as a miss
+                    cond_res.cache_status(HitOrMiss::MISS);
+                    cond_res.cache_lookup_status(HitOrMiss::HIT);
+                    Ok((cond_res, CacheLookupStatus::Failed))
+                } else if self.is_storable_response(&cond_res, true) {
+                    let policy = self.response_policy(middleware, &cond_res)?;
+                    cond_res.cache_status(HitOrMiss::MISS);
+                    cond_res.cache_lookup_status(HitOrMiss::HIT);
+                    let res =
+                        self.store(middleware, cond_res, policy, timer).await?;
+                    Ok((res, CacheLookupStatus::Modified))
                 } else {
+                    if let Some(until) = self.retry_at(&cond_res, now) {
+                        self.hold_back(
+                            middleware,
+                            via_alias,
+                            &cached_res,
+                            until,
+                        )
+                        .await?;
+                    }
                     cached_res.cache_status(HitOrMiss::HIT);
-                    Ok(cached_res)
+                    Ok((cached_res, CacheLookupStatus::Failed))
                 }
             }
             Err(e) => {
-                if cached_res.must_revalidate() {
+                if body_missing || cached_res.must_revalidate() {
                     Err(e)
                 } else {
                     //   111 Revalidation failed
@@ -546,7 +4372,7 @@ impl<T: CacheManager> HttpCache<T> {
                         "Revalidation failed",
                     );
                     cached_res.cache_status(HitOrMiss::HIT);
-                    Ok(cached_res)
+                    Ok((cached_res, CacheLookupStatus::Failed))
                 }
             }
         }
@@ -1,20 +1,45 @@
-use crate::{CacheManager, HttpResponse, Result};
+use super::parse_key;
+use crate::{
+    CacheError, CacheManager, HttpResponse, HttpVersion, Result,
+    SerializationFormat,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
+use bytes::Bytes;
+use futures::{AsyncWriteExt, Stream, StreamExt};
 use http_cache_semantics::CachePolicy;
 use serde::{Deserialize, Serialize};
+use ssri::Integrity;
 use url::Url;
 
 /// Implements [`CacheManager`] with [`cacache`](https://github.com/zkat/cacache-rs) as the backend.
+///
+/// Entries are written to a temporary file and only indexed once their
+/// content has been verified against its hash, so readers never see a
+/// partial entry, even with concurrent puts to the same key. Content that
+/// fails its integrity check when read is removed and treated as a miss.
+///
+/// cacache does no size-based eviction, as entries stay on disk until they
+/// are deleted or the directory is cleared. [`pin`](CacheManager::pin) and
+/// [`unpin`](CacheManager::unpin) therefore have no effect.
 #[cfg_attr(docsrs, doc(cfg(feature = "manager-cacache")))]
 #[derive(Debug, Clone)]
 pub struct CACacheManager {
     /// Directory where the cache will be stored.
     pub path: String,
+    /// The format new entries are serialized with
+    pub format: SerializationFormat,
 }
 
 impl Default for CACacheManager {
     fn default() -> Self {
-        Self { path: "./http-cacache".into() }
+        Self {
+            path: "./http-cacache".into(),
+            format: SerializationFormat::default(),
+        }
     }
 }
 
@@ -24,17 +49,104 @@ struct Store {
     policy: CachePolicy,
 }
 
+// Layout of bincode entries written before responses had trailers, which
+// can't be read with the current layout as bincode has no field names
+#[derive(Deserialize)]
+struct LegacyStore {
+    response: LegacyResponse,
+    policy: CachePolicy,
+}
+
+#[derive(Deserialize)]
+struct LegacyResponse {
+    #[serde(with = "crate::body_serde")]
+    body: Bytes,
+    headers: HashMap<String, String>,
+    status: u16,
+    url: Url,
+    version: HttpVersion,
+}
+
+impl From<LegacyStore> for Store {
+    fn from(legacy: LegacyStore) -> Self {
+        let res = legacy.response;
+        Self {
+            response: HttpResponse {
+                body: res.body,
+                headers: res.headers,
+                trailers: HashMap::new(),
+                status: res.status,
+                url: res.url,
+                version: res.version,
+            },
+            policy: legacy.policy,
+        }
+    }
+}
+
+// Recorded with entries using the current layout
+const LAYOUT_VERSION: u64 = 2;
+
+// Key of the record written by health checks, which can't be mistaken for
+// a stored response as it holds no url
+const HEALTH_CHECK_KEY: &str = "http-cache:health-check";
+
 fn req_key(method: &str, url: &Url) -> String {
     format!("{}:{}", method, url)
 }
 
 #[allow(dead_code)]
 impl CACacheManager {
+    /// Creates a manager storing the cache in the directory, such as one
+    /// computed at startup. Managers with different directories are fully
+    /// independent, even within one process. A path that isn't valid
+    /// Unicode has its invalid sequences replaced.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_string_lossy().into_owned(),
+            format: SerializationFormat::default(),
+        }
+    }
+
     /// Clears out the entire cache.
     pub async fn clear(&self) -> Result<()> {
         cacache::clear(&self.path).await?;
         Ok(())
     }
+
+    /// Attempts to cache a response and related policy, writing the body to
+    /// disk in chunks as they arrive from the stream rather than holding it
+    /// in memory. Any body already set on the response is ignored. The
+    /// record only becomes readable once the whole body has been committed.
+    pub async fn put_stream<S, B, E>(
+        &self,
+        method: &str,
+        url: &Url,
+        mut response: HttpResponse,
+        policy: CachePolicy,
+        mut body: S,
+    ) -> Result<()>
+    where
+        S: Stream<Item = std::result::Result<B, E>> + Unpin,
+        B: AsRef<[u8]>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        response.body = Bytes::new();
+        let data = Store { response, policy };
+        let mut writer = cacache::WriteOpts::new()
+            .metadata(serde_json::to_value(&data)?)
+            .open(&self.path, &req_key(method, url))
+            .await?;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(anyhow::Error::new)?;
+            writer
+                .write_all(chunk.as_ref())
+                .await
+                .map_err(anyhow::Error::new)?;
+        }
+        writer.commit().await?;
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -44,13 +156,48 @@ impl CacheManager for CACacheManager {
         method: &str,
         url: &Url,
     ) -> Result<Option<(HttpResponse, CachePolicy)>> {
-        let store: Store =
-            match cacache::read(&self.path, &req_key(method, url)).await {
-                Ok(d) => bincode::deserialize(&d)?,
-                Err(_e) => {
-                    return Ok(None);
-                }
+        let metadata =
+            match cacache::metadata(&self.path, &req_key(method, url)).await {
+                Ok(Some(m)) => m,
+                _ => return Ok(None),
             };
+        let data = match cacache::read_hash(&self.path, &metadata.integrity)
+            .await
+        {
+            Ok(d) => d,
+            Err(cacache::Error::IntegrityError { .. }) => {
+                // The content doesn't match the hash it was committed
+                // with, so it's removed and the entry treated as a miss.
+                // Only the content goes, an entry a concurrent put has
+                // just committed under the key has a different hash.
+                let _ =
+                    cacache::remove_hash(&self.path, &metadata.integrity).await;
+                return Ok(None);
+            }
+            Err(_e) => {
+                return Ok(None);
+            }
+        };
+        let store: Store = if metadata.metadata.is_null() {
+            // Written before the format was recorded
+            bincode::deserialize::<LegacyStore>(&data)?.into()
+        } else if let Some(format) = metadata.metadata.get("format") {
+            let format = format.as_str().unwrap_or_default();
+            let format = SerializationFormat::from_name(format)?;
+            let version = metadata.metadata.get("version");
+            if format == SerializationFormat::Bincode
+                && version.and_then(|v| v.as_u64()) != Some(LAYOUT_VERSION)
+            {
+                format.deserialize::<LegacyStore>(&data)?.into()
+            } else {
+                format.deserialize(&data)?
+            }
+        } else {
+            // Written by `put_stream`, the content is just the body
+            let mut store: Store = serde_json::from_value(metadata.metadata)?;
+            store.response.body = Bytes::from(data);
+            store
+        };
         Ok(Some((store.response, store.policy)))
     }
 
@@ -62,12 +209,60 @@ impl CacheManager for CACacheManager {
         policy: CachePolicy,
     ) -> Result<HttpResponse> {
         let data = Store { response: response.clone(), policy };
-        let bytes = bincode::serialize(&data)?;
-        cacache::write(&self.path, &req_key(method, url), bytes).await?;
+        let bytes = self.format.serialize(&data)?;
+        // The content is checked against its hash and size before the index
+        // entry is written, which is what makes it visible to readers
+        let mut writer = cacache::WriteOpts::new()
+            .integrity(Integrity::from(&bytes))
+            .size(bytes.len())
+            .metadata(serde_json::json!({
+                "format": self.format.name(),
+                "version": LAYOUT_VERSION,
+            }))
+            .open(&self.path, &req_key(method, url))
+            .await?;
+        writer.write_all(&bytes).await.map_err(anyhow::Error::new)?;
+        writer.commit().await?;
         Ok(response)
     }
 
     async fn delete(&self, method: &str, url: &Url) -> Result<()> {
         Ok(cacache::remove(&self.path, &req_key(method, url)).await?)
     }
+
+    async fn keys(&self) -> Result<Vec<(String, Url)>> {
+        // The index lists every entry ever written under a key, including
+        // those since replaced or removed, so each key is checked for a
+        // current entry
+        let mut listed = HashSet::new();
+        if !Path::new(&self.path).exists() {
+            return Ok(Vec::new());
+        }
+        for metadata in cacache::list_sync(&self.path) {
+            listed.insert(metadata?.key);
+        }
+        let mut keys = Vec::new();
+        for key in listed {
+            if cacache::metadata(&self.path, &key).await?.is_some() {
+                keys.extend(parse_key(&key));
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        let sentinel = b"ok";
+        let integrity =
+            cacache::write(&self.path, HEALTH_CHECK_KEY, sentinel).await?;
+        let read = cacache::read(&self.path, HEALTH_CHECK_KEY).await?;
+        cacache::remove(&self.path, HEALTH_CHECK_KEY).await?;
+        cacache::remove_hash(&self.path, &integrity).await?;
+        if read != sentinel {
+            return Err(CacheError::General(anyhow::anyhow!(
+                "health check read back {:?}",
+                String::from_utf8_lossy(&read)
+            )));
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,122 @@
+use super::parse_key;
+use crate::{CacheManager, HttpResponse, Result};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use http_cache_semantics::CachePolicy;
+use url::Url;
+
+/// Implements [`CacheManager`] with a plain `HashMap` as the backend. Entries
+/// are held as is rather than serialized, and nothing depends on threads or
+/// the filesystem, so it is also available on `wasm32-unknown-unknown`.
+/// It's unbounded, which makes it best suited to tests and examples.
+///
+/// ```
+/// use http_cache::{
+///     CacheManager, CacheMode, HttpCache, HttpCacheOptions, HttpResponse,
+///     HttpVersion, MemoryManager,
+/// };
+/// use http_cache_semantics::CachePolicy;
+/// use std::collections::HashMap;
+/// use url::Url;
+///
+/// # fn main() -> http_cache::Result<()> {
+/// # futures::executor::block_on(async {
+/// let manager = MemoryManager::default();
+/// let cache = HttpCache {
+///     mode: CacheMode::Default,
+///     manager: manager.clone(),
+///     options: HttpCacheOptions::default(),
+/// };
+/// let url = Url::parse("https://example.com/")?;
+/// let response = HttpResponse {
+///     body: "hello".into(),
+///     headers: HashMap::from([(
+///         "cache-control".to_string(),
+///         "max-age=60".to_string(),
+///     )]),
+///     trailers: HashMap::new(),
+///     status: 200,
+///     url: url.clone(),
+///     version: HttpVersion::Http11,
+/// };
+/// let request = http::Request::get(url.as_str()).body(())?;
+/// let policy = CachePolicy::new(&request, &response.parts()?);
+/// manager.put("GET", &url, response, policy).await?;
+///
+/// // The cache now answers for the url without an origin to ask
+/// let (stored, is_fresh) = cache.peek("GET", &url).await?.unwrap();
+/// assert!(is_fresh);
+/// assert_eq!(&stored.body[..], b"hello");
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-memory")))]
+#[derive(Clone, Default)]
+pub struct MemoryManager {
+    /// The stored responses and their policies, keyed by method and url
+    pub cache: Arc<RwLock<HashMap<String, (HttpResponse, CachePolicy)>>>,
+}
+
+impl fmt::Debug for MemoryManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // need to add more data, anything helpful
+        f.debug_struct("MemoryManager").finish_non_exhaustive()
+    }
+}
+
+fn req_key(method: &str, url: &Url) -> String {
+    format!("{}:{}", method, url)
+}
+
+impl MemoryManager {
+    /// Clears out the entire cache.
+    pub async fn clear(&self) -> Result<()> {
+        self.cache.write().expect("lock poisoned").clear();
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheManager for MemoryManager {
+    async fn get(
+        &self,
+        method: &str,
+        url: &Url,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let cache = self.cache.read().expect("lock poisoned");
+        Ok(cache.get(&req_key(method, url)).cloned())
+    }
+
+    async fn put(
+        &self,
+        method: &str,
+        url: &Url,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        self.cache
+            .write()
+            .expect("lock poisoned")
+            .insert(req_key(method, url), (response.clone(), policy));
+        Ok(response)
+    }
+
+    async fn delete(&self, method: &str, url: &Url) -> Result<()> {
+        self.cache
+            .write()
+            .expect("lock poisoned")
+            .remove(&req_key(method, url));
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<(String, Url)>> {
+        let cache = self.cache.read().expect("lock poisoned");
+        Ok(cache.keys().filter_map(|key| parse_key(key)).collect())
+    }
+}
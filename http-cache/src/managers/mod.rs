@@ -3,3 +3,103 @@ pub mod cacache;
 
 #[cfg(feature = "manager-moka")]
 pub mod moka;
+
+#[cfg(feature = "manager-memory")]
+pub mod memory;
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+use crate::{CacheError, Result};
+
+#[cfg(any(
+    feature = "manager-cacache",
+    feature = "manager-moka",
+    feature = "manager-memory"
+))]
+use url::Url;
+
+// Splits a `METHOD:url` key records are stored under back into the method
+// and url, `None` when it isn't one
+#[cfg(any(
+    feature = "manager-cacache",
+    feature = "manager-moka",
+    feature = "manager-memory"
+))]
+pub(crate) fn parse_key(key: &str) -> Option<(String, Url)> {
+    let (method, url) = key.split_once(':')?;
+    Some((method.to_string(), Url::parse(url).ok()?))
+}
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The format managers that persist bytes serialize stored responses with.
+/// The format is recorded alongside each entry, so entries written in one
+/// format can still be read after switching to another.
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerializationFormat {
+    /// Compact binary encoding with [`bincode`](https://github.com/bincode-org/bincode)
+    Bincode,
+    /// Human-readable JSON, for inspecting entries with other tooling
+    Json,
+}
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+impl Default for SerializationFormat {
+    fn default() -> Self {
+        Self::Bincode
+    }
+}
+
+#[cfg(any(feature = "manager-cacache", feature = "manager-moka"))]
+#[allow(dead_code)]
+impl SerializationFormat {
+    pub(crate) fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Bincode => bincode::serialize(value)?,
+            Self::Json => serde_json::to_vec(value)?,
+        })
+    }
+
+    pub(crate) fn deserialize<T: DeserializeOwned>(
+        self,
+        bytes: &[u8],
+    ) -> Result<T> {
+        Ok(match self {
+            Self::Bincode => bincode::deserialize(bytes)?,
+            Self::Json => serde_json::from_slice(bytes)?,
+        })
+    }
+
+    // Name recorded with entries that carry metadata
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Bincode => "bincode",
+            Self::Json => "json",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "bincode" => Ok(Self::Bincode),
+            "json" => Ok(Self::Json),
+            _ => Err(CacheError::UnknownFormat),
+        }
+    }
+
+    // Tag byte prefixed to entries that are stored as plain bytes
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            Self::Bincode => 0,
+            Self::Json => 1,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(Self::Bincode),
+            1 => Ok(Self::Json),
+            _ => Err(CacheError::UnknownFormat),
+        }
+    }
+}
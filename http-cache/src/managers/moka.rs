@@ -1,7 +1,17 @@
-use crate::{CacheManager, HttpResponse, Result};
+use super::parse_key;
+use crate::{
+    now, CacheManager, HttpResponse, Result, SerializationFormat, Spawner,
+};
 
-use std::{fmt, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
+use bytes::Bytes;
+use futures::lock::Mutex;
 use http_cache_semantics::CachePolicy;
 use moka::future::{Cache, ConcurrentCacheExt};
 use serde::{Deserialize, Serialize};
@@ -12,7 +22,26 @@ use url::Url;
 #[derive(Clone)]
 pub struct MokaManager {
     /// The instance of `moka::future::Cache`
-    pub cache: Arc<Cache<String, Arc<Vec<u8>>>>,
+    pub cache: Arc<Cache<String, MokaEntry>>,
+    /// The format new entries are serialized with
+    pub format: SerializationFormat,
+    /// Removes each entry once this long has passed since its policy said
+    /// it went stale, keeping its validators around for cheap revalidation
+    /// in the meantime. When `None`, entries are kept until moka evicts
+    /// them, so modes like [`CacheMode::ForceCache`] can still serve them.
+    ///
+    /// The moka version in use has no per-entry expiry, so an entry past
+    /// its grace period is only removed once it's looked up again. Entries
+    /// nobody asks for stay in memory until moka evicts them, unless
+    /// they're swept with [`remove_expired`] or [`sweep_expired`].
+    ///
+    /// [`CacheMode::ForceCache`]: crate::CacheMode::ForceCache
+    /// [`remove_expired`]: MokaManager::remove_expired
+    /// [`sweep_expired`]: MokaManager::sweep_expired
+    pub stale_grace: Option<Duration>,
+    /// The pinned entries, held apart from `cache` so that it can never
+    /// evict them, see [`CacheManager::pin`]
+    pub pinned: PinnedEntries,
 }
 
 impl fmt::Debug for MokaManager {
@@ -28,6 +57,26 @@ impl Default for MokaManager {
     }
 }
 
+/// An entry held by [`MokaManager`]. The body is kept apart from the
+/// serialized response and policy, so every hit hands out the same buffer
+/// rather than a copy.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+#[derive(Debug, Clone)]
+pub struct MokaEntry {
+    // The format tag followed by the serialized store, without the body
+    head: Arc<Vec<u8>>,
+    body: Bytes,
+    // When the entry stops being worth keeping, see `stale_grace`
+    expires: Option<SystemTime>,
+}
+
+/// The entries pinned in a [`MokaManager`], by key. Clones share the
+/// entries. The lock is held across changes to moka for a pinned key, so an
+/// entry can't end up in both or be lost between them.
+#[cfg_attr(docsrs, doc(cfg(feature = "manager-moka")))]
+#[derive(Debug, Clone, Default)]
+pub struct PinnedEntries(Arc<Mutex<HashMap<String, Option<MokaEntry>>>>);
+
 #[derive(Debug, Deserialize, Serialize)]
 struct Store {
     response: HttpResponse,
@@ -38,17 +87,99 @@ fn req_key(method: &str, url: &Url) -> String {
     format!("{}:{}", method, url)
 }
 
+fn is_expired(entry: &MokaEntry, now: SystemTime) -> bool {
+    entry.expires.map_or(false, |expires| expires <= now)
+}
+
+async fn remove_expired(cache: &Cache<String, MokaEntry>) {
+    let time = now();
+    let expired: Vec<_> = cache
+        .iter()
+        .filter(|(_, entry)| is_expired(entry, time))
+        .map(|(key, _)| key)
+        .collect();
+    for key in expired {
+        // Looked at again right before, to leave alone an entry replaced
+        // since the scan
+        if cache
+            .get(key.as_ref())
+            .map_or(false, |entry| is_expired(&entry, time))
+        {
+            cache.invalidate(key.as_ref()).await;
+        }
+    }
+    cache.sync();
+}
+
 impl MokaManager {
     /// Create a new manager from a pre-configured Cache
-    pub fn new(cache: Cache<String, Arc<Vec<u8>>>) -> Self {
-        Self { cache: Arc::new(cache) }
+    pub fn new(cache: Cache<String, MokaEntry>) -> Self {
+        Self {
+            cache: Arc::new(cache),
+            format: SerializationFormat::default(),
+            stale_grace: None,
+            pinned: PinnedEntries::default(),
+        }
+    }
+    /// Create a new manager from a pre-configured Cache, removing entries
+    /// the grace period after they go stale, see [`stale_grace`]. A
+    /// `time_to_live` or `time_to_idle` configured on the cache itself
+    /// still applies on top of that.
+    ///
+    /// [`stale_grace`]: MokaManager::stale_grace
+    pub fn with_stale_grace(
+        cache: Cache<String, MokaEntry>,
+        grace: Duration,
+    ) -> Self {
+        Self { stale_grace: Some(grace), ..Self::new(cache) }
+    }
+    /// Removes every entry past its grace period, see [`stale_grace`].
+    /// Pinned entries are never removed.
+    ///
+    /// [`stale_grace`]: MokaManager::stale_grace
+    pub async fn remove_expired(&self) {
+        remove_expired(&self.cache).await;
     }
-    /// Clears out the entire cache.
+    /// Calls [`remove_expired`] every `interval` on the spawner, until the
+    /// manager and all its clones are dropped.
+    ///
+    /// [`remove_expired`]: MokaManager::remove_expired
+    pub fn sweep_expired(&self, spawner: Arc<dyn Spawner>, interval: Duration) {
+        // Held weakly, so the sweep doesn't keep the entries alive itself
+        let cache = Arc::downgrade(&self.cache);
+        let sleep = spawner.clone();
+        spawner.spawn(Box::pin(async move {
+            loop {
+                sleep.sleep(interval).await;
+                match cache.upgrade() {
+                    Some(cache) => remove_expired(&cache).await,
+                    None => break,
+                }
+            }
+        }));
+    }
+    /// Clears out the entire cache. Pinned entries are removed as well,
+    /// but stay pinned.
     pub async fn clear(&self) -> Result<()> {
         self.cache.invalidate_all();
         self.cache.sync();
+        for entry in self.pinned.0.lock().await.values_mut() {
+            *entry = None;
+        }
         Ok(())
     }
+
+    fn decode(entry: MokaEntry) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        // The first byte records the format the entry was written with
+        let mut store: Store = match entry.head.split_first() {
+            Some((tag, bytes)) => {
+                SerializationFormat::from_tag(*tag)?.deserialize(bytes)?
+            }
+            None => return Ok(None),
+        };
+        store.response.body = entry.body;
+        Ok(Some((store.response, store.policy)))
+    }
 }
 
 #[async_trait::async_trait]
@@ -58,11 +189,25 @@ impl CacheManager for MokaManager {
         method: &str,
         url: &Url,
     ) -> Result<Option<(HttpResponse, CachePolicy)>> {
-        let store: Store = match self.cache.get(&req_key(method, url)) {
-            Some(d) => bincode::deserialize(&d)?,
+        let key = req_key(method, url);
+        let pinned = self.pinned.0.lock().await.get(&key).cloned();
+        if let Some(entry) = pinned {
+            // Never dropped, not even once past the grace period
+            return match entry {
+                Some(entry) => Self::decode(entry),
+                None => Ok(None),
+            };
+        }
+        let entry = match self.cache.get(&key) {
+            Some(entry) => entry,
             None => return Ok(None),
         };
-        Ok(Some((store.response, store.policy)))
+        if is_expired(&entry, now()) {
+            self.cache.invalidate(&key).await;
+            self.cache.sync();
+            return Ok(None);
+        }
+        Self::decode(entry)
     }
 
     async fn put(
@@ -72,16 +217,79 @@ impl CacheManager for MokaManager {
         response: HttpResponse,
         policy: CachePolicy,
     ) -> Result<HttpResponse> {
-        let data = Store { response: response.clone(), policy };
-        let bytes = bincode::serialize(&data)?;
-        self.cache.insert(req_key(method, url), Arc::new(bytes)).await;
-        self.cache.sync();
-        Ok(response)
+        let body = response.body.clone();
+        let time = now();
+        let expires = self
+            .stale_grace
+            .map(|grace| time + policy.time_to_live(time) + grace);
+        let mut data = Store { response, policy };
+        data.response.body = Bytes::new();
+        let mut head = vec![self.format.tag()];
+        head.extend(self.format.serialize(&data)?);
+        let entry =
+            MokaEntry { head: Arc::new(head), body: body.clone(), expires };
+        let key = req_key(method, url);
+        let mut pinned = self.pinned.0.lock().await;
+        match pinned.get_mut(&key) {
+            Some(slot) => *slot = Some(entry),
+            None => {
+                // Still holding the lock, so it can't be pinned meanwhile
+                self.cache.insert(key, entry).await;
+                self.cache.sync();
+            }
+        }
+        drop(pinned);
+        data.response.body = body;
+        Ok(data.response)
     }
 
     async fn delete(&self, method: &str, url: &Url) -> Result<()> {
-        self.cache.invalidate(&req_key(method, url)).await;
+        let key = req_key(method, url);
+        let mut pinned = self.pinned.0.lock().await;
+        if let Some(slot) = pinned.get_mut(&key) {
+            *slot = None;
+        }
+        self.cache.invalidate(&key).await;
+        self.cache.sync();
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<(String, Url)>> {
+        let mut keys: Vec<_> = self
+            .pinned
+            .0
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, entry)| entry.is_some())
+            .filter_map(|(key, _)| parse_key(key))
+            .collect();
+        keys.extend(self.cache.iter().filter_map(|(key, _)| parse_key(&key)));
+        Ok(keys)
+    }
+
+    async fn pin(&self, method: &str, url: &Url) -> Result<()> {
+        let key = req_key(method, url);
+        let mut pinned = self.pinned.0.lock().await;
+        if pinned.contains_key(&key) {
+            return Ok(());
+        }
+        // Moved out of moka's reach, under the lock so that a concurrent put
+        // either lands before the move or in the pinned slot
+        let entry = self.cache.get(&key);
+        self.cache.invalidate(&key).await;
         self.cache.sync();
+        pinned.insert(key, entry);
+        Ok(())
+    }
+
+    async fn unpin(&self, method: &str, url: &Url) -> Result<()> {
+        let key = req_key(method, url);
+        let mut pinned = self.pinned.0.lock().await;
+        if let Some(entry) = pinned.remove(&key).flatten() {
+            self.cache.insert(key, entry).await;
+            self.cache.sync();
+        }
         Ok(())
     }
 }
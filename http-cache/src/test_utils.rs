@@ -0,0 +1,415 @@
+//! Stand-ins for a cache manager and a client, for testing code built on the
+//! cache without a network or a storage backend. [`MockManager`] records the
+//! calls made to it and can be made to fail, while [`ScriptedMiddleware`]
+//! answers requests with the responses queued on a [`Script`].
+use crate::{
+    now, CacheError, CacheManager, CacheMode, CacheOptions, HttpCache,
+    HttpCacheOptions, HttpResponse, HttpVersion, Middleware, Result,
+};
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use futures::channel::oneshot;
+use http::{request, HeaderMap, Method};
+use http_cache_semantics::CachePolicy;
+use url::Url;
+
+/// Builds a response for the url with the status, headers and body, for
+/// queueing on a [`Script`] or storing in a [`MockManager`]
+pub fn response(
+    url: &Url,
+    status: u16,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> HttpResponse {
+    HttpResponse {
+        body: Bytes::copy_from_slice(body),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.to_string()))
+            .collect(),
+        trailers: HashMap::new(),
+        status,
+        url: url.clone(),
+        version: HttpVersion::Http11,
+    }
+}
+
+/// Builds a cache in the default mode with the options, storing its responses
+/// in a clone of the manager so the test can inspect them
+pub fn build_cache(
+    manager: &MockManager,
+    options: HttpCacheOptions,
+) -> HttpCache<MockManager> {
+    HttpCache { mode: CacheMode::Default, manager: manager.clone(), options }
+}
+
+/// A call made to a [`MockManager`], with the method and url it was for
+#[derive(Debug, Clone)]
+pub enum ManagerCall {
+    /// A lookup
+    Get(String, Url),
+    /// A response being stored
+    Put(String, Url, Box<HttpResponse>),
+    /// A removal
+    Delete(String, Url),
+    /// A listing of the stored keys
+    Keys,
+}
+
+/// The operations of a [`MockManager`] that can be made to fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ManagerOperation {
+    /// [`CacheManager::get`]
+    Get,
+    /// [`CacheManager::put`]
+    Put,
+    /// [`CacheManager::delete`]
+    Delete,
+}
+
+// The stored entries, by method and url
+type Entries = HashMap<(String, Url), (HttpResponse, CachePolicy)>;
+
+/// Implements [`CacheManager`] in memory, recording every call made to it.
+/// Clones share the entries and the record.
+#[derive(Debug, Clone, Default)]
+pub struct MockManager {
+    entries: Arc<Mutex<Entries>>,
+    calls: Arc<Mutex<Vec<ManagerCall>>>,
+    failing: Arc<Mutex<HashSet<ManagerOperation>>>,
+    put_delay: Arc<Mutex<Duration>>,
+}
+
+impl MockManager {
+    /// Stores a canned entry, without recording a call
+    pub fn insert(
+        &self,
+        method: &str,
+        url: &Url,
+        response: HttpResponse,
+        policy: CachePolicy,
+    ) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((method.to_string(), url.clone()), (response, policy));
+    }
+
+    /// Returns the entry stored for the method and url, without recording
+    /// a call
+    pub fn entry(
+        &self,
+        method: &str,
+        url: &Url,
+    ) -> Option<(HttpResponse, CachePolicy)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&(method.to_string(), url.clone()))
+            .cloned()
+    }
+
+    /// Returns the calls made so far, oldest first
+    pub fn calls(&self) -> Vec<ManagerCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Returns the number of responses stored so far
+    pub fn puts(&self) -> usize {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| matches!(call, ManagerCall::Put(..)))
+            .count()
+    }
+
+    /// Forgets the calls made so far
+    pub fn clear_calls(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+
+    /// Makes the operation fail until [`recover`](MockManager::recover) is
+    /// called for it. Failed calls are still recorded.
+    pub fn fail(&self, operation: ManagerOperation) {
+        self.failing.lock().unwrap().insert(operation);
+    }
+
+    /// Lets the operation succeed again
+    pub fn recover(&self, operation: ManagerOperation) {
+        self.failing.lock().unwrap().remove(&operation);
+    }
+
+    /// Makes every put take this long before it stores the response, as
+    /// with a backend slower than the origin
+    pub fn delay_puts(&self, delay: Duration) {
+        *self.put_delay.lock().unwrap() = delay;
+    }
+
+    // Records the call, failing it if the operation was made to fail
+    fn record(
+        &self,
+        call: ManagerCall,
+        operation: ManagerOperation,
+    ) -> Result<()> {
+        self.calls.lock().unwrap().push(call);
+        if self.failing.lock().unwrap().contains(&operation) {
+            return Err(CacheError::General(anyhow!(
+                "injected {:?} failure",
+                operation
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheManager for MockManager {
+    async fn get(
+        &self,
+        method: &str,
+        url: &Url,
+    ) -> Result<Option<(HttpResponse, CachePolicy)>> {
+        let call = ManagerCall::Get(method.to_string(), url.clone());
+        self.record(call, ManagerOperation::Get)?;
+        Ok(self.entry(method, url))
+    }
+
+    async fn put(
+        &self,
+        method: &str,
+        url: &Url,
+        res: HttpResponse,
+        policy: CachePolicy,
+    ) -> Result<HttpResponse> {
+        let delay = *self.put_delay.lock().unwrap();
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        let call = ManagerCall::Put(
+            method.to_string(),
+            url.clone(),
+            Box::new(res.clone()),
+        );
+        self.record(call, ManagerOperation::Put)?;
+        self.insert(method, url, res.clone(), policy);
+        Ok(res)
+    }
+
+    async fn delete(&self, method: &str, url: &Url) -> Result<()> {
+        let call = ManagerCall::Delete(method.to_string(), url.clone());
+        self.record(call, ManagerOperation::Delete)?;
+        self.entries.lock().unwrap().remove(&(method.to_string(), url.clone()));
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<(String, Url)>> {
+        self.calls.lock().unwrap().push(ManagerCall::Keys);
+        Ok(self.entries.lock().unwrap().keys().cloned().collect())
+    }
+}
+
+// Waits for the time to pass on a thread of its own, which works under any
+// runtime
+async fn sleep(duration: Duration) {
+    let (done, waited) = oneshot::channel();
+    thread::spawn(move || {
+        thread::sleep(duration);
+        done.send(()).ok();
+    });
+    waited.await.ok();
+}
+
+/// The responses a [`ScriptedMiddleware`] answers with, in order, along with
+/// the requests that were sent for them. Clones share the queue and the
+/// record, so a script can be inspected after the middleware was consumed.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    responses: Arc<Mutex<VecDeque<Option<HttpResponse>>>>,
+    requests: Arc<Mutex<Vec<HeaderMap>>>,
+    // The fetches waiting on the origin now, and the most there ever were
+    in_flight: Arc<Mutex<(usize, usize)>>,
+}
+
+impl Script {
+    /// Queues a response
+    pub fn respond(&self, response: HttpResponse) -> &Self {
+        self.responses.lock().unwrap().push_back(Some(response));
+        self
+    }
+
+    /// Queues a fetch that fails, as if the origin couldn't be reached
+    pub fn fail(&self) -> &Self {
+        self.responses.lock().unwrap().push_back(None);
+        self
+    }
+
+    /// Returns the headers of each request sent to the origin so far
+    pub fn requests(&self) -> Vec<HeaderMap> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the number of requests sent to the origin so far
+    pub fn fetches(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// Returns the most fetches that were waiting on the origin at once
+    pub fn max_in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().1
+    }
+
+    /// Returns the number of queued responses not yet served
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().unwrap().len()
+    }
+}
+
+/// Implements [`Middleware`] for a single request, answering it with the
+/// next response of its [`Script`]. Running out of responses is an error.
+/// It can be detached, the copy answering from the same script.
+#[derive(Debug)]
+pub struct ScriptedMiddleware {
+    parts: request::Parts,
+    url: Url,
+    script: Script,
+    /// Overrides the cache mode, as a request extension would with a client
+    pub mode: Option<CacheMode>,
+    /// Overrides the cache key, as a request extension would with a client
+    pub cache_key: Option<String>,
+    /// Overrides the cache options, as a request extension would with a
+    /// client
+    pub cache_options: Option<CacheOptions>,
+    /// How long the origin takes to answer, so that requests run together
+    /// overlap. Zero by default.
+    pub delay: Duration,
+}
+
+impl ScriptedMiddleware {
+    /// Creates a request with the method for the url, answered by the
+    /// script
+    pub fn new(method: &str, url: &Url, script: &Script) -> Result<Self> {
+        let parts = request::Builder::new()
+            .method(Method::from_bytes(method.as_bytes())?)
+            .uri(url.as_str())
+            .body(())?
+            .into_parts()
+            .0;
+        Ok(Self {
+            parts,
+            url: url.clone(),
+            script: script.clone(),
+            mode: None,
+            cache_key: None,
+            cache_options: None,
+            delay: Duration::ZERO,
+        })
+    }
+
+    /// Returns the request headers, for adding to before the request is run
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.parts.headers
+    }
+
+    fn copy_parts(&self) -> Result<request::Parts> {
+        let mut parts = request::Builder::new()
+            .method(self.parts.method.clone())
+            .uri(self.parts.uri.clone())
+            .body(())?
+            .into_parts()
+            .0;
+        parts.headers = self.parts.headers.clone();
+        Ok(parts)
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for ScriptedMiddleware {
+    fn is_method_get_head(&self) -> bool {
+        self.parts.method == Method::GET || self.parts.method == Method::HEAD
+    }
+    fn policy(&self, response: &HttpResponse) -> Result<CachePolicy> {
+        self.policy_with_options(response, CacheOptions::default())
+    }
+    fn policy_with_options(
+        &self,
+        response: &HttpResponse,
+        options: CacheOptions,
+    ) -> Result<CachePolicy> {
+        Ok(CachePolicy::new_options(
+            &self.copy_parts()?,
+            &response.parts()?,
+            now(),
+            options,
+        ))
+    }
+    fn update_headers(&mut self, parts: &request::Parts) -> Result<()> {
+        for (name, value) in parts.headers.iter() {
+            self.parts.headers.insert(name.clone(), value.clone());
+        }
+        Ok(())
+    }
+    fn force_no_cache(&mut self) -> Result<()> {
+        self.parts.headers.insert(
+            http::header::CACHE_CONTROL,
+            http::HeaderValue::from_static("no-cache"),
+        );
+        Ok(())
+    }
+    fn parts(&self) -> Result<request::Parts> {
+        self.copy_parts()
+    }
+    fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+    fn method(&self) -> Result<String> {
+        Ok(self.parts.method.as_str().to_string())
+    }
+    fn overridden_cache_mode(&self) -> Option<CacheMode> {
+        self.mode
+    }
+    fn overridden_cache_key(&self) -> Option<String> {
+        self.cache_key.clone()
+    }
+    fn overridden_cache_options(&self) -> Option<CacheOptions> {
+        self.cache_options
+    }
+    fn detach(&self) -> Option<Box<dyn Middleware + Sync>> {
+        Some(Box::new(Self {
+            parts: self.copy_parts().ok()?,
+            url: self.url.clone(),
+            script: self.script.clone(),
+            mode: self.mode,
+            cache_key: self.cache_key.clone(),
+            cache_options: self.cache_options,
+            delay: self.delay,
+        }))
+    }
+    async fn remote_fetch(&mut self) -> Result<HttpResponse> {
+        {
+            let mut in_flight = self.script.in_flight.lock().unwrap();
+            in_flight.0 += 1;
+            in_flight.1 = in_flight.1.max(in_flight.0);
+        }
+        if !self.delay.is_zero() {
+            sleep(self.delay).await;
+        }
+        self.script.in_flight.lock().unwrap().0 -= 1;
+        self.script.requests.lock().unwrap().push(self.parts.headers.clone());
+        match self.script.responses.lock().unwrap().pop_front() {
+            Some(Some(response)) => Ok(response),
+            Some(None) => {
+                Err(CacheError::General(anyhow!("scripted fetch failure")))
+            }
+            None => Err(CacheError::General(anyhow!("no response scripted"))),
+        }
+    }
+}